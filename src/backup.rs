@@ -0,0 +1,328 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+use crate::trace::Traced;
+
+/// Backups from the last 30 days are kept...
+const KEPT_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// ...and so are the newest 20, however old.
+const KEPT_BACKUPS: usize = 20;
+
+/// Changes backed up before checkout discarded them: a patch of the tracked
+/// changes, and a tarball of the untracked files when there were any.
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    pub patch: PathBuf,
+    pub untracked: Option<PathBuf>,
+    /// The worktree the changes were taken from, per the patch's header
+    pub worktree: Option<PathBuf>,
+    /// The commit the patch applies on
+    pub head: Option<String>,
+    pub created: SystemTime,
+}
+
+pub fn backups_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("backups")
+}
+
+fn untracked_path(patch: &Path) -> PathBuf {
+    patch.with_extension("untracked.tar")
+}
+
+fn git_stdout(worktree: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(worktree)
+        .args(args)
+        .traced_output()
+        .map_err(|e| format!("Failed to run git {}: {}", args.join(" "), e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// Save `worktree`'s uncommitted changes under `<data_dir>/backups` as
+/// `<worktree-name>-<timestamp>.patch` (`_2`, `_3`... after the timestamp
+/// for more in the same second): `git diff HEAD`, so staged and unstaged
+/// changes together, behind a header naming the worktree and HEAD. Untracked
+/// files go in a `.untracked.tar` next to it. `None` when there's nothing to
+/// back up.
+pub fn create(data_dir: &Path, worktree: &Path) -> Result<Option<PathBuf>, String> {
+    let diff = git_stdout(worktree, &["diff", "--binary", "HEAD"])?;
+    let untracked = git_stdout(worktree, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+    if diff.is_empty() && untracked.is_empty() {
+        return Ok(None);
+    }
+    let head = String::from_utf8_lossy(&git_stdout(worktree, &["rev-parse", "HEAD"])?).trim().to_string();
+
+    let dir = backups_dir(data_dir);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let name = worktree.file_name().map_or_else(|| "worktree".into(), |name| name.to_string_lossy());
+    let (patch, file) = create_patch_file(&dir, &name, unix_now())?;
+    let written = write_backup(worktree, &patch, file, &head, &diff, &untracked);
+    if written.is_err() {
+        let _ = fs::remove_file(&patch);
+        let _ = fs::remove_file(untracked_path(&patch));
+    }
+    written.map(|()| Some(patch))
+}
+
+/// Claim a new `<name>-<now>.patch` in `dir`, counting up from `_2` when
+/// another backup of the same worktree took this second's name.
+fn create_patch_file(dir: &Path, name: &str, now: u64) -> Result<(PathBuf, File), String> {
+    for n in 1.. {
+        let suffix = if n == 1 { String::new() } else { format!("_{}", n) };
+        let patch = dir.join(format!("{}-{}{}.patch", name, now, suffix));
+        match File::options().write(true).create_new(true).open(&patch) {
+            Ok(file) => return Ok((patch, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to create backup {}: {}", patch.display(), e)),
+        }
+    }
+    unreachable!("ran out of backup names")
+}
+
+/// Fill in the backup `create` claimed at `patch`: the untracked files'
+/// tarball first, then the patch itself.
+fn write_backup(worktree: &Path, patch: &Path, mut file: File, head: &str, diff: &[u8], untracked: &[u8]) -> Result<(), String> {
+    if !untracked.is_empty() {
+        let tarball = untracked_path(patch);
+        let mut tar = Command::new("tar")
+            .arg("-C")
+            .arg(worktree)
+            .arg("-cf")
+            .arg(&tarball)
+            .args(["--null", "-T", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run tar: {}", e))?;
+        if let Some(mut stdin) = tar.stdin.take() {
+            stdin.write_all(untracked).map_err(|e| format!("Failed to run tar: {}", e))?;
+        }
+        let output = tar.wait_with_output().map_err(|e| format!("Failed to run tar: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to back up untracked files: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+
+    // `git apply` skips everything before the first `diff --git` line
+    let mut content = format!("# checkout backup of {}\n# HEAD {}\n\n", worktree.display(), head).into_bytes();
+    content.extend_from_slice(diff);
+    file.write_all(&content).map_err(|e| format!("Failed to write backup {}: {}", patch.display(), e))
+}
+
+/// Read a backup's header and find its untracked-files tarball. Only the
+/// header is read, not the patch after it.
+pub fn read(patch: &Path) -> Result<Backup, String> {
+    let file = File::open(patch).map_err(|e| format!("Failed to read {}: {}", patch.display(), e))?;
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).split(b'\n') {
+        let line = String::from_utf8_lossy(&line.map_err(|e| format!("Failed to read {}: {}", patch.display(), e))?).into_owned();
+        if !(line.starts_with('#') || line.is_empty()) {
+            break;
+        }
+        lines.push(line);
+    }
+    let header = |prefix: &str| lines.iter().find_map(|line| line.strip_prefix(prefix).map(str::to_string));
+    let untracked = untracked_path(patch);
+    Ok(Backup {
+        patch: patch.to_path_buf(),
+        untracked: untracked.exists().then_some(untracked),
+        worktree: header("# checkout backup of ").map(PathBuf::from),
+        head: header("# HEAD "),
+        created: created_at(patch).unwrap_or(SystemTime::UNIX_EPOCH),
+    })
+}
+
+/// When a backup was made, from the timestamp ending its file name.
+fn created_at(patch: &Path) -> Option<SystemTime> {
+    let stem = patch.file_stem()?.to_str()?;
+    let (_, secs) = stem.rsplit_once('-')?;
+    let secs = secs.split_once('_').map_or(secs, |(secs, _)| secs);
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?))
+}
+
+/// Every backup's patch with when it was made, newest first, going by the
+/// file names alone.
+fn patches(data_dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let mut patches: Vec<(PathBuf, SystemTime)> = fs::read_dir(backups_dir(data_dir))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "patch"))
+        .map(|path| {
+            let created = created_at(&path).unwrap_or(SystemTime::UNIX_EPOCH);
+            (path, created)
+        })
+        .collect();
+    patches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    patches
+}
+
+/// All backups, newest first.
+pub fn list(data_dir: &Path) -> Vec<Backup> {
+    patches(data_dir).into_iter().filter_map(|(patch, _)| read(&patch).ok()).collect()
+}
+
+/// Apply a backup's patch to `worktree`, then unpack its untracked files
+/// there, overwriting files of the same name. Nothing is changed when the
+/// patch doesn't apply.
+pub fn restore(backup: &Backup, worktree: &Path) -> Result<(), String> {
+    // A backup of only untracked files has no patch for git to apply
+    let content = fs::read(&backup.patch).map_err(|e| format!("Failed to read {}: {}", backup.patch.display(), e))?;
+    if String::from_utf8_lossy(&content).lines().any(|line| line.starts_with("diff --git ")) {
+        apply(&backup.patch, worktree)?;
+    }
+    if let Some(tarball) = &backup.untracked {
+        let output = Command::new("tar")
+            .arg("-C")
+            .arg(worktree)
+            .arg("-xf")
+            .arg(tarball)
+            .traced_output()
+            .map_err(|e| format!("Failed to run tar: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to restore untracked files from {}: {}",
+                tarball.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn apply(patch: &Path, worktree: &Path) -> Result<(), String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(worktree)
+        .args(["apply", "--binary"])
+        .arg(patch)
+        .traced_output()
+        .map_err(|e| format!("Failed to run git apply: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "The backup doesn't apply to {}: {} (try git apply --3way {})",
+            worktree.display(),
+            String::from_utf8_lossy(&output.stderr).trim(),
+            patch.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Remove backups older than 30 days, except the newest `KEPT_BACKUPS`.
+pub fn prune(data_dir: &Path) {
+    let now = SystemTime::now();
+    for (patch, created) in patches(data_dir).into_iter().skip(KEPT_BACKUPS) {
+        if now.duration_since(created).is_ok_and(|age| age > KEPT_AGE) {
+            let _ = fs::remove_file(&patch);
+            let _ = fs::remove_file(untracked_path(&patch));
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::run_git;
+
+    #[test]
+    fn backs_up_and_restores_a_dirty_worktree() {
+        let dir = std::env::temp_dir().join(format!("checkout-backup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (worktree, data_dir) = (dir.join("pr-5-fix"), dir.join("data"));
+        fs::create_dir_all(&worktree).unwrap();
+        run_git(&worktree, &["init", "-q", "-b", "main"]);
+        fs::write(worktree.join("staged.txt"), "one\n").unwrap();
+        fs::write(worktree.join("edited.txt"), "one\n").unwrap();
+        fs::write(worktree.join("removed.txt"), "one\n").unwrap();
+        fs::write(worktree.join(".gitignore"), "ignored.txt\n").unwrap();
+        run_git(&worktree, &["add", "."]);
+        run_git(&worktree, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "-m", "init"]);
+        assert_eq!(create(&data_dir, &worktree).unwrap(), None);
+
+        fs::write(worktree.join("staged.txt"), "two\n").unwrap();
+        run_git(&worktree, &["add", "staged.txt"]);
+        fs::write(worktree.join("edited.txt"), "two\n").unwrap();
+        fs::remove_file(worktree.join("removed.txt")).unwrap();
+        fs::create_dir_all(worktree.join("notes")).unwrap();
+        fs::write(worktree.join("notes/todo.md"), "untracked\n").unwrap();
+        fs::write(worktree.join("ignored.txt"), "build output\n").unwrap();
+
+        let patch = create(&data_dir, &worktree).unwrap().unwrap();
+        let backup = read(&patch).unwrap();
+        assert!(patch.file_name().unwrap().to_string_lossy().starts_with("pr-5-fix-"));
+        assert_eq!(backup.worktree.as_deref(), Some(worktree.as_path()));
+        assert_eq!(backup.head.as_deref().map(str::len), Some(40));
+        assert!(backup.untracked.is_some());
+        assert_eq!(list(&data_dir), vec![read(&patch).unwrap()]);
+
+        // What discarding does, including the untracked files
+        run_git(&worktree, &["reset", "-q", "--hard"]);
+        fs::remove_dir_all(worktree.join("notes")).unwrap();
+        restore(&backup, &worktree).unwrap();
+        assert_eq!(fs::read_to_string(worktree.join("staged.txt")).unwrap(), "two\n");
+        assert_eq!(fs::read_to_string(worktree.join("edited.txt")).unwrap(), "two\n");
+        assert!(!worktree.join("removed.txt").exists());
+        assert_eq!(fs::read_to_string(worktree.join("notes/todo.md")).unwrap(), "untracked\n");
+
+        // Applying it again conflicts with the restored changes
+        assert!(restore(&backup, &worktree).unwrap_err().contains("doesn't apply"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backups_in_the_same_second_get_their_own_names() {
+        let dir = std::env::temp_dir().join(format!("checkout-backup-names-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let names: Vec<PathBuf> = (0..3).map(|_| create_patch_file(&dir, "pr-5-fix", 1000).unwrap().0).collect();
+        assert_eq!(names, ["pr-5-fix-1000.patch", "pr-5-fix-1000_2.patch", "pr-5-fix-1000_3.patch"].map(|name| dir.join(name)));
+        assert_eq!(created_at(&names[2]), Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1000)));
+        assert_eq!(untracked_path(&names[1]), dir.join("pr-5-fix-1000_2.untracked.tar"));
+
+        // Only the header is parsed; the patch after it can be any bytes
+        fs::write(&names[0], b"# HEAD abc\n\ndiff --git \xff\n").unwrap();
+        assert_eq!(read(&names[0]).unwrap().head.as_deref(), Some("abc"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_recent_and_the_newest_backups() {
+        let dir = std::env::temp_dir().join(format!("checkout-backup-prune-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(backups_dir(&dir)).unwrap();
+        let old = unix_now() - KEPT_AGE.as_secs() - 60;
+        for i in 0..KEPT_BACKUPS as u64 + 3 {
+            fs::write(backups_dir(&dir).join(format!("pr-1-{}.patch", old - i)), "").unwrap();
+        }
+        fs::write(backups_dir(&dir).join(format!("pr-1-{}.untracked.tar", old - 22)), "").unwrap();
+        fs::write(backups_dir(&dir).join(format!("pr-2-{}.patch", unix_now())), "").unwrap();
+
+        prune(&dir);
+        let kept = list(&dir);
+        assert_eq!(kept.len(), KEPT_BACKUPS);
+        assert!(kept[0].patch.file_name().unwrap().to_string_lossy().starts_with("pr-2-"));
+        assert_eq!(kept[KEPT_BACKUPS - 1].created, SystemTime::UNIX_EPOCH + Duration::from_secs(old - 18));
+        assert_eq!(fs::read_dir(backups_dir(&dir)).unwrap().count(), KEPT_BACKUPS);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,543 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::terminal::TerminalKind;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// User configuration read from `$XDG_CONFIG_HOME/checkout/config.toml`.
+/// Every key is optional; unset keys fall back to the built-in defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path to the main git repo, used when `$CHECKOUT_REPO` is unset
+    pub repo: Option<PathBuf>,
+    /// Directory for worktrees, used when `$CHECKOUT_WORKTREE_DIR` is unset
+    pub worktree_dir: Option<PathBuf>,
+    /// Parent of per-repo worktree directories, `<worktree_root>/<repo>`,
+    /// used when `worktree_dir` is unset too (default: `~/worktrees`)
+    pub worktree_root: Option<PathBuf>,
+    /// Prefix for branches checkout names itself (default: `darren/`)
+    pub prefix: Option<String>,
+    /// Directory-name prefix for PR worktrees (default: `pr-`)
+    pub pr_dir_prefix: Option<String>,
+    /// Directory-name prefix for branch and `new` worktrees (default: `branch-`)
+    pub branch_dir_prefix: Option<String>,
+    /// How to name a new worktree when its preferred directory already exists
+    pub collision_strategy: CollisionStrategy,
+    /// Where `checkout pr` fetches a PR from
+    pub pr_fetch: PrFetch,
+    /// Highest PR number accepted, so a pasted typo fails before reaching
+    /// `gh` (default: GitHub's limit, 2147483647)
+    pub max_pr_number: Option<u64>,
+    /// Worktree directory-name globs that `checkout clean` never removes
+    pub clean_exclude: Vec<String>,
+    /// Path globs, relative to the worktree root, whose changes don't make a
+    /// worktree count as modified (e.g. generated files the build always touches)
+    pub status_ignore: Vec<String>,
+    /// Initial agent prompt for `checkout pr`; the PR number is appended
+    /// (default: `/checkout:checkout-pr`)
+    pub pr_prompt: Option<String>,
+    /// Claude prompt used instead when `pr_prompt`'s slash command isn't
+    /// installed; `{pr}` is the PR number. Unset or empty turns the check off.
+    pub pr_fallback_prompt: Option<String>,
+    /// Per-repo overrides, keyed by the repo directory name
+    pub repos: BTreeMap<String, RepoConfig>,
+    /// Terminal title with `{name}` (session name) and `{repo}` placeholders (default: `{name}`)
+    pub title_template: Option<String>,
+    /// Set the terminal title for agent sessions (default: true)
+    pub set_title: Option<bool>,
+    /// Executable run in the worktree before the agent starts, with
+    /// `CHECKOUT_WORKTREE`, `CHECKOUT_COLOR` and `CHECKOUT_SESSION_NAME` set
+    pub on_enter_script: Option<PathBuf>,
+    /// Terminal escape dialect: `iterm2`, `osc`, `kitty` or `none` (default: detected)
+    pub terminal: Option<TerminalKind>,
+    /// Show worktree paths and PR numbers as OSC 8 links on a terminal (default: true)
+    pub hyperlinks: Option<bool>,
+    /// Oldest git checkout runs with, e.g. `"2.31"` (default: 2.31)
+    pub min_git_version: Option<String>,
+    /// Base directory for checkout's persistent state (default: `$XDG_DATA_HOME/checkout`)
+    pub data_dir: Option<PathBuf>,
+    /// Directory for per-worktree background colors (default: `<data_dir>/colors`)
+    pub color_dir: Option<PathBuf>,
+}
+
+/// Settings under `[repos.<name>]` that take precedence over the top-level
+/// keys when checking out from that repo.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// Where the repo lives, making `<name>` an alias for `--repo <name>`
+    pub path: Option<PathBuf>,
+    pub pr_prompt: Option<String>,
+    /// Clone from here when `path` doesn't exist yet, as with `--clone-if-missing`
+    pub clone_url: Option<String>,
+}
+
+impl Config {
+    pub fn pr_dir_prefix(&self) -> &str {
+        self.pr_dir_prefix.as_deref().unwrap_or("pr-")
+    }
+
+    pub fn branch_dir_prefix(&self) -> &str {
+        self.branch_dir_prefix.as_deref().unwrap_or("branch-")
+    }
+
+    /// Where `repo`'s worktrees go without a `worktree_dir`:
+    /// `<worktree_root>/<repo>`, unless only the older `~/<repo>-worktrees`
+    /// exists, which keeps working until it's migrated.
+    pub fn repo_worktree_dir(&self, repo: &str) -> PathBuf {
+        let root = self.worktree_root.as_deref().map_or_else(|| home_dir().join("worktrees"), expand_tilde);
+        repo_worktree_dir_in(&root, &home_dir(), repo)
+    }
+
+    /// The repo `--repo <alias>` names, if a `[repos.<alias>]` table sets `path`
+    pub fn repo_path(&self, alias: &str) -> Option<PathBuf> {
+        self.repos.get(alias)?.path.as_deref().map(expand_tilde)
+    }
+
+    /// The `[repos.<name>]` table whose `path` is `repo_root`, so an alias's
+    /// overrides apply even when it differs from the directory name
+    pub fn repo_alias_for(&self, repo_root: &Path) -> Option<&str> {
+        let canonical_root = repo_root.canonicalize().ok();
+        self.repos.iter().find_map(|(name, repo)| {
+            let path = expand_tilde(repo.path.as_deref()?);
+            let same = path == repo_root || (canonical_root.is_some() && path.canonicalize().ok() == canonical_root);
+            same.then_some(name.as_str())
+        })
+    }
+
+    /// Where to clone the repo at `repo_root` from when it's missing: the
+    /// `clone_url` of the `[repos.<name>]` table whose `path` it is
+    pub fn clone_url_for(&self, repo_root: &Path) -> Option<&str> {
+        self.repos.get(self.repo_alias_for(repo_root)?)?.clone_url.as_deref()
+    }
+
+    /// The `checkout pr` prompt for `repo`: its `[repos.<repo>]` override,
+    /// else the global `pr_prompt`. `None` means use the built-in skill.
+    pub fn pr_prompt(&self, repo: &str) -> Option<&str> {
+        self.repos
+            .get(repo)
+            .and_then(|repo| repo.pr_prompt.as_deref())
+            .or(self.pr_prompt.as_deref())
+    }
+}
+
+fn repo_worktree_dir_in(root: &Path, home: &Path, repo: &str) -> PathBuf {
+    let dir = root.join(repo);
+    let legacy = home.join(format!("{}-worktrees", repo));
+    if !dir.exists() && legacy.is_dir() {
+        legacy
+    } else {
+        dir
+    }
+}
+
+/// Suffix scheme used when a worktree directory name is already taken.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionStrategy {
+    /// `-2`, `-3`, ... up to `-100`
+    #[default]
+    Numeric,
+    /// Milliseconds since the epoch, e.g. `-1760611200123`
+    Timestamp,
+    /// Seven hex chars derived from the pid and current time
+    Hash,
+}
+
+/// How `checkout pr` fetches a PR's commits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrFetch {
+    /// `origin/<headRefName>`, falling back to `pull/<n>/head` when the
+    /// branch is gone. Fork PRs always use `pull/<n>/head`.
+    #[default]
+    Branch,
+    /// `pull/<n>/head` into `refs/checkout/pr-<n>`, for every PR
+    PullRef,
+}
+
+/// Keys accepted by `checkout config get/set`. Must list every `Config` field.
+pub const KEYS: &[&str] = &["repo", "worktree_dir", "worktree_root", "prefix", "pr_dir_prefix", "branch_dir_prefix",
+    "collision_strategy", "pr_fetch", "max_pr_number",
+    "clean_exclude", "status_ignore",
+    "pr_prompt", "pr_fallback_prompt", "repos",
+    "title_template", "set_title", "on_enter_script",
+    "terminal", "hyperlinks", "min_git_version",
+    "data_dir", "color_dir"];
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap_or_else(|_| ".".to_string()))
+}
+
+/// Resolve an XDG base directory, ignoring empty or relative values as the
+/// spec requires.
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    env::var(var)
+        .ok()
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| home_dir().join(fallback))
+}
+
+/// Expand a leading `~/` so config values can be written the way they'd be
+/// typed in a shell.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("checkout/config.toml")
+}
+
+pub fn default_data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share").join("checkout")
+}
+
+pub fn branch_prefix() -> &'static str {
+    get().prefix.as_deref().unwrap_or("darren/")
+}
+
+pub fn pr_dir_prefix() -> &'static str {
+    get().pr_dir_prefix()
+}
+
+pub fn branch_dir_prefix() -> &'static str {
+    get().branch_dir_prefix()
+}
+
+pub fn parse_config(content: &str) -> Result<Config, String> {
+    parse_config_with_profile(content, None)
+}
+
+/// Parse the config with the `[profiles.<profile>]` table, if any, merged over
+/// the top-level keys. Every profile is checked, not just the selected one.
+pub fn parse_config_with_profile(content: &str, profile: Option<&str>) -> Result<Config, String> {
+    let mut base = content.parse::<toml::Table>().map_err(|e| e.to_string())?;
+    let profiles = match base.remove("profiles") {
+        None => toml::Table::new(),
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => return Err("profiles must be a table of [profiles.<name>] sections".to_string()),
+    };
+    for (name, overlay) in &profiles {
+        profile_config(&base, name, overlay).map_err(|e| format!("profile '{}': {}", name, e))?;
+    }
+    match profile {
+        None => build_config(base),
+        Some(name) => match profiles.get(name) {
+            Some(overlay) => profile_config(&base, name, overlay),
+            None if profiles.is_empty() => Err(format!("Unknown profile '{}': no [profiles.<name>] sections", name)),
+            None => Err(format!(
+                "Unknown profile '{}'. Known profiles: {}",
+                name,
+                profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            )),
+        },
+    }
+}
+
+fn profile_config(base: &toml::Table, name: &str, overlay: &toml::Value) -> Result<Config, String> {
+    let toml::Value::Table(overlay) = overlay else {
+        return Err(format!("[profiles.{}] must be a table", name));
+    };
+    if overlay.contains_key("profiles") {
+        return Err("profiles can't be nested".to_string());
+    }
+    let mut merged = base.clone();
+    merge_tables(&mut merged, overlay.clone());
+    build_config(merged)
+}
+
+/// Overlay `overlay` on `base`, merging nested tables such as `[repos.<name>]`
+/// key by key and replacing everything else.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn build_config(table: toml::Table) -> Result<Config, String> {
+    let config: Config = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| e.to_string())?;
+    // PR and branch worktrees are told apart by directory name alone
+    if config.pr_dir_prefix() == config.branch_dir_prefix() {
+        return Err(format!(
+            "pr_dir_prefix and branch_dir_prefix must differ (both are '{}')",
+            config.pr_dir_prefix()
+        ));
+    }
+    if config.max_pr_number == Some(0) {
+        return Err("max_pr_number must be at least 1".to_string());
+    }
+    Ok(config)
+}
+
+fn read_config(profile: Option<&str>) -> Result<Config, String> {
+    let path = config_path();
+    match fs::read_to_string(&path) {
+        Ok(content) => parse_config_with_profile(&content, profile)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => match profile {
+            Some(name) => Err(format!("Unknown profile '{}': no config file at {}", name, path.display())),
+            None => Ok(Config::default()),
+        },
+        Err(e) => Err(format!("Failed to read config {}: {}", path.display(), e)),
+    }
+}
+
+/// Load the config file once at startup so parse errors surface as a normal
+/// command error instead of a panic deep inside a helper. `profile` (from
+/// `--profile` or `$CHECKOUT_PROFILE`) selects a `[profiles.<name>]` table.
+pub fn init(profile: Option<&str>) -> Result<(), String> {
+    let config = read_config(profile)?;
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(unconfigured)
+}
+
+/// The config when `init` never ran: the built-in defaults.
+#[cfg(not(test))]
+fn unconfigured() -> Config {
+    Config::default()
+}
+
+/// Unit tests never call `init`; keep the metadata, backups and colors they
+/// write in a scratch data dir instead of the user's.
+#[cfg(test)]
+fn unconfigured() -> Config {
+    Config {
+        data_dir: Some(env::temp_dir().join(format!("checkout-test-data-{}", std::process::id()))),
+        ..Config::default()
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), String> {
+    if KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(format!("Unknown config key '{}'. Known keys: {}", key, KEYS.join(", ")))
+    }
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .parse::<toml::Table>()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(format!("Failed to read config {}: {}", path.display(), e)),
+    }
+}
+
+/// Parse a value given on the command line. Anything that is valid TOML
+/// (numbers, booleans, arrays, quoted strings) is taken as-is; everything else
+/// is a bare string, so `checkout config set repo ~/figma` needs no quoting.
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {}", value)
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn format_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Read a single key from the config file, or `None` if it isn't set.
+pub fn get_value(path: &Path, key: &str) -> Result<Option<String>, String> {
+    validate_key(key)?;
+    Ok(read_table(path)?.get(key).map(format_value))
+}
+
+/// Set a single key in the config file, validating the result against
+/// `Config` before writing so a bad value can't leave the file unloadable.
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<(), String> {
+    validate_key(key)?;
+    let mut table = read_table(path)?;
+    table.insert(key.to_string(), parse_value(value));
+    let mut content = toml::to_string(&table).map_err(|e| format!("Failed to serialize config: {}", e))?;
+    if let Err(e) = parse_config(&content) {
+        // A value that only fits as a string, like `min_git_version 2.31`
+        table.insert(key.to_string(), toml::Value::String(value.to_string()));
+        content = toml::to_string(&table).map_err(|e| format!("Failed to serialize config: {}", e))?;
+        parse_config(&content).map_err(|_| format!("Invalid value for '{}': {}", key, e))?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create config dir {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, content).map_err(|e| format!("Failed to write config {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directory_overrides_and_rejects_unknown_keys() {
+        let config = parse_config("data_dir = \"~/state\"\ncolor_dir = \"/tmp/colors\"\n").unwrap();
+        assert_eq!(config.data_dir, Some(PathBuf::from("~/state")));
+        assert_eq!(config.color_dir, Some(PathBuf::from("/tmp/colors")));
+        assert_eq!(parse_config("").unwrap(), Config::default());
+        assert!(parse_config("colour_dir = \"/tmp\"").is_err());
+
+        assert_eq!(expand_tilde(Path::new("~/state")), home_dir().join("state"));
+        assert_eq!(expand_tilde(Path::new("/abs")), PathBuf::from("/abs"));
+    }
+
+    #[test]
+    fn every_known_key_is_a_config_field() {
+        for key in KEYS {
+            if let Err(e) = parse_config(&format!("{} = \"x\"", key)) {
+                assert!(!e.contains("unknown field"), "{}: {}", key, e);
+            }
+        }
+        assert_eq!(
+            parse_config("collision_strategy = \"hash\"").unwrap().collision_strategy,
+            CollisionStrategy::Hash
+        );
+        assert!(parse_config("collision_strategy = \"random\"").is_err());
+        assert_eq!(parse_config("pr_fetch = \"pull-ref\"").unwrap().pr_fetch, PrFetch::PullRef);
+        assert_eq!(Config::default().pr_fetch, PrFetch::Branch);
+        assert_eq!(
+            parse_config("status_ignore = [\"gen/.buildinfo\", \"*.lock.tmp\"]").unwrap().status_ignore,
+            vec!["gen/.buildinfo", "*.lock.tmp"]
+        );
+    }
+
+    #[test]
+    fn dir_prefixes_default_and_must_differ() {
+        let config = Config::default();
+        assert_eq!((config.pr_dir_prefix(), config.branch_dir_prefix()), ("pr-", "branch-"));
+
+        let config = parse_config("pr_dir_prefix = \"\"\nbranch_dir_prefix = \"b-\"").unwrap();
+        assert_eq!((config.pr_dir_prefix(), config.branch_dir_prefix()), ("", "b-"));
+        assert!(parse_config("pr_dir_prefix = \"x-\"\nbranch_dir_prefix = \"x-\"").unwrap_err().contains("must differ"));
+        assert!(parse_config("max_pr_number = 0").unwrap_err().contains("at least 1"));
+        assert_eq!(parse_config("max_pr_number = 99999").unwrap().max_pr_number, Some(99999));
+        assert!(parse_config("branch_dir_prefix = \"pr-\"").is_err());
+    }
+
+    #[test]
+    fn pr_prompt_prefers_repo_override_then_global() {
+        let config = parse_config(
+            "pr_prompt = \"/team:pr\"\n\n[repos.figma]\npr_prompt = \"/a:review\"\n\n[repos.docs]\n",
+        )
+        .unwrap();
+        assert_eq!(config.pr_prompt("figma"), Some("/a:review"));
+        assert_eq!(config.pr_prompt("docs"), Some("/team:pr"));
+        assert_eq!(config.pr_prompt("other"), Some("/team:pr"));
+        assert_eq!(Config::default().pr_prompt("figma"), None);
+        assert!(parse_config("[repos.figma]\nprompt = \"/a:review\"").is_err());
+    }
+
+    #[test]
+    fn repo_tables_with_a_path_are_aliases() {
+        let config = parse_config(
+            "[repos.web]\npath = \"/src/webapp\"\npr_prompt = \"/web:pr\"\n\n[repos.home]\npath = \"~/side\"\n\n[repos.docs]\n",
+        )
+        .unwrap();
+        assert_eq!(config.repo_path("web"), Some(PathBuf::from("/src/webapp")));
+        assert_eq!(config.repo_path("home"), Some(home_dir().join("side")));
+        assert_eq!(config.repo_path("docs"), None);
+        assert_eq!(config.repo_path("other"), None);
+        assert_eq!(config.repo_alias_for(Path::new("/src/webapp")), Some("web"));
+        assert_eq!(config.repo_alias_for(Path::new("/src/docs")), None);
+    }
+
+    #[test]
+    fn clone_urls_come_from_the_repo_table_with_that_path() {
+        let config = parse_config(
+            "[repos.web]\npath = \"/src/missing-webapp\"\nclone_url = \"git@github.com:figma/webapp.git\"\n\n[repos.docs]\nclone_url = \"x\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.clone_url_for(Path::new("/src/missing-webapp")), Some("git@github.com:figma/webapp.git"));
+        assert_eq!(config.clone_url_for(Path::new("/src/docs")), None);
+    }
+
+    #[test]
+    fn worktree_dirs_are_namespaced_per_repo_with_a_legacy_fallback() {
+        let tmp = env::temp_dir().join(format!("checkout-worktree-root-{}", std::process::id()));
+        let (root, home) = (tmp.join("worktrees"), tmp.join("home"));
+        fs::create_dir_all(home.join("figma-worktrees")).unwrap();
+
+        // Two repos never share a directory; figma keeps its existing one
+        assert_eq!(repo_worktree_dir_in(&root, &home, "figma"), home.join("figma-worktrees"));
+        assert_eq!(repo_worktree_dir_in(&root, &home, "docs"), root.join("docs"));
+        // Once the new directory exists, it wins
+        fs::create_dir_all(root.join("figma")).unwrap();
+        assert_eq!(repo_worktree_dir_in(&root, &home, "figma"), root.join("figma"));
+
+        let config = parse_config("worktree_root = \"/src/trees\"").unwrap();
+        assert_eq!(config.repo_worktree_dir("checkout-pr-no-such-repo"), PathBuf::from("/src/trees/checkout-pr-no-such-repo"));
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn profiles_merge_over_the_base_config() {
+        let content = "prefix = \"darren/\"\nrepo = \"~/figma\"\n\n[repos.figma]\npr_prompt = \"/a:review\"\n\n\
+                       [profiles.personal]\nrepo = \"~/side\"\nbranch_dir_prefix = \"b-\"\n\n\
+                       [profiles.personal.repos.side]\npr_prompt = \"/me:pr\"\n\n[profiles.work]\n";
+        let base = parse_config(content).unwrap();
+        assert_eq!(base.repo, Some(PathBuf::from("~/figma")));
+        assert_eq!(base.branch_dir_prefix(), "branch-");
+
+        let personal = parse_config_with_profile(content, Some("personal")).unwrap();
+        assert_eq!(personal.repo, Some(PathBuf::from("~/side")));
+        assert_eq!(personal.prefix.as_deref(), Some("darren/"));
+        assert_eq!(personal.branch_dir_prefix(), "b-");
+        assert_eq!(personal.pr_prompt("figma"), Some("/a:review"));
+        assert_eq!(personal.pr_prompt("side"), Some("/me:pr"));
+        assert_eq!(parse_config_with_profile(content, Some("work")).unwrap(), base);
+
+        let unknown = parse_config_with_profile(content, Some("home")).unwrap_err();
+        assert!(unknown.contains("Known profiles: personal, work"), "{}", unknown);
+        assert!(parse_config_with_profile("", Some("work")).unwrap_err().contains("Unknown profile"));
+        // A broken profile fails even when it isn't selected
+        assert!(parse_config("[profiles.typo]\nrepoo = \"x\"").unwrap_err().contains("profile 'typo'"));
+        assert!(parse_config("[profiles.clash]\nbranch_dir_prefix = \"pr-\"").is_err());
+    }
+
+    #[test]
+    fn get_and_set_round_trip_known_keys_only() {
+        let path = std::env::temp_dir()
+            .join(format!("checkout-config-{}", std::process::id()))
+            .join("config.toml");
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(get_value(&path, "repo").unwrap(), None);
+        set_value(&path, "repo", "~/figma").unwrap();
+        set_value(&path, "worktree_dir", "\"/tmp/worktrees\"").unwrap();
+        assert_eq!(get_value(&path, "repo").unwrap().as_deref(), Some("~/figma"));
+        assert_eq!(get_value(&path, "worktree_dir").unwrap().as_deref(), Some("/tmp/worktrees"));
+        set_value(&path, "min_git_version", "2.31").unwrap();
+        assert_eq!(get_value(&path, "min_git_version").unwrap().as_deref(), Some("2.31"));
+        assert!(set_value(&path, "set_title", "maybe").unwrap_err().contains("Invalid value for 'set_title'"));
+        assert!(set_value(&path, "prefix_typo", "x").unwrap_err().contains("Unknown config key"));
+        assert!(get_value(&path, "prefix_typo").is_err());
+
+        let config = parse_config(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.repo, Some(PathBuf::from("~/figma")));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
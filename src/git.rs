@@ -0,0 +1,929 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use crate::trace::Traced;
+
+fn git(repo: &Path, args: &[&str]) -> Result<Output, String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .traced_output()
+        .map_err(|e| format!("Failed to run git {}: {}", args.join(" "), e))
+}
+
+/// One entry from `git worktree list --porcelain`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorktreeEntry {
+    pub path: PathBuf,
+    /// Commit SHA checked out (empty for a bare repo)
+    pub head: String,
+    /// Checked-out branch, or `None` for a detached HEAD
+    pub branch: Option<String>,
+}
+
+pub fn parse_worktree_list(porcelain: &str) -> Vec<WorktreeEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<WorktreeEntry> = None;
+    for line in porcelain.lines() {
+        if let Some(path) = line.strip_prefix("worktree ") {
+            entries.extend(current.take());
+            current = Some(WorktreeEntry { path: PathBuf::from(path), head: String::new(), branch: None });
+        } else if let Some(head) = line.strip_prefix("HEAD ") {
+            if let Some(entry) = current.as_mut() {
+                entry.head = head.to_string();
+            }
+        } else if let Some(branch) = line.strip_prefix("branch refs/heads/") {
+            if let Some(entry) = current.as_mut() {
+                entry.branch = Some(branch.to_string());
+            }
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// All worktrees of `repo`, including the main checkout.
+pub fn list_worktrees(repo: &Path) -> Result<Vec<WorktreeEntry>, String> {
+    let output = git(repo, &["worktree", "list", "--porcelain"])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git worktree list failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_worktree_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The main working tree that `repo` belongs to. For a linked worktree this
+/// is the clone that owns it; for the main checkout it's `repo` itself, with
+/// symlinks resolved the way `git worktree list` reports it. `None` for a
+/// bare repo, which has no main working tree.
+pub fn main_worktree(repo: &Path) -> Result<Option<PathBuf>, String> {
+    let output = git(repo, &["rev-parse", "--git-common-dir"])?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} is not a git repo: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // Relative to `repo` when run from the main checkout (usually just `.git`)
+    let common_dir = repo.join(String::from_utf8_lossy(&output.stdout).trim());
+    let common_dir = common_dir
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", common_dir.display(), e))?;
+    if common_dir.file_name().is_some_and(|name| name == ".git") {
+        Ok(common_dir.parent().map(Path::to_path_buf))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Name of the branch checked out in `repo`, or `None` for a detached HEAD
+/// (which is how PR worktrees are normally created).
+pub fn current_branch(repo: &Path) -> Result<Option<String>, String> {
+    let output = git(repo, &["symbolic-ref", "--short", "-q", "HEAD"])?;
+    // `-q` makes symbolic-ref exit 1 silently when HEAD is detached; anything
+    // else (e.g. not a repository) is a real error.
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        Some(1) => Ok(None),
+        _ => Err(format!(
+            "git symbolic-ref failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+/// The remote branch a local branch tracks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    pub remote: String,
+    /// Branch name on `remote`, without `refs/heads/`
+    pub branch: String,
+}
+
+fn config_value(repo: &Path, key: &str) -> Result<Option<String>, String> {
+    let output = git(repo, &["config", "--get", key])?;
+    // Exit 1 means the key is unset
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        Some(1) => Ok(None),
+        _ => Err(format!(
+            "git config --get {} failed: {}",
+            key,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+/// The configured upstream of `branch`, read from `branch.<name>.remote` and
+/// `branch.<name>.merge` rather than resolved, so an upstream whose remote
+/// branch was renamed or deleted is still reported.
+pub fn upstream(repo: &Path, branch: &str) -> Result<Option<Upstream>, String> {
+    let remote = config_value(repo, &format!("branch.{}.remote", branch))?;
+    let merge = config_value(repo, &format!("branch.{}.merge", branch))?;
+    Ok(remote.zip(merge).map(|(remote, merge)| Upstream {
+        remote,
+        branch: merge.strip_prefix("refs/heads/").unwrap_or(&merge).to_string(),
+    }))
+}
+
+/// Point `branch` at `upstream` by writing its config directly, which unlike
+/// `git branch --set-upstream-to` works before the remote ref is fetched.
+pub fn set_upstream(repo: &Path, branch: &str, upstream: &Upstream) -> Result<(), String> {
+    git_checked(repo, &["config", &format!("branch.{}.remote", branch), &upstream.remote])?;
+    git_checked(
+        repo,
+        &["config", &format!("branch.{}.merge", branch), &format!("refs/heads/{}", upstream.branch)],
+    )
+}
+
+/// `git branch -m old new`; fails if `new` already exists.
+pub fn rename_branch(repo: &Path, old: &str, new: &str) -> Result<(), String> {
+    git_checked(repo, &["branch", "-m", old, new])
+}
+
+/// Whether `rev` resolves to a commit without touching the network.
+pub fn ref_exists(repo: &Path, rev: &str) -> Result<bool, String> {
+    let output = git(repo, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)])?;
+    Ok(output.status.success())
+}
+
+pub fn local_branch_exists(repo: &Path, branch: &str) -> Result<bool, String> {
+    ref_exists(repo, &format!("refs/heads/{}", branch))
+}
+
+/// Remote that PR and base branches are fetched from.
+pub const REMOTE: &str = "origin";
+
+static VERSION_NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(\d+)\.(\d+)(?:\.(\d+))?").unwrap());
+
+/// A git release as `major.minor.patch`. Anything after the patch number
+/// (`.rc1`, `.windows.1`, a fourth component) is ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// The first `X.Y[.Z]` in `text`, e.g. `git version 2.39.3 (Apple Git-146)`
+    /// or a bare `2.31`.
+    pub fn parse(text: &str) -> Option<Version> {
+        let caps = VERSION_NUMBER.captures(text)?;
+        let number = |i: usize| caps.get(i).map_or(Some(0), |m| m.as_str().parse().ok());
+        Some(Version { major: number(1)?, minor: number(2)?, patch: number(3)? })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The installed git's version, from `git --version`.
+pub fn version() -> Result<Version, String> {
+    let output = Command::new("git")
+        .arg("--version")
+        .traced_output()
+        .map_err(|e| format!("Failed to run git --version: {}", e))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    Version::parse(&text).ok_or_else(|| format!("Couldn't read a version from `git --version`: {}", text.trim()))
+}
+
+fn is_full_sha(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Strip `refs/remotes/<remote>/`, `<remote>/` or `refs/heads/` so `origin/x`
+/// and `x` name the same base branch.
+pub fn strip_remote_prefix<'a>(rev: &'a str, remote: &str) -> &'a str {
+    let remote_prefix = format!("{}/", remote);
+    rev.strip_prefix("refs/remotes/")
+        .and_then(|rest| rest.strip_prefix(&remote_prefix))
+        .or_else(|| rev.strip_prefix(&remote_prefix))
+        .or_else(|| rev.strip_prefix("refs/heads/"))
+        .unwrap_or(rev)
+}
+
+/// Refspec that fetches `tag` from a remote into `refs/tags/`, like
+/// `git fetch <remote> tag <tag>`.
+pub fn tag_refspec(tag: &str) -> String {
+    format!("refs/tags/{0}:refs/tags/{0}", tag)
+}
+
+/// Resolve a user-supplied `--base` to a start point for `git worktree add`.
+/// Full SHAs and tags are used as-is when they already resolve locally;
+/// anything else is a branch on `remote`, fetched via `fetch` and used as
+/// `<remote>/<branch>`, or failing that a tag fetched via `tag_refspec`.
+/// Errors suggest similarly named remote branches/tags.
+pub fn resolve_base(
+    repo: &Path,
+    base: &str,
+    remote: &str,
+    mut fetch: impl FnMut(&str) -> Result<(), String>,
+) -> Result<String, String> {
+    let base = base.trim();
+    let tag = base.strip_prefix("refs/tags/").unwrap_or(base);
+    if ref_exists(repo, &format!("refs/tags/{}", tag))? {
+        return Ok(format!("refs/tags/{}", tag));
+    }
+    if is_full_sha(base) {
+        if !ref_exists(repo, base)? {
+            // GitHub serves reachable commits by SHA; not finding one is reported below
+            let _ = fetch(base);
+        }
+        if ref_exists(repo, base)? {
+            return Ok(base.to_string());
+        }
+        return Err(format!("base commit '{}' not found locally or on remote '{}'", base, remote));
+    }
+
+    let branch = strip_remote_prefix(base, remote);
+    let fetch_error = fetch(branch).err();
+    let start_point = format!("{}/{}", remote, branch);
+    if ref_exists(repo, &start_point)? {
+        return Ok(start_point);
+    }
+    if fetch(&tag_refspec(tag)).is_ok() && ref_exists(repo, &format!("refs/tags/{}", tag))? {
+        return Ok(format!("refs/tags/{}", tag));
+    }
+
+    let mut message = format!("base ref '{}' not found on remote '{}'", branch, remote);
+    let suggestions = similar_refs(repo, remote, branch)?;
+    if !suggestions.is_empty() {
+        message.push_str(&format!(". Did you mean: {}?", suggestions.join(", ")));
+    }
+    if let Some(e) = fetch_error {
+        message.push_str(&format!(" (fetch failed: {})", e));
+    }
+    Err(message)
+}
+
+/// The first tag (by name) pointing at each commit. Annotated tags are
+/// peeled, so both kinds map from the commit they mark.
+pub fn tags_by_commit(repo: &Path) -> Result<HashMap<String, String>, String> {
+    let output = git(
+        repo,
+        &["for-each-ref", "--format=%(objectname) %(*objectname) %(refname:short)", "refs/tags"],
+    )?;
+    if !output.status.success() {
+        return Err(format!(
+            "git for-each-ref refs/tags failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(parse_tag_refs(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_tag_refs(output: &str) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    for line in output.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some(object), Some(peeled), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let commit = if peeled.is_empty() { object } else { peeled };
+        tags.entry(commit.to_string()).or_insert_with(|| name.to_string());
+    }
+    tags
+}
+
+/// Remote branches and tags whose names are close to `name`.
+fn similar_refs(repo: &Path, remote: &str, name: &str) -> Result<Vec<String>, String> {
+    let output = git(
+        repo,
+        &[
+            "for-each-ref",
+            "--format=%(refname:short)",
+            &format!("refs/remotes/{}", remote),
+            "refs/tags",
+        ],
+    )?;
+    let refs = String::from_utf8_lossy(&output.stdout);
+    let candidates = refs
+        .lines()
+        .map(|line| strip_remote_prefix(line, remote))
+        .filter(|candidate| *candidate != "HEAD" && *candidate != remote);
+    Ok(closest_names(name, candidates, 5))
+}
+
+/// Up to `limit` candidates that contain `name` (or vice versa) or are within
+/// a couple of edits of it, closest first.
+pub fn closest_names<'a>(name: &str, candidates: impl Iterator<Item = &'a str>, limit: usize) -> Vec<String> {
+    let needle = name.to_lowercase();
+    let mut scored: Vec<(usize, &str)> = candidates
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let distance = edit_distance(&needle, &lower);
+            let related = lower.contains(&needle) || needle.contains(&lower);
+            (distance <= 2 || related).then_some((distance, candidate))
+        })
+        .collect();
+    scored.sort();
+    scored.dedup_by(|a, b| a.1 == b.1);
+    scored.into_iter().take(limit).map(|(_, name)| name.to_string()).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Resolve a full or abbreviated commit SHA to the full SHA of a commit in
+/// `repo`. `Ok(None)` if no such commit exists locally; an abbreviation that
+/// matches several commits is an error listing them.
+pub fn resolve_commit(repo: &Path, sha: &str) -> Result<Option<String>, String> {
+    if !(4..=40).contains(&sha.len()) || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{}' is not a commit SHA (expected 4-40 hex characters)", sha));
+    }
+    let sha = sha.to_ascii_lowercase();
+    if is_full_sha(&sha) {
+        return Ok(ref_exists(repo, &sha)?.then_some(sha));
+    }
+
+    let output = git(repo, &["rev-parse", &format!("--disambiguate={}", sha)])?;
+    let candidates: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|object| ref_exists(repo, object).unwrap_or(false))
+        .map(str::to_string)
+        .collect();
+    match candidates.as_slice() {
+        [] => Ok(None),
+        [commit] => Ok(Some(commit.clone())),
+        _ => {
+            let descriptions: Vec<String> = candidates
+                .iter()
+                .map(|commit| {
+                    git(repo, &["log", "-1", "--format=%h %s", commit])
+                        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                        .unwrap_or_else(|_| commit.clone())
+                })
+                .collect();
+            Err(format!(
+                "Commit '{}' is ambiguous; it matches:\n  {}",
+                sha,
+                descriptions.join("\n  ")
+            ))
+        }
+    }
+}
+
+/// Abbreviated SHA as git would print it for `rev`.
+pub fn short_sha(repo: &Path, rev: &str) -> Result<String, String> {
+    let output = git(repo, &["rev-parse", "--short", rev])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git rev-parse --short {} failed: {}",
+            rev,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Full SHA that `rev` resolves to.
+pub fn rev_parse(repo: &Path, rev: &str) -> Result<String, String> {
+    let output = git(repo, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)])?;
+    if !output.status.success() {
+        return Err(format!("{} does not name a commit in {}", rev, repo.display()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether `rev` resolves locally to exactly `oid`, e.g. a remote-tracking
+/// ref still at the tip GitHub reports. A missing ref is simply `false`.
+pub fn ref_points_at(repo: &Path, rev: &str, oid: &str) -> bool {
+    rev_parse(repo, rev).is_ok_and(|sha| sha.eq_ignore_ascii_case(oid))
+}
+
+/// Number of commits in `range`, e.g. `abc123..HEAD`.
+pub fn commit_count(repo: &Path, range: &str) -> Result<usize, String> {
+    let output = git(repo, &["rev-list", "--count", range])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git rev-list --count {} failed: {}",
+            range,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|e| format!("Unexpected git rev-list output: {}", e))
+}
+
+/// Commits on HEAD that aren't pushed: `@{u}..HEAD` when the branch has an
+/// upstream, otherwise commits not reachable from any remote-tracking ref.
+pub fn unpushed_commit_count(repo: &Path) -> Result<usize, String> {
+    let has_upstream = git(repo, &["rev-parse", "--verify", "--quiet", "@{u}"])?.status.success();
+    let args: &[&str] = if has_upstream {
+        &["rev-list", "--count", "@{u}..HEAD"]
+    } else {
+        &["rev-list", "--count", "HEAD", "--not", "--remotes"]
+    };
+    let output = git(repo, args)?;
+    if !output.status.success() {
+        return Err(format!(
+            "git rev-list failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|e| format!("Unexpected git rev-list output: {}", e))
+}
+
+/// `git log --oneline` for `range`, newest first.
+pub fn log_oneline(repo: &Path, range: &str) -> Result<Vec<String>, String> {
+    let output = git(repo, &["log", "--oneline", "--no-decorate", range])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git log {} failed: {}",
+            range,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// Commits HEAD has that no local branch or remote-tracking ref does (oneline,
+/// newest first): what moving a detached HEAD would leave unreachable.
+pub fn unreferenced_commits(repo: &Path) -> Result<Vec<String>, String> {
+    let output = git(repo, &["log", "--oneline", "--no-decorate", "HEAD", "--not", "--branches", "--remotes"])?;
+    if !output.status.success() {
+        return Err(format!("git log failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// Check out `branch` without resetting: the local branch if one exists,
+/// otherwise a detached HEAD at `origin/<branch>`. Uncommitted changes are
+/// carried over, and git refuses if they would be overwritten.
+pub fn switch_to_branch(repo: &Path, branch: &str) -> Result<(), String> {
+    let output = if local_branch_exists(repo, branch)? {
+        git(repo, &["checkout", branch])?
+    } else {
+        git(repo, &["checkout", "--detach", &format!("origin/{}", branch)])?
+    };
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "git checkout {} failed: {}",
+            branch,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Move a worktree's HEAD to `rev`, detached. Git refuses if uncommitted
+/// changes would be overwritten.
+pub fn detach_at(repo: &Path, rev: &str) -> Result<(), String> {
+    git_checked(repo, &["checkout", "-q", "--detach", rev])
+}
+
+/// Like `detach_at`, but discards uncommitted changes to tracked files.
+pub fn force_detach_at(repo: &Path, rev: &str) -> Result<(), String> {
+    git_checked(repo, &["checkout", "-q", "-f", "--detach", rev])
+}
+
+/// The root of the working tree that contains `dir`.
+pub fn toplevel(dir: &Path) -> Result<PathBuf, String> {
+    let output = git(dir, &["rev-parse", "--show-toplevel"])?;
+    if !output.status.success() {
+        return Err(format!("{} is not inside a git worktree", dir.display()));
+    }
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Move a linked worktree's directory to `to` and update git's records.
+pub fn move_worktree(repo: &Path, from: &Path, to: &Path) -> Result<(), String> {
+    git_checked(repo, &["worktree", "move", &from.to_string_lossy(), &to.to_string_lossy()])
+}
+
+/// Point git's records at a worktree whose directory was moved by hand.
+pub fn repair_worktree(repo: &Path, path: &Path) -> Result<(), String> {
+    git_checked(repo, &["worktree", "repair", &path.to_string_lossy()])
+}
+
+/// Create `branch` at HEAD and check it out, keeping the worktree as is.
+pub fn create_branch_at_head(repo: &Path, branch: &str) -> Result<(), String> {
+    git_checked(repo, &["checkout", "-q", "-b", branch])
+}
+
+/// Delete `git_ref` (a full `refs/...` name) if it exists.
+pub fn delete_ref(repo: &Path, git_ref: &str) -> Result<(), String> {
+    git_checked(repo, &["update-ref", "-d", git_ref])
+}
+
+/// Run `git <args>` in `repo`, turning a non-zero exit into an error that
+/// carries git's stderr.
+fn git_checked(repo: &Path, args: &[&str]) -> Result<(), String> {
+    let output = git(repo, args)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Push `branch` to `remote` and make it the upstream, so a plain `git push`
+/// or `git pull` works afterwards.
+pub fn push_set_upstream(repo: &Path, remote: &str, branch: &str) -> Result<(), String> {
+    git_checked(repo, &["push", "--quiet", "--set-upstream", remote, branch])
+}
+
+/// Stash uncommitted changes to tracked files under `message`.
+pub fn stash_push(repo: &Path, message: &str) -> Result<(), String> {
+    git_checked(repo, &["stash", "push", "--quiet", "--message", message])
+}
+
+/// Delete the local `branch`. Without `force` git refuses if it has commits
+/// that aren't merged into its upstream or HEAD.
+pub fn delete_branch(repo: &Path, branch: &str, force: bool) -> Result<(), String> {
+    git_checked(repo, &["branch", if force { "-D" } else { "-d" }, branch])
+}
+
+/// The remote's default branch from `refs/remotes/<remote>/HEAD` (set by
+/// clone or `git remote set-head`), e.g. `master`. `None` if it isn't set.
+pub fn default_branch(repo: &Path, remote: &str) -> Result<Option<String>, String> {
+    let output = git(repo, &["symbolic-ref", "--quiet", "--short", &format!("refs/remotes/{}/HEAD", remote)])?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let head = String::from_utf8_lossy(&output.stdout);
+    Ok(Some(strip_remote_prefix(head.trim(), remote).to_string()))
+}
+
+/// `git diff --stat <base>...HEAD`: what HEAD changed since it forked from `base`.
+pub fn diff_stat_since(repo: &Path, base: &str, color: bool) -> Result<String, String> {
+    let color = if color { "--color=always" } else { "--color=never" };
+    let output = git(repo, &["diff", "--stat", color, &format!("{}...HEAD", base)])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git diff {}...HEAD failed: {}",
+            base,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Drop administrative files for worktrees whose directories are gone.
+/// Returns how many registrations were pruned.
+pub fn prune_worktrees(repo: &Path) -> Result<usize, String> {
+    let output = git(repo, &["worktree", "prune", "--verbose"])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git worktree prune failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // --verbose reports on stderr, one "Removing worktrees/<id>: ..." per entry
+    Ok(String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.starts_with("Removing "))
+        .count())
+}
+
+/// When `repo` last fetched, from the mtime of `FETCH_HEAD` in the git dir
+/// its worktrees share. `None` if it has never fetched.
+pub fn last_fetch_time(repo: &Path) -> Option<SystemTime> {
+    fs::metadata(fetch_head(repo)?).and_then(|m| m.modified()).ok()
+}
+
+/// Put `FETCH_HEAD`'s mtime back after a failed fetch, which still rewrites it
+pub fn restore_last_fetch_time(repo: &Path, time: SystemTime) {
+    if let Some(file) = fetch_head(repo).and_then(|path| fs::File::options().append(true).open(path).ok()) {
+        let _ = file.set_modified(time);
+    }
+}
+
+fn fetch_head(repo: &Path) -> Option<PathBuf> {
+    let output = git(repo, &["rev-parse", "--git-common-dir"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let common_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Some(repo.join(common_dir).join("FETCH_HEAD"))
+}
+
+/// Delete remote-tracking refs whose branches no longer exist on `remote`.
+/// Returns the pruned ref names.
+pub fn prune_remote(repo: &Path, remote: &str) -> Result<Vec<String>, String> {
+    let output = git(repo, &["remote", "prune", remote])?;
+    if !output.status.success() {
+        return Err(format!(
+            "git remote prune {} failed: {}",
+            remote,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("* [pruned] "))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Record an empty commit, e.g. so a new branch has something to open a PR from.
+pub fn commit_empty(repo: &Path, message: &str) -> Result<(), String> {
+    git_checked(repo, &["commit", "--quiet", "--allow-empty", "--no-verify", "-m", message])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_repo::{run_git, scratch_repo};
+
+    #[test]
+    fn parses_git_versions() {
+        let v = |major, minor, patch| Some(Version { major, minor, patch });
+        assert_eq!(Version::parse("git version 2.39.5\n"), v(2, 39, 5));
+        assert_eq!(Version::parse("git version 2.39.3 (Apple Git-146)"), v(2, 39, 3));
+        assert_eq!(Version::parse("git version 2.45.0.rc1"), v(2, 45, 0));
+        assert_eq!(Version::parse("git version 2.46.0-rc0"), v(2, 46, 0));
+        assert_eq!(Version::parse("git version 2.47.1.windows.1"), v(2, 47, 1));
+        assert_eq!(Version::parse("git version 3.1.4.2"), v(3, 1, 4));
+        assert_eq!(Version::parse("2.31"), v(2, 31, 0));
+        assert_eq!(Version::parse("git version unknown"), None);
+
+        assert!(Version::parse("2.17.1") < Version::parse("2.31"));
+        assert!(Version::parse("2.100.0") > Version::parse("2.31.9"));
+        assert_eq!(Version::parse("2.31").unwrap().to_string(), "2.31.0");
+    }
+
+    #[test]
+    fn parses_porcelain_worktree_list() {
+        let porcelain = "worktree /repo\nHEAD abc\nbranch refs/heads/master\n\n\
+                         worktree /wt/pr-5-fix\nHEAD def\ndetached\n\n\
+                         worktree /wt/branch-foo\nHEAD 123\nbranch refs/heads/darren/foo\nlocked\n";
+        assert_eq!(
+            parse_worktree_list(porcelain),
+            vec![
+                WorktreeEntry {
+                    path: PathBuf::from("/repo"),
+                    head: "abc".to_string(),
+                    branch: Some("master".to_string()),
+                },
+                WorktreeEntry { path: PathBuf::from("/wt/pr-5-fix"), head: "def".to_string(), branch: None },
+                WorktreeEntry {
+                    path: PathBuf::from("/wt/branch-foo"),
+                    head: "123".to_string(),
+                    branch: Some("darren/foo".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn base_refs_normalize_remote_prefixes() {
+        assert_eq!(strip_remote_prefix("origin/release-24", "origin"), "release-24");
+        assert_eq!(strip_remote_prefix("release-24", "origin"), "release-24");
+        assert_eq!(strip_remote_prefix("refs/remotes/origin/release-24", "origin"), "release-24");
+        assert_eq!(strip_remote_prefix("upstream/release-24", "upstream"), "release-24");
+        assert_eq!(strip_remote_prefix("upstream/release-24", "origin"), "upstream/release-24");
+
+        assert_eq!(
+            closest_names("relase-24", ["release-24", "release-23", "master"].into_iter(), 5),
+            vec!["release-24", "release-23"]
+        );
+        assert_eq!(closest_names("zzz", ["master"].into_iter(), 5), Vec::<String>::new());
+    }
+
+    #[test]
+    fn resolve_base_accepts_branches_tags_and_shas() {
+        let (scratch, dir) = scratch_repo("git-base");
+        run_git(&dir, &["update-ref", "refs/remotes/origin/release-24", "HEAD"]);
+        run_git(&dir, &["tag", "v1.2.0"]);
+        let sha = run_git(&dir, &["rev-parse", "HEAD"]);
+        let sha = sha.as_str();
+
+        let mut fetched = Vec::new();
+        let mut fetch = |rev: &str| {
+            fetched.push(rev.to_string());
+            Ok(())
+        };
+        assert_eq!(resolve_base(&dir, "origin/release-24", "origin", &mut fetch).unwrap(), "origin/release-24");
+        assert_eq!(resolve_base(&dir, "release-24", "origin", &mut fetch).unwrap(), "origin/release-24");
+        assert_eq!(resolve_base(&dir, "v1.2.0", "origin", &mut fetch).unwrap(), "refs/tags/v1.2.0");
+        assert_eq!(resolve_base(&dir, sha, "origin", &mut fetch).unwrap(), sha);
+        // Tags and local SHAs never hit the network
+        assert_eq!(fetched, vec!["release-24", "release-24"]);
+
+        // A tag missing locally is fetched once the branch lookup fails
+        let mut fetch_tag = |rev: &str| {
+            if rev == tag_refspec("v2.0.0") {
+                assert!(git(&dir, &["-c", "user.name=t", "-c", "user.email=t@t", "tag", "-a", "-m", "v2", "v2.0.0"]).unwrap().status.success());
+                Ok(())
+            } else {
+                Err("no such branch".to_string())
+            }
+        };
+        assert_eq!(resolve_base(&dir, "v2.0.0", "origin", &mut fetch_tag).unwrap(), "refs/tags/v2.0.0");
+        assert_eq!(rev_parse(&dir, "refs/tags/v2.0.0").unwrap(), sha);
+        let tags = tags_by_commit(&dir).unwrap();
+        assert_eq!(tags.get(sha).map(String::as_str), Some("v1.2.0"));
+        assert_eq!(tags.len(), 1);
+
+        let err = resolve_base(&dir, "relase-24", "origin", |_| Ok(())).unwrap_err();
+        assert!(err.contains("base ref 'relase-24' not found on remote 'origin'"), "{}", err);
+        assert!(err.contains("release-24"), "{}", err);
+
+        let _ = fs::remove_dir_all(&scratch);
+    }
+
+    #[test]
+    fn ref_points_at_compares_local_refs_with_a_reported_tip() {
+        let (scratch, dir) = scratch_repo("git-oid");
+        run_git(&dir, &["update-ref", "refs/remotes/origin/darren/fix", "HEAD"]);
+        let fetched = rev_parse(&dir, "HEAD").unwrap();
+        run_git(&dir, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "two"]);
+        let pushed_since = rev_parse(&dir, "HEAD").unwrap();
+
+        // Matched, stale, and missing locally
+        assert!(ref_points_at(&dir, "origin/darren/fix", &fetched));
+        assert!(ref_points_at(&dir, "origin/darren/fix", &fetched.to_uppercase()));
+        assert!(!ref_points_at(&dir, "origin/darren/fix", &pushed_since));
+        assert!(!ref_points_at(&dir, "origin/darren/gone", &fetched));
+
+        let _ = fs::remove_dir_all(&scratch);
+    }
+
+    #[test]
+    fn current_branch_reports_named_and_detached_heads() {
+        let (scratch, dir) = scratch_repo("git-branch");
+
+        // An unborn branch has a name but no commit yet
+        run_git(&dir, &["checkout", "-q", "--orphan", "unborn"]);
+        assert_eq!(current_branch(&dir).unwrap().as_deref(), Some("unborn"));
+        run_git(&dir, &["checkout", "-q", "main"]);
+        assert_eq!(current_branch(&dir).unwrap().as_deref(), Some("main"));
+
+        run_git(&dir, &["checkout", "-q", "-b", "darren/feature"]);
+        assert_eq!(current_branch(&dir).unwrap().as_deref(), Some("darren/feature"));
+        assert!(local_branch_exists(&dir, "main").unwrap());
+        assert!(!local_branch_exists(&dir, "missing").unwrap());
+        assert!(ref_exists(&dir, "HEAD").unwrap());
+        assert!(!ref_exists(&dir, "origin/main").unwrap());
+
+        run_git(&dir, &["checkout", "-q", "--detach"]);
+        assert_eq!(current_branch(&dir).unwrap(), None);
+
+        switch_to_branch(&dir, "main").unwrap();
+        assert_eq!(current_branch(&dir).unwrap().as_deref(), Some("main"));
+
+        assert_eq!(upstream(&dir, "darren/feature").unwrap(), None);
+        let old = Upstream { remote: "origin".to_string(), branch: "old-name".to_string() };
+        set_upstream(&dir, "darren/feature", &old).unwrap();
+        assert_eq!(upstream(&dir, "darren/feature").unwrap(), Some(old));
+        rename_branch(&dir, "darren/feature", "new-name").unwrap();
+        assert!(!local_branch_exists(&dir, "darren/feature").unwrap());
+        // The upstream config moves with the branch
+        assert_eq!(upstream(&dir, "new-name").unwrap().unwrap().branch, "old-name");
+        assert!(rename_branch(&dir, "new-name", "main").unwrap_err().contains("git branch -m"));
+
+        let head = run_git(&dir, &["rev-parse", "HEAD"]);
+        assert_eq!(resolve_commit(&dir, &head).unwrap(), Some(head.clone()));
+        assert_eq!(resolve_commit(&dir, &head[..8].to_uppercase()).unwrap(), Some(head.clone()));
+        assert_eq!(resolve_commit(&dir, &"0".repeat(40)).unwrap(), None);
+        assert!(resolve_commit(&dir, "main").is_err());
+        assert!(resolve_commit(&dir, "abc").is_err());
+        assert!(head.starts_with(&short_sha(&dir, &head).unwrap()));
+        assert_eq!(rev_parse(&dir, "main").unwrap(), head);
+        assert!(rev_parse(&dir, "missing").is_err());
+        run_git(&dir, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "second"]);
+        assert_eq!(commit_count(&dir, &format!("{}..HEAD", head)).unwrap(), 1);
+        assert_eq!(commit_count(&dir, "HEAD..HEAD").unwrap(), 0);
+        let log = log_oneline(&dir, &format!("{}..HEAD", head)).unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].ends_with(" second"), "{:?}", log);
+        assert!(log_oneline(&dir, "HEAD..HEAD").unwrap().is_empty());
+
+        // Forced detaching leaves the branch and discards tracked edits
+        fs::write(dir.join("a.txt"), "1\n").unwrap();
+        run_git(&dir, &["add", "a.txt"]);
+        run_git(&dir, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "-m", "third"]);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        assert_eq!(toplevel(&dir.join("sub")).unwrap().canonicalize().unwrap(), dir.canonicalize().unwrap());
+        fs::write(dir.join("a.txt"), "edited\n").unwrap();
+        assert!(detach_at(&dir, &head).is_err());
+        force_detach_at(&dir, &head).unwrap();
+        assert_eq!(current_branch(&dir).unwrap(), None);
+        assert!(!dir.join("a.txt").exists());
+        assert!(rev_parse(&dir, "main").unwrap() != head);
+
+        let _ = fs::remove_dir_all(&scratch);
+        assert!(current_branch(&dir).is_err());
+    }
+
+    #[test]
+    fn push_prune_and_default_branch_against_local_remote() {
+        let (dir, work) = scratch_repo("git-push");
+        let remote = dir.join("remote.git");
+        run_git(&work, &["checkout", "-q", "-b", "darren/fix"]);
+        run_git(&work, &["config", "user.name", "t"]);
+        run_git(&work, &["config", "user.email", "t@t"]);
+        assert_eq!(last_fetch_time(&work), None);
+
+        commit_empty(&work, "darren/fix").unwrap();
+        push_set_upstream(&work, REMOTE, "darren/fix").unwrap();
+        let upstream = git(&work, &["rev-parse", "--abbrev-ref", "@{upstream}"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&upstream.stdout).trim(), "origin/darren/fix");
+        assert!(push_set_upstream(&work, "missing", "darren/fix").unwrap_err().contains("git push"));
+        assert_eq!(unpushed_commit_count(&work).unwrap(), 0);
+        run_git(&work, &["fetch", "-q", "origin"]);
+        assert!(last_fetch_time(&work).unwrap().elapsed().unwrap().as_secs() < 60);
+        restore_last_fetch_time(&work, SystemTime::UNIX_EPOCH);
+        assert_eq!(last_fetch_time(&work), Some(SystemTime::UNIX_EPOCH));
+        commit_empty(&work, "local").unwrap();
+        assert_eq!(unpushed_commit_count(&work).unwrap(), 1);
+        run_git(&work, &["reset", "-q", "--hard", "@{u}"]);
+
+        run_git(&work, &["branch", "darren/merged"]);
+        run_git(&work, &["checkout", "-q", "-b", "darren/ahead"]);
+        commit_empty(&work, "ahead").unwrap();
+        assert_eq!(unpushed_commit_count(&work).unwrap(), 1, "no upstream: compared against all remotes");
+        run_git(&work, &["checkout", "-q", "darren/fix"]);
+        delete_branch(&work, "darren/merged", false).unwrap();
+        assert!(delete_branch(&work, "darren/ahead", false).unwrap_err().contains("not fully merged"));
+        delete_branch(&work, "darren/ahead", true).unwrap();
+        assert!(!local_branch_exists(&work, "darren/ahead").unwrap());
+
+        assert!(prune_remote(&work, REMOTE).unwrap().is_empty());
+        run_git(&work, &["update-ref", "refs/remotes/origin/gone", "HEAD"]);
+        assert_eq!(prune_remote(&work, REMOTE).unwrap(), vec!["origin/gone"]);
+
+        run_git(&work, &["worktree", "add", "-q", "--detach", "../extra"]);
+        let work = work.canonicalize().unwrap();
+        assert_eq!(main_worktree(&dir.join("extra")).unwrap(), Some(work.clone()));
+        assert_eq!(main_worktree(&work).unwrap(), Some(work.clone()));
+        assert_eq!(main_worktree(&remote).unwrap(), None);
+        assert_eq!(prune_worktrees(&work).unwrap(), 0);
+        fs::remove_dir_all(dir.join("extra")).unwrap();
+        assert_eq!(prune_worktrees(&work).unwrap(), 1);
+
+        assert_eq!(default_branch(&work, REMOTE).unwrap(), None);
+        run_git(&work, &["remote", "set-head", REMOTE, "darren/fix"]);
+        assert_eq!(default_branch(&work, REMOTE).unwrap().as_deref(), Some("darren/fix"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
+
+/// Scratch repos for tests that need a real git.
+#[cfg(test)]
+pub mod test_repo {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    /// Run git in `cwd`, asserting it succeeds, and return its trimmed stdout.
+    pub fn run_git(cwd: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").arg("-C").arg(cwd).args(args).output().unwrap();
+        assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// A fresh scratch dir for the test `name` holding `work`, a repo on
+    /// `main` with one empty commit, and `remote.git`, the bare repo that is
+    /// its `origin`. Returns the dir and `work`.
+    pub fn scratch_repo(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("checkout-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let work = dir.join("work");
+        fs::create_dir_all(&work).unwrap();
+        run_git(&dir, &["init", "-q", "--bare", "remote.git"]);
+        run_git(&work, &["init", "-q", "-b", "main"]);
+        run_git(&work, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "init"]);
+        run_git(&work, &["remote", "add", "origin", &dir.join("remote.git").to_string_lossy()]);
+        (dir, work)
+    }
+}
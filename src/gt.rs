@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use crate::trace::Traced;
+
+/// Graphite parent of each worktree's branch asked about this run. gt starts
+/// slowly, so every worktree is asked at most once until `forget_parents`.
+static PARENTS: LazyLock<Mutex<HashMap<PathBuf, Option<String>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Set once gt turns out not to be installed, so it isn't tried again
+static MISSING: AtomicBool = AtomicBool::new(false);
+
+/// The branch the worktree's branch is stacked on, per `gt parent`. `None`
+/// when gt isn't installed, the branch isn't tracked, or gt fails in any
+/// other way: callers fall back to the plain upstream comparison.
+pub fn parent(worktree: &Path) -> Option<String> {
+    if MISSING.load(Ordering::Relaxed) {
+        return None;
+    }
+    if let Some(parent) = PARENTS.lock().unwrap_or_else(|e| e.into_inner()).get(worktree) {
+        return parent.clone();
+    }
+    let output = Command::new("gt")
+        .args(["parent", "--no-interactive"])
+        .current_dir(worktree)
+        .stdin(Stdio::null())
+        .traced_output();
+    let parent = match output {
+        Ok(output) if output.status.success() => parse_parent(&String::from_utf8_lossy(&output.stdout)),
+        Ok(_) => None,
+        Err(_) => {
+            MISSING.store(true, Ordering::Relaxed);
+            None
+        }
+    };
+    PARENTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(worktree.to_path_buf(), parent.clone());
+    parent
+}
+
+/// Drop the remembered parents, so the next `parent` asks gt again: a
+/// long-running caller like `status --watch` would otherwise keep showing a
+/// branch's old parent after it's restacked.
+pub fn forget_parents() {
+    PARENTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Pull the branch name out of `gt parent` output: its last non-empty line,
+/// without color codes. Anything that doesn't look like a single branch name
+/// (a message rather than an answer) yields `None`.
+pub fn parse_parent(output: &str) -> Option<String> {
+    let line = strip_ansi(output).lines().map(str::trim).rfind(|line| !line.is_empty())?.to_string();
+    (!line.contains(char::is_whitespace)).then_some(line)
+}
+
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end at the first letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hand-written from gt's documented output rather than captured from a
+    // real run, so recheck them against gt when it changes
+    #[test]
+    fn parses_gt_parent_output() {
+        assert_eq!(parse_parent("darren/auth-base\n").as_deref(), Some("darren/auth-base"));
+        assert_eq!(parse_parent("master\n").as_deref(), Some("master"));
+        // Colored output, as gt prints when it thinks it's on a terminal
+        assert_eq!(parse_parent("\x1b[36mdarren/auth-base\x1b[39m\n").as_deref(), Some("darren/auth-base"));
+        // An upgrade notice ahead of the answer
+        assert_eq!(
+            parse_parent("A new version of gt is available: 1.4.2\n\ndarren/auth-base\n").as_deref(),
+            Some("darren/auth-base")
+        );
+        assert_eq!(parse_parent(""), None);
+        assert_eq!(parse_parent("ERROR: Cannot perform this operation on untracked branch darren/x.\n"), None);
+    }
+
+    #[test]
+    fn forgotten_parents_are_asked_for_again() {
+        let worktree = PathBuf::from("/nonexistent/checkout-gt-test");
+        PARENTS.lock().unwrap().insert(worktree.clone(), Some("darren/old-base".to_string()));
+        forget_parents();
+        assert!(!PARENTS.lock().unwrap().contains_key(&worktree));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::write_atomic;
+
+/// Summary of the most recent successful `pr`/`branch` checkout, written to
+/// `<data_dir>/last-checkout.json` for editor and terminal automation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LastCheckout {
+    pub path: PathBuf,
+    pub branch: String,
+    pub pr_number: Option<u64>,
+    pub title: Option<String>,
+    /// Background color as a hex string without `#`
+    pub color: Option<String>,
+    /// Arguments of the `checkout` invocation, replayed by `checkout last`
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl LastCheckout {
+    /// The same values as environment variables for the spawned agent.
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("CHECKOUT_WORKTREE_PATH", self.path.display().to_string()),
+            ("CHECKOUT_BRANCH", self.branch.clone()),
+        ];
+        if let Some(pr_number) = self.pr_number {
+            vars.push(("CHECKOUT_PR_NUMBER", pr_number.to_string()));
+        }
+        if let Some(color) = &self.color {
+            vars.push(("CHECKOUT_COLOR", color.clone()));
+        }
+        vars
+    }
+}
+
+pub fn last_checkout_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("last-checkout.json")
+}
+
+pub fn save(data_dir: &Path, checkout: &LastCheckout) -> Result<(), String> {
+    fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Failed to create data dir {}: {}", data_dir.display(), e))?;
+    let content = serde_json::to_string_pretty(checkout)
+        .map_err(|e| format!("Failed to serialize last checkout: {}", e))?;
+    write_atomic(&last_checkout_file(data_dir), &content)
+}
+
+/// Read the last checkout, or `None` if nothing has been recorded yet.
+pub fn load(data_dir: &Path) -> Result<Option<LastCheckout>, String> {
+    let path = last_checkout_file(data_dir);
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Invalid {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_exports_env_vars() {
+        let dir = std::env::temp_dir().join(format!("checkout-last-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(load(&dir).unwrap(), None);
+
+        let checkout = LastCheckout {
+            path: PathBuf::from("/wt/pr-42-fix-login"),
+            branch: "darren/fix-login".to_string(),
+            pr_number: Some(42),
+            title: Some("Fix login".to_string()),
+            color: Some("1e2233".to_string()),
+            args: vec!["pr".to_string(), "42".to_string()],
+            timestamp: 1_760_611_200,
+        };
+        save(&dir, &checkout).unwrap();
+        assert_eq!(load(&dir).unwrap(), Some(checkout.clone()));
+        assert_eq!(
+            checkout.env_vars(),
+            vec![
+                ("CHECKOUT_WORKTREE_PATH", "/wt/pr-42-fix-login".to_string()),
+                ("CHECKOUT_BRANCH", "darren/fix-login".to_string()),
+                ("CHECKOUT_PR_NUMBER", "42".to_string()),
+                ("CHECKOUT_COLOR", "1e2233".to_string()),
+            ]
+        );
+
+        let branch_only = LastCheckout { pr_number: None, color: None, ..checkout };
+        assert_eq!(branch_only.env_vars().len(), 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
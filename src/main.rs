@@ -1,7 +1,7 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size as terminal_size, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::ExecutableCommand;
 use ratatui::backend::CrosstermBackend;
 use ratatui::layout::{Constraint, Layout};
@@ -12,19 +12,32 @@ use ratatui::Terminal;
 use regex::Regex;
 use serde::Deserialize;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
+use std::fmt::Write as _;
 use std::fs;
-use std::io::{self, BufRead, Read, Write};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
-use std::sync::Mutex;
+use std::sync::{Condvar, LazyLock, Mutex};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime};
 
+mod backup;
+mod config;
+mod git;
+mod gt;
+mod last_checkout;
+mod metadata;
+mod terminal;
+mod trace;
+mod ui;
+
+use trace::Traced;
+
 /// Color palette - subtle dark backgrounds with pastel hues
 const COLOR_PALETTE: &[&str] = &[
     "1e2233", // soft navy
@@ -42,12 +55,54 @@ const COLOR_PALETTE: &[&str] = &[
 ];
 
 static TIMINGS_ENABLED: AtomicBool = AtomicBool::new(false);
+/// `--quiet`: hide checkout's progress lines during a checkout
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// `pr --dir-only`: stdout carries only the worktree path, so checkout's own
+/// output (`say!` and `progress!`) goes to stderr
+static OUTPUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// `--git-output shown`: let fetches and `worktree add` write to the terminal
+static GIT_OUTPUT_SHOWN: AtomicBool = AtomicBool::new(false);
+/// `--parallel`; 0 until set, meaning `default_parallelism()`
+static PARALLEL: AtomicUsize = AtomicUsize::new(0);
+/// Batch operations currently running, and the wakeup for those waiting on a slot
+static BATCH_RUNNING: Mutex<usize> = Mutex::new(0);
+static BATCH_SLOT_FREED: Condvar = Condvar::new();
 static ACTIVE_WORKTREE: Mutex<Option<PathBuf>> = Mutex::new(None);
 static ACTIVE_CHILD_PID: Mutex<Option<u32>> = Mutex::new(None);
 static ACTIVE_AGENT: Mutex<Option<Agent>> = Mutex::new(None);
+/// Set while `read_input` waits, so Ctrl+C cancels the prompt instead of exiting
+static PROMPTING: AtomicBool = AtomicBool::new(false);
+static PROMPT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// A prompt read that was cancelled before its line arrived; the next prompt
+/// takes it over rather than racing it for stdin
+static PENDING_INPUT: Mutex<Option<mpsc::Receiver<io::Result<Option<String>>>>> = Mutex::new(None);
+/// `CHECKOUT_*` variables describing the current checkout, passed to the agent
+static CHECKOUT_ENV: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+/// Arguments of the running pr/branch command, recorded for `checkout last`
+static INVOCATION: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// GitHub Enterprise host from a PR URL argument, passed to `gh` as `GH_HOST`
+static GH_HOST: Mutex<Option<String>> = Mutex::new(None);
+
+// Regexes are compiled on first use rather than on every call
+static DASH_RUNS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-+").unwrap());
+/// `123`, `#123`, `pull/123` or anything ending in `pull/123`
+static PR_NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:#?(\d+)|(?:.*/)?pull/(\d+)(?:[/?#].*)?)$").unwrap());
+/// A leading conventional-commit type such as `fix: ` or `feat(ui)!: `
+static COMMIT_TYPE_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*[A-Za-z][\w-]*(?:\([^()]*\))?!?:\s+").unwrap());
+/// A PR page URL, with its optional scheme and its host captured
+static PR_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(https?://)?([^/\s]+\.[^/\s]+)/[^/]+/[^/]+/pull/\d+").unwrap());
 
 thread_local! {
     static TIMING_DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    /// Best-effort setup steps that failed, for the ready banner. A checkout
+    /// runs its steps on one thread, so tests running side by side don't see
+    /// each other's.
+    static SETUP_WARNINGS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+    /// Set by `Interrupt::raise` on the thread whose prompt was interrupted
+    static INTERRUPT: std::cell::Cell<Option<Interrupt>> = const { std::cell::Cell::new(None) };
 }
 
 struct TimingSpan {
@@ -88,6 +143,57 @@ impl Drop for TimingSpan {
     }
 }
 
+/// `print!` for checkout's own output during a checkout: prompts, notes and
+/// the ready line. It moves to stderr under `pr --dir-only`.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+            eprint!($($arg)*);
+        } else {
+            print!($($arg)*);
+        }
+    };
+}
+
+/// `println!` counterpart of `say!`
+macro_rules! sayln {
+    ($($arg:tt)*) => {
+        if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `say!` for checkout's own progress lines, which `--quiet` hides.
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            say!($($arg)*);
+        }
+    };
+}
+
+/// `println!` counterpart of `progress!`
+macro_rules! progressln {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            sayln!($($arg)*);
+        }
+    };
+}
+
+/// Where a command checkout runs during a checkout sends its stdout: the
+/// terminal, or stderr under `pr --dir-only`.
+fn say_stdio() -> Stdio {
+    if OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+        io::stderr().into()
+    } else {
+        Stdio::inherit()
+    }
+}
+
 /// Convenience macro for creating a timing span in the current scope.
 macro_rules! timing {
     ($label:expr) => {
@@ -103,13 +209,105 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    /// Print timing information for each operation
+    /// Print timing information for each operation, and the git version found
     #[arg(long, global = true)]
     timings: bool,
 
     /// Coding agent for new sessions and resume-last
     #[arg(long, global = true, value_enum, default_value_t = Agent::Codex)]
     agent: Agent,
+
+    /// Use the `[profiles.<name>]` config table over the top-level keys
+    /// (default: $CHECKOUT_PROFILE)
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Hide checkout's progress lines while checking out; prompts, warnings,
+    /// errors and the ready line still show
+    #[arg(long, short = 'q', global = true)]
+    quiet: bool,
+
+    /// Whether git's own output from fetches and `worktree add` (e.g. fetch
+    /// progress) is shown, independently of --quiet
+    #[arg(long, global = true, value_enum, default_value_t = GitOutput::Hidden)]
+    git_output: GitOutput,
+
+    /// Run at most N batch git operations (update-all, worktree status) at once
+    /// (default: the CPU count, capped at 8)
+    #[arg(long, global = true, value_name = "N", value_parser = parse_parallel)]
+    parallel: Option<usize>,
+
+    /// Record every git/gh/setup command this run executes, with its exit code,
+    /// duration and stderr, to FILE as JSON lines (failed runs are always
+    /// recorded under the data dir)
+    #[arg(long, global = true, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Write the worktree's path to FILE once it's ready, also when an agent
+    /// runs (pr, branch, new, open, resume, resume-last and which); the
+    /// function `checkout shell-init` prints uses it to cd there afterwards.
+    /// Global because that function passes it to every command it wraps.
+    #[arg(long, global = true, value_name = "FILE")]
+    write_path: Option<PathBuf>,
+}
+
+/// Flags for the commands that create or reuse a worktree and start an agent
+/// in it (pr, walkthrough, review, statsig, branch, commit, tag, new, begin).
+#[derive(Args, Clone, Debug, Default)]
+struct CheckoutArgs {
+    /// Use local origin/* refs instead of fetching (fetches anyway if the ref is missing)
+    #[arg(long)]
+    no_fetch: bool,
+
+    /// Register new worktrees without checking out files (for sparse checkouts); skips setup and the agent
+    #[arg(long)]
+    no_checkout: bool,
+
+    /// Leave the terminal title alone; the background color is still set
+    #[arg(long)]
+    no_spinner_title: bool,
+
+    /// Keep the worktree's background color after the agent exits, for staying
+    /// in the worktree shell. It isn't reset on Ctrl+C either.
+    #[arg(long)]
+    keep_color_on_exit: bool,
+
+    /// Set an environment variable for the spawned agent (repeatable)
+    #[arg(long = "env", value_name = "KEY=VAL", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
+    /// Fail the checkout when a best-effort setup step (mise, Graphite, Claude settings/trust) fails
+    #[arg(long)]
+    strict: bool,
+
+    /// Clone the repo from URL when its path is missing, e.g. on a new machine
+    /// (also `clone_url` in its `[repos.<name>]` table)
+    #[arg(long, value_name = "URL")]
+    clone_if_missing: Option<String>,
+}
+
+impl Default for &CheckoutArgs {
+    /// No checkout flags, so option structs holding them can derive `Default`
+    fn default() -> Self {
+        static NONE: CheckoutArgs = CheckoutArgs {
+            no_fetch: false,
+            no_checkout: false,
+            no_spinner_title: false,
+            keep_color_on_exit: false,
+            env: Vec::new(),
+            strict: false,
+            clone_if_missing: None,
+        };
+        &NONE
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum GitOutput {
+    /// Behind a spinner, as usual
+    Hidden,
+    /// Straight to the terminal, to watch a slow fetch
+    Shown,
 }
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, ValueEnum)]
@@ -155,23 +353,96 @@ impl Agent {
 enum Commands {
     /// Check out a GitHub PR into a worktree
     Pr {
-        /// PR number or GitHub PR URL (e.g., 123 or https://github.com/org/repo/pull/123)
-        pr: String,
-
-        /// Optional skill to run after checkout (e.g., /walkthrough)
-        skill: Option<String>,
+        /// PR number, #number, pull/number or GitHub PR URL (e.g., 123, #123 or https://github.com/org/repo/pull/123),
+        /// optionally followed by a skill to run after checkout, starting with / or $ (e.g., /walkthrough).
+        /// Several PRs are checked out one after another without an agent.
+        /// Omit to pick from open PRs.
+        #[arg(value_name = "PR")]
+        pr: Vec<String>,
 
         /// Skip launching the coding agent after creating the worktree
         #[arg(long = "no-agent", alias = "no-claude")]
         no_agent: bool,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
         /// Resume the existing worktree session without prompting
         #[arg(long)]
         resume_existing: bool,
+
+        /// Use (and update) the existing worktree without prompting
+        #[arg(long, conflicts_with = "resume_existing")]
+        use_existing: bool,
+
+        /// Reopen the existing worktree as it is, keeping uncommitted changes
+        /// and skipping the update, without prompting
+        #[arg(long, conflicts_with_all = ["resume_existing", "use_existing"])]
+        allow_dirty: bool,
+
+        /// Point the checkout worktree you're in at the PR instead of creating
+        /// another one; uncommitted changes are confirmed before being discarded
+        #[arg(
+            long,
+            conflicts_with_all = ["resume_existing", "use_existing", "allow_dirty", "reuse_branch_worktree", "worktree_in_repo"]
+        )]
+        into_current: bool,
+
+        /// Print only the worktree's path to stdout, with everything else on
+        /// stderr, for `dir=$(checkout pr 123 --dir-only)`; implies --no-agent
+        #[arg(long)]
+        dir_only: bool,
+
+        /// With --use-existing, update without any prompts, handling uncommitted
+        /// changes per --on-dirty, and print the resulting HEAD
+        #[arg(long, requires = "use_existing")]
+        force_update: bool,
+
+        /// What --force-update does with uncommitted changes
+        #[arg(long, value_enum, default_value_t = OnDirty::Fail, requires = "force_update")]
+        on_dirty: OnDirty,
+
+        /// Reuse a worktree that already has the PR's branch checked out, even if it
+        /// was created by `checkout branch`, without prompting
+        #[arg(long)]
+        reuse_branch_worktree: bool,
+
+        /// Print a `git diff --stat` against the PR's base branch before spawning the agent
+        #[arg(long)]
+        base_compare: bool,
+
+        /// Check out a merged or closed PR without asking for confirmation
+        #[arg(long)]
+        force: bool,
+
+        /// Show the diff since the PR head recorded at your last checkout of it
+        #[arg(long)]
+        since_commit: bool,
+
+        /// Mark the PR's files as viewed on GitHub after checking it out
+        #[arg(long)]
+        mark_viewed: bool,
+
+        /// Create the worktree next to the repo instead of in the worktree dir
+        #[arg(long)]
+        worktree_in_repo: bool,
+
+        /// Color a new worktree by hashing the PR's author, base branch or
+        /// number into the palette, instead of taking the first unused color
+        #[arg(long, value_enum, value_name = "KEY")]
+        worktree_color_by: Option<ColorBy>,
+
+        /// Also check out the PR's base branch in a `base-<branch>` worktree,
+        /// shared by PRs with the same base, for side-by-side comparison
+        #[arg(long)]
+        with_base: bool,
+
+        #[command(flatten)]
+        picker: PrPickerArgs,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Open a resource in its existing iTerm session or a new checkout tab
     Open {
@@ -188,39 +459,48 @@ enum Commands {
         /// Statsig gate name
         gate: String,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
         /// Resume the existing worktree session without prompting
         #[arg(long)]
         resume_existing: bool,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Check out a GitHub PR into a worktree and generate a walkthrough
     Walkthrough {
-        /// PR number or GitHub PR URL (e.g., 123 or https://github.com/org/repo/pull/123)
+        /// PR number, #number, pull/number or GitHub PR URL (e.g., 123, #123 or https://github.com/org/repo/pull/123)
         pr: String,
 
         /// Skip launching the coding agent after creating the worktree
         #[arg(long = "no-agent", alias = "no-claude")]
         no_agent: bool,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Check out a GitHub PR into a worktree and review it
     Review {
-        /// PR number or GitHub PR URL (e.g., 123 or https://github.com/org/repo/pull/123)
+        /// PR number, #number, pull/number or GitHub PR URL (e.g., 123, #123 or https://github.com/org/repo/pull/123)
         pr: String,
 
         /// Skip launching the coding agent after creating the worktree
         #[arg(long = "no-agent", alias = "no-claude")]
         no_agent: bool,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Create a new branch in a worktree
     Branch {
@@ -235,9 +515,68 @@ enum Commands {
         #[arg(long = "prompt", alias = "claude-prompt")]
         prompt: Option<PathBuf>,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+
+        /// Start the branch from this ref instead of origin/master (branch, origin/branch, tag, or SHA)
+        #[arg(long)]
+        base: Option<String>,
+
+        /// Push the branch and set its upstream so a later `git push` needs no flags
+        #[arg(long)]
+        push: bool,
+
+        /// With --push, add an empty commit and open a draft PR with `gh pr create`
+        #[arg(long, requires = "push")]
+        pr: bool,
+
+        /// Use the branch name verbatim and name the worktree after all of it (e.g. release/1.2.0)
+        #[arg(long = "no-prefix")]
+        no_prefix: bool,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
+    },
+    /// Check out a commit into a detached worktree (e.g. master from three days ago)
+    Commit {
+        /// Full or abbreviated commit SHA
+        sha: String,
+
+        /// Worktree directory name (default: commit-<short-sha>)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Skip launching the coding agent after creating the worktree
+        #[arg(long = "no-agent", alias = "no-claude")]
+        no_agent: bool,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
+    },
+    /// Check out a tag into a detached worktree, e.g. to poke at a release
+    Tag {
+        /// Tag name, fetched from origin if it isn't local (e.g. v1.2.0)
+        tag: String,
+
+        /// Worktree directory name (default: tag-<tag>, with dots as dashes)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Skip launching the coding agent after creating the worktree
+        #[arg(long = "no-agent", alias = "no-claude")]
+        no_agent: bool,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Create a new worktree with a random name
     New {
@@ -249,41 +588,389 @@ enum Commands {
         #[arg(long = "prompt", alias = "claude-prompt")]
         prompt: Option<PathBuf>,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// Create a new worktree and start the workstream-begin skill
     Begin {
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
+
+        #[command(flatten)]
+        checkout: CheckoutArgs,
     },
     /// List all worktrees and their status
     Status {
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+
+        /// Always show output through $PAGER (default: only when it doesn't fit)
+        #[arg(long)]
+        pager: bool,
+
+        /// Output format; `path`, `name` and `json` are for scripts
+        #[arg(long, value_enum, default_value_t = StatusFormat::Human)]
+        format: StatusFormat,
+
+        /// Run `git fetch --prune origin` first so unpushed counts are current
+        #[arg(long)]
+        fetch: bool,
+
+        /// Also show each worktree's last Claude session cost, as `usage` does
+        #[arg(long)]
+        usage: bool,
+
+        /// Keep redrawing the status until `q` or Ctrl+C
+        #[arg(long, conflicts_with_all = ["pager", "format"])]
+        watch: bool,
+
+        /// How often `--watch` redraws, e.g. `500ms`, `2s` or `1m`
+        #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_duration)]
+        watch_interval: Duration,
+
+        #[command(flatten)]
+        order: WorktreeOrderArgs,
+    },
+    /// Show the cost, duration, line counts and model of each worktree's last
+    /// Claude session, from ~/.claude.json
+    Usage {
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
     },
     /// Remove worktrees that have no uncommitted changes
     Clean {
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+
+        /// Skip confirmation prompt
+        #[arg(long, short = 'y')]
+        yes: bool,
+
+        /// Never remove worktrees whose directory name matches this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Only consider worktrees whose directory name matches this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        only: Vec<String>,
+
+        /// Also delete remote-tracking refs for branches deleted on origin
+        #[arg(long)]
+        prune_remote: bool,
+
+        /// Delete the local branch of each removed worktree (git branch -d)
+        #[arg(long)]
+        delete_branch: bool,
+
+        /// With --delete-branch, delete branches with unmerged commits too (git branch -D)
+        #[arg(long, requires = "delete_branch")]
+        force: bool,
+
+        /// Also consider worktrees that have a note (kept by default)
+        #[arg(long)]
+        include_noted: bool,
+
+        #[command(flatten)]
+        order: WorktreeOrderArgs,
+    },
+    /// Point git, colors, notes and ~/.claude.json at worktrees whose
+    /// directory moved, moving any that haven't been moved yet
+    Migrate {
+        /// Directory the worktrees were in
+        #[arg(long, value_name = "OLD_DIR")]
+        from: PathBuf,
+
+        /// Directory they are in now, or should be moved to
+        #[arg(long, value_name = "NEW_DIR")]
+        to: PathBuf,
+
+        /// Show what each worktree would need without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Remove everything checkout has created: worktrees, their Claude
+    /// project entries, colors and the data dir. The main repo is never touched.
+    Uninstall {
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
+        /// List what would be removed, grouped by category, without removing it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Also delete the local branches of removed worktrees
+        #[arg(long)]
+        delete_branches: bool,
+
+        /// Remove worktrees with uncommitted changes or unpushed commits too,
+        /// and delete unmerged branches
+        #[arg(long)]
+        force: bool,
+
         /// Skip confirmation prompt
         #[arg(long, short = 'y')]
         yes: bool,
     },
     /// Browse all worktree sessions and resume one with its original agent
     Resume {
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Full-screen worktree dashboard: open, update, rename, or delete worktrees
+    Ui {
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
     },
     /// Resume the most recently exited session for the selected agent
     ResumeLast {
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Re-run the most recent pr/branch checkout
+    Last {
+        /// Print the recorded checkout as JSON instead of replaying it
+        #[arg(long)]
+        json: bool,
+
+        /// Describe the recorded checkout instead of replaying it
+        #[arg(long, conflicts_with = "json")]
+        show: bool,
+    },
+    /// Read or write a key in the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Manage remembered worktree colors
+    Colors {
+        #[command(subcommand)]
+        action: ColorsAction,
+    },
+    /// Inspect command traces from `--trace` or from failed runs
+    Trace {
+        #[command(subcommand)]
+        action: TraceAction,
+    },
+    /// List or re-apply the uncommitted changes saved before checkout
+    /// discarded them
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
+    /// Annotate a worktree with a one-line note shown in `status`; noted
+    /// worktrees are kept by `clean`
+    Note {
+        /// Worktree directory name (or a unique part of it) or path
+        worktree: String,
+
+        /// The note
+        #[arg(required_unless_present = "clear")]
+        text: Option<String>,
+
+        /// Remove the worktree's note
+        #[arg(long, conflicts_with = "text")]
+        clear: bool,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Update every clean, inactive PR worktree to the latest of its branch
+    UpdateAll {
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Put PR worktrees left on a detached HEAD back on the PR's branch, created
+    /// at their current commit and tracking origin
+    FixDetached {
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+
+        /// Skip confirmation prompt
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Rename the branch checked out in a worktree, keeping its directory;
+    /// a branch pushed under its own name is pushed under the new one too
+    RenameBranch {
+        /// Worktree directory name (or a unique part of it) or path
+        worktree: String,
+
+        /// New branch name
+        new_name: String,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Link Claude settings into a worktree and trust it in ~/.claude.json,
+    /// for worktrees made outside checkout or where those steps failed
+    Trust {
+        /// Worktree directory name (or a unique part of it) or path
+        worktree: String,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Set a worktree's background color: a hex color forces that background,
+    /// `none` keeps the terminal's own, `auto` goes back to a picked color
+    Color {
+        /// Worktree directory name (or a unique part of it) or path
+        worktree: String,
+
+        /// A hex background like `#1e2233`, `none`, or `auto`
+        #[arg(value_parser = parse_worktree_color)]
+        color: String,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Print the absolute path of a worktree, for scripts, e.g.
+    /// `cd "$(checkout which 4821)"`
+    Which {
+        /// Worktree directory name (or a unique part of it) or path
+        worktree: String,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Print the main repo's path, e.g. `cd "$(checkout main)"` to get back
+    /// from a worktree
+    Main {
+        /// Start the agent in the main repo instead of printing its path
+        #[arg(long)]
+        spawn: bool,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: the repo
+        /// of the current directory, else $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+    /// Print a shell function that runs checkout and cds into the worktree
+    /// it prepares, e.g. `eval "$(checkout shell-init)"` in ~/.zshrc
+    ShellInit {
+        /// Name of the shell function
+        #[arg(long, default_value = "cw")]
+        name: String,
+    },
+}
+
+/// Filters for the open-PR picker shown when `checkout pr` gets no PR,
+/// passed through to `gh pr list`.
+#[derive(Args, Clone, Debug, Default)]
+struct PrPickerArgs {
+    /// Number of open PRs to list in the picker
+    #[arg(long, default_value_t = 30, conflicts_with = "pr")]
+    limit: u32,
+
+    /// Only list PRs by this author (e.g. @me)
+    #[arg(long, conflicts_with = "pr")]
+    author: Option<String>,
+
+    /// Only list PRs assigned to this user (e.g. @me)
+    #[arg(long, conflicts_with = "pr")]
+    assignee: Option<String>,
+}
+
+/// Order of the worktrees `status` lists and `clean` considers, and how
+/// many of them to act on.
+#[derive(Args, Clone, Copy, Debug, Default)]
+struct WorktreeOrderArgs {
+    /// Order worktrees by path or by age (oldest first)
+    #[arg(long, value_enum, default_value_t = WorktreeSort::Path)]
+    sort: WorktreeSort,
+
+    /// Only show the first N worktrees (status), or remove at most N clean ones (clean)
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum WorktreeSort {
+    #[default]
+    Path,
+    /// When the worktree was created, oldest first
+    Age,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the value of a config key
+    Get {
+        /// Config key (e.g. repo, worktree_dir)
+        key: String,
+    },
+    /// Set a config key
+    Set {
+        /// Config key (e.g. repo, worktree_dir)
+        key: String,
+
+        /// New value
+        value: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TraceAction {
+    /// Pretty-print a trace file
+    Show {
+        /// Trace file written by `--trace` or saved after a failed run
+        file: PathBuf,
+    },
+    /// Show the most recent failed run's trace
+    Last,
+}
+
+#[derive(Subcommand)]
+enum BackupsAction {
+    /// List backups, newest first
+    List,
+    /// Apply a backup's changes and untracked files to a worktree
+    Restore {
+        /// Backup file, as `backups list` shows it (a bare file name is
+        /// looked up in the backups directory)
+        file: PathBuf,
+
+        /// Worktree directory name (or a unique part of it) or path (default:
+        /// the worktree you're in)
+        worktree: Option<String>,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
+        #[arg(long)]
+        repo: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ColorsAction {
+    /// Forget the color remembered for a PR or branch
+    Forget {
+        /// PR number, GitHub PR URL, or branch name
+        target: String,
+
+        /// Path to the repo, or a `[repos.<name>]` alias (default: the repo
+        /// of the current directory, else $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
     },
@@ -296,7 +983,7 @@ enum OpenTarget {
         /// PR number or GitHub PR URL
         pr: String,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
@@ -309,7 +996,7 @@ enum OpenTarget {
         /// Statsig gate name
         gate: String,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
@@ -340,7 +1027,7 @@ enum SessionTarget {
         #[arg(long)]
         branch: Option<String>,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
@@ -353,7 +1040,7 @@ enum SessionTarget {
         /// Statsig gate name
         gate: String,
 
-        /// Path to the repo (default: $CHECKOUT_REPO)
+        /// Path to the repo, or a `[repos.<name>]` alias (default: $CHECKOUT_REPO)
         #[arg(long)]
         repo: Option<PathBuf>,
 
@@ -377,11 +1064,50 @@ enum SessionTarget {
     },
 }
 
-#[derive(Deserialize)]
-struct PrDetails {
-    #[serde(rename = "headRefName")]
-    head_ref_name: String,
-    title: String,
+/// Declare `PrDetails` together with the `gh pr view --json` field list, so
+/// a field added here is both requested and parsed. Fields gh may omit should
+/// be `#[serde(default)]`; unknown fields in the response are ignored.
+macro_rules! pr_details {
+    ($($(#[$attr:meta])* $json:literal => $field:ident: $ty:ty,)*) => {
+        #[derive(Deserialize)]
+        struct PrDetails {
+            $($(#[$attr])* #[serde(rename = $json)] $field: $ty,)*
+        }
+
+        impl PrDetails {
+            /// Value for `gh pr view --json`
+            const JSON_FIELDS: &'static [&'static str] = &[$($json),*];
+        }
+    };
+}
+
+pr_details! {
+    "headRefName" => head_ref_name: String,
+    "title" => title: String,
+    /// `OPEN`, `CLOSED` or `MERGED`
+    #[serde(default)]
+    "state" => state: String,
+    /// Missing for PRs whose author account was deleted
+    #[serde(default)]
+    "author" => author: Option<PrAuthor>,
+    #[serde(default)]
+    "baseRefName" => base_ref_name: String,
+    /// Tip of the head branch, to skip fetching when it's already local
+    #[serde(default)]
+    "headRefOid" => head_ref_oid: String,
+    /// Whether the head branch lives in a fork rather than on origin
+    #[serde(default)]
+    "isCrossRepository" => is_cross_repository: bool,
+    #[serde(default)]
+    "headRepositoryOwner" => head_repository_owner: Option<PrAuthor>,
+}
+
+impl PrDetails {
+    /// The head branch, if it can be fetched from origin by name. A fork's
+    /// branch can't, and may share its name with an unrelated origin branch.
+    fn origin_branch(&self) -> Option<&str> {
+        (!self.is_cross_repository).then_some(self.head_ref_name.as_str())
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -394,30 +1120,97 @@ struct ResumeTarget {
 #[derive(Debug)]
 enum ExistingWorktreeAction {
     UseExisting,
+    /// Reopen the worktree without updating it, keeping uncommitted changes
+    UseAsIs,
     ResumeSession(ResumeTarget),
     CreateNew,
 }
 
+/// Why a prompt stopped the command. The prompt returns an ordinary error,
+/// which unwinds like any other so guards still run, and records the reason
+/// on the side, so a caller that wraps the message can't turn a Ctrl+C into a
+/// failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Interrupt {
+    /// Ctrl+C or the end of stdin at a prompt: `main` exits with 130 as
+    /// Ctrl+C would
+    Cancelled,
+    /// Answering no to a confirmation: `main` exits with 0, since nothing went
+    /// wrong
+    Declined,
+}
+
+impl Interrupt {
+    /// Record the interrupt and return the error to unwind with.
+    fn raise(self) -> String {
+        INTERRUPT.set(Some(self));
+        match self {
+            Interrupt::Cancelled => "Cancelled",
+            Interrupt::Declined => "Declined",
+        }
+        .to_string()
+    }
+
+    /// The interrupt behind the error this thread is handling, if any,
+    /// clearing it.
+    fn take() -> Option<Interrupt> {
+        INTERRUPT.take()
+    }
+}
+
 fn main() {
     setup_ctrlc_handler();
 
-    if let Err(e) = run() {
+    let result = run();
+    match result.is_err().then(Interrupt::take).flatten() {
+        Some(Interrupt::Cancelled) => {
+            println!("\n{} Cancelled", "→".blue().bold());
+            save_trace(None);
+            std::process::exit(130);
+        }
+        Some(Interrupt::Declined) => {
+            println!("{} Cancelled", "→".blue().bold());
+            save_trace(None);
+            return;
+        }
+        None => {}
+    }
+    if let Err(e) = &result {
         eprintln!("{} {}", "error:".red().bold(), e);
+    }
+    save_trace(result.as_ref().err());
+    if result.is_err() {
         std::process::exit(1);
     }
 }
 
-/// Set iTerm2 background color using proprietary escape sequence.
-fn set_iterm_background(hex_color: &str) {
-    print!("\x1b]1337;SetColors=bg={}\x07", hex_color);
-    std::io::stdout().flush().ok();
-}
+/// Where `--trace` asked for this run's trace to go.
+static TRACE_FILE: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+/// `--write-path`: where to write the worktree's path once it's ready
+static WRITE_PATH: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
 
-/// Reset iTerm2 background to default
-fn reset_iterm_background() {
-    // OSC 111 resets background color to default (standard xterm sequence)
-    print!("\x1b]111\x07");
-    std::io::stdout().flush().ok();
+/// Write the trace to `--trace <file>`, or under the data dir if the run failed.
+fn save_trace(error: Option<&String>) {
+    let path = match (TRACE_FILE.get(), error) {
+        (Some(path), _) => path.clone(),
+        (None, Some(_)) => trace::failed_trace_path(&get_data_dir()),
+        (None, None) => return,
+    };
+    match trace::write(&path, error.map(String::as_str)) {
+        Ok(false) => {}
+        Ok(true) if TRACE_FILE.get().is_some() => {
+            eprintln!("{} Trace written to {}", "→".blue().bold(), path.display().to_string().cyan());
+        }
+        Ok(true) => {
+            trace::prune(&get_data_dir());
+            eprintln!(
+                "{} Trace saved to {}; `checkout trace last` shows it",
+                "→".blue().bold(),
+                path.display().to_string().dimmed()
+            );
+        }
+        Err(e) => eprintln!("{} {}", "⚠".yellow(), e),
+    }
 }
 
 fn rename_iterm_session(title: &str) -> Result<(), String> {
@@ -438,56 +1231,57 @@ fn rename_iterm_session(title: &str) -> Result<(), String> {
     }
 }
 
-/// Set iTerm2 session title
-fn set_iterm_title(title: &str) {
-    // Target the exact session as well as writing OSC title sequences. The
-    // latter do not reach iTerm2 from every coding-agent subprocess.
-    let _ = rename_iterm_session(title);
-    // OSC 1 sets tab/icon title, OSC 2 sets window title
-    // Set both to ensure the title shows
-    print!("\x1b]1;{}\x07\x1b]2;{}\x07", title, title);
-    std::io::stdout().flush().ok();
+/// Set terminal working directory via OSC 7 escape sequence
+/// This tells the terminal what directory cmd-click paths should resolve from
+fn set_terminal_cwd(path: &Path) {
+    terminal::set_cwd(&local_hostname(), path);
 }
 
-/// Reset iTerm2 session title
-fn reset_iterm_title() {
-    let _ = rename_iterm_session("");
-    print!("\x1b]1;\x07\x1b]2;\x07");
-    std::io::stdout().flush().ok();
+fn local_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "localhost".to_string())
 }
 
-/// Set terminal working directory via OSC 7 escape sequence
-/// This tells the terminal what directory cmd-click paths should resolve from
-fn set_terminal_cwd(path: &PathBuf) {
-    let hostname = hostname::get()
-        .map(|h| h.to_string_lossy().to_string())
-        .unwrap_or_else(|_| "localhost".to_string());
-    print!("\x1b]7;file://{}{}\x07", hostname, path.display());
-    std::io::stdout().flush().ok();
+/// Whether stdout is an interactive terminal that can take escape sequences.
+fn stdout_is_tty() -> bool {
+    io::stdout().is_terminal() && env::var("TERM").map_or(true, |term| term != "dumb")
 }
 
-// Track whether we've modified iTerm settings
-static ITERM_MODIFIED: AtomicBool = AtomicBool::new(false);
+/// `file://host/path` URL with everything outside the URL-safe set percent-encoded.
+fn file_url(hostname: &str, path: &Path) -> String {
+    let mut url = format!("file://{}", hostname);
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
 
-/// RAII guard that resets iTerm settings on drop
-struct ItermGuard;
+/// Wrap `text` in an OSC 8 hyperlink to `url`.
+fn osc8_link(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
 
-impl ItermGuard {
-    fn new(bg_color: &str, title: &str) -> Self {
-        set_iterm_background(bg_color);
-        set_iterm_title(title);
-        ITERM_MODIFIED.store(true, Ordering::SeqCst);
-        Self
-    }
+/// Display a path styled as `styled`, cmd-clickable when stdout is a terminal.
+/// Terminals without OSC 8 support ignore the escapes and show plain text.
+fn path_link(path: &Path, styled: impl std::fmt::Display) -> String {
+    url_link(&file_url(&local_hostname(), path), styled)
 }
 
-impl Drop for ItermGuard {
-    fn drop(&mut self) {
-        if ITERM_MODIFIED.load(Ordering::SeqCst) {
-            reset_iterm_background();
-            reset_iterm_title();
-            ITERM_MODIFIED.store(false, Ordering::SeqCst);
-        }
+/// Display `styled` as a link to `url` when stdout is a terminal, unless
+/// turned off with `hyperlinks = false` or `terminal = "none"`.
+fn url_link(url: &str, styled: impl std::fmt::Display) -> String {
+    let config = config::get();
+    let enabled = config.hyperlinks.unwrap_or(true) && config.terminal != Some(terminal::TerminalKind::None);
+    if enabled && stdout_is_tty() {
+        osc8_link(url, &styled.to_string())
+    } else {
+        styled.to_string()
     }
 }
 
@@ -496,10 +1290,15 @@ fn setup_ctrlc_handler() {
     // SIGHUP matters for iTerm tab-close: a child agent can otherwise survive
     // the wrapper and keep the worktree from being reused.
     ctrlc::set_handler(move || {
-        if ITERM_MODIFIED.load(Ordering::SeqCst) {
-            reset_iterm_background();
-            reset_iterm_title();
+        // At a prompt, let `read_input` raise `Interrupt::Cancelled` so the error
+        // unwinds through the guards; a second Ctrl+C while that happens
+        // still exits straight away.
+        if PROMPTING.load(Ordering::SeqCst) {
+            PROMPT_INTERRUPTED.store(true, Ordering::SeqCst);
+            return;
         }
+        ui::restore_terminal();
+        terminal::reset_if_modified();
         // Kill the agent child before we exit — otherwise it can be reparented
         // to launchd and keep holding the pid/worktree.
         if let Ok(guard) = ACTIVE_CHILD_PID.lock() {
@@ -529,35 +1328,117 @@ fn setup_ctrlc_handler() {
     .ok();
 }
 
+/// Read a line of prompt input. Ctrl+C while waiting, or the end of stdin,
+/// raises `Interrupt::Cancelled` instead of exiting the process.
+fn read_input() -> Result<String, String> {
+    next_input_line()?.ok_or_else(|| Interrupt::Cancelled.raise())
+}
+
+/// Read the answer to a `[y/N]` or `[Y/n]` question. The end of stdin answers
+/// with an empty line, so scripted runs get the prompt's default; Ctrl+C
+/// still cancels.
+fn read_confirmation() -> Result<String, String> {
+    Ok(next_input_line()?.unwrap_or_default())
+}
+
+/// The next line of stdin, or `None` at its end.
+fn next_input_line() -> Result<Option<String>, String> {
+    let receiver = PENDING_INPUT.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let receiver = receiver.unwrap_or_else(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = String::new();
+            let _ = sender.send(io::stdin().read_line(&mut input).map(|n| (n > 0).then_some(input)));
+        });
+        receiver
+    });
+    PROMPT_INTERRUPTED.store(false, Ordering::SeqCst);
+    PROMPTING.store(true, Ordering::SeqCst);
+    let result = wait_for_input(&receiver, &PROMPT_INTERRUPTED);
+    PROMPTING.store(false, Ordering::SeqCst);
+    if PROMPT_INTERRUPTED.load(Ordering::SeqCst) {
+        *PENDING_INPUT.lock().unwrap_or_else(|e| e.into_inner()) = Some(receiver);
+    }
+    result
+}
+
+fn wait_for_input(
+    receiver: &mpsc::Receiver<io::Result<Option<String>>>,
+    interrupted: &AtomicBool,
+) -> Result<Option<String>, String> {
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return Err(Interrupt::Cancelled.raise());
+        }
+        match receiver.recv_timeout(Duration::from_millis(50)) {
+            Ok(Ok(input)) => return Ok(input),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(None),
+            Ok(Err(e)) => return Err(format!("Failed to read input: {}", e)),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+/// Base directory for checkout's persistent state. Honors `data_dir` in the
+/// config, then `$XDG_DATA_HOME/checkout`, then `~/.local/share/checkout`.
+fn get_data_dir() -> PathBuf {
+    match &config::get().data_dir {
+        Some(dir) => config::expand_tilde(dir),
+        None => config::default_data_dir(),
+    }
+}
+
 fn get_color_dir() -> PathBuf {
-    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(format!("{}/.local/share/checkout/colors", home))
+    match &config::get().color_dir {
+        Some(dir) => config::expand_tilde(dir),
+        None => get_data_dir().join("colors"),
+    }
 }
 
-fn worktree_color_file(worktree_path: &PathBuf) -> PathBuf {
-    // Use the worktree directory name as the color file name
-    let name = worktree_path
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
-    get_color_dir().join(name)
+/// The directory name plus a short hash of the full path, so same-named
+/// worktrees in different places (e.g. `--worktree-in-repo` ones for two
+/// repos) don't share a color
+fn color_file_name(worktree_path: &Path) -> String {
+    let hash = format!("{:x}", md5::compute(worktree_path.to_string_lossy().as_bytes()));
+    format!("{}-{}", worktree_dir_name(worktree_path), &hash[..8])
+}
+
+fn worktree_color_file(worktree_path: &Path) -> PathBuf {
+    get_color_dir().join(color_file_name(worktree_path))
 }
 
-fn get_worktree_color(worktree_path: &PathBuf) -> Option<String> {
+/// Color files used to be named after the directory alone
+fn legacy_color_file(worktree_path: &Path) -> PathBuf {
+    get_color_dir().join(worktree_dir_name(worktree_path))
+}
+
+fn get_worktree_color(worktree_path: &Path) -> Option<String> {
     let color_file = worktree_color_file(worktree_path);
+    let legacy = legacy_color_file(worktree_path);
+    if !color_file.exists() && legacy.is_file() {
+        // Best effort: the first worktree to look claims the old file
+        let _ = fs::rename(&legacy, &color_file);
+    }
     fs::read_to_string(color_file).ok().map(|s| s.trim().to_string())
 }
 
-fn save_worktree_color(worktree_path: &PathBuf, color: &str) -> Result<(), String> {
+fn save_worktree_color(worktree_path: &Path, color: &str) -> Result<(), String> {
     let color_dir = get_color_dir();
     fs::create_dir_all(&color_dir).map_err(|e| format!("Failed to create color dir: {}", e))?;
 
     let color_file = worktree_color_file(worktree_path);
     fs::write(&color_file, color).map_err(|e| format!("Failed to save color: {}", e))?;
+    // Don't let a stale old-style file keep its color marked as used
+    let _ = fs::remove_file(legacy_color_file(worktree_path));
 
     Ok(())
 }
 
+fn remove_worktree_color(worktree_path: &Path) {
+    let _ = fs::remove_file(worktree_color_file(worktree_path));
+    let _ = fs::remove_file(legacy_color_file(worktree_path));
+}
+
 fn get_used_colors() -> HashSet<String> {
     let mut used = HashSet::new();
     let color_dir = get_color_dir();
@@ -567,7 +1448,9 @@ fn get_used_colors() -> HashSet<String> {
             let path = entry.path();
             if path.is_file() {
                 if let Ok(color) = fs::read_to_string(&path) {
-                    used.insert(color.trim().to_string());
+                    if color.trim() != terminal::NO_COLOR {
+                        used.insert(color.trim().to_string());
+                    }
                 }
             }
         }
@@ -576,4589 +1459,9483 @@ fn get_used_colors() -> HashSet<String> {
     used
 }
 
-fn pick_available_color(current_worktree: &PathBuf) -> String {
-    if let Some(existing) = get_worktree_color(current_worktree) {
-        return existing;
+/// What a worktree's color is remembered under, so re-checking out the same PR
+/// or branch after `clean` brings back the same background.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ColorKey {
+    Pr(u64),
+    Branch(String),
+}
+
+impl ColorKey {
+    /// The remembered-colors map for this kind of key, and the entry in it
+    /// for `repo_root`'s PR or branch: PR numbers and branch names are only
+    /// unique within a repo.
+    fn entry<'a>(&self, metadata: &'a mut metadata::Metadata, repo_root: &Path) -> (&'a mut BTreeMap<String, String>, String) {
+        match self {
+            ColorKey::Pr(number) => (&mut metadata.pr_colors, metadata::pr_key(repo_root, *number)),
+            ColorKey::Branch(branch) => (&mut metadata.branch_colors, metadata::branch_key(repo_root, branch)),
+        }
     }
+}
 
-    let used = get_used_colors();
+fn remembered_color(repo_root: &Path, key: &ColorKey) -> Option<String> {
+    let mut metadata = metadata::load(&get_data_dir());
+    let (colors, entry) = key.entry(&mut metadata, repo_root);
+    colors.remove(&entry)
+}
 
-    for color in COLOR_PALETTE {
-        if !used.contains(*color) {
-            return color.to_string();
-        }
+fn remember_color(repo_root: &Path, key: &ColorKey, color: &str) -> Result<(), String> {
+    metadata::update(&get_data_dir(), |metadata| {
+        let (colors, entry) = key.entry(metadata, repo_root);
+        colors.insert(entry, color.to_string());
+    })
+}
+
+/// Interpret a `colors forget` target: anything that parses as a PR reference
+/// is a PR, everything else is a branch name.
+fn parse_color_key(target: &str) -> ColorKey {
+    match extract_pr_number(target) {
+        Ok(number) => ColorKey::Pr(number),
+        Err(_) => ColorKey::Branch(target.to_string()),
     }
+}
 
-    let hash = current_worktree.to_string_lossy().bytes().fold(0usize, |acc, b| acc.wrapping_add(b as usize));
-    COLOR_PALETTE[hash % COLOR_PALETTE.len()].to_string()
+fn run_colors_forget(target: &str, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let key = parse_color_key(target);
+    let removed = metadata::update(&get_data_dir(), |metadata| {
+        let (colors, entry) = key.entry(metadata, &repo_root);
+        colors.remove(&entry)
+    })?;
+    let label = match &key {
+        ColorKey::Pr(number) => format!("PR #{}", number),
+        ColorKey::Branch(branch) => format!("branch {}", branch),
+    };
+    match removed {
+        Some(color) => println!(
+            "{} Forgot color {} for {}",
+            "✓".green().bold(),
+            format!("#{}", color).dimmed(),
+            label.cyan()
+        ),
+        None => println!("{} No color remembered for {}", "→".blue().bold(), label.cyan()),
+    }
+    Ok(())
 }
 
-fn get_session_dir() -> PathBuf {
-    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(format!("{}/.local/share/checkout/sessions", home))
+/// The worktree `query` names: a path to one, an exact directory name, or a
+/// part of exactly one directory name. `None` when nothing matches; matching
+/// several is an error.
+fn match_worktree<'a>(worktrees: &'a [PathBuf], query: &str) -> Result<Option<&'a PathBuf>, String> {
+    let query_path = Path::new(query);
+    if let Some(path) = worktrees.iter().find(|path| path.as_path() == query_path) {
+        return Ok(Some(path));
+    }
+    if let Some(path) = worktrees.iter().find(|path| worktree_dir_name(path) == query) {
+        return Ok(Some(path));
+    }
+    let matches: Vec<&PathBuf> = worktrees.iter().filter(|path| worktree_dir_name(path).contains(query)).collect();
+    match matches.as_slice() {
+        [path] => Ok(Some(path)),
+        [] => Ok(None),
+        _ => Err(format!(
+            "{} matches several worktrees: {}",
+            query,
+            matches.iter().map(|path| worktree_dir_name(path)).collect::<Vec<_>>().join(", ")
+        )),
+    }
 }
 
-fn session_file_name(worktree_path: &Path) -> String {
-    worktree_path
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| "unknown".to_string())
+/// The worktree of `repo_root` that `query` names (see `match_worktree`),
+/// or `None` when there's no such worktree.
+fn find_worktree_query(repo_root: &Path, query: &str) -> Result<Option<PathBuf>, String> {
+    let worktrees = linked_worktree_paths(repo_root)?;
+    // Accept relative paths and symlinked spellings of a worktree path too
+    if let Some(path) = Path::new(query).canonicalize().ok().filter(|path| worktrees.contains(path)) {
+        return Ok(Some(path));
+    }
+    Ok(match_worktree(&worktrees, query)?.cloned())
 }
 
-fn session_pid_file(worktree_path: &Path) -> PathBuf {
-    get_session_dir().join(format!("{}.pid", session_file_name(worktree_path)))
+fn resolve_worktree_query(repo_root: &Path, query: &str) -> Result<PathBuf, String> {
+    find_worktree_query(repo_root, query)?.ok_or_else(|| format!("No worktree matches {}", query))
 }
 
-fn session_exited_file(worktree_path: &Path) -> PathBuf {
-    get_session_dir().join(format!("{}.exited", session_file_name(worktree_path)))
+/// Every worktree of `repo_root` but the main checkout
+fn linked_worktree_paths(repo_root: &Path) -> Result<Vec<PathBuf>, String> {
+    Ok(git::list_worktrees(repo_root)?
+        .into_iter()
+        .map(|entry| entry.path)
+        .filter(|path| path != repo_root)
+        .collect())
+}
+
+/// `which`: only the path goes to stdout. When nothing matches, the error
+/// lists every worktree so the right name is at hand.
+fn run_which(query: &str, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let Some(path) = find_worktree_query(&repo_root, query)? else {
+        let names: Vec<String> = linked_worktree_paths(&repo_root)?.iter().map(|path| worktree_dir_name(path)).collect();
+        return Err(if names.is_empty() {
+            format!("No worktree matches {} (there are no worktrees)", query)
+        } else {
+            format!("No worktree matches {}; worktrees: {}", query, names.join(", "))
+        });
+    };
+    println!("{}", path.display());
+    write_worktree_path(&path)
 }
 
-fn session_name_file(worktree_path: &Path) -> PathBuf {
-    get_session_dir().join(format!("{}.name", session_file_name(worktree_path)))
+/// `checkout main`: the main repo for `--repo`, else the one owning the
+/// current directory when it's inside a checkout, else the configured repo.
+fn run_main(spawn: bool, repo: Option<PathBuf>, agent: Agent) -> Result<(), String> {
+    let current = match repo {
+        Some(_) => None,
+        None => env::current_dir().ok().and_then(|cwd| git::main_worktree(&cwd).ok().flatten()),
+    };
+    let repo_root = current.unwrap_or_else(|| resolve_repo_root(repo));
+    if !spawn {
+        println!("{}", repo_root.display());
+        return Ok(());
+    }
+    progressln!("{} Spawning {} in {}...", "→".blue().bold(), agent.display_name(), repo_root.display());
+    spawn_agent(agent, &repo_root, None, &repo_slug(&repo_root), &[])
 }
 
-fn worktree_iterm_session_file(worktree_path: &Path) -> PathBuf {
-    let digest = format!("{:x}", md5::compute(worktree_path.to_string_lossy().as_bytes()));
-    get_session_dir().join(format!("worktree-{}.iterm", digest))
+/// Parse the `color` argument: `none`, `auto`, or six hex digits with an
+/// optional `#`, normalized to the color file's lowercase form.
+fn parse_worktree_color(value: &str) -> Result<String, String> {
+    if value == terminal::NO_COLOR || value == "auto" {
+        return Ok(value.to_string());
+    }
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} is not a color: expected a hex value like #1e2233, none, or auto", value));
+    }
+    Ok(hex.to_ascii_lowercase())
 }
 
-fn resource_iterm_session_file(resource: &str, identifier: &str, repo_root: &Path) -> PathBuf {
-    let key = format!("{}:{}:{}", repo_root.display(), resource, identifier);
-    let digest = format!("{:x}", md5::compute(key.as_bytes()));
-    get_session_dir().join(format!("resource-{}.iterm", digest))
+fn run_color(query: &str, color: &str, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let worktree = resolve_worktree_query(&repo_root, query)?;
+    let name = worktree_dir_name(&worktree);
+    match color {
+        terminal::NO_COLOR => {
+            save_worktree_color(&worktree, terminal::NO_COLOR)?;
+            println!("{} {} will keep the terminal's background", "✓".green().bold(), name.cyan());
+        }
+        "auto" => {
+            remove_worktree_color(&worktree);
+            println!("{} {} will get a picked background color", "✓".green().bold(), name.cyan());
+        }
+        hex => {
+            // pick_available_color prefers the saved color, so the next
+            // spawn or reopen there uses it
+            save_worktree_color(&worktree, hex)?;
+            println!(
+                "{} {} will use background {}",
+                "✓".green().bold(),
+                name.cyan(),
+                format!("#{}", hex).dimmed()
+            );
+        }
+    }
+    Ok(())
 }
 
-fn iterm_api_socket_file() -> PathBuf {
-    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(format!("{}/.local/share/checkout/iterm-api.sock", home))
+fn note_key(worktree_path: &Path) -> String {
+    worktree_path.to_string_lossy().into_owned()
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ItermApiResponse {
-    ok: bool,
-    exists: Option<bool>,
-    session_id: Option<String>,
-    action: Option<String>,
-    error: Option<String>,
+/// Carry everything checkout keeps per worktree path (color, session name,
+/// iTerm session, note, `.claude.json` project and last checkout) over to
+/// `new_path` after the worktree moved. `old_paths` is the old location as git
+/// reports it, then any other spellings `.claude.json` may use for it.
+fn move_worktree_state(old_paths: &[PathBuf], new_path: &Path) -> Result<(), String> {
+    let old_path = &old_paths[0];
+    if let Some(color) = get_worktree_color(old_path) {
+        save_worktree_color(new_path, &color)?;
+        if worktree_color_file(old_path) != worktree_color_file(new_path) {
+            remove_worktree_color(old_path);
+        }
+    }
+    move_file(&session_name_file(old_path), &session_name_file(new_path))?;
+    move_file(&worktree_iterm_session_file(old_path), &worktree_iterm_session_file(new_path))?;
+    move_worktree_note(old_path, new_path)?;
+
+    let claude_json = claude_json_path()?;
+    if let Ok(content) = fs::read_to_string(&claude_json) {
+        let mut data: Value =
+            serde_json::from_str(&content).map_err(|e| format!("Failed to parse .claude.json: {}", e))?;
+        if rename_claude_project(&mut data, old_paths, new_path) {
+            let content = serde_json::to_string_pretty(&data)
+                .map_err(|e| format!("Failed to serialize .claude.json: {}", e))?;
+            fs::write(&claude_json, content).map_err(|e| format!("Failed to write .claude.json: {}", e))?;
+        }
+    }
+
+    let data_dir = get_data_dir();
+    if let Some(mut checkout) = last_checkout::load(&data_dir)?.filter(|checkout| old_paths.contains(&checkout.path)) {
+        checkout.path = new_path.to_path_buf();
+        last_checkout::save(&data_dir, &checkout)?;
+    }
+    Ok(())
 }
 
-fn iterm_api_request(request: &Value) -> Result<ItermApiResponse, String> {
-    iterm_api_request_at(&iterm_api_socket_file(), request)
+/// Read everything `move_worktree_state` rewrites, so a file it couldn't
+/// parse stops `migrate` before any worktree moves rather than halfway.
+fn check_worktree_state() -> Result<(), String> {
+    if let Ok(content) = fs::read_to_string(claude_json_path()?) {
+        serde_json::from_str::<Value>(&content).map_err(|e| format!("Failed to parse .claude.json: {}", e))?;
+    }
+    last_checkout::load(&get_data_dir())?;
+    Ok(())
 }
 
-fn iterm_api_request_at(socket_path: &Path, request: &Value) -> Result<ItermApiResponse, String> {
-    let mut stream = UnixStream::connect(socket_path).map_err(|error| {
-        format!(
-            "iTerm Python API helper is unavailable: {}. Run checkout-pr/iterm/install.sh and restart iTerm2",
-            error
-        )
-    })?;
-    let timeout = Some(Duration::from_millis(500));
-    stream
-        .set_read_timeout(timeout)
-        .map_err(|error| format!("Failed to configure iTerm Python API helper: {}", error))?;
-    stream
-        .set_write_timeout(timeout)
-        .map_err(|error| format!("Failed to configure iTerm Python API helper: {}", error))?;
-    stream
-        .write_all(request.to_string().as_bytes())
-        .map_err(|error| format!("Failed to write to iTerm Python API helper: {}", error))?;
-    stream
-        .write_all(b"\n")
-        .map_err(|error| format!("Failed to write to iTerm Python API helper: {}", error))?;
-    let mut output = String::new();
-    stream
-        .read_to_string(&mut output)
-        .map_err(|error| format!("Failed to read from iTerm Python API helper: {}", error))?;
-    let response: ItermApiResponse = serde_json::from_str(output.trim())
-        .map_err(|error| format!("iTerm Python API helper returned invalid JSON: {}", error))?;
-    if !response.ok {
-        return Err(response
-            .error
-            .clone()
-            .unwrap_or_else(|| "iTerm Python API request failed".to_string()));
+/// Rename `from` to `to` if it exists and they differ
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if from == to || !from.exists() {
+        return Ok(());
     }
-    Ok(response)
+    fs::rename(from, to).map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
 }
 
-fn normalized_iterm_session_id(value: &str) -> Option<String> {
-    let id = value.rsplit_once(':').map_or(value, |(_, id)| id).trim();
-    (!id.is_empty()).then(|| id.to_string())
+/// Re-key the first `projects` entry found under one of `old_paths` to
+/// `new_path`, unless `new_path` already has one. Returns whether it changed.
+fn rename_claude_project(data: &mut Value, old_paths: &[PathBuf], new_path: &Path) -> bool {
+    let Some(projects) = data.get_mut("projects").and_then(Value::as_object_mut) else {
+        return false;
+    };
+    let new_key = new_path.to_string_lossy().into_owned();
+    if projects.contains_key(&new_key) {
+        return false;
+    }
+    let Some(entry) = old_paths.iter().find_map(|old| projects.remove(old.to_string_lossy().as_ref())) else {
+        return false;
+    };
+    projects.insert(new_key, entry);
+    true
 }
 
-fn save_iterm_session_id(path: PathBuf, session_id: &str) -> Result<(), String> {
-    let session_id = normalized_iterm_session_id(session_id)
-        .ok_or_else(|| "iTerm session ID is empty".to_string())?;
-    let dir = get_session_dir();
-    fs::create_dir_all(&dir)
-        .map_err(|error| format!("Failed to create session dir {}: {}", dir.display(), error))?;
-    fs::write(path, session_id)
-        .map_err(|error| format!("Failed to save iTerm session ID: {}", error))
+/// Carry a worktree's note and last-used time over to its new path after a move
+fn move_worktree_note(old_path: &Path, new_path: &Path) -> Result<(), String> {
+    metadata::update(&get_data_dir(), |metadata| {
+        if let Some(note) = metadata.notes.remove(&note_key(old_path)) {
+            metadata.notes.insert(note_key(new_path), note);
+        }
+        if let Some(last_used) = metadata.last_used.remove(&note_key(old_path)) {
+            metadata.last_used.insert(note_key(new_path), last_used);
+        }
+    })
 }
 
-fn read_iterm_session_id(path: PathBuf) -> Option<String> {
-    normalized_iterm_session_id(&fs::read_to_string(path).ok()?)
+fn remove_worktree_note(worktree_path: &Path) {
+    let _ = metadata::update(&get_data_dir(), |metadata| {
+        metadata.notes.remove(&note_key(worktree_path));
+        metadata.last_used.remove(&note_key(worktree_path));
+    });
 }
 
-fn save_worktree_iterm_session(worktree_path: &Path, session_id: &str) -> Result<(), String> {
-    save_iterm_session_id(worktree_iterm_session_file(worktree_path), session_id)
+/// Remember a PR's head branch, so its worktree can be put back on it later
+/// without asking GitHub, and the argument it was checked out with. A fork
+/// PR (`branch` None) has no branch on origin to remember.
+fn remember_pr(repo_root: &Path, pr_number: u64, branch: Option<&str>, input: Option<&str>) {
+    let key = metadata::pr_key(repo_root, pr_number);
+    let _ = metadata::update(&get_data_dir(), |metadata| {
+        match branch {
+            Some(branch) => metadata.pr_branches.insert(key.clone(), branch.to_string()),
+            None => metadata.pr_branches.remove(&key),
+        };
+        if let Some(input) = input {
+            let input = pr_input_to_keep(metadata.pr_inputs.get(&key).map(String::as_str), input);
+            metadata.pr_inputs.insert(key, input);
+        }
+    });
 }
 
-fn read_worktree_iterm_session(worktree_path: &Path) -> Option<String> {
-    read_iterm_session_id(worktree_iterm_session_file(worktree_path))
+/// The `checkout pr` argument to record: a later bare number says nothing
+/// new about where the PR lives, so it doesn't replace an earlier URL.
+fn pr_input_to_keep(recorded: Option<&str>, input: &str) -> String {
+    match recorded {
+        Some(recorded) if pr_input_url(input).is_none() && pr_input_url(recorded).is_some() => recorded.to_string(),
+        _ => input.trim().to_string(),
+    }
 }
 
-fn save_resource_iterm_session(
-    resource: &str,
-    identifier: &str,
-    repo_root: &Path,
-    session_id: &str,
-) -> Result<(), String> {
-    save_iterm_session_id(
-        resource_iterm_session_file(resource, identifier, repo_root),
-        session_id,
-    )
+/// The PR page for a `checkout pr` argument, if it was a URL. Bare numbers
+/// don't say which host or repo they belong to.
+fn pr_input_url(input: &str) -> Option<String> {
+    let caps = PR_URL.captures(input.trim())?;
+    Some(match caps.get(1) {
+        Some(_) => input.trim().to_string(),
+        None => format!("https://{}", input.trim()),
+    })
 }
 
-fn read_resource_iterm_session(resource: &str, identifier: &str, repo_root: &Path) -> Option<String> {
-    read_iterm_session_id(resource_iterm_session_file(resource, identifier, repo_root))
+/// Note that `path` was just opened or resumed, for `status` and `clean`'s
+/// "last used"
+fn touch_last_used(path: &Path) {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let _ = metadata::update(&get_data_dir(), |metadata| metadata.last_used.insert(note_key(path), now));
 }
 
-fn record_current_iterm_session(worktree_path: &Path) -> Result<(), String> {
-    let Ok(session_id) = env::var("ITERM_SESSION_ID") else {
-        return Ok(());
-    };
-    save_worktree_iterm_session(worktree_path, &session_id)
+/// The head branch `remember_pr` recorded at a PR's last checkout
+fn remembered_pr_branch(repo_root: &Path, pr_number: u64) -> Option<String> {
+    metadata::load(&get_data_dir()).pr_branches.remove(&metadata::pr_key(repo_root, pr_number))
 }
 
-fn save_session_name(worktree_path: &Path, name: &str) -> Result<(), String> {
-    let dir = get_session_dir();
-    fs::create_dir_all(&dir)
-        .map_err(|e| format!("Failed to create session dir {}: {}", dir.display(), e))?;
-    fs::write(session_name_file(worktree_path), name)
-        .map_err(|e| format!("Failed to save session name: {}", e))
+/// A PR's head branch on origin: as recorded at its last checkout, else
+/// from GitHub. Fork PRs have none.
+fn pr_head_branch(repo_root: &PathBuf, pr_number: u64) -> Result<String, String> {
+    if let Some(branch) = remembered_pr_branch(repo_root, pr_number) {
+        return Ok(branch);
+    }
+    let details = fetch_pr_details(pr_number, repo_root)?;
+    match details.origin_branch() {
+        Some(branch) => Ok(branch.to_string()),
+        None => Err(format!("PR #{} is from a fork, so its branch isn't on {}", pr_number, git::REMOTE)),
+    }
 }
 
-fn read_session_name(worktree_path: &Path) -> Option<String> {
-    let name = fs::read_to_string(session_name_file(worktree_path)).ok()?;
-    let name = name.trim();
-    (!name.is_empty()).then(|| name.to_string())
+#[derive(Debug, PartialEq)]
+enum Reattached {
+    /// Created the branch at the worktree's commit
+    Created,
+    /// The branch already existed at that commit
+    Switched,
 }
 
-/// Convert a git branch into the short, stable name shared by Codex and iTerm.
-/// Branch namespaces such as `darren/` or `dependabot/npm_and_yarn/` are dropped.
-fn session_name_from_branch(branch: &str) -> String {
-    let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
-    let branch = branch.strip_prefix("origin/").unwrap_or(branch);
-    let leaf = branch.rsplit('/').next().unwrap_or(branch);
+/// Check out `branch` in a detached `worktree` without moving HEAD, and
+/// make it track `origin/<branch>`. Refuses if `branch` exists elsewhere.
+fn reattach_pr_branch(worktree: &Path, branch: &str) -> Result<Reattached, String> {
+    let head = git::rev_parse(worktree, "HEAD")?;
+    let reattached = if git::local_branch_exists(worktree, branch)? {
+        if !git::ref_points_at(worktree, &format!("refs/heads/{}", branch), &head) {
+            return Err(format!("branch {} already exists at another commit", branch));
+        }
+        git::switch_to_branch(worktree, branch)?;
+        Reattached::Switched
+    } else {
+        git::create_branch_at_head(worktree, branch)?;
+        Reattached::Created
+    };
+    track_pr_branch(worktree, branch, branch)?;
+    Ok(reattached)
+}
 
-    let mut name = String::new();
-    let mut previous_was_separator = false;
-    for character in leaf.chars() {
-        if character.is_ascii_alphanumeric() {
-            name.push(character.to_ascii_lowercase());
-            previous_was_separator = false;
-        } else if !name.is_empty() && !previous_was_separator {
-            name.push('-');
-            previous_was_separator = true;
+fn run_fix_detached(repo: Option<PathBuf>, skip_confirm: bool) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let worktrees = get_all_worktrees(&repo_root)?;
+    let detached: Vec<(&WorktreeInfo, Result<String, String>)> = worktrees
+        .iter()
+        .filter(|wt| wt.branch == "(detached)")
+        .filter_map(|wt| ui::pr_number_from_dir(&worktree_dir_name(&wt.path)).map(|pr_number| (wt, pr_number)))
+        .map(|(wt, pr_number)| (wt, pr_head_branch(&repo_root, pr_number)))
+        .collect();
+
+    if detached.is_empty() {
+        println!("{} No detached PR worktrees", "→".blue().bold());
+        return Ok(());
+    }
+
+    println!("{} {} detached PR worktree(s):\n", "→".blue().bold(), detached.len());
+    for (wt, branch) in &detached {
+        match branch {
+            Ok(branch) => println!("  {} → {}", worktree_dir_name(&wt.path).cyan(), branch.yellow()),
+            Err(e) => println!("  {} {}", worktree_dir_name(&wt.path).cyan(), format!("(skipped: {})", e).dimmed()),
         }
     }
 
-    if name.len() > 25 {
-        let boundary_is_separator = name.as_bytes().get(25) == Some(&b'-');
-        name.truncate(25);
-        if !boundary_is_separator {
-            if let Some(separator) = name.rfind('-') {
-                name.truncate(separator);
-            }
+    if !skip_confirm {
+        print!("\n{} Check out these branches at each worktree's current commit? [y/N]: ", "?".magenta().bold());
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let input = read_confirmation()?;
+        if input.trim().to_lowercase() != "y" {
+            return Err(Interrupt::Declined.raise());
         }
     }
-    while name.ends_with('-') {
-        name.pop();
-    }
-    if name.is_empty() {
-        "worktree".to_string()
-    } else {
-        name
+
+    println!();
+    for (wt, branch) in &detached {
+        let Ok(branch) = branch else { continue };
+        print!("{} Checking out {} in {}... ", "→".blue().bold(), branch.yellow(), worktree_dir_name(&wt.path).cyan());
+        io::stdout().flush().ok();
+        match reattach_pr_branch(&wt.path, branch) {
+            Ok(Reattached::Created) => println!("{}", "done".green()),
+            Ok(Reattached::Switched) => println!("{} {}", "done".green(), "(existing branch)".dimmed()),
+            Err(e) => println!("{}\n    {} {}", "failed".red(), "error:".red(), e),
+        }
     }
+    Ok(())
 }
 
-fn session_name_for_resume(worktree_path: &Path, branch_hint: &str) -> String {
-    read_session_name(worktree_path).unwrap_or_else(|| session_name_from_branch(branch_hint))
-}
+fn run_update_all(repo: Option<PathBuf>) -> Result<(), String> {
+    timing!("run_update_all");
+    let repo_root = resolve_repo_root(repo);
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
 
-fn write_session_pid(worktree_path: &Path, pid: u32, agent: Agent) {
-    let dir = get_session_dir();
-    let _ = fs::create_dir_all(&dir);
-    let _ = fs::write(
-        session_pid_file(worktree_path),
-        format!("{}\n{}", pid, agent.command()),
-    );
-}
+    let worktrees = get_all_worktrees(&repo_root)?;
+    let mut updates: Vec<(PathBuf, String)> = Vec::new();
+    for wt in worktrees.iter() {
+        let name = worktree_dir_name(&wt.path);
+        let Some(pr_number) = ui::pr_number_from_dir(&name) else {
+            continue;
+        };
+        let skip_reason = if wt.has_active_session {
+            Some("active agent session".to_string())
+        } else {
+            local_work_summary(wt)
+        };
+        let branch = match skip_reason {
+            Some(reason) => Err(reason),
+            None => pr_head_branch(&repo_root, pr_number),
+        };
+        match branch {
+            Ok(branch) => updates.push((wt.path.clone(), branch)),
+            Err(reason) => println!("{} Skipping {} {}", "→".blue().bold(), name.cyan(), format!("({})", reason).dimmed()),
+        }
+    }
 
-fn remove_session_pid(worktree_path: &Path) {
-    let _ = fs::remove_file(session_pid_file(worktree_path));
-}
+    if updates.is_empty() {
+        println!("{} No clean PR worktrees to update", "→".blue().bold());
+        return Ok(());
+    }
 
-/// Remove the bazel output base directory for a worktree.
-/// Bazel stores its cache in /private/var/tmp/_bazel_<user>/<md5(workspace_path)>/,
-/// which can consume tens of GB per worktree and is never cleaned up automatically.
-fn remove_bazel_output_base(worktree_path: &Path) {
-    let path_str = worktree_path.to_string_lossy();
-    let hash = format!("{:x}", md5::compute(path_str.as_bytes()));
-    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
-    let output_base = PathBuf::from(format!("/private/var/tmp/_bazel_{}/{}", user, hash));
-    if !output_base.exists() {
-        return;
+    // Each worktree fetches its own branch, so the fetches run side by side
+    // (at most --parallel at once).
+    // Commits that aren't on origin are never dropped unattended.
+    print!("{} Updating {} PR worktree(s)... ", "→".blue().bold(), updates.len());
+    io::stdout().flush().ok();
+    let handles: Vec<_> = updates
+        .iter()
+        .cloned()
+        .map(|(path, branch)| {
+            thread::spawn(move || {
+                let _slot = batch_slot();
+                update_worktree(&path, Upstream::Branch(&branch), LocalCommits::Refuse, false)
+            })
+        })
+        .collect();
+    let results: Vec<Result<(), String>> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap_or_else(|_| Err("update panicked".to_string())))
+        .collect();
+    println!("{}", "done".green());
+
+    let mut failed = 0;
+    for ((path, branch), result) in updates.iter().zip(results) {
+        let name = worktree_dir_name(path);
+        match result {
+            Ok(()) => println!(
+                "  {} {} {} {}",
+                "✓".green().bold(),
+                name.cyan(),
+                format!("({})", branch).dimmed(),
+                git::short_sha(path, "HEAD").unwrap_or_default().dimmed()
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("  {} {} {}", "✗".red().bold(), name.cyan(), e.dimmed());
+            }
+        }
     }
-    // Bazel write-protects its `external/` repos, so a plain remove hits EACCES
-    // partway through. chmod the tree writable first, then shell out to `rm -rf`
-    // since the on-disk shape (symlinks, deep external repos) is more than
-    // `fs::remove_dir_all` wants to chase down.
-    let _ = Command::new("chmod")
-        .args(["-R", "u+w", &output_base.to_string_lossy()])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
-    let status = Command::new("rm")
-        .args(["-rf", &output_base.to_string_lossy()])
-        .status();
-    match status {
-        Ok(s) if s.success() => println!(
-            "    {} Removed bazel cache {}",
-            "→".blue().bold(),
-            output_base.display().to_string().dimmed()
-        ),
-        Ok(s) => println!(
-            "    {} Failed to remove bazel cache: rm exited with {}",
-            "⚠".yellow(),
-            s.to_string().dimmed()
-        ),
-        Err(e) => println!(
-            "    {} Failed to remove bazel cache: {}",
-            "⚠".yellow(),
-            e.to_string().dimmed()
-        ),
+    if failed > 0 {
+        return Err(format!("Failed to update {} worktree(s)", failed));
     }
+    Ok(())
 }
 
-fn write_session_exited(worktree_path: &Path, agent: Agent) {
-    let dir = get_session_dir();
-    let _ = fs::create_dir_all(&dir);
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let clean = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "status", "--short"])
-        .output()
-        .map(|o| String::from_utf8_lossy(&o.stdout).trim().is_empty())
-        .unwrap_or(false);
-    let _ = fs::write(
-        session_exited_file(worktree_path),
-        format!(
-            "{}\n{}\n{}\n{}",
-            timestamp,
-            worktree_path.display(),
-            if clean { "clean" } else { "dirty" },
-            agent.command()
-        ),
-    );
-}
+/// What `rename_worktree_branch` did besides `git branch -m`
+#[derive(Debug, PartialEq)]
+struct RenamedBranch {
+    old: String,
+    /// Pushed under the new name, which is now the upstream
+    pushed: bool,
+    /// The upstream, left alone because it isn't named after the branch
+    /// (e.g. a PR branch checked out under a local name)
+    kept_upstream: Option<String>,
+}
+
+/// Rename the branch checked out in `worktree` to `new`. If it was pushed
+/// under its own name, push it under `new` as well and track that; the old
+/// remote branch is left for the caller to report, since deleting it would
+/// close any PR open from it.
+fn rename_worktree_branch(worktree: &Path, new: &str) -> Result<RenamedBranch, String> {
+    let Some(old) = git::current_branch(worktree)? else {
+        return Err(format!("{} is on a detached HEAD; there is no branch to rename", worktree.display()));
+    };
+    if git::local_branch_exists(worktree, new)? {
+        return Err(format!("A branch named {} already exists", new));
+    }
+    let upstream = git::upstream(worktree, &old)?;
+    git::rename_branch(worktree, &old, new)?;
 
-fn read_session_pid(worktree_path: &Path) -> Option<u32> {
-    fs::read_to_string(session_pid_file(worktree_path))
-        .ok()?
-        .lines()
-        .next()?
-        .trim()
-        .parse()
-        .ok()
+    let (pushed, kept_upstream) = match upstream {
+        Some(upstream) if upstream.remote == git::REMOTE && upstream.branch == old => {
+            git::push_set_upstream(worktree, git::REMOTE, new)?;
+            (true, None)
+        }
+        Some(upstream) => (false, Some(format!("{}/{}", upstream.remote, upstream.branch))),
+        None => (false, None),
+    };
+    Ok(RenamedBranch { old, pushed, kept_upstream })
 }
 
-fn read_session_agent(worktree_path: &Path) -> Option<Agent> {
-    fs::read_to_string(session_pid_file(worktree_path))
-        .ok()?
-        .lines()
-        .nth(1)
-        .and_then(Agent::parse)
-}
+fn run_rename_branch(query: &str, new: &str, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let worktree = resolve_worktree_query(&repo_root, query)?;
+    if git::current_branch(&worktree)?.as_deref() == Some(new) {
+        println!("{} {} is already on {}", "→".blue().bold(), worktree_dir_name(&worktree).cyan(), new.yellow());
+        return Ok(());
+    }
 
-#[derive(Debug, Eq, PartialEq)]
-struct ExitedSession {
-    timestamp: u64,
-    worktree_path: PathBuf,
-    clean: bool,
-    agent: Agent,
-}
+    let renamed = rename_worktree_branch(&worktree, new)?;
 
-fn parse_exited_session(content: &str) -> Option<ExitedSession> {
-    let mut lines = content.lines();
-    let timestamp = lines.next()?.trim().parse().ok()?;
-    let worktree_path = PathBuf::from(lines.next()?.trim());
-    let clean = lines.next()?.trim() == "clean";
-    // Markers written before multi-agent support could only have come from Claude.
-    let agent = lines.next().and_then(Agent::parse).unwrap_or(Agent::Claude);
-    Some(ExitedSession {
-        timestamp,
-        worktree_path,
-        clean,
-        agent,
-    })
+    // The remembered color follows the branch
+    let _ = metadata::update(&get_data_dir(), |metadata| {
+        if let Some(color) = metadata.branch_colors.remove(&metadata::branch_key(&repo_root, &renamed.old)) {
+            metadata.branch_colors.insert(metadata::branch_key(&repo_root, new), color);
+        }
+    });
+
+    println!(
+        "{} Renamed {} to {} in {}",
+        "✓".green().bold(),
+        renamed.old.yellow(),
+        new.yellow(),
+        worktree_dir_name(&worktree).cyan()
+    );
+    if renamed.pushed {
+        println!("{} Pushed {} and set it as the upstream", "✓".green().bold(), format!("{}/{}", git::REMOTE, new).cyan());
+        println!(
+            "{} {} still exists; delete it with {} once no open PR uses it",
+            "!".yellow().bold(),
+            format!("{}/{}", git::REMOTE, renamed.old).cyan(),
+            format!("git push {} --delete {}", git::REMOTE, renamed.old).cyan()
+        );
+    } else if let Some(upstream) = renamed.kept_upstream {
+        println!("{} Still tracking {}", "→".blue().bold(), upstream.cyan());
+    }
+    Ok(())
 }
 
-fn is_pid_alive(pid: u32) -> bool {
-    Command::new("kill")
-        .args(["-0", &pid.to_string()])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map(|s| s.success())
-        .unwrap_or(false)
+/// Set the worktree's note, or clear it when `text` is `None`
+fn run_note(query: &str, text: Option<&str>, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let worktree = resolve_worktree_query(&repo_root, query)?;
+    let name = worktree_dir_name(&worktree);
+    // Notes are one-liners
+    let text = text.map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "));
+    let previous = metadata::update(&get_data_dir(), |metadata| match &text {
+        Some(text) => metadata.notes.insert(note_key(&worktree), text.clone()),
+        None => metadata.notes.remove(&note_key(&worktree)),
+    })?;
+    match (text, previous) {
+        (Some(text), _) => println!("{} Noted {}: {}", "✓".green().bold(), name.cyan(), text.dimmed()),
+        (None, Some(_)) => println!("{} Cleared the note on {}", "✓".green().bold(), name.cyan()),
+        (None, None) => println!("{} {} has no note", "→".blue().bold(), name.cyan()),
+    }
+    Ok(())
 }
 
-/// RAII guard that removes the PID file on drop (normal exit or panic).
-/// Ctrl+C bypasses Drop, but stale PID files are self-healing: the next
-/// `status`/`clean` run detects the dead PID and cleans up.
-struct PidFileGuard {
-    worktree_path: PathBuf,
-    agent: Agent,
+/// `text` cut to `max` characters, ending in `…` when shortened
+fn truncate_chars(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
 }
 
-impl PidFileGuard {
-    fn new(worktree_path: &Path, pid: u32, agent: Agent) -> Self {
-        write_session_pid(worktree_path, pid, agent);
-        // Clear the exited marker so this worktree isn't considered idle
-        let _ = fs::remove_file(session_exited_file(worktree_path));
-        // Register so the ctrlc handler can clean up and kill the child
-        if let Ok(mut guard) = ACTIVE_WORKTREE.lock() {
-            *guard = Some(worktree_path.to_path_buf());
-        }
-        if let Ok(mut guard) = ACTIVE_CHILD_PID.lock() {
-            *guard = Some(pid);
-        }
-        if let Ok(mut guard) = ACTIVE_AGENT.lock() {
-            *guard = Some(agent);
+fn pick_available_color(current_worktree: &Path, key: Option<(&Path, &ColorKey)>, seed: Option<&str>) -> String {
+    if let Some(existing) = get_worktree_color(current_worktree) {
+        return existing;
+    }
+    if let Some(seed) = seed {
+        return seeded_color(seed).to_string();
+    }
+
+    let used = get_used_colors();
+
+    // Prefer the color this PR/branch had last time, unless a live worktree
+    // has claimed it since.
+    if let Some(remembered) = key.and_then(|(repo_root, key)| remembered_color(repo_root, key)) {
+        if !used.contains(&remembered) {
+            return remembered;
         }
-        Self {
-            worktree_path: worktree_path.to_path_buf(),
-            agent,
+    }
+
+    for color in COLOR_PALETTE {
+        if !used.contains(*color) {
+            return color.to_string();
         }
     }
+
+    let hash = current_worktree.to_string_lossy().bytes().fold(0usize, |acc, b| acc.wrapping_add(b as usize));
+    COLOR_PALETTE[hash % COLOR_PALETTE.len()].to_string()
 }
 
-impl Drop for PidFileGuard {
-    fn drop(&mut self) {
-        // Clear the globals first so ctrlc handler won't double-write
-        if let Ok(mut guard) = ACTIVE_WORKTREE.lock() {
-            *guard = None;
-        }
-        if let Ok(mut guard) = ACTIVE_CHILD_PID.lock() {
-            *guard = None;
-        }
-        if let Ok(mut guard) = ACTIVE_AGENT.lock() {
-            *guard = None;
+/// The palette color `seed` hashes to, so e.g. every PR by one author gets
+/// the same one whether or not another worktree already shows it.
+fn seeded_color(seed: &str) -> &'static str {
+    let digest = md5::compute(seed.as_bytes());
+    let hash = u64::from_le_bytes(digest.0[..8].try_into().unwrap_or_default());
+    COLOR_PALETTE[(hash % COLOR_PALETTE.len() as u64) as usize]
+}
+
+/// Pick a color for the worktree, persist it, and remember it for the PR or
+/// branch in its repo. Failing to remember is only worth a warning. With a
+/// `seed` (see `--worktree-color-by`), a new worktree's color comes from
+/// hashing it.
+fn assign_worktree_color(
+    worktree_path: &Path,
+    key: Option<(&Path, &ColorKey)>,
+    seed: Option<&str>,
+) -> Result<String, String> {
+    let bg_color = pick_available_color(worktree_path, key, seed);
+    save_worktree_color(worktree_path, &bg_color)?;
+    // An uncolored worktree shouldn't make the PR or branch uncolored for good
+    if let Some((repo_root, key)) = key.filter(|_| bg_color != terminal::NO_COLOR) {
+        if let Err(e) = remember_color(repo_root, key, &bg_color) {
+            eprintln!("{} Failed to remember worktree color: {}", "⚠".yellow(), e);
         }
-        remove_session_pid(&self.worktree_path);
-        write_session_exited(&self.worktree_path, self.agent);
     }
+    Ok(bg_color)
 }
 
-fn default_repo_root() -> PathBuf {
-    PathBuf::from(env::var("CHECKOUT_REPO").expect("CHECKOUT_REPO env var must be set"))
+fn get_session_dir() -> PathBuf {
+    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(format!("{}/.local/share/checkout/sessions", home))
 }
 
-fn default_worktree_dir() -> PathBuf {
-    PathBuf::from(env::var("CHECKOUT_WORKTREE_DIR").expect("CHECKOUT_WORKTREE_DIR env var must be set"))
+fn session_file_name(worktree_path: &Path) -> String {
+    worktree_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
 }
 
-fn run() -> Result<(), String> {
-    let cli = Cli::parse();
-    let agent = cli.agent;
-
-    if cli.timings {
-        TIMINGS_ENABLED.store(true, Ordering::Relaxed);
-    }
+fn session_pid_file(worktree_path: &Path) -> PathBuf {
+    get_session_dir().join(format!("{}.pid", session_file_name(worktree_path)))
+}
 
-    match cli.command {
-        Commands::Pr { pr, no_agent, repo, skill, resume_existing } => {
-            let initial_skill = agent.skill("/checkout:checkout-pr", "$checkout-pr");
-            let chained_skill = skill.as_deref().map(|skill| normalize_skill(agent, skill));
-            run_pr(&pr, no_agent, repo, initial_skill, chained_skill.as_deref(), agent, resume_existing)
-        },
-        Commands::Open { target } => match target {
-            OpenTarget::Pr { pr, repo, json } => run_open_pr(&pr, repo, json, agent),
-            OpenTarget::Statsig { gate, repo, json } => run_open_statsig(&gate, repo, json, agent),
-            OpenTarget::Workspace { repo, json } => run_open_workspace(repo, json, agent),
-        },
-        Commands::Session { target } => match target {
-            SessionTarget::Pr { pr, branch, repo, json } => {
-                run_session_pr(&pr, branch.as_deref(), repo, json)
-            }
-            SessionTarget::Statsig { gate, repo, json } => run_session_statsig(&gate, repo, json),
-            SessionTarget::Workspace { repo, register_current, json } => {
-                run_session_workspace(repo, register_current, json)
-            }
-        },
-        Commands::Statsig { gate, repo, resume_existing } => run_statsig(&gate, repo, agent, resume_existing),
-        Commands::Walkthrough { pr, no_agent, repo } => run_pr(
-            &pr,
-            no_agent,
-            repo,
-            agent.skill("/checkout:checkout-pr", "$checkout-pr"),
-            Some(agent.skill("/walkthrough", "$walkthrough")),
-            agent,
-            false,
-        ),
-        Commands::Review { pr, no_agent, repo } => run_pr(
-            &pr,
-            no_agent,
-            repo,
-            agent.skill("/checkout:checkout-and-review-pr", "$checkout-and-review-pr"),
-            None,
-            agent,
-            false,
-        ),
-        Commands::Branch { name, no_agent, prompt, repo } => {
-            let prompt = read_prompt_file(prompt)?;
-            run_branch(&name, no_agent, prompt, repo, agent, false)
-        },
-        Commands::New { no_agent, prompt, repo } => {
-            let prompt = read_prompt_file(prompt)?;
-            run_new(no_agent, prompt, repo, agent)
-        },
-        Commands::Begin { repo } => run_new(
-            false,
-            Some(agent.skill("/darren:workstream-begin sandbox", "$darren-workstream-begin sandbox").to_string()),
-            repo,
-            agent,
-        ),
-        Commands::Status { repo } => run_status(repo),
-        Commands::Clean { repo, yes } => run_clean(repo, yes),
-        Commands::Resume { repo } => run_resume(repo),
-        Commands::ResumeLast { repo } => run_resume_last(repo, agent),
-    }
+fn session_exited_file(worktree_path: &Path) -> PathBuf {
+    get_session_dir().join(format!("{}.exited", session_file_name(worktree_path)))
 }
 
-fn normalize_skill(agent: Agent, skill: &str) -> String {
-    if agent == Agent::Codex {
-        if let Some(name) = skill.strip_prefix('/') {
-            let name = name.strip_prefix("checkout:").unwrap_or(name);
-            return format!("${}", name.replace(':', "-"));
-        }
-    }
-    skill.to_string()
+fn session_name_file(worktree_path: &Path) -> PathBuf {
+    get_session_dir().join(format!("{}.name", session_file_name(worktree_path)))
 }
 
-fn read_prompt_file(path: Option<PathBuf>) -> Result<Option<String>, String> {
-    match path {
-        Some(p) => {
-            let content = fs::read_to_string(&p)
-                .map_err(|e| format!("Failed to read prompt file {}: {}", p.display(), e))?;
-            Ok(Some(content))
-        }
-        None => Ok(None),
-    }
+fn worktree_iterm_session_file(worktree_path: &Path) -> PathBuf {
+    let digest = format!("{:x}", md5::compute(worktree_path.to_string_lossy().as_bytes()));
+    get_session_dir().join(format!("worktree-{}.iterm", digest))
 }
 
-fn shell_quote(value: &str) -> String {
-    format!("'{}'", value.replace('\'', "'\"'\"'"))
+fn resource_iterm_session_file(resource: &str, identifier: &str, repo_root: &Path) -> PathBuf {
+    let key = format!("{}:{}:{}", repo_root.display(), resource, identifier);
+    let digest = format!("{:x}", md5::compute(key.as_bytes()));
+    get_session_dir().join(format!("resource-{}.iterm", digest))
 }
 
-fn statsig_slug(gate: &str) -> String {
-    let mut slug = gate
-        .chars()
-        .map(|character| if character.is_ascii_alphanumeric() { character.to_ascii_lowercase() } else { '-' })
-        .collect::<String>();
-    slug = Regex::new(r"-+").unwrap().replace_all(&slug, "-").trim_matches('-').to_string();
-    if slug.is_empty() {
-        slug = "gate".to_string();
-    }
-    if slug.len() > 17 {
-        let digest = format!("{:x}", md5::compute(gate.as_bytes()));
-        slug.truncate(10);
-        while slug.ends_with('-') {
-            slug.pop();
-        }
-        slug.push('-');
-        slug.push_str(&digest[..6]);
+fn iterm_api_socket_file() -> PathBuf {
+    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(format!("{}/.local/share/checkout/iterm-api.sock", home))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ItermApiResponse {
+    ok: bool,
+    exists: Option<bool>,
+    session_id: Option<String>,
+    action: Option<String>,
+    error: Option<String>,
+}
+
+fn iterm_api_request(request: &Value) -> Result<ItermApiResponse, String> {
+    iterm_api_request_at(&iterm_api_socket_file(), request)
+}
+
+fn iterm_api_request_at(socket_path: &Path, request: &Value) -> Result<ItermApiResponse, String> {
+    let mut stream = UnixStream::connect(socket_path).map_err(|error| {
+        format!(
+            "iTerm Python API helper is unavailable: {}. Run checkout-pr/iterm/install.sh and restart iTerm2",
+            error
+        )
+    })?;
+    let timeout = Some(Duration::from_millis(500));
+    stream
+        .set_read_timeout(timeout)
+        .map_err(|error| format!("Failed to configure iTerm Python API helper: {}", error))?;
+    stream
+        .set_write_timeout(timeout)
+        .map_err(|error| format!("Failed to configure iTerm Python API helper: {}", error))?;
+    stream
+        .write_all(request.to_string().as_bytes())
+        .map_err(|error| format!("Failed to write to iTerm Python API helper: {}", error))?;
+    stream
+        .write_all(b"\n")
+        .map_err(|error| format!("Failed to write to iTerm Python API helper: {}", error))?;
+    let mut output = String::new();
+    stream
+        .read_to_string(&mut output)
+        .map_err(|error| format!("Failed to read from iTerm Python API helper: {}", error))?;
+    let response: ItermApiResponse = serde_json::from_str(output.trim())
+        .map_err(|error| format!("iTerm Python API helper returned invalid JSON: {}", error))?;
+    if !response.ok {
+        return Err(response
+            .error
+            .clone()
+            .unwrap_or_else(|| "iTerm Python API request failed".to_string()));
     }
-    slug
+    Ok(response)
 }
 
-fn statsig_branch_name(gate: &str) -> String {
-    format!("darren/statsig-{}", statsig_slug(gate))
+fn normalized_iterm_session_id(value: &str) -> Option<String> {
+    let id = value.rsplit_once(':').map_or(value, |(_, id)| id).trim();
+    (!id.is_empty()).then(|| id.to_string())
 }
 
-fn checkout_launch_command(
-    resource: &str,
-    identifier: &str,
-    repo_root: &Path,
-    agent: Agent,
-) -> Result<String, String> {
-    let executable = env::current_exe()
-        .map_err(|error| format!("Failed to locate checkout executable: {}", error))?;
-    Ok([
-        shell_quote(&executable.to_string_lossy()),
-        resource.to_string(),
-        shell_quote(identifier),
-        "--resume-existing".to_string(),
-        "--repo".to_string(),
-        shell_quote(&repo_root.to_string_lossy()),
-        "--agent".to_string(),
-        agent.command().to_string(),
-    ].join(" "))
+fn save_iterm_session_id(path: PathBuf, session_id: &str) -> Result<(), String> {
+    let session_id = normalized_iterm_session_id(session_id)
+        .ok_or_else(|| "iTerm session ID is empty".to_string())?;
+    let dir = get_session_dir();
+    fs::create_dir_all(&dir)
+        .map_err(|error| format!("Failed to create session dir {}: {}", dir.display(), error))?;
+    fs::write(path, session_id)
+        .map_err(|error| format!("Failed to save iTerm session ID: {}", error))
 }
 
-struct ItermOpenResult {
-    action: String,
-    session_id: String,
+fn read_iterm_session_id(path: PathBuf) -> Option<String> {
+    normalized_iterm_session_id(&fs::read_to_string(path).ok()?)
 }
 
-fn find_live_iterm_session(
-    resource_session_id: Option<&str>,
-    worktree_session_id: Option<&str>,
-    session_name: &str,
-    legacy_prefix: Option<&str>,
-) -> Result<Option<String>, String> {
-    let response = iterm_api_request(&serde_json::json!({
-        "action": "status",
-        "sessionIds": [
-            resource_session_id.unwrap_or(""),
-            worktree_session_id.unwrap_or(""),
-        ],
-        "sessionName": session_name,
-        "legacyPrefix": legacy_prefix.unwrap_or(""),
-    }))?;
-    if response.exists != Some(true) {
-        return Ok(None);
-    }
-    let session_id = response
-        .session_id
-        .and_then(|value| normalized_iterm_session_id(&value))
-        .ok_or_else(|| "iTerm Python API helper returned an empty session ID".to_string())?;
-    Ok(Some(session_id))
+fn save_worktree_iterm_session(worktree_path: &Path, session_id: &str) -> Result<(), String> {
+    save_iterm_session_id(worktree_iterm_session_file(worktree_path), session_id)
 }
 
-fn focus_or_open_iterm(
-    resource_session_id: Option<&str>,
-    worktree_session_id: Option<&str>,
-    session_name: &str,
-    legacy_prefix: Option<&str>,
-    launch_command: &str,
-) -> Result<ItermOpenResult, String> {
-    let response = iterm_api_request(&serde_json::json!({
-        "action": "open",
-        "sessionIds": [
-            resource_session_id.unwrap_or(""),
-            worktree_session_id.unwrap_or(""),
-        ],
-        "sessionName": session_name,
-        "legacyPrefix": legacy_prefix.unwrap_or(""),
-        "launchCommand": launch_command,
-    }))?;
-    let action = response
-        .action
-        .filter(|action| action == "focused" || action == "opened")
-        .ok_or_else(|| "iTerm Python API helper returned an invalid action".to_string())?;
-    let session_id = response
-        .session_id
-        .and_then(|value| normalized_iterm_session_id(&value))
-        .ok_or_else(|| "iTerm Python API helper returned an empty session ID".to_string())?;
-    Ok(ItermOpenResult { action, session_id })
+fn read_worktree_iterm_session(worktree_path: &Path) -> Option<String> {
+    read_iterm_session_id(worktree_iterm_session_file(worktree_path))
 }
 
-fn print_open_result(
-    json: bool,
-    action: &str,
+fn save_resource_iterm_session(
     resource: &str,
     identifier: &str,
-    session_name: &str,
+    repo_root: &Path,
     session_id: &str,
-) {
-    if json {
-        println!("{}", serde_json::json!({
-            "action": action,
-            "resourceType": resource,
-            "resourceId": identifier,
-            "sessionName": session_name,
-            "sessionId": session_id,
-        }));
-    } else {
-        let verb = if action == "focused" { "Focused" } else { "Opened" };
-        println!("{} {} {} in iTerm", "→".blue().bold(), verb, session_name.cyan());
-    }
+) -> Result<(), String> {
+    save_iterm_session_id(
+        resource_iterm_session_file(resource, identifier, repo_root),
+        session_id,
+    )
 }
 
-fn print_session_result(
-    json: bool,
-    session_id: Option<&str>,
-    resource: &str,
-    identifier: &str,
-    session_name: &str,
-) {
-    let exists = session_id.is_some();
-    if json {
-        println!("{}", serde_json::json!({
-            "exists": exists,
-            "resourceType": resource,
-            "resourceId": identifier,
-            "sessionName": session_name,
-            "sessionId": session_id,
-        }));
-    } else {
-        let state = if exists { "Live".green() } else { "Not running".dimmed() };
-        println!("{} {} {}", "→".blue().bold(), session_name.cyan(), state);
-    }
+fn read_resource_iterm_session(resource: &str, identifier: &str, repo_root: &Path) -> Option<String> {
+    read_iterm_session_id(resource_iterm_session_file(resource, identifier, repo_root))
 }
 
-fn workspace_session_name(repo_root: &Path) -> String {
-    let name = repo_root.file_name().and_then(|value| value.to_str()).unwrap_or("workspace");
-    session_name_from_branch(name)
+fn record_current_iterm_session(worktree_path: &Path) -> Result<(), String> {
+    let Ok(session_id) = env::var("ITERM_SESSION_ID") else {
+        return Ok(());
+    };
+    save_worktree_iterm_session(worktree_path, &session_id)
 }
 
-fn workspace_launch_command(repo_root: &Path, agent: Agent) -> String {
-    let agent_command = match agent {
-        Agent::Codex => "codex resume --last || codex",
-        Agent::Claude => "claude --continue || claude",
-    };
-    format!("cd {} && {}", shell_quote(&repo_root.to_string_lossy()), agent_command)
+fn save_session_name(worktree_path: &Path, name: &str) -> Result<(), String> {
+    let dir = get_session_dir();
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create session dir {}: {}", dir.display(), e))?;
+    fs::write(session_name_file(worktree_path), name)
+        .map_err(|e| format!("Failed to save session name: {}", e))
 }
 
-fn save_live_session(
-    resource: &str,
-    identifier: &str,
-    repo_root: &Path,
-    worktree: Option<&Path>,
-    session_id: &str,
-) -> Result<(), String> {
-    save_resource_iterm_session(resource, identifier, repo_root, session_id)?;
-    if let Some(worktree) = worktree {
-        save_worktree_iterm_session(worktree, session_id)?;
-    }
-    Ok(())
+fn read_session_name(worktree_path: &Path) -> Option<String> {
+    let name = fs::read_to_string(session_name_file(worktree_path)).ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
 }
 
-fn clear_stale_session(resource: &str, identifier: &str, repo_root: &Path, worktree: Option<&Path>) {
-    let _ = fs::remove_file(resource_iterm_session_file(resource, identifier, repo_root));
-    if let Some(worktree) = worktree {
-        let _ = fs::remove_file(worktree_iterm_session_file(worktree));
-    }
+/// Directory name of the main repo, used as `{repo}` in `title_template`.
+fn repo_slug(repo_root: &Path) -> String {
+    repo_root
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "repo".to_string())
 }
 
-fn session_status(
-    resource: &str,
-    identifier: &str,
-    repo_root: &Path,
-    worktree: Option<&Path>,
-    session_name: &str,
-    legacy_prefix: Option<&str>,
-) -> Result<Option<String>, String> {
-    let resource_session_id = read_resource_iterm_session(resource, identifier, repo_root);
-    let worktree_session_id = worktree.and_then(read_worktree_iterm_session);
-    let session_id = find_live_iterm_session(
-        resource_session_id.as_deref(),
-        worktree_session_id.as_deref(),
-        session_name,
-        legacy_prefix,
-    )?;
-    if let Some(session_id) = session_id {
-        save_live_session(resource, identifier, repo_root, worktree, &session_id)?;
-        return Ok(Some(session_id));
-    }
-    clear_stale_session(resource, identifier, repo_root, worktree);
-    Ok(None)
+fn render_title(template: &str, name: &str, repo: &str) -> String {
+    template.replace("{name}", name).replace("{repo}", repo)
 }
 
-fn find_pr_worktree(
-    repo_root: &PathBuf,
-    pr_number: u64,
-    branch: &str,
-) -> Result<Option<PathBuf>, String> {
-    let branch_slug = branch.rsplit('/').next().unwrap_or(branch);
-    Ok(find_existing_worktree(repo_root, &format!("branch-{}", branch_slug))?
-        .or(find_existing_worktree(repo_root, &format!("pr-{}-", pr_number))?)
-        .or(find_existing_worktree(repo_root, &format!("[{}]", branch))?))
+/// Terminal title for a session, from the `title_template` config (default
+/// `{name}`). iTerm tab lookups by title use the same string.
+fn terminal_title(session_name: &str, repo_root: &Path) -> String {
+    let template = config::get().title_template.as_deref().unwrap_or("{name}");
+    render_title(template, session_name, &repo_slug(repo_root))
 }
 
-fn find_branch_worktree(repo_root: &PathBuf, branch: &str) -> Result<Option<PathBuf>, String> {
-    let slug = branch.rsplit('/').next().unwrap_or(branch);
-    Ok(find_existing_worktree(repo_root, &format!("branch-{}", slug))?
-        .or(find_existing_worktree(repo_root, &format!("[{}]", branch))?))
+/// What agent sessions change in the terminal: the title is skipped with
+/// `--no-spinner-title` or `set_title = false`, and `--keep-color-on-exit`
+/// leaves the background set afterwards.
+fn terminal_guard_options(checkout: &CheckoutArgs) -> terminal::GuardOptions {
+    terminal::GuardOptions {
+        title: !checkout.no_spinner_title && config::get().set_title.unwrap_or(true),
+        keep_background: checkout.keep_color_on_exit,
+        ..terminal::GuardOptions::default()
+    }
 }
 
-fn run_open_pr(pr: &str, repo: Option<PathBuf>, json: bool, agent: Agent) -> Result<(), String> {
-    let pr_number = extract_pr_number(pr)?;
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+/// Run the `on_enter_script` config hook, if set, just before an agent starts
+/// in `worktree_path`. Failures are reported but don't stop the checkout.
+fn run_on_enter_script(worktree_path: &Path, bg_color: &str, session_name: &str) {
+    let Some(script) = config::get().on_enter_script.as_deref() else {
+        return;
+    };
+    if let Err(e) = run_enter_script(&config::expand_tilde(script), worktree_path, bg_color, session_name) {
+        println!("{} {}", "⚠".yellow().bold(), e);
     }
-    let details = fetch_pr_details(pr_number, &repo_root)?;
-    let session_name = session_name_from_branch(&details.head_ref_name);
-    let identifier = pr_number.to_string();
-    let existing_worktree = find_pr_worktree(&repo_root, pr_number, &details.head_ref_name)?;
-    let resource_session_id = read_resource_iterm_session("pr", &identifier, &repo_root);
-    let worktree_session_id = existing_worktree.as_deref().and_then(read_worktree_iterm_session);
-    let command = checkout_launch_command("pr", &identifier, &repo_root, agent)?;
-    let legacy_prefix = format!("pr-{}-", pr_number);
-    let result = focus_or_open_iterm(
-        resource_session_id.as_deref(),
-        worktree_session_id.as_deref(),
-        &session_name,
-        Some(&legacy_prefix),
-        &command,
-    )?;
-    save_resource_iterm_session("pr", &identifier, &repo_root, &result.session_id)?;
-    if let Some(worktree) = existing_worktree {
-        save_worktree_iterm_session(&worktree, &result.session_id)?;
+}
+
+/// The script runs in the worktree with the terminal attached, so it can
+/// print its own escape sequences or prompt tweaks.
+fn run_enter_script(script: &Path, worktree_path: &Path, bg_color: &str, session_name: &str) -> Result<(), String> {
+    let status = Command::new(script)
+        .current_dir(worktree_path)
+        .env("CHECKOUT_WORKTREE", worktree_path)
+        .env("CHECKOUT_COLOR", bg_color)
+        .env("CHECKOUT_SESSION_NAME", session_name)
+        .traced_status()
+        .map_err(|e| format!("Failed to run on_enter_script {}: {}", script.display(), e))?;
+    if !status.success() {
+        return Err(format!("on_enter_script {} exited with {}", script.display(), status));
     }
-    print_open_result(
-        json,
-        &result.action,
-        "pr",
-        &identifier,
-        &session_name,
-        &result.session_id,
-    );
     Ok(())
 }
 
-fn run_open_statsig(gate: &str, repo: Option<PathBuf>, json: bool, agent: Agent) -> Result<(), String> {
-    let gate = gate.trim();
-    if gate.is_empty() {
-        return Err("Statsig gate name is required".to_string());
+/// Convert a git branch into the short, stable name shared by Codex and iTerm.
+/// Branch namespaces such as `darren/` or `dependabot/npm_and_yarn/` are dropped.
+fn session_name_from_branch(branch: &str) -> String {
+    let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+    let branch = branch.strip_prefix("origin/").unwrap_or(branch);
+    let leaf = branch.rsplit('/').next().unwrap_or(branch);
+
+    let mut name = String::new();
+    let mut previous_was_separator = false;
+    for character in leaf.chars() {
+        if character.is_ascii_alphanumeric() {
+            name.push(character.to_ascii_lowercase());
+            previous_was_separator = false;
+        } else if !name.is_empty() && !previous_was_separator {
+            name.push('-');
+            previous_was_separator = true;
+        }
     }
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+
+    if name.len() > 25 {
+        let boundary_is_separator = name.as_bytes().get(25) == Some(&b'-');
+        name.truncate(25);
+        if !boundary_is_separator {
+            if let Some(separator) = name.rfind('-') {
+                name.truncate(separator);
+            }
+        }
     }
-    let branch = statsig_branch_name(gate);
-    let session_name = session_name_from_branch(&branch);
-    let existing_worktree = find_branch_worktree(&repo_root, &branch)?;
-    let resource_session_id = read_resource_iterm_session("statsig", gate, &repo_root);
-    let worktree_session_id = existing_worktree.as_deref().and_then(read_worktree_iterm_session);
-    let command = checkout_launch_command("statsig", gate, &repo_root, agent)?;
-    let result = focus_or_open_iterm(
-        resource_session_id.as_deref(),
-        worktree_session_id.as_deref(),
-        &session_name,
-        None,
-        &command,
-    )?;
-    save_resource_iterm_session("statsig", gate, &repo_root, &result.session_id)?;
-    if let Some(worktree) = existing_worktree {
-        save_worktree_iterm_session(&worktree, &result.session_id)?;
+    while name.ends_with('-') {
+        name.pop();
+    }
+    if name.is_empty() {
+        "worktree".to_string()
+    } else {
+        name
     }
-    print_open_result(
-        json,
-        &result.action,
-        "statsig",
-        gate,
-        &session_name,
-        &result.session_id,
-    );
-    Ok(())
 }
 
-fn run_open_workspace(repo_root: PathBuf, json: bool, agent: Agent) -> Result<(), String> {
-    if !repo_root.exists() {
-        return Err(format!("Workspace not found at {}", repo_root.display()));
-    }
-    let identifier = "development";
-    let session_name = workspace_session_name(&repo_root);
-    let resource_session_id = read_resource_iterm_session("workspace", identifier, &repo_root);
-    let worktree_session_id = read_worktree_iterm_session(&repo_root);
-    let command = workspace_launch_command(&repo_root, agent);
-    let result = focus_or_open_iterm(
-        resource_session_id.as_deref(),
-        worktree_session_id.as_deref(),
-        &session_name,
-        None,
-        &command,
-    )?;
-    save_live_session("workspace", identifier, &repo_root, Some(&repo_root), &result.session_id)?;
-    print_open_result(
-        json,
-        &result.action,
-        "workspace",
-        identifier,
-        &session_name,
-        &result.session_id,
-    );
-    Ok(())
+fn session_name_for_resume(worktree_path: &Path, branch_hint: &str) -> String {
+    read_session_name(worktree_path).unwrap_or_else(|| session_name_from_branch(branch_hint))
 }
 
-fn run_session_pr(
-    pr: &str,
-    branch: Option<&str>,
-    repo: Option<PathBuf>,
-    json: bool,
-) -> Result<(), String> {
-    let pr_number = extract_pr_number(pr)?;
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
-    }
-    let branch = match branch {
-        Some(branch) => branch.to_string(),
-        None => fetch_pr_details(pr_number, &repo_root)?.head_ref_name,
-    };
-    let session_name = session_name_from_branch(&branch);
-    let worktree = find_pr_worktree(&repo_root, pr_number, &branch)?;
-    let identifier = pr_number.to_string();
-    let legacy_prefix = format!("pr-{}-", pr_number);
-    let session_id = session_status(
-        "pr",
-        &identifier,
-        &repo_root,
-        worktree.as_deref(),
-        &session_name,
-        Some(&legacy_prefix),
-    )?;
-    print_session_result(
-        json,
-        session_id.as_deref(),
-        "pr",
-        &identifier,
-        &session_name,
+fn write_session_pid(worktree_path: &Path, pid: u32, agent: Agent) {
+    let dir = get_session_dir();
+    let _ = fs::create_dir_all(&dir);
+    let _ = fs::write(
+        session_pid_file(worktree_path),
+        format!("{}\n{}", pid, agent.command()),
     );
-    Ok(())
 }
 
-fn run_session_statsig(gate: &str, repo: Option<PathBuf>, json: bool) -> Result<(), String> {
-    let gate = gate.trim();
-    if gate.is_empty() {
-        return Err("Statsig gate name is required".to_string());
-    }
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
-    }
-    let branch = statsig_branch_name(gate);
-    let session_name = session_name_from_branch(&branch);
-    let worktree = find_branch_worktree(&repo_root, &branch)?;
-    let session_id = session_status(
-        "statsig",
-        gate,
-        &repo_root,
-        worktree.as_deref(),
-        &session_name,
-        None,
-    )?;
-    print_session_result(json, session_id.as_deref(), "statsig", gate, &session_name);
-    Ok(())
+fn remove_session_pid(worktree_path: &Path) {
+    let _ = fs::remove_file(session_pid_file(worktree_path));
 }
 
-fn run_session_workspace(repo_root: PathBuf, register_current: bool, json: bool) -> Result<(), String> {
-    if !repo_root.exists() {
-        return Err(format!("Workspace not found at {}", repo_root.display()));
+/// Remove the bazel output base directory for a worktree.
+/// Bazel stores its cache in /private/var/tmp/_bazel_<user>/<md5(workspace_path)>/,
+/// which can consume tens of GB per worktree and is never cleaned up automatically.
+fn remove_bazel_output_base(worktree_path: &Path) {
+    let path_str = worktree_path.to_string_lossy();
+    let hash = format!("{:x}", md5::compute(path_str.as_bytes()));
+    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    let output_base = PathBuf::from(format!("/private/var/tmp/_bazel_{}/{}", user, hash));
+    if !output_base.exists() {
+        return;
     }
-    let identifier = "development";
-    let session_name = workspace_session_name(&repo_root);
-    if register_current {
-        let session_id = env::var("ITERM_SESSION_ID")
-            .map_err(|_| "ITERM_SESSION_ID is not set".to_string())?;
-        save_live_session("workspace", identifier, &repo_root, Some(&repo_root), &session_id)?;
-        let session_id = normalized_iterm_session_id(&session_id)
-            .ok_or_else(|| "ITERM_SESSION_ID is empty".to_string())?;
-        print_session_result(
-            json,
-            Some(&session_id),
-            "workspace",
-            identifier,
-            &session_name,
-        );
-        return Ok(());
+    // Bazel write-protects its `external/` repos, so a plain remove hits EACCES
+    // partway through. chmod the tree writable first, then shell out to `rm -rf`
+    // since the on-disk shape (symlinks, deep external repos) is more than
+    // `fs::remove_dir_all` wants to chase down.
+    let _ = Command::new("chmod")
+        .args(["-R", "u+w", &output_base.to_string_lossy()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .traced_status();
+    let status = Command::new("rm")
+        .args(["-rf", &output_base.to_string_lossy()])
+        .traced_status();
+    match status {
+        Ok(s) if s.success() => println!(
+            "    {} Removed bazel cache {}",
+            "→".blue().bold(),
+            output_base.display().to_string().dimmed()
+        ),
+        Ok(s) => println!(
+            "    {} Failed to remove bazel cache: rm exited with {}",
+            "⚠".yellow(),
+            s.to_string().dimmed()
+        ),
+        Err(e) => println!(
+            "    {} Failed to remove bazel cache: {}",
+            "⚠".yellow(),
+            e.to_string().dimmed()
+        ),
     }
-    let session_id = session_status(
-        "workspace",
-        identifier,
-        &repo_root,
-        Some(&repo_root),
-        &session_name,
-        None,
-    )?;
-    print_session_result(
-        json,
-        session_id.as_deref(),
-        "workspace",
-        identifier,
-        &session_name,
+}
+
+fn write_session_exited(worktree_path: &Path, agent: Agent) {
+    let dir = get_session_dir();
+    let _ = fs::create_dir_all(&dir);
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let clean = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "status", "--short"])
+        .traced_output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().is_empty())
+        .unwrap_or(false);
+    let _ = fs::write(
+        session_exited_file(worktree_path),
+        format!(
+            "{}\n{}\n{}\n{}",
+            timestamp,
+            worktree_path.display(),
+            if clean { "clean" } else { "dirty" },
+            agent.command()
+        ),
     );
-    Ok(())
 }
 
-const STATSIG_ROLLOUT_PROMPT: &str = include_str!("prompts/statsig-rollout.md");
+fn read_session_pid(worktree_path: &Path) -> Option<u32> {
+    fs::read_to_string(session_pid_file(worktree_path))
+        .ok()?
+        .lines()
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
 
-fn statsig_rollout_prompt(gate: &str, agent: Agent) -> String {
-    STATSIG_ROLLOUT_PROMPT
-        .replace("{{GATE}}", gate)
-        .replace(
-            "{{STATSIG_SKILL}}",
-            agent.skill("/statsig-cli", "$statsig-cli"),
-        )
-        .replace(
-            "{{DOCUMENT_SKILL}}",
-            agent.skill("/darren:document", "$document"),
-        )
+fn read_session_agent(worktree_path: &Path) -> Option<Agent> {
+    fs::read_to_string(session_pid_file(worktree_path))
+        .ok()?
+        .lines()
+        .nth(1)
+        .and_then(Agent::parse)
 }
 
-fn run_statsig(
-    gate: &str,
-    repo: Option<PathBuf>,
+#[derive(Debug, Eq, PartialEq)]
+struct ExitedSession {
+    timestamp: u64,
+    worktree_path: PathBuf,
+    clean: bool,
     agent: Agent,
-    resume_existing: bool,
-) -> Result<(), String> {
-    let gate = gate.trim();
-    if gate.is_empty() {
-        return Err("Statsig gate name is required".to_string());
-    }
-    let prompt = statsig_rollout_prompt(gate, agent);
-    run_branch(
-        &statsig_branch_name(gate),
-        false,
-        Some(prompt),
-        repo,
+}
+
+fn parse_exited_session(content: &str) -> Option<ExitedSession> {
+    let mut lines = content.lines();
+    let timestamp = lines.next()?.trim().parse().ok()?;
+    let worktree_path = PathBuf::from(lines.next()?.trim());
+    let clean = lines.next()?.trim() == "clean";
+    // Markers written before multi-agent support could only have come from Claude.
+    let agent = lines.next().and_then(Agent::parse).unwrap_or(Agent::Claude);
+    Some(ExitedSession {
+        timestamp,
+        worktree_path,
+        clean,
         agent,
-        resume_existing,
-    )
+    })
 }
 
-fn run_pr(
-    pr: &str,
-    no_agent: bool,
-    repo: Option<PathBuf>,
-    initial_prompt: &str,
-    chained_skill: Option<&str>,
-    agent: Agent,
-    resume_existing: bool,
-) -> Result<(), String> {
-    timing!("run_pr");
-    let pr_number = extract_pr_number(pr)?;
-    println!(
-        "{} PR #{}",
-        "→".blue().bold(),
-        pr_number.to_string().cyan()
-    );
+fn is_pid_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .traced_status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
 
-    let repo_root = repo.unwrap_or_else(default_repo_root);
+/// RAII guard that removes the PID file on drop (normal exit or panic).
+/// Ctrl+C bypasses Drop, but stale PID files are self-healing: the next
+/// `status`/`clean` run detects the dead PID and cleans up.
+struct PidFileGuard {
+    worktree_path: PathBuf,
+    agent: Agent,
+}
 
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+impl PidFileGuard {
+    fn new(worktree_path: &Path, pid: u32, agent: Agent) -> Self {
+        write_session_pid(worktree_path, pid, agent);
+        // Clear the exited marker so this worktree isn't considered idle
+        let _ = fs::remove_file(session_exited_file(worktree_path));
+        // Register so the ctrlc handler can clean up and kill the child
+        if let Ok(mut guard) = ACTIVE_WORKTREE.lock() {
+            *guard = Some(worktree_path.to_path_buf());
+        }
+        if let Ok(mut guard) = ACTIVE_CHILD_PID.lock() {
+            *guard = Some(pid);
+        }
+        if let Ok(mut guard) = ACTIVE_AGENT.lock() {
+            *guard = Some(agent);
+        }
+        Self {
+            worktree_path: worktree_path.to_path_buf(),
+            agent,
+        }
     }
+}
 
-    print!("{} Fetching PR details... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    let pr_details = fetch_pr_details(pr_number, &repo_root)?;
-    println!("{}", "done".green());
-
-    println!(
-        "  {} {}",
-        "title:".dimmed(),
-        pr_details.title.white().bold()
-    );
-    println!(
-        "  {} {}",
-        "branch:".dimmed(),
-        pr_details.head_ref_name.yellow()
-    );
+impl Drop for PidFileGuard {
+    fn drop(&mut self) {
+        // Clear the globals first so ctrlc handler won't double-write
+        if let Ok(mut guard) = ACTIVE_WORKTREE.lock() {
+            *guard = None;
+        }
+        if let Ok(mut guard) = ACTIVE_CHILD_PID.lock() {
+            *guard = None;
+        }
+        if let Ok(mut guard) = ACTIVE_AGENT.lock() {
+            *guard = None;
+        }
+        remove_session_pid(&self.worktree_path);
+        write_session_exited(&self.worktree_path, self.agent);
+    }
+}
 
-    let slug = create_slug(&pr_details.title);
-    let worktree_dir = default_worktree_dir();
-    let worktree_path = worktree_dir.join(format!("pr-{}-{}", pr_number, slug));
+fn default_repo_root() -> PathBuf {
+    match env::var("CHECKOUT_REPO") {
+        Ok(repo) => PathBuf::from(repo),
+        Err(_) => config::get()
+            .repo
+            .as_deref()
+            .map(config::expand_tilde)
+            .expect("CHECKOUT_REPO env var or `repo` config must be set"),
+    }
+}
 
-    let existing = find_pr_worktree(&repo_root, pr_number, &pr_details.head_ref_name)?;
+/// The repo to operate on: `--repo` (a `[repos.<alias>]` name or a path),
+/// else the configured default. If that points at a linked worktree, use the
+/// main clone that owns it so worktree listing and the "skip the main repo"
+/// checks see the same paths git does.
+fn resolve_repo_root(repo: Option<PathBuf>) -> PathBuf {
+    main_repo_root(requested_repo_path(repo))
+}
 
-    let mut resume_target = None;
-    let mut is_new_worktree = false;
+/// [`resolve_repo_root`] for commands that check something out: a repo path
+/// that doesn't exist yet is cloned first (see [`clone_if_missing`]). Other
+/// commands only report a missing repo.
+fn resolve_checkout_repo_root(repo: Option<PathBuf>, checkout: &CheckoutArgs) -> PathBuf {
+    let repo_root = requested_repo_path(repo);
+    if !repo_root.exists() {
+        // A failed clone leaves the repo missing, which the command reports
+        if let Err(e) = clone_if_missing(&repo_root, checkout.clone_if_missing.as_deref()) {
+            eprintln!("{} {}", "✗".red(), e);
+        }
+    }
+    main_repo_root(repo_root)
+}
 
-    let final_path = if let Some(existing_path) = existing {
-        println!(
-            "\n{} Worktree already exists at {}",
-            "!".yellow().bold(),
-            existing_path.display().to_string().cyan()
+fn requested_repo_path(repo: Option<PathBuf>) -> PathBuf {
+    match repo {
+        Some(repo) => repo
+            .to_str()
+            .and_then(|alias| config::get().repo_path(alias))
+            .unwrap_or(repo),
+        None => default_repo_root(),
+    }
+}
+
+fn main_repo_root(repo_root: PathBuf) -> PathBuf {
+    let Ok(Some(main)) = git::main_worktree(&repo_root) else {
+        return repo_root;
+    };
+    if repo_root.canonicalize().is_ok_and(|path| path != main) {
+        eprintln!(
+            "{} {} is a worktree; using its main repo {}",
+            "→".blue().bold(),
+            repo_root.display(),
+            main.display().to_string().cyan()
         );
+    }
+    main
+}
 
-        let available_resume = find_worktree_resume_target(&existing_path);
-        let action = if resume_existing {
-            available_resume
-                .map(ExistingWorktreeAction::ResumeSession)
-                .unwrap_or(ExistingWorktreeAction::UseExisting)
-        } else {
-            let changes_handle = {
-                let path = existing_path.clone();
-                thread::spawn(move || get_uncommitted_status(&path))
-            };
-            prompt_existing_worktree_action(changes_handle, agent, available_resume)?
-        };
+/// Clone a missing repo from `--clone-if-missing` or its `clone_url`, with
+/// git's progress showing. Without either, nothing is cloned: a network
+/// operation only happens when asked for.
+fn clone_if_missing(repo_root: &Path, url: Option<&str>) -> Result<(), String> {
+    let config = config::get();
+    let Some(url) = url.or_else(|| config.clone_url_for(repo_root)) else {
+        return Ok(());
+    };
+    if let Some(parent) = repo_root.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    eprintln!("{} Cloning {} into {}", "→".blue().bold(), url.cyan(), repo_root.display());
+    let status = Command::new("git")
+        .args(["clone", "--progress", "--", url])
+        .arg(repo_root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .traced_status()
+        .map_err(|e| format!("Failed to run git clone: {}", e))?;
+    if !status.success() {
+        return Err(format!("git clone {} failed", url));
+    }
+    eprintln!("{} Cloned {}", "✓".green(), repo_root.display());
+    Ok(())
+}
 
-        match action {
-            ExistingWorktreeAction::ResumeSession(target) => {
-                resume_target = Some(target);
-                existing_path
+/// Where `repo_root`'s worktrees go: `$CHECKOUT_WORKTREE_DIR`, else
+/// `worktree_dir`, else a directory of its own under `worktree_root`.
+fn default_worktree_dir(repo_root: &Path) -> PathBuf {
+    match env::var("CHECKOUT_WORKTREE_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => match config::get().worktree_dir.as_deref() {
+            Some(dir) => config::expand_tilde(dir),
+            None => config::get().repo_worktree_dir(&repo_slug(repo_root)),
+        },
+    }
+}
+
+/// Where `--worktree-in-repo` puts worktrees: alongside the repo, for IDEs
+/// that expect related checkouts to share a parent directory. Worktrees are
+/// found through `git worktree list`, so status and clean still see them.
+fn sibling_worktree_dir(repo_root: &Path) -> PathBuf {
+    repo_root.parent().unwrap_or(repo_root).to_path_buf()
+}
+
+fn run() -> Result<(), String> {
+    let cli = Cli::parse();
+    let profile = cli.profile.clone().or_else(|| env::var("CHECKOUT_PROFILE").ok().filter(|p| !p.is_empty()));
+    config::init(profile.as_deref())?;
+    // Reading traces shouldn't leave a trace of its own behind
+    if !matches!(cli.command, Commands::Trace { .. }) {
+        let config = serde_json::to_value(config::get()).unwrap_or_default();
+        trace::start(&env::args().skip(1).collect::<Vec<_>>(), config);
+        if let Some(path) = &cli.trace {
+            let _ = TRACE_FILE.set(path.clone());
+        }
+    }
+    // `config` stays usable to lower the minimum; `shell-init` runs from shell rc files
+    if !matches!(cli.command, Commands::Config { .. } | Commands::ShellInit { .. }) {
+        let found = check_git_version()?;
+        if cli.timings {
+            eprintln!("⏱ {}", format!("git {}", found).dimmed());
+        }
+    }
+    if is_replayable(&cli.command) {
+        set_invocation(env::args().skip(1).collect());
+    }
+    backup::prune(&get_data_dir());
+    dispatch(cli)
+}
+
+/// Oldest git whose `worktree list --porcelain` and `worktree remove` behave
+/// the way checkout expects, unless `min_git_version` says otherwise.
+const MIN_GIT_VERSION: &str = "2.31";
+
+/// Fail fast on a git older than the minimum, rather than with confusing
+/// errors from worktree commands later. Returns the version found.
+fn check_git_version() -> Result<git::Version, String> {
+    let required = config::get().min_git_version.as_deref().unwrap_or(MIN_GIT_VERSION);
+    let minimum = git::Version::parse(required)
+        .ok_or_else(|| format!("Invalid min_git_version '{}': expected a version like 2.31", required))?;
+    let found = git::version()?;
+    if found < minimum {
+        return Err(format!(
+            "git {} is too old: checkout needs git {} or newer (set min_git_version to override)",
+            found, minimum
+        ));
+    }
+    Ok(found)
+}
+
+/// Commands `checkout last` can replay.
+fn is_replayable(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Pr { .. } | Commands::Review { .. } | Commands::Walkthrough { .. } | Commands::Branch { .. }
+    )
+}
+
+fn set_invocation(args: Vec<String>) {
+    if let Ok(mut invocation) = INVOCATION.lock() {
+        *invocation = args;
+    }
+}
+
+fn dispatch(cli: Cli) -> Result<(), String> {
+    let agent = cli.agent;
+    if cli.timings {
+        TIMINGS_ENABLED.store(true, Ordering::Relaxed);
+    }
+    if let Some(parallel) = cli.parallel {
+        PARALLEL.store(parallel, Ordering::Relaxed);
+    }
+    if let Some(path) = cli.write_path {
+        let _ = WRITE_PATH.set(path);
+    }
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    GIT_OUTPUT_SHOWN.store(cli.git_output == GitOutput::Shown, Ordering::Relaxed);
+    // Either this or --no-agent keeps a checkout from starting the agent
+    let no_spawn = no_spawn_from_env();
+
+    match cli.command {
+        Commands::Pr {
+            pr,
+            no_agent,
+            repo,
+            resume_existing,
+            use_existing,
+            allow_dirty,
+            into_current,
+            dir_only,
+            force_update,
+            on_dirty,
+            reuse_branch_worktree,
+            base_compare,
+            force,
+            since_commit,
+            mark_viewed,
+            worktree_in_repo,
+            worktree_color_by,
+            with_base,
+            picker,
+            checkout,
+        } => {
+            let (mut prs, skill) = split_pr_args(pr);
+            if dir_only && prs.len() > 1 {
+                return Err("--dir-only takes a single PR".to_string());
             }
-            ExistingWorktreeAction::UseExisting => {
-                if !resume_existing {
-                    print!("{} Updating to latest... ", "→".blue().bold());
-                    std::io::stdout().flush().ok();
-                    match update_worktree(&existing_path, &pr_details.head_ref_name) {
-                        Ok(()) => println!("{}", "done".green()),
-                        Err(e) => println!("{}\n  {} {}", "skipped".yellow(), "⚠".yellow().bold(), e.dimmed()),
+            // Only the worktree path goes to stdout, for `dir=$(checkout pr 123 --dir-only)`
+            OUTPUT_TO_STDERR.store(dir_only, Ordering::Relaxed);
+            let repo_root = resolve_checkout_repo_root(repo.clone(), &checkout);
+            if prs.is_empty() {
+                prs.push(pick_open_pr(&repo_root, &picker)?.to_string());
+            }
+            let repo_name = config::get().repo_alias_for(&repo_root).map_or_else(|| repo_slug(&repo_root), str::to_string);
+            let initial_skill = match config::get().pr_prompt(&repo_name) {
+                Some(prompt) => normalize_skill(agent, prompt),
+                None => agent.skill("/checkout:checkout-pr", "$checkout-pr").to_string(),
+            };
+            let chained_skill = skill.as_deref().map(|skill| normalize_skill(agent, skill));
+            let flags = PrFlags {
+                resume_existing,
+                use_existing,
+                allow_dirty,
+                into_current,
+                force_update: force_update.then_some(on_dirty),
+                reuse_branch_worktree,
+                base_compare,
+                force,
+                since_commit,
+                mark_viewed,
+                worktree_in_repo,
+                color_by: worktree_color_by,
+                with_base,
+                checkout: &checkout,
+            };
+            if into_current && prs.len() > 1 {
+                return Err("--into-current takes a single PR".to_string());
+            }
+            match prs.as_slice() {
+                [pr] => {
+                    let no_agent = no_agent || no_spawn || dir_only;
+                    let path = run_pr(pr, no_agent, repo, &initial_skill, chained_skill.as_deref(), agent, flags)?;
+                    if dir_only {
+                        println!("{}", path.display());
                     }
+                    Ok(())
                 }
-                existing_path
+                prs => run_pr_batch(prs, repo, &initial_skill, chained_skill.as_deref(), agent, flags),
             }
-            ExistingWorktreeAction::CreateNew => {
-                let new_path = find_next_worktree_path(&worktree_dir, &format!("pr-{}-{}", pr_number, slug))?;
-                create_new_worktree_from_remote(&repo_root, &worktree_dir, &new_path, &pr_details.head_ref_name, pr_number)?;
-                is_new_worktree = true;
-                new_path
+        },
+        Commands::Open { target } => match target {
+            OpenTarget::Pr { pr, repo, json } => run_open_pr(&pr, repo, json, agent),
+            OpenTarget::Statsig { gate, repo, json } => run_open_statsig(&gate, repo, json, agent),
+            OpenTarget::Workspace { repo, json } => run_open_workspace(repo, json, agent),
+        },
+        Commands::Session { target } => match target {
+            SessionTarget::Pr { pr, branch, repo, json } => {
+                run_session_pr(&pr, branch.as_deref(), repo, json)
+            }
+            SessionTarget::Statsig { gate, repo, json } => run_session_statsig(&gate, repo, json),
+            SessionTarget::Workspace { repo, register_current, json } => {
+                run_session_workspace(repo, register_current, json)
             }
+        },
+        Commands::Statsig { gate, repo, resume_existing, checkout } => {
+            run_statsig(&gate, repo, agent, resume_existing, &checkout)
         }
-    } else {
-        create_new_worktree_from_remote(&repo_root, &worktree_dir, &worktree_path, &pr_details.head_ref_name, pr_number)?;
-        is_new_worktree = true;
-        worktree_path
-    };
-
-    let bg_handle = if is_new_worktree {
-        Some(start_new_worktree_setup(final_path.clone(), repo_root.clone())?)
-    } else {
-        None
-    };
+        Commands::Walkthrough { pr, no_agent, repo, checkout } => run_pr(
+            &pr,
+            no_agent || no_spawn,
+            repo,
+            agent.skill("/checkout:checkout-pr", "$checkout-pr"),
+            Some(agent.skill("/walkthrough", "$walkthrough")),
+            agent,
+            PrFlags { checkout: &checkout, ..PrFlags::default() },
+        )
+        .map(drop),
+        Commands::Review { pr, no_agent, repo, checkout } => run_pr(
+            &pr,
+            no_agent || no_spawn,
+            repo,
+            agent.skill("/checkout:checkout-and-review-pr", "$checkout-and-review-pr"),
+            None,
+            agent,
+            PrFlags { checkout: &checkout, ..PrFlags::default() },
+        )
+        .map(drop),
+        Commands::Branch { name, no_agent, prompt, repo, base, push, pr, no_prefix, checkout } => {
+            let prompt = read_prompt_file(prompt)?;
+            let setup = BranchSetup { base: base.as_deref(), push, open_pr: pr, no_prefix, checkout: &checkout };
+            run_branch(&name, no_agent || no_spawn, prompt, repo, agent, false, setup)
+        },
+        Commands::Commit { sha, name, no_agent, repo, checkout } => {
+            run_commit(&sha, name.as_deref(), no_agent || no_spawn, repo, agent, &checkout)
+        }
+        Commands::Tag { tag, name, no_agent, repo, checkout } => {
+            run_tag(&tag, name.as_deref(), no_agent || no_spawn, repo, agent, &checkout)
+        }
+        Commands::New { no_agent, prompt, repo, checkout } => {
+            let prompt = read_prompt_file(prompt)?;
+            run_new(no_agent || no_spawn, prompt, repo, agent, &checkout)
+        },
+        Commands::Begin { repo, checkout } => run_new(
+            no_spawn,
+            Some(agent.skill("/darren:workstream-begin sandbox", "$darren-workstream-begin sandbox").to_string()),
+            repo,
+            agent,
+            &checkout,
+        ),
+        Commands::Status { repo, pager, format, fetch, usage, watch, watch_interval, order } => {
+            run_status(repo, pager, format, fetch, usage, watch.then_some(watch_interval), order)
+        }
+        Commands::Usage { repo } => run_usage(repo),
+        Commands::Clean { repo, yes, exclude, only, prune_remote, delete_branch, force, include_noted, order } => {
+            let mut exclude_patterns = config::get().clean_exclude.clone();
+            exclude_patterns.extend(exclude);
+            let delete_branches = delete_branch.then_some(BranchDeletion { force });
+            let filter = CleanFilter { exclude: exclude_patterns, only, include_noted };
+            run_clean(repo, yes, &filter, prune_remote, delete_branches, order)
+        }
+        Commands::Migrate { from, to, dry_run, repo } => run_migrate(&from, &to, dry_run, repo),
+        Commands::Uninstall { repo, dry_run, delete_branches, force, yes } => {
+            run_uninstall(repo, &UninstallOptions { dry_run, delete_branches, force, yes })
+        }
+        Commands::Resume { repo } => run_resume(repo),
+        Commands::Ui { repo } => {
+            let repo_root = resolve_repo_root(repo);
+            if !repo_root.exists() {
+                return Err(format!("Repo not found at {}", repo_root.display()));
+            }
+            ui::run(repo_root, agent)
+        }
+        Commands::ResumeLast { repo } => run_resume_last(repo, agent),
+        Commands::Last { json, show } => run_last(json, show),
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key } => run_config_get(&key),
+            ConfigAction::Set { key, value } => run_config_set(&key, &value),
+        },
+        Commands::Colors { action } => match action {
+            ColorsAction::Forget { target, repo } => run_colors_forget(&target, repo),
+        },
+        Commands::Trace { action } => match action {
+            TraceAction::Show { file } => run_trace_show(&file),
+            TraceAction::Last => run_trace_last(),
+        },
+        Commands::Backups { action } => match action {
+            BackupsAction::List => run_backups_list(),
+            BackupsAction::Restore { file, worktree, repo } => run_backups_restore(&file, worktree.as_deref(), repo),
+        },
+        Commands::UpdateAll { repo } => run_update_all(repo),
+        Commands::FixDetached { repo, yes } => run_fix_detached(repo, yes),
+        Commands::RenameBranch { worktree, new_name, repo } => run_rename_branch(&worktree, &new_name, repo),
+        Commands::Trust { worktree, repo } => run_trust(&worktree, repo),
+        Commands::Color { worktree, color, repo } => run_color(&worktree, &color, repo),
+        Commands::Which { worktree, repo } => run_which(&worktree, repo),
+        Commands::Main { spawn, repo } => run_main(spawn, repo, agent),
+        Commands::Note { worktree, text, clear: _, repo } => run_note(&worktree, text.as_deref(), repo),
+        Commands::ShellInit { name } => {
+            print!("{}", shell_init_script(&name)?);
+            Ok(())
+        }
+    }
+}
 
-    println!();
-    println!(
-        "{} Worktree ready at {}",
-        "✓".green().bold(),
-        final_path.display().to_string().cyan().bold()
-    );
+/// `CHECKOUT_NO_SPAWN=1`: act as if every checkout had `--no-agent`, for CI
+/// and scripts. Empty, `0`, `false`, `no` and `off` leave spawning on.
+fn no_spawn_from_env() -> bool {
+    env::var("CHECKOUT_NO_SPAWN").is_ok_and(|value| is_truthy(&value))
+}
 
-    let launch_agent = resume_target.as_ref().map_or(agent, |target| target.agent);
-    prepare_agent_worktree(launch_agent, &final_path, &repo_root)?;
-    if no_agent {
-        println!(
-            "\n{} Run: {} {} {}",
-            "tip:".yellow().bold(),
-            "cd".dimmed(),
-            final_path.display(),
-            format!("&& {}", launch_agent.command()).dimmed()
-        );
-    } else {
-        let bg_color = pick_available_color(&final_path);
-        save_worktree_color(&final_path, &bg_color)?;
-        record_current_iterm_session(&final_path)?;
-        let session_name = session_name_from_branch(&pr_details.head_ref_name);
+fn is_truthy(value: &str) -> bool {
+    !matches!(value.trim().to_ascii_lowercase().as_str(), "" | "0" | "false" | "no" | "off")
+}
 
-        // Guard ensures iTerm settings are reset even on Ctrl+C or panic
-        let _iterm_guard = ItermGuard::new(&bg_color, &session_name);
+/// Record a finished checkout for automation (`checkout last --json`) and as
+/// the worktree's last use, and expose it to the agent's environment. Never
+/// fails the checkout.
+fn record_last_checkout(
+    path: &Path,
+    branch: &str,
+    pr_number: Option<u64>,
+    title: Option<&str>,
+    color: Option<String>,
+) {
+    let checkout = last_checkout::LastCheckout {
+        path: path.to_path_buf(),
+        branch: branch.to_string(),
+        pr_number,
+        title: title.map(str::to_string),
+        color,
+        args: INVOCATION.lock().map(|args| args.clone()).unwrap_or_default(),
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    };
+    if let Ok(mut env) = CHECKOUT_ENV.lock() {
+        *env = checkout.env_vars();
+    }
+    if let Err(e) = last_checkout::save(&get_data_dir(), &checkout) {
+        eprintln!("{} Failed to record last checkout: {}", "⚠".yellow(), e);
+    }
+    let _ = metadata::update(&get_data_dir(), |metadata| {
+        metadata.last_used.insert(note_key(path), checkout.timestamp)
+    });
+}
 
-        let system_prompt = build_worktree_system_prompt();
+fn run_last(json: bool, show: bool) -> Result<(), String> {
+    let path = last_checkout::last_checkout_file(&get_data_dir());
+    let checkout = last_checkout::load(&get_data_dir())?.ok_or_else(|| {
+        format!(
+            "No previous checkout to replay ({} not found). Run `checkout pr` or `checkout branch` first.",
+            path.display()
+        )
+    })?;
+    if json {
+        let out = serde_json::to_string_pretty(&checkout)
+            .map_err(|e| format!("Failed to serialize last checkout: {}", e))?;
+        println!("{}", out);
+        return Ok(());
+    }
 
-        if let Some(target) = &resume_target {
-            println!();
-            println!(
-                "{} Resuming last {} session...",
-                "→".blue().bold(),
-                target.agent.display_name(),
-            );
-            println!();
-            spawn_agent_continue(
-                target.agent,
-                &final_path,
-                None,
-                Some(&system_prompt),
-                target.resume_id.as_deref(),
-                &session_name,
-            )?;
-        } else {
-            let full_prompt = match chained_skill {
-                Some(skill) => format!("{} {}\n\nAfter completing the above, run: {}", initial_prompt, pr_number, skill),
-                None => format!("{} {}", initial_prompt, pr_number),
-            };
-            println!();
-            println!(
-                "{} Spawning {} with {}...",
-                "→".blue().bold(),
-                agent.display_name(),
-                full_prompt.cyan()
-            );
-            println!();
-            spawn_agent_with_prompt(
-                agent,
-                &final_path,
-                &full_prompt,
-                Some(&system_prompt),
-                &session_name,
-            )?;
+    if show {
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(checkout.timestamp);
+        match checkout.pr_number {
+            Some(pr_number) => println!("{} PR #{}", "→".blue().bold(), pr_number.to_string().cyan()),
+            None => println!("{} Branch {}", "→".blue().bold(), checkout.branch.cyan()),
+        }
+        if let Some(title) = &checkout.title {
+            println!("  {} {}", "title:".dimmed(), title.white().bold());
         }
+        println!("  {} {}", "branch:".dimmed(), checkout.branch.yellow());
+        println!("  {} {}", "path:".dimmed(), checkout.path.display().to_string().cyan());
+        println!("  {} {}", "when:".dimmed(), format_time_ago(when));
+        if !checkout.args.is_empty() {
+            println!("  {} checkout {}", "command:".dimmed(), checkout.args.join(" "));
+        }
+        return Ok(());
     }
 
-    if let Some(handle) = bg_handle {
-        let _ = handle.join();
+    let cli = parse_replay_args(&checkout.args)?;
+    println!(
+        "{} Replaying {}",
+        "→".blue().bold(),
+        format!("checkout {}", checkout.args.join(" ")).cyan()
+    );
+    set_invocation(checkout.args);
+    dispatch(cli)
+}
+
+/// Rebuild the recorded command line, refusing anything `checkout last` can't replay.
+fn parse_replay_args(args: &[String]) -> Result<Cli, String> {
+    if args.is_empty() {
+        return Err("The last checkout wasn't started by `checkout pr` or `checkout branch`, so it can't be replayed".to_string());
+    }
+    let cli = Cli::try_parse_from(std::iter::once("checkout").chain(args.iter().map(String::as_str)))
+        .map_err(|e| format!("Can't replay `checkout {}`: {}", args.join(" "), e))?;
+    if !is_replayable(&cli.command) {
+        return Err(format!("`checkout {}` can't be replayed", args.join(" ")));
     }
+    Ok(cli)
+}
 
+fn run_trace_show(path: &Path) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    print!("{}", format_trace(&trace::parse(&content)?));
     Ok(())
 }
 
-fn run_branch(
-    name: &str,
-    no_agent: bool,
-    prompt: Option<String>,
-    repo: Option<PathBuf>,
-    agent: Agent,
-    resume_existing: bool,
-) -> Result<(), String> {
-    timing!("run_branch");
-    let branch_name = name.to_string();
+fn run_backups_list() -> Result<(), String> {
+    let backups = backup::list(&get_data_dir());
+    if backups.is_empty() {
+        println!("{} No backups", "→".blue().bold());
+        return Ok(());
+    }
+    for backup in &backups {
+        let file_name = backup.patch.file_name().unwrap_or_default().to_string_lossy();
+        let mut details = vec![format_time_ago(backup.created)];
+        if let Some(worktree) = &backup.worktree {
+            details.push(format!("from {}", worktree.display()));
+        }
+        if let Some(head) = &backup.head {
+            details.push(format!("on {}", &head[..head.len().min(7)]));
+        }
+        if backup.untracked.is_some() {
+            details.push("with untracked files".to_string());
+        }
+        println!("  {} {}", file_name.cyan(), format!("({})", details.join(", ")).dimmed());
+    }
+    println!("\n{} {}", "→".blue().bold(), backup::backups_dir(&get_data_dir()).display().to_string().dimmed());
+    Ok(())
+}
 
+fn run_backups_restore(file: &Path, worktree: Option<&str>, repo: Option<PathBuf>) -> Result<(), String> {
+    let patch = if file.exists() {
+        file.to_path_buf()
+    } else {
+        backup::backups_dir(&get_data_dir()).join(file)
+    };
+    if !patch.exists() {
+        return Err(format!("No backup {}; `checkout backups list` shows them", file.display()));
+    }
+    let worktree = match worktree {
+        Some(query) => resolve_worktree_query(&resolve_repo_root(repo), query)?,
+        None => {
+            let cwd = env::current_dir().map_err(|e| format!("Failed to get current directory: {}", e))?;
+            git::toplevel(&cwd)?
+        }
+    };
+    let backup = backup::read(&patch)?;
+    backup::restore(&backup, &worktree)?;
     println!(
-        "{} Branch {}",
-        "→".blue().bold(),
-        branch_name.cyan()
+        "{} Restored {} to {}",
+        "✓".green().bold(),
+        patch.file_name().unwrap_or_default().to_string_lossy(),
+        worktree_dir_name(&worktree).cyan()
     );
+    Ok(())
+}
 
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+fn run_trace_last() -> Result<(), String> {
+    let path = trace::last_failed(&get_data_dir()).ok_or("No failed runs have been traced")?;
+    println!("{} {}\n", "→".blue().bold(), path.display().to_string().dimmed());
+    run_trace_show(&path)
+}
+
+/// One line per command, with a failed command's stderr indented under it.
+fn format_trace(events: &[trace::Event]) -> String {
+    let mut out = String::new();
+    for event in events {
+        match event {
+            trace::Event::Start { args, cwd, version, timestamp, .. } => {
+                let when = SystemTime::UNIX_EPOCH + Duration::from_secs(*timestamp);
+                let _ = writeln!(
+                    out,
+                    "{} {} {}",
+                    "→".blue().bold(),
+                    format!("checkout {}", args.join(" ")).cyan(),
+                    format!(
+                        "(v{}, {}{})",
+                        version,
+                        format_time_ago(when),
+                        cwd.as_ref().map(|cwd| format!(", in {}", cwd.display())).unwrap_or_default()
+                    )
+                    .dimmed()
+                );
+            }
+            trace::Event::Command { program, args, cwd, duration_ms, exit_code, stderr, error } => {
+                let command = std::iter::once(program)
+                    .chain(args)
+                    .map(|arg| if arg.is_empty() || arg.contains(char::is_whitespace) { shell_quote(arg) } else { arg.clone() })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let cwd = cwd.as_ref().map(|cwd| format!(" in {}", cwd.display())).unwrap_or_default();
+                let (mark, outcome) = match (exit_code, error) {
+                    (Some(0), _) => ("✓".green(), String::new()),
+                    (Some(code), _) => ("✗".red(), format!(" exit {}", code)),
+                    (None, Some(error)) => ("✗".red(), format!(" {}", error)),
+                    (None, None) => ("✗".red(), " killed".to_string()),
+                };
+                let _ = writeln!(
+                    out,
+                    "  {} {}{} {}",
+                    mark,
+                    command,
+                    outcome.red(),
+                    format!("{}{}", format_duration_ms_precise(*duration_ms), cwd).dimmed()
+                );
+                if *exit_code != Some(0) {
+                    for line in stderr.iter().flat_map(|stderr| stderr.lines()) {
+                        let _ = writeln!(out, "      {}", line.dimmed());
+                    }
+                }
+            }
+            trace::Event::Error { message } => {
+                let _ = writeln!(out, "{} {}", "✗".red(), message);
+            }
+        }
     }
+    out
+}
 
-    // Create slug from branch name (strip any prefix like darren/ for the directory name)
-    let slug = branch_name.rsplit('/').next().unwrap_or(&branch_name);
-    let worktree_dir = default_worktree_dir();
-    let worktree_path = worktree_dir.join(format!("branch-{}", slug));
+/// `format_duration_ms`, but with milliseconds for anything under a second
+fn format_duration_ms_precise(ms: u64) -> String {
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else {
+        format_duration_ms(ms)
+    }
+}
 
-    let existing = find_branch_worktree(&repo_root, &branch_name)?;
+fn run_config_get(key: &str) -> Result<(), String> {
+    match config::get_value(&config::config_path(), key)? {
+        Some(value) => {
+            println!("{}", value);
+            Ok(())
+        }
+        None => Err(format!("{} is not set", key)),
+    }
+}
 
-    let mut resume_target = None;
-    let mut is_new_worktree = false;
+fn run_config_set(key: &str, value: &str) -> Result<(), String> {
+    let path = config::config_path();
+    config::set_value(&path, key, value)?;
+    println!(
+        "{} Set {} in {}",
+        "✓".green().bold(),
+        key.cyan(),
+        path.display().to_string().dimmed()
+    );
+    Ok(())
+}
 
-    let final_path = if let Some(existing_path) = existing {
-        println!(
-            "\n{} Worktree already exists at {}",
-            "!".yellow().bold(),
-            existing_path.display().to_string().cyan()
-        );
+fn normalize_skill(agent: Agent, skill: &str) -> String {
+    if agent == Agent::Codex {
+        if let Some(name) = skill.strip_prefix('/') {
+            let name = name.strip_prefix("checkout:").unwrap_or(name);
+            return format!("${}", name.replace(':', "-"));
+        }
+    }
+    skill.to_string()
+}
 
-        let available_resume = find_worktree_resume_target(&existing_path);
-        let action = if resume_existing {
-            available_resume
-                .map(ExistingWorktreeAction::ResumeSession)
-                .unwrap_or(ExistingWorktreeAction::UseExisting)
-        } else {
-            let changes_handle = {
-                let path = existing_path.clone();
-                thread::spawn(move || get_uncommitted_status(&path))
-            };
-            prompt_existing_worktree_action(changes_handle, agent, available_resume)?
-        };
+/// The slash command a prompt starts with, e.g. `checkout:checkout-pr` for
+/// `/checkout:checkout-pr 42`. Plain prompts have none.
+fn slash_command(prompt: &str) -> Option<&str> {
+    let command = prompt.strip_prefix('/')?.split(char::is_whitespace).next()?;
+    let valid = command.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'));
+    (valid && !command.is_empty() && !command.starts_with(':') && !command.ends_with(':')).then_some(command)
+}
 
-        match action {
-            ExistingWorktreeAction::ResumeSession(target) => {
-                resume_target = Some(target);
-                existing_path
-            }
-            ExistingWorktreeAction::UseExisting => {
-                existing_path
-            }
-            ExistingWorktreeAction::CreateNew => {
-                let new_path = find_next_worktree_path(&worktree_dir, &format!("branch-{}", slug))?;
-                create_new_worktree_new_branch(&repo_root, &worktree_dir, &new_path, &branch_name)?;
-                is_new_worktree = true;
-                new_path
-            }
-        }
-    } else {
-        create_new_worktree_new_branch(&repo_root, &worktree_dir, &worktree_path, &branch_name)?;
-        is_new_worktree = true;
-        worktree_path
+/// Whether Claude Code would find `/<command>` in one of `dirs`: under
+/// `.claude/commands/` (namespaces are subdirectories, so `a:b` is
+/// `a/b.md`), as a skill in `.claude/skills/<command>/SKILL.md`, or, for
+/// `plugin:name`, in an installed plugin's `commands/` or `skills/`.
+fn slash_command_installed(command: &str, dirs: &[PathBuf]) -> bool {
+    let mut file: PathBuf = command.split(':').collect();
+    file.set_extension("md");
+    let skill = Path::new(".claude/skills").join(command).join("SKILL.md");
+    if dirs.iter().any(|dir| dir.join(".claude/commands").join(&file).is_file() || dir.join(&skill).is_file()) {
+        return true;
+    }
+    let Some((plugin, name)) = command.split_once(':') else {
+        return false;
     };
+    dirs.iter().any(|dir| plugin_provides(&dir.join(".claude/plugins"), plugin, name, 4))
+}
 
-    let bg_handle = if is_new_worktree {
-        Some(start_new_worktree_setup(final_path.clone(), repo_root.clone())?)
-    } else {
-        None
+/// Whether a `plugin` directory within `depth` levels of `dir` (plugins sit
+/// under marketplace and cache directories) has the command or skill `name`.
+fn plugin_provides(dir: &Path, plugin: &str, name: &str, depth: usize) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
     };
+    entries.flatten().filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir())).any(|entry| {
+        let path = entry.path();
+        (entry.file_name() == plugin
+            && (path.join("commands").join(format!("{}.md", name)).is_file()
+                || path.join("skills").join(name).join("SKILL.md").is_file()))
+            || (depth > 0 && plugin_provides(&path, plugin, name, depth - 1))
+    })
+}
 
-    println!();
+/// The Claude prompt for a new PR session: `skill` as given, or, when a
+/// `pr_fallback_prompt` is configured and `skill` names a slash command that
+/// isn't installed for the worktree or user, the fallback in its place
+/// (keeping any chained instructions).
+fn claude_pr_prompt(skill: &str, pr_number: u64, worktree_path: &Path) -> String {
+    let fallback = config::get().pr_fallback_prompt.as_deref().unwrap_or_default();
+    let Some(command) = slash_command(skill).filter(|_| !fallback.is_empty()) else {
+        return skill.to_string();
+    };
+    let mut dirs = vec![worktree_path.to_path_buf()];
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(PathBuf::from(home));
+    }
+    if slash_command_installed(command, &dirs) {
+        return skill.to_string();
+    }
     println!(
-        "{} Worktree ready at {}",
-        "✓".green().bold(),
-        final_path.display().to_string().cyan().bold()
+        "{} /{} isn't installed for Claude {}",
+        "!".yellow().bold(),
+        command,
+        "(using a plain prompt instead)".dimmed()
     );
-
-    let launch_agent = resume_target.as_ref().map_or(agent, |target| target.agent);
-    prepare_agent_worktree(launch_agent, &final_path, &repo_root)?;
-    if no_agent {
-        println!(
-            "\n{} Run: {} {} {}",
-            "tip:".yellow().bold(),
-            "cd".dimmed(),
-            final_path.display(),
-            format!("&& {}", launch_agent.command()).dimmed()
-        );
+    let rest = skill.split_once('\n').map_or("", |(_, rest)| rest);
+    let prompt = fallback.replace("{pr}", &pr_number.to_string());
+    if rest.is_empty() {
+        prompt
     } else {
-        let bg_color = pick_available_color(&final_path);
-        save_worktree_color(&final_path, &bg_color)?;
-        record_current_iterm_session(&final_path)?;
-        let session_name = session_name_from_branch(&branch_name);
-
-        // Guard ensures iTerm settings are reset even on Ctrl+C or panic
-        let _iterm_guard = ItermGuard::new(&bg_color, &session_name);
-
-        let system_prompt = build_worktree_system_prompt();
-
-        if let Some(target) = &resume_target {
-            println!();
-            println!(
-                "{} Resuming last {} session...",
-                "→".blue().bold(),
-                target.agent.display_name(),
-            );
-            println!();
-            spawn_agent_continue(
-                target.agent,
-                &final_path,
-                prompt.as_deref(),
-                Some(&system_prompt),
-                target.resume_id.as_deref(),
-                &session_name,
-            )?;
-        } else {
-            println!();
-            println!(
-                "{} Spawning {}...",
-                "→".blue().bold(),
-                agent.display_name(),
-            );
-            println!();
+        format!("{}\n{}", prompt, rest)
+    }
+}
 
-            if let Some(prompt) = &prompt {
-                spawn_agent_with_prompt(
-                    agent,
-                    &final_path,
-                    prompt,
-                    Some(&system_prompt),
-                    &session_name,
-                )?;
-            } else {
-                spawn_agent(agent, &final_path, Some(&system_prompt), &session_name)?;
-            }
+fn read_prompt_file(path: Option<PathBuf>) -> Result<Option<String>, String> {
+    match path {
+        Some(p) => {
+            let content = fs::read_to_string(&p)
+                .map_err(|e| format!("Failed to read prompt file {}: {}", p.display(), e))?;
+            Ok(Some(content))
         }
+        None => Ok(None),
     }
+}
 
-    if let Some(handle) = bg_handle {
-        let _ = handle.join();
+/// Tell the user how to enter a worktree checkout didn't launch an agent in.
+/// Under `--write-path` a wrapper like the `shell-init` function cds there
+/// itself, so the tip is left out.
+fn print_enter_hint(worktree_path: &Path, agent: Agent) {
+    if WRITE_PATH.get().is_some() {
+        return;
     }
-
-    Ok(())
+    sayln!(
+        "\n{} Run: {} {} {}",
+        "tip:".yellow().bold(),
+        "cd".dimmed(),
+        worktree_path.display(),
+        format!("&& {}", agent.command()).dimmed()
+    );
 }
 
-const ADJECTIVES: &[&str] = &[
-    "azure", "bold", "calm", "deft", "eager", "fair", "glad", "hale",
-    "keen", "lush", "mild", "neat", "open", "pure", "quick", "rare",
-    "sage", "tame", "vast", "warm", "zesty", "brave", "crisp", "dense",
-    "fond", "grim", "hazy", "idle", "just", "kind", "lean", "mute",
-    "agile", "apt", "arid", "ashen", "avid", "blunt", "brisk", "coral",
-    "coy", "damp", "dim", "dry", "dusky", "elfin", "even", "faint",
-    "fell", "firm", "flat", "fleet", "flush", "frail", "fresh", "frost",
-    "full", "gaunt", "glib", "gold", "grand", "gray", "green", "gruff",
-    "gusty", "hardy", "hazel", "hex", "high", "hoary", "hush", "icy",
-    "inky", "ivory", "jet", "jolly", "lax", "light", "lithe", "live",
-    "lone", "lucid", "lunar", "meek", "misty", "mossy", "new", "numb",
-    "oaken", "opal", "pale", "peak", "plaid", "plum", "polar", "proud",
-    "quiet", "rapid", "raw", "regal", "rigid", "rocky", "rosy", "rough",
-    "rusty", "sandy", "sharp", "sheer", "shy", "silky", "slim", "snowy",
-    "solar", "solid", "spare", "stark", "steep", "still", "stony", "stout",
-    "swift", "terse", "thin", "tidal", "tiny", "trim", "true", "twin",
-    "vivid", "wary", "wavy", "white", "whole", "wide", "wild", "wiry",
-];
-
-const NOUNS: &[&str] = &[
-    "brook", "cedar", "dune", "elm", "flint", "grove", "heron", "iris",
-    "jade", "knoll", "lark", "moss", "nova", "oak", "pine", "quartz",
-    "reef", "slate", "thorn", "vale", "wren", "birch", "cliff", "delta",
-    "fern", "glade", "hawk", "isle", "junco", "kelp", "lynx", "marsh",
-    "alder", "amber", "anvil", "aspen", "basil", "bay", "blaze", "bloom",
-    "bolt", "brine", "cairn", "cave", "clam", "cloud", "coal", "colt",
-    "cone", "coral", "cove", "crane", "creek", "crest", "crow", "dale",
-    "dawn", "doe", "dove", "drift", "dusk", "ember", "finch", "fjord",
-    "flame", "flax", "foam", "fog", "forge", "fox", "frost", "gale",
-    "gem", "glen", "glow", "goose", "grain", "hare", "hazel", "heath",
-    "hedge", "herb", "hive", "holly", "inlet", "ivy", "jay", "kite",
-    "lake", "larch", "leaf", "ledge", "lily", "lime", "lodge", "maple",
-    "mesa", "mink", "mint", "mist", "moon", "moor", "moth", "nectar",
-    "nest", "oat", "olive", "orca", "otter", "owl", "pear", "plum",
-    "pond", "poppy", "rain", "ridge", "robin", "root", "rose", "rush",
-    "sage", "seal", "seed", "shade", "shell", "shore", "shrub", "smoke",
-    "snow", "spark", "spire", "spruce", "star", "stem", "stone", "storm",
-    "stork", "swift", "thyme", "tide", "trail", "trout", "tulip", "vine",
-    "wave", "wheat", "willow", "wolf", "yew",
-];
+/// Write the ready worktree's path to the `--write-path` file, if given, for a
+/// wrapper to cd into after checkout (and the agent) exits.
+fn write_worktree_path(worktree_path: &Path) -> Result<(), String> {
+    let Some(file) = WRITE_PATH.get() else {
+        return Ok(());
+    };
+    fs::write(file, format!("{}\n", worktree_path.display()))
+        .map_err(|e| format!("Failed to write the worktree path to {}: {}", file.display(), e))
+}
+
+/// Shell function for `checkout shell-init`. checkout writes the worktree's
+/// path to a temp file through `--write-path`, and the function cds there
+/// once it exits; stdin and stdout stay on the terminal for prompts and the
+/// agent.
+fn shell_init_script(name: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("Invalid shell function name '{}'", name));
+    }
+    Ok(format!(
+        r#"{name}() {{
+  local path_file rc
+  path_file="$(mktemp "${{TMPDIR:-/tmp}}/checkout-path.XXXXXX")" || return
+  command checkout --write-path "$path_file" "$@"
+  rc=$?
+  if [ -s "$path_file" ]; then
+    cd "$(cat "$path_file")" || rc=$?
+  fi
+  rm -f "$path_file"
+  return $rc
+}}
+"#
+    ))
+}
 
-fn generate_workspace_name(existing: &HashSet<String>) -> String {
-    let nanos = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .subsec_nanos() as usize;
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\"'\"'"))
+}
 
-    let total = ADJECTIVES.len() * NOUNS.len();
-    for offset in 0..total {
-        let idx = (nanos + offset) % total;
-        let adj = ADJECTIVES[idx % ADJECTIVES.len()];
-        let noun = NOUNS[idx / ADJECTIVES.len()];
-        let name = format!("{}-{}", adj, noun);
-        if !existing.contains(&name) {
-            return name;
+fn statsig_slug(gate: &str) -> String {
+    let mut slug = gate
+        .chars()
+        .map(|character| if character.is_ascii_alphanumeric() { character.to_ascii_lowercase() } else { '-' })
+        .collect::<String>();
+    slug = DASH_RUNS.replace_all(&slug, "-").trim_matches('-').to_string();
+    if slug.is_empty() {
+        slug = "gate".to_string();
+    }
+    if slug.len() > 17 {
+        let digest = format!("{:x}", md5::compute(gate.as_bytes()));
+        slug.truncate(10);
+        while slug.ends_with('-') {
+            slug.pop();
         }
+        slug.push('-');
+        slug.push_str(&digest[..6]);
     }
-
-    // Fallback: all names taken (very unlikely), append timestamp
-    let adj = ADJECTIVES[nanos % ADJECTIVES.len()];
-    let noun = NOUNS[(nanos / ADJECTIVES.len()) % NOUNS.len()];
-    format!("{}-{}-{}", adj, noun, nanos)
+    slug
 }
 
-/// Check if a worktree directory name matches the adjective-noun pattern from `checkout new`.
-fn is_checkout_new_worktree(dir_name: &str) -> bool {
-    let slug = dir_name.strip_prefix("branch-").unwrap_or(dir_name);
-    let parts: Vec<&str> = slug.splitn(2, '-').collect();
-    if parts.len() != 2 {
-        return false;
-    }
-    ADJECTIVES.contains(&parts[0]) && NOUNS.contains(&parts[1])
+fn statsig_branch_name(gate: &str) -> String {
+    format!("{}statsig-{}", config::branch_prefix(), statsig_slug(gate))
 }
 
-/// Find local `darren/<adj>-<noun>` branches older than `max_age_days` that
-/// aren't currently checked out by any worktree. Returns `(branch, age_days)`
-/// sorted oldest-first.
-fn find_stale_workspace_branches(
+fn checkout_launch_command(
+    resource: &str,
+    identifier: &str,
     repo_root: &Path,
-    active_branches: &HashSet<String>,
-    max_age_days: u64,
-) -> Result<Vec<(String, u64)>, String> {
-    let output = Command::new("git")
-        .args([
-            "-C", &repo_root.to_string_lossy(),
-            "for-each-ref",
-            "--format=%(refname:short) %(committerdate:unix)",
-            "refs/heads/darren/",
-        ])
-        .output()
-        .map_err(|e| format!("Failed to list branches: {}", e))?;
-    if !output.status.success() {
-        return Err(format!(
-            "git for-each-ref failed: {}",
-            String::from_utf8_lossy(&output.stderr).trim()
-        ));
-    }
-
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-    let max_age_secs = max_age_days * 24 * 60 * 60;
+    agent: Agent,
+) -> Result<String, String> {
+    let executable = env::current_exe()
+        .map_err(|error| format!("Failed to locate checkout executable: {}", error))?;
+    Ok([
+        shell_quote(&executable.to_string_lossy()),
+        resource.to_string(),
+        shell_quote(identifier),
+        "--resume-existing".to_string(),
+        "--repo".to_string(),
+        shell_quote(&repo_root.to_string_lossy()),
+        "--agent".to_string(),
+        agent.command().to_string(),
+    ].join(" "))
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut stale: Vec<(String, u64)> = Vec::new();
-    for line in stdout.lines() {
-        let mut parts = line.splitn(2, ' ');
-        let name = parts.next().unwrap_or("").trim();
-        let ts: u64 = parts.next().unwrap_or("0").trim().parse().unwrap_or(0);
-        if name.is_empty() || active_branches.contains(name) {
-            continue;
-        }
-        let slug = name.strip_prefix("darren/").unwrap_or(name);
-        if !is_checkout_new_worktree(slug) {
-            continue;
-        }
-        let age = now.saturating_sub(ts);
-        if age >= max_age_secs {
-            stale.push((name.to_string(), age / 86_400));
-        }
-    }
-    stale.sort_by(|a, b| b.1.cmp(&a.1));
-    Ok(stale)
+struct ItermOpenResult {
+    action: String,
+    session_id: String,
 }
 
-/// Find the oldest idle worktree created by `checkout new`: no alive session
-/// PID, and has an `.exited` marker that says "clean". Legacy worktrees closed
-/// before the SIGHUP fix won't be reused until they're closed once with the
-/// fixed binary. `git status` is deliberately not used here — it's slow enough
-/// that running it on tens of worktrees noticeably delays `checkout begin`.
-fn find_reusable_worktree(repo_root: &PathBuf) -> Result<Option<PathBuf>, String> {
-    timing!("find_reusable_worktree");
-    let entries = list_worktree_paths(repo_root)?;
-
-    let mut candidates: Vec<(u64, PathBuf)> = Vec::new();
-
-    for (path, _branch) in &entries {
-        let dir_name = match path.file_name().and_then(|s| s.to_str()) {
-            Some(n) => n,
-            None => continue,
-        };
-
-        if !is_checkout_new_worktree(dir_name) {
-            continue;
-        }
+fn find_live_iterm_session(
+    resource_session_id: Option<&str>,
+    worktree_session_id: Option<&str>,
+    session_name: &str,
+    legacy_prefix: Option<&str>,
+) -> Result<Option<String>, String> {
+    let response = iterm_api_request(&serde_json::json!({
+        "action": "status",
+        "sessionIds": [
+            resource_session_id.unwrap_or(""),
+            worktree_session_id.unwrap_or(""),
+        ],
+        "sessionName": session_name,
+        "legacyPrefix": legacy_prefix.unwrap_or(""),
+    }))?;
+    if response.exists != Some(true) {
+        return Ok(None);
+    }
+    let session_id = response
+        .session_id
+        .and_then(|value| normalized_iterm_session_id(&value))
+        .ok_or_else(|| "iTerm Python API helper returned an empty session ID".to_string())?;
+    Ok(Some(session_id))
+}
 
-        if let Some(pid) = read_session_pid(path) {
-            if is_pid_alive(pid) {
-                continue;
-            }
-        }
+fn focus_or_open_iterm(
+    resource_session_id: Option<&str>,
+    worktree_session_id: Option<&str>,
+    session_name: &str,
+    legacy_prefix: Option<&str>,
+    launch_command: &str,
+) -> Result<ItermOpenResult, String> {
+    let response = iterm_api_request(&serde_json::json!({
+        "action": "open",
+        "sessionIds": [
+            resource_session_id.unwrap_or(""),
+            worktree_session_id.unwrap_or(""),
+        ],
+        "sessionName": session_name,
+        "legacyPrefix": legacy_prefix.unwrap_or(""),
+        "launchCommand": launch_command,
+    }))?;
+    let action = response
+        .action
+        .filter(|action| action == "focused" || action == "opened")
+        .ok_or_else(|| "iTerm Python API helper returned an invalid action".to_string())?;
+    let session_id = response
+        .session_id
+        .and_then(|value| normalized_iterm_session_id(&value))
+        .ok_or_else(|| "iTerm Python API helper returned an empty session ID".to_string())?;
+    Ok(ItermOpenResult { action, session_id })
+}
 
-        // Must have an `.exited` marker that recorded "clean" at close time.
-        let content = match fs::read_to_string(session_exited_file(path)) {
-            Ok(c) => c,
-            Err(_) => continue,
-        };
-        let Some(exited) = parse_exited_session(&content) else {
-            continue;
-        };
-        if !exited.clean {
-            continue;
-        }
+fn print_open_result(
+    json: bool,
+    action: &str,
+    resource: &str,
+    identifier: &str,
+    session_name: &str,
+    session_id: &str,
+) {
+    if json {
+        println!("{}", serde_json::json!({
+            "action": action,
+            "resourceType": resource,
+            "resourceId": identifier,
+            "sessionName": session_name,
+            "sessionId": session_id,
+        }));
+    } else {
+        let verb = if action == "focused" { "Focused" } else { "Opened" };
+        println!("{} {} {} in iTerm", "→".blue().bold(), verb, session_name.cyan());
+    }
+}
 
-        candidates.push((exited.timestamp, path.clone()));
+fn print_session_result(
+    json: bool,
+    session_id: Option<&str>,
+    resource: &str,
+    identifier: &str,
+    session_name: &str,
+) {
+    let exists = session_id.is_some();
+    if json {
+        println!("{}", serde_json::json!({
+            "exists": exists,
+            "resourceType": resource,
+            "resourceId": identifier,
+            "sessionName": session_name,
+            "sessionId": session_id,
+        }));
+    } else {
+        let state = if exists { "Live".green() } else { "Not running".dimmed() };
+        println!("{} {} {}", "→".blue().bold(), session_name.cyan(), state);
     }
+}
 
-    // Pick the oldest (earliest exit timestamp) so freshly-closed worktrees
-    // remain easy to re-enter.
-    candidates.sort_by_key(|(ts, _)| *ts);
-    Ok(candidates.into_iter().next().map(|(_, path)| path))
+fn workspace_session_name(repo_root: &Path) -> String {
+    let name = repo_root.file_name().and_then(|value| value.to_str()).unwrap_or("workspace");
+    session_name_from_branch(name)
 }
 
-/// Remove stale `*.lock` files in the worktree's per-worktree git dir.
-///
-/// A crashed prior session can leave `index.lock` (or similar) behind, which
-/// then blocks every git operation in the recycled worktree with a confusing
-/// "Another git process seems to be running" error. We only call this when
-/// recycling an idle worktree — no live session should be holding a real lock.
-fn clear_stale_worktree_locks(worktree_path: &Path) {
-    let output = match Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "rev-parse", "--git-dir"])
-        .output()
-    {
-        Ok(o) if o.status.success() => o,
-        _ => return,
-    };
-    let git_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
-    let entries = match fs::read_dir(&git_dir) {
-        Ok(e) => e,
-        Err(_) => return,
+fn workspace_launch_command(repo_root: &Path, agent: Agent) -> String {
+    let agent_command = match agent {
+        Agent::Codex => "codex resume --last || codex",
+        Agent::Claude => "claude --continue || claude",
     };
-    for entry in entries.flatten() {
-        let path = entry.path();
-        let is_lock = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .map(|n| n.ends_with(".lock"))
-            .unwrap_or(false);
-        if !is_lock {
-            continue;
-        }
-        // Only remove locks older than 60s as a paranoia belt — a live process
-        // would still be actively touching it.
-        let too_old = entry
-            .metadata()
-            .and_then(|m| m.modified())
-            .ok()
-            .and_then(|t| t.elapsed().ok())
-            .map(|d| d > Duration::from_secs(60))
-            .unwrap_or(false);
-        if !too_old {
-            continue;
-        }
-        if fs::remove_file(&path).is_ok() {
-            println!(
-                "{} Cleared stale lock {}",
-                "→".blue().bold(),
-                path.display().to_string().dimmed()
-            );
-        }
-    }
+    format!("cd {} && {}", shell_quote(&repo_root.to_string_lossy()), agent_command)
 }
 
-fn reset_worktree_to_master(worktree_path: &PathBuf) -> Result<(), String> {
-    timing!("reset_worktree_to_master");
-    clear_stale_worktree_locks(worktree_path);
-    // Fetch latest master
-    print!("{} Fetching latest master... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    let output = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "fetch", "origin", "master"])
-        .output()
-        .map_err(|e| format!("Failed to spawn git fetch: {}", e))?;
-    if !output.status.success() {
-        println!("{}", "error".red());
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!(
-            "git fetch origin master failed in {} (exit {}):\n{}",
-            worktree_path.display(),
-            output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
-            stderr.trim()
-        ));
-    }
-    println!("{}", "done".green());
-
-    // Reset branch to origin/master
-    print!("{} Resetting to latest master... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    let output = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "reset", "--hard", "origin/master"])
-        .output()
-        .map_err(|e| format!("Failed to spawn git reset: {}", e))?;
-    if !output.status.success() {
-        println!("{}", "error".red());
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        return Err(format!(
-            "git reset --hard origin/master failed in {} (exit {}):\n{}{}{}",
-            worktree_path.display(),
-            output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
-            stderr.trim(),
-            if !stderr.trim().is_empty() && !stdout.trim().is_empty() { "\n" } else { "" },
-            stdout.trim()
-        ));
+fn save_live_session(
+    resource: &str,
+    identifier: &str,
+    repo_root: &Path,
+    worktree: Option<&Path>,
+    session_id: &str,
+) -> Result<(), String> {
+    save_resource_iterm_session(resource, identifier, repo_root, session_id)?;
+    if let Some(worktree) = worktree {
+        save_worktree_iterm_session(worktree, session_id)?;
     }
-    println!("{}", "done".green());
-
     Ok(())
 }
 
-fn run_new(no_agent: bool, prompt: Option<String>, repo: Option<PathBuf>, agent: Agent) -> Result<(), String> {
-    timing!("run_new");
-    let repo_root = repo.clone().unwrap_or_else(default_repo_root);
-
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+fn clear_stale_session(resource: &str, identifier: &str, repo_root: &Path, worktree: Option<&Path>) {
+    let _ = fs::remove_file(resource_iterm_session_file(resource, identifier, repo_root));
+    if let Some(worktree) = worktree {
+        let _ = fs::remove_file(worktree_iterm_session_file(worktree));
     }
+}
 
-    // Collect existing worktree names so we don't generate a collision
-    let existing_names: HashSet<String> = list_worktree_paths(&repo_root)?
-        .iter()
-        .filter_map(|(path, _)| {
-            path.file_name()
-                .and_then(|f| f.to_str())
-                .and_then(|s| s.strip_prefix("branch-"))
-                .map(|s| s.to_string())
-        })
-        .collect();
+fn session_status(
+    resource: &str,
+    identifier: &str,
+    repo_root: &Path,
+    worktree: Option<&Path>,
+    session_name: &str,
+    legacy_prefix: Option<&str>,
+) -> Result<Option<String>, String> {
+    let resource_session_id = read_resource_iterm_session(resource, identifier, repo_root);
+    let worktree_session_id = worktree.and_then(read_worktree_iterm_session);
+    let session_id = find_live_iterm_session(
+        resource_session_id.as_deref(),
+        worktree_session_id.as_deref(),
+        session_name,
+        legacy_prefix,
+    )?;
+    if let Some(session_id) = session_id {
+        save_live_session(resource, identifier, repo_root, worktree, &session_id)?;
+        return Ok(Some(session_id));
+    }
+    clear_stale_session(resource, identifier, repo_root, worktree);
+    Ok(None)
+}
 
-    // Try to reuse an idle scratch worktree
-    if let Some(reusable) = find_reusable_worktree(&repo_root)? {
-        let workspace_name = generate_workspace_name(&existing_names);
-        let branch_name = format!("darren/{}", workspace_name);
+/// Directory-name prefix shared by every worktree of PR `pr_number`,
+/// `pr-<n>-` unless `pr_dir_prefix` is configured.
+fn pr_dir_prefix(pr_number: u64) -> String {
+    format!("{}{}-", config::pr_dir_prefix(), pr_number)
+}
 
-        let old_dir = reusable.file_name().unwrap().to_string_lossy().to_string();
-        let old_name = old_dir.strip_prefix("branch-").unwrap_or(&old_dir);
+/// Directory name for a branch worktree: the branch's last path component
+/// after `branch_dir_prefix` (default `branch-`).
+fn branch_dir_name(branch: &str) -> String {
+    let slug = branch.rsplit('/').next().unwrap_or(branch);
+    format!("{}{}", config::branch_dir_prefix(), slug)
+}
 
-        println!(
-            "{} Recycling idle workspace {} {} {}",
-            "→".blue().bold(),
-            old_name.dimmed(),
-            "→".dimmed(),
-            workspace_name.cyan()
-        );
+/// Worktree directory for a `branch --no-prefix` branch: the whole name, with
+/// slashes flattened, so `release/1.2.0` doesn't collide with `hotfix/1.2.0`.
+fn verbatim_branch_dir_name(branch: &str) -> String {
+    format!("{}{}", config::branch_dir_prefix(), branch.replace('/', "-"))
+}
 
-        reset_worktree_to_master(&reusable)?;
+/// Find a PR's worktree: its own `pr-<n>-*` directory first, then any
+/// worktree (of either naming scheme) with the PR's branch checked out.
+/// Fork PRs pass no branch, since theirs says nothing about origin's.
+fn find_pr_worktree(
+    repo_root: &PathBuf,
+    pr_number: u64,
+    branch: Option<&str>,
+    interactive: bool,
+) -> Result<Option<PathBuf>, String> {
+    let existing = find_existing_worktree(repo_root, DirPattern::Prefix(&pr_dir_prefix(pr_number)), interactive)?;
+    let (Some(branch), None) = (branch, &existing) else {
+        return Ok(existing);
+    };
+    // Later lookups only run when the earlier ones found nothing, so a
+    // PR worktree found by name never prompts about branch worktrees
+    find_worktree_by_branch(repo_root, branch)?.map_or_else(
+        || find_existing_worktree(repo_root, DirPattern::Name(&branch_dir_name(branch)), interactive),
+        |path| Ok(Some(path)),
+    )
+}
 
-        // Create a new branch for this workspace
-        let output = Command::new("git")
-            .args(["-C", &reusable.to_string_lossy(), "checkout", "-b", &branch_name])
-            .output()
-            .map_err(|e| format!("Failed to spawn git checkout: {}", e))?;
-        if !output.status.success() {
-            return Err(format!(
-                "git checkout -b {} failed in {} (exit {}):\n{}",
-                branch_name,
-                reusable.display(),
-                output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
-                String::from_utf8_lossy(&output.stderr).trim()
-            ));
-        }
+fn find_branch_worktree(repo_root: &PathBuf, branch: &str, interactive: bool) -> Result<Option<PathBuf>, String> {
+    find_existing_worktree(repo_root, DirPattern::Name(&branch_dir_name(branch)), interactive)?
+        .map_or_else(|| find_worktree_by_branch(repo_root, branch), |path| Ok(Some(path)))
+}
 
-        // Rename the worktree directory to match the new workspace name
-        let new_path = reusable.parent().unwrap().join(format!("branch-{}", workspace_name));
-        let output = Command::new("git")
-            .args([
-                "-C", &repo_root.to_string_lossy(),
-                "worktree", "move",
-                &reusable.to_string_lossy(),
-                &new_path.to_string_lossy(),
-            ])
-            .output()
-            .map_err(|e| format!("Failed to spawn git worktree move: {}", e))?;
-        if !output.status.success() {
-            return Err(format!(
-                "git worktree move {} → {} failed (exit {}):\n{}",
-                reusable.display(),
-                new_path.display(),
-                output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
-                String::from_utf8_lossy(&output.stderr).trim()
-            ));
-        }
+/// Find a worktree (other than the main checkout) that has exactly `branch`
+/// checked out, whatever its directory is called.
+fn find_worktree_by_branch(repo_root: &Path, branch: &str) -> Result<Option<PathBuf>, String> {
+    Ok(git::list_worktrees(repo_root)?
+        .into_iter()
+        .find(|entry| entry.path != repo_root && entry.branch.as_deref() == Some(branch))
+        .map(|entry| entry.path))
+}
 
-        // `git worktree move` doesn't rename the metadata dir under
-        // `.git/worktrees/`. Rename it to match so that the working-dir name
-        // and the metadata-dir name stay in sync across recycles.
-        let new_metadata_name = format!("branch-{}", workspace_name);
-        rename_worktree_metadata(&new_path, &new_metadata_name)?;
+fn is_pr_worktree_dir(path: &Path, pr_number: u64) -> bool {
+    worktree_dir_name(path).starts_with(&pr_dir_prefix(pr_number))
+}
 
-        let bg_handle = start_new_worktree_setup(new_path.clone(), repo_root.clone())?;
+/// How to describe a PR state that is probably a mistake to check out, or
+/// `None` for open PRs.
+fn inactive_pr_state(state: &str) -> Option<&'static str> {
+    match state {
+        "MERGED" => Some("merged"),
+        "CLOSED" => Some("closed"),
+        _ => None,
+    }
+}
 
-        println!();
-        println!(
-            "{} Worktree ready at {}",
-            "✓".green().bold(),
-            new_path.display().to_string().cyan().bold()
-        );
+/// Checking out a merged or closed PR is usually a typo'd number, and its
+/// head branch may already be deleted. Require `--force` or a confirmation.
+fn confirm_inactive_pr(pr_number: u64, state: &str, force: bool) -> Result<(), String> {
+    let Some(description) = inactive_pr_state(state) else {
+        return Ok(());
+    };
+    sayln!(
+        "\n{} {}",
+        "⚠".yellow().bold(),
+        format!("PR #{} is already {}", pr_number, description).yellow().bold()
+    );
+    sayln!(
+        "  {}",
+        format!("If its branch was deleted, pull/{}/head is checked out instead.", pr_number).dimmed()
+    );
+    if force {
+        return Ok(());
+    }
 
-        prepare_agent_worktree(agent, &new_path, &repo_root)?;
-        if no_agent {
-            println!(
-                "\n{} Run: {} {} {}",
-                "tip:".yellow().bold(),
-                "cd".dimmed(),
-                new_path.display(),
-                format!("&& {}", agent.command()).dimmed()
-            );
-        } else {
-            let bg_color = pick_available_color(&new_path);
-            save_worktree_color(&new_path, &bg_color)?;
-            record_current_iterm_session(&new_path)?;
-            let session_name = session_name_from_branch(&branch_name);
+    say!("{} Check it out anyway? [y/N]: ", "?".magenta().bold());
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let input = read_confirmation()?;
+    if input.trim().to_lowercase() != "y" {
+        return Err(Interrupt::Declined.raise());
+    }
+    Ok(())
+}
 
-            let _iterm_guard = ItermGuard::new(&bg_color, &session_name);
+/// The branch `--base-compare` diffs against: the PR's `base`, or the
+/// remote's default branch when GitHub didn't report one.
+fn base_compare_branch(repo_root: &Path, base: &str) -> String {
+    if !base.is_empty() {
+        return base.to_string();
+    }
+    match git::default_branch(repo_root, git::REMOTE) {
+        Ok(Some(branch)) => branch,
+        _ => "master".to_string(),
+    }
+}
 
-            let system_prompt = build_worktree_system_prompt();
+/// Print the file-level diff of the worktree against `base` on the remote
+/// (see `base_compare_branch`). Informational only, so failures are shown as
+/// a warning.
+fn print_base_compare(repo_root: &PathBuf, worktree_path: &Path, base: &str, no_fetch: bool) {
+    let base = base_compare_branch(repo_root, base);
+    let base_ref = format!("{}/{}", git::REMOTE, base);
+    // A release-branch base may never have been fetched
+    if !no_fetch && !git::ref_exists(repo_root, &base_ref).unwrap_or(false) {
+        if let Err(e) = fetch_branch(repo_root, &base) {
+            sayln!("\n{} Could not fetch {} to compare against: {}", "⚠".yellow(), base_ref, e);
+            return;
+        }
+    }
+    match git::diff_stat_since(worktree_path, &base_ref, stdout_is_tty()) {
+        Ok(stat) if stat.trim().is_empty() => {
+            sayln!("\n{} No changes against {}", "→".blue().bold(), base_ref.cyan());
+        }
+        Ok(stat) => {
+            sayln!("\n{} Changes against {}:", "→".blue().bold(), base_ref.cyan());
+            say!("{}", stat);
+        }
+        Err(e) => sayln!("\n{} Could not compare against {}: {}", "⚠".yellow(), base_ref, e),
+    }
+}
 
-            println!();
-            println!(
-                "{} Spawning {}...",
+/// Print the commits a PR gained since its last checkout (and with
+/// `--since-commit`, their diff), then record the current head for next time.
+/// Review tracking is a convenience, so failures only warn.
+fn report_review_progress(repo_root: &Path, worktree_path: &Path, pr_number: u64, show_diff: bool) {
+    let key = metadata::pr_key(repo_root, pr_number);
+    let head = match git::rev_parse(worktree_path, "HEAD") {
+        Ok(head) => head,
+        Err(e) => {
+            sayln!("{} Could not read HEAD: {}", "⚠".yellow(), e);
+            return;
+        }
+    };
+    let last = metadata::load(&get_data_dir()).reviewed_commits.get(&key).cloned();
+    match last.as_deref() {
+        Some(last) if last == head => {
+            if show_diff {
+                sayln!("\n{} No new commits since your last checkout", "→".blue().bold());
+            }
+        }
+        // A force-push can drop the old head from the object store
+        Some(last) if !git::ref_exists(worktree_path, last).unwrap_or(false) => {
+            sayln!(
+                "\n{} Last checked-out head {} is no longer available (force-pushed?)",
+                "⚠".yellow(),
+                &last[..last.len().min(7)]
+            );
+        }
+        Some(last) => {
+            let range = format!("{}..{}", &last[..last.len().min(12)], &head[..head.len().min(12)]);
+            let count = git::commit_count(worktree_path, &format!("{}..{}", last, head))
+                .map_or_else(|_| "new".to_string(), |count| count.to_string());
+            sayln!(
+                "\n{} Since your last checkout: {} {}",
                 "→".blue().bold(),
-                agent.display_name(),
+                range.cyan(),
+                format!("({} commits)", count).dimmed()
             );
-            println!();
-
-            if let Some(prompt) = &prompt {
-                spawn_agent_with_prompt(
-                    agent,
-                    &new_path,
-                    prompt,
-                    Some(&system_prompt),
-                    &session_name,
-                )?;
-            } else {
-                spawn_agent(agent, &new_path, Some(&system_prompt), &session_name)?;
+            if show_diff {
+                let _ = Command::new("git")
+                    .args(["-C", &worktree_path.to_string_lossy(), "diff", last, &head])
+                    .stdout(say_stdio())
+                    .traced_status();
+            }
+        }
+        None => {
+            if show_diff {
+                sayln!(
+                    "\n{} No earlier checkout of PR #{} recorded; showing changes from next time",
+                    "!".yellow().bold(),
+                    pr_number
+                );
             }
         }
-
-        let _ = bg_handle.join();
-
-        return Ok(());
     }
 
-    // No reusable worktree, create a new one
-    let workspace_name = generate_workspace_name(&existing_names);
-    let branch_name = format!("darren/{}", workspace_name);
-
-    println!(
-        "{} New workspace {}",
-        "→".blue().bold(),
-        workspace_name.cyan()
-    );
-
-    run_branch(&branch_name, no_agent, prompt, repo, agent, false)
+    if let Err(e) = metadata::update(&get_data_dir(), |metadata| {
+        metadata.reviewed_commits.insert(key, head)
+    }) {
+        sayln!("{} Failed to record reviewed commit: {}", "⚠".yellow(), e);
+    }
 }
 
-#[derive(Clone)]
-struct WorktreeInfo {
-    path: PathBuf,
-    branch: String,
-    has_changes: bool,
-    has_active_session: bool,
-    active_agent: Option<Agent>,
-    orphaned_pids: Vec<u32>,
+#[derive(Deserialize)]
+struct PrFile {
+    path: String,
 }
 
-fn get_all_worktrees(repo_root: &PathBuf) -> Result<Vec<WorktreeInfo>, String> {
-    let output = Command::new("git")
-        .args(["-C", &repo_root.to_string_lossy(), "worktree", "list", "--porcelain"])
-        .output()
-        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // First pass: collect paths and branches
-    let mut entries: Vec<(PathBuf, String)> = Vec::new();
-    let mut current_path: Option<PathBuf> = None;
-    let mut current_branch: Option<String> = None;
-
-    for line in stdout.lines() {
-        if let Some(path_str) = line.strip_prefix("worktree ") {
-            current_path = Some(PathBuf::from(path_str));
-        } else if let Some(branch_str) = line.strip_prefix("branch refs/heads/") {
-            current_branch = Some(branch_str.to_string());
-        } else if line.is_empty() {
-            if let Some(path) = current_path.take() {
-                if path != *repo_root {
-                    let branch = current_branch.take().unwrap_or_else(|| "(detached)".to_string());
-                    entries.push((path, branch));
-                }
-            }
-            current_branch = None;
-        }
-    }
+/// `gh pr view --json id,files`: the node ID GraphQL mutations need, and
+/// the changed files (gh returns at most 100)
+#[derive(Deserialize)]
+struct PrFiles {
+    id: String,
+    files: Vec<PrFile>,
+}
 
-    // Check active sessions first (fast PID file reads) so we can skip
-    // expensive git-status calls for worktrees we're keeping anyway.
-    let session_status: Vec<bool> = entries
-        .iter()
-        .map(|(path, _)| {
-            if let Some(pid) = read_session_pid(path) {
-                if is_pid_alive(pid) {
-                    true
-                } else {
-                    remove_session_pid(path); // stale PID file from a crash
-                    false
-                }
-            } else {
-                false
-            }
-        })
+/// One GraphQL mutation marking `count` files viewed, taking the PR node ID
+/// as `$id` and the paths as `$p0`, `$p1`, ...
+fn mark_viewed_mutation(count: usize) -> String {
+    let params: String = (0..count).map(|i| format!(", $p{}: String!", i)).collect();
+    let fields: String = (0..count)
+        .map(|i| format!(" f{i}: markFileAsViewed(input: {{pullRequestId: $id, path: $p{i}}}) {{ clientMutationId }}"))
         .collect();
+    format!("mutation($id: ID!{}) {{{} }}", params, fields)
+}
 
-    // Only spawn git-status for inactive worktrees
-    let children: Vec<_> = entries
-        .iter()
-        .zip(&session_status)
-        .map(|((path, _), &active)| {
-            if active {
-                None
-            } else {
-                Command::new("git")
-                    .args(["-C", &path.to_string_lossy(), "status", "--porcelain"])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .ok()
-            }
-        })
-        .collect();
+/// `--mark-viewed`: tick "Viewed" on every file of the PR so GitHub tracks
+/// review progress from here. This is interop on top of a finished
+/// checkout, so failures only warn.
+fn mark_pr_files_viewed(repo_root: &Path, pr_number: u64) {
+    progress!("{} Marking PR #{} files as viewed... ", "→".blue().bold(), pr_number);
+    io::stdout().flush().ok();
+    match request_files_viewed(repo_root, pr_number) {
+        Ok(count) => progressln!("{} ({} file(s))", "done".green(), count),
+        Err(e) => sayln!("{}\n{} Could not mark files as viewed: {}", "skipped".yellow(), "⚠".yellow(), e),
+    }
+}
 
-    // Collect results
-    let mut worktrees: Vec<WorktreeInfo> = entries
-        .into_iter()
-        .zip(children)
-        .zip(session_status)
-        .map(|(((path, branch), child), has_active_session)| {
-            let has_changes = child
-                .and_then(|c| c.wait_with_output().ok())
-                .map(|o| {
-                    let stdout = String::from_utf8_lossy(&o.stdout);
-                    stdout.lines().any(|l| !l.trim().is_empty() && !l.starts_with("??"))
-                })
-                .unwrap_or(false);
-            let active_agent = has_active_session
-                .then(|| read_session_agent(&path).unwrap_or(Agent::Claude));
-            let orphaned_pids: Vec<u32> = Vec::new();
-            WorktreeInfo { path, branch, has_changes, has_active_session, active_agent, orphaned_pids }
-        })
-        .collect();
+fn request_files_viewed(repo_root: &Path, pr_number: u64) -> Result<usize, String> {
+    let output = gh_command()
+        .args(["pr", "view", &pr_number.to_string(), "--json", "id,files"])
+        .current_dir(repo_root)
+        .traced_output()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("gh pr view failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let pr: PrFiles =
+        serde_json::from_slice(&output.stdout).map_err(|e| format!("Failed to parse PR files: {}", e))?;
+    if pr.files.is_empty() {
+        return Ok(0);
+    }
+
+    let mut args = vec![
+        "api".to_string(),
+        "graphql".to_string(),
+        "-f".to_string(),
+        format!("query={}", mark_viewed_mutation(pr.files.len())),
+        "-f".to_string(),
+        format!("id={}", pr.id),
+    ];
+    for (i, file) in pr.files.iter().enumerate() {
+        args.push("-f".to_string());
+        args.push(format!("p{}={}", i, file.path));
+    }
+    let output = gh_command()
+        .args(&args)
+        .current_dir(repo_root)
+        .traced_output()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("gh api graphql failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(pr.files.len())
+}
 
-    // Sort by path for consistent output
-    worktrees.sort_by(|a, b| a.path.cmp(&b.path));
+/// A PR's branch was found checked out in a worktree that wasn't created for
+/// the PR (e.g. `branch-foo`). Reuse it if asked to, otherwise confirm.
+fn confirm_branch_worktree_reuse(
+    path: &Path,
+    branch: &str,
+    flags: PrFlags<'_>,
+) -> Result<bool, String> {
+    sayln!(
+        "\n{} Branch {} is already checked out in {}",
+        "!".yellow().bold(),
+        branch.yellow(),
+        worktree_dir_name(path).cyan()
+    );
+    if flags.reuse_branch_worktree || flags.resume_existing || flags.use_existing || flags.allow_dirty {
+        return Ok(true);
+    }
 
-    Ok(worktrees)
+    say!("{} Reuse it for this PR? [Y/n]: ", "?".magenta().bold());
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let input = read_confirmation()?;
+    Ok(!matches!(input.trim().to_lowercase().as_str(), "n" | "no"))
 }
 
-fn run_status(repo: Option<PathBuf>) -> Result<(), String> {
-    timing!("run_status");
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-
+fn run_open_pr(pr: &str, repo: Option<PathBuf>, json: bool, agent: Agent) -> Result<(), String> {
+    let pr_number = parse_pr_arg(pr)?;
+    let repo_root = resolve_repo_root(repo);
     if !repo_root.exists() {
         return Err(format!("Repo not found at {}", repo_root.display()));
     }
+    let details = fetch_pr_details(pr_number, &repo_root)?;
+    let session_name = session_name_from_branch(&details.head_ref_name);
+    let identifier = pr_number.to_string();
+    let existing_worktree = find_pr_worktree(&repo_root, pr_number, details.origin_branch(), !json)?;
+    let resource_session_id = read_resource_iterm_session("pr", &identifier, &repo_root);
+    let worktree_session_id = existing_worktree.as_deref().and_then(read_worktree_iterm_session);
+    let command = checkout_launch_command("pr", &identifier, &repo_root, agent)?;
+    let legacy_prefix = format!("pr-{}-", pr_number);
+    let result = focus_or_open_iterm(
+        resource_session_id.as_deref(),
+        worktree_session_id.as_deref(),
+        &terminal_title(&session_name, &repo_root),
+        Some(&legacy_prefix),
+        &command,
+    )?;
+    save_resource_iterm_session("pr", &identifier, &repo_root, &result.session_id)?;
+    if let Some(worktree) = existing_worktree {
+        save_worktree_iterm_session(&worktree, &result.session_id)?;
+        touch_last_used(&worktree);
+        write_worktree_path(&worktree)?;
+    }
+    print_open_result(
+        json,
+        &result.action,
+        "pr",
+        &identifier,
+        &session_name,
+        &result.session_id,
+    );
+    Ok(())
+}
 
-    let worktrees = get_all_worktrees(&repo_root)?;
-
-    if worktrees.is_empty() {
-        println!("{} No worktrees found", "→".blue().bold());
-        return Ok(());
+fn run_open_statsig(gate: &str, repo: Option<PathBuf>, json: bool, agent: Agent) -> Result<(), String> {
+    let gate = gate.trim();
+    if gate.is_empty() {
+        return Err("Statsig gate name is required".to_string());
+    }
+    let repo_root = resolve_repo_root(repo);
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
+    let branch = statsig_branch_name(gate);
+    let session_name = session_name_from_branch(&branch);
+    let existing_worktree = find_branch_worktree(&repo_root, &branch, !json)?;
+    let resource_session_id = read_resource_iterm_session("statsig", gate, &repo_root);
+    let worktree_session_id = existing_worktree.as_deref().and_then(read_worktree_iterm_session);
+    let command = checkout_launch_command("statsig", gate, &repo_root, agent)?;
+    let result = focus_or_open_iterm(
+        resource_session_id.as_deref(),
+        worktree_session_id.as_deref(),
+        &terminal_title(&session_name, &repo_root),
+        None,
+        &command,
+    )?;
+    save_resource_iterm_session("statsig", gate, &repo_root, &result.session_id)?;
+    if let Some(worktree) = existing_worktree {
+        save_worktree_iterm_session(&worktree, &result.session_id)?;
+        touch_last_used(&worktree);
+        write_worktree_path(&worktree)?;
     }
+    print_open_result(
+        json,
+        &result.action,
+        "statsig",
+        gate,
+        &session_name,
+        &result.session_id,
+    );
+    Ok(())
+}
 
-    println!(
-        "{} {} worktree(s) found:\n",
-        "→".blue().bold(),
-        worktrees.len()
+fn run_open_workspace(repo_root: PathBuf, json: bool, agent: Agent) -> Result<(), String> {
+    if !repo_root.exists() {
+        return Err(format!("Workspace not found at {}", repo_root.display()));
+    }
+    let identifier = "development";
+    let session_name = workspace_session_name(&repo_root);
+    let resource_session_id = read_resource_iterm_session("workspace", identifier, &repo_root);
+    let worktree_session_id = read_worktree_iterm_session(&repo_root);
+    let command = workspace_launch_command(&repo_root, agent);
+    let result = focus_or_open_iterm(
+        resource_session_id.as_deref(),
+        worktree_session_id.as_deref(),
+        &session_name,
+        None,
+        &command,
+    )?;
+    save_live_session("workspace", identifier, &repo_root, Some(&repo_root), &result.session_id)?;
+    touch_last_used(&repo_root);
+    write_worktree_path(&repo_root)?;
+    print_open_result(
+        json,
+        &result.action,
+        "workspace",
+        identifier,
+        &session_name,
+        &result.session_id,
     );
+    Ok(())
+}
 
-    for wt in &worktrees {
-        let status = if wt.has_active_session {
-            format!(
-                "active {}",
-                wt.active_agent.unwrap_or_default().command()
-            )
-            .blue()
-            .bold()
-        } else if wt.has_changes {
-            "modified".yellow().bold()
-        } else if !wt.orphaned_pids.is_empty() {
-            "orphaned".magenta().bold()
-        } else {
-            "clean".green()
-        };
+fn run_session_pr(
+    pr: &str,
+    branch: Option<&str>,
+    repo: Option<PathBuf>,
+    json: bool,
+) -> Result<(), String> {
+    let pr_number = parse_pr_arg(pr)?;
+    let repo_root = resolve_repo_root(repo);
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
+    let branch = match branch {
+        Some(branch) => branch.to_string(),
+        None => fetch_pr_details(pr_number, &repo_root)?.head_ref_name,
+    };
+    let session_name = session_name_from_branch(&branch);
+    let worktree = find_pr_worktree(&repo_root, pr_number, Some(&branch), !json)?;
+    let identifier = pr_number.to_string();
+    let legacy_prefix = format!("pr-{}-", pr_number);
+    let session_id = session_status(
+        "pr",
+        &identifier,
+        &repo_root,
+        worktree.as_deref(),
+        &terminal_title(&session_name, &repo_root),
+        Some(&legacy_prefix),
+    )?;
+    print_session_result(
+        json,
+        session_id.as_deref(),
+        "pr",
+        &identifier,
+        &session_name,
+    );
+    Ok(())
+}
 
-        let dir_name = wt.path.file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_else(|| wt.path.display().to_string());
+fn run_session_statsig(gate: &str, repo: Option<PathBuf>, json: bool) -> Result<(), String> {
+    let gate = gate.trim();
+    if gate.is_empty() {
+        return Err("Statsig gate name is required".to_string());
+    }
+    let repo_root = resolve_repo_root(repo);
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
+    let branch = statsig_branch_name(gate);
+    let session_name = session_name_from_branch(&branch);
+    let worktree = find_branch_worktree(&repo_root, &branch, !json)?;
+    let session_id = session_status(
+        "statsig",
+        gate,
+        &repo_root,
+        worktree.as_deref(),
+        &terminal_title(&session_name, &repo_root),
+        None,
+    )?;
+    print_session_result(json, session_id.as_deref(), "statsig", gate, &session_name);
+    Ok(())
+}
 
-        println!(
-            "  {} {} {}",
-            format!("[{}]", status).to_string(),
-            dir_name.cyan(),
-            format!("({})", wt.branch).dimmed()
+fn run_session_workspace(repo_root: PathBuf, register_current: bool, json: bool) -> Result<(), String> {
+    if !repo_root.exists() {
+        return Err(format!("Workspace not found at {}", repo_root.display()));
+    }
+    let identifier = "development";
+    let session_name = workspace_session_name(&repo_root);
+    if register_current {
+        let session_id = env::var("ITERM_SESSION_ID")
+            .map_err(|_| "ITERM_SESSION_ID is not set".to_string())?;
+        save_live_session("workspace", identifier, &repo_root, Some(&repo_root), &session_id)?;
+        let session_id = normalized_iterm_session_id(&session_id)
+            .ok_or_else(|| "ITERM_SESSION_ID is empty".to_string())?;
+        print_session_result(
+            json,
+            Some(&session_id),
+            "workspace",
+            identifier,
+            &session_name,
         );
+        return Ok(());
     }
-
+    let session_id = session_status(
+        "workspace",
+        identifier,
+        &repo_root,
+        Some(&repo_root),
+        &session_name,
+        None,
+    )?;
+    print_session_result(
+        json,
+        session_id.as_deref(),
+        "workspace",
+        identifier,
+        &session_name,
+    );
     Ok(())
 }
 
-fn remove_worktrees(worktrees: &[WorktreeInfo], repo_root: &PathBuf) -> Result<(), String> {
-    let mut removed_count = 0;
-    let mut failed: Vec<String> = Vec::new();
+const STATSIG_ROLLOUT_PROMPT: &str = include_str!("prompts/statsig-rollout.md");
 
-    for wt in worktrees {
-        let dir_name = wt.path.file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_else(|| wt.path.display().to_string());
+fn statsig_rollout_prompt(gate: &str, agent: Agent) -> String {
+    STATSIG_ROLLOUT_PROMPT
+        .replace("{{GATE}}", gate)
+        .replace(
+            "{{STATSIG_SKILL}}",
+            agent.skill("/statsig-cli", "$statsig-cli"),
+        )
+        .replace(
+            "{{DOCUMENT_SKILL}}",
+            agent.skill("/darren:document", "$document"),
+        )
+}
 
-        for pid in &wt.orphaned_pids {
-            print!(
-                "{} Killing orphaned agent process (pid {})... ",
-                "→".blue().bold(),
-                pid.to_string().dimmed()
-            );
-            std::io::stdout().flush().ok();
+fn run_statsig(
+    gate: &str,
+    repo: Option<PathBuf>,
+    agent: Agent,
+    resume_existing: bool,
+    checkout: &CheckoutArgs,
+) -> Result<(), String> {
+    let gate = gate.trim();
+    if gate.is_empty() {
+        return Err("Statsig gate name is required".to_string());
+    }
+    let prompt = statsig_rollout_prompt(gate, agent);
+    run_branch(
+        &statsig_branch_name(gate),
+        false,
+        Some(prompt),
+        repo,
+        agent,
+        resume_existing,
+        BranchSetup { checkout, ..BranchSetup::default() },
+    )
+}
 
-            let kill_result = Command::new("kill")
-                .arg(pid.to_string())
-                .status();
+/// Optional `run_pr` behaviors, mostly how it treats a worktree that already
+/// exists for the PR.
+#[derive(Clone, Copy, Default)]
+struct PrFlags<'a> {
+    /// Resume the existing worktree session without prompting
+    resume_existing: bool,
+    /// Use the existing worktree without prompting
+    use_existing: bool,
+    /// Use the existing worktree as it is, without updating it or prompting
+    allow_dirty: bool,
+    /// Move the worktree the shell is in to the PR instead of using another
+    into_current: bool,
+    /// Update the existing worktree unattended, with this dirty-changes policy
+    force_update: Option<OnDirty>,
+    /// Reuse a worktree found by branch under another naming scheme without prompting
+    reuse_branch_worktree: bool,
+    /// Print `git diff --stat` against the base branch before spawning the agent
+    base_compare: bool,
+    /// Check out merged or closed PRs without confirmation
+    force: bool,
+    /// Show the diff since the PR head recorded at the last checkout
+    since_commit: bool,
+    /// Mark the PR's files as viewed on GitHub after checkout
+    mark_viewed: bool,
+    /// Create a new worktree next to the repo rather than in the worktree dir
+    worktree_in_repo: bool,
+    /// Color a new worktree by hashing this PR attribute into the palette
+    color_by: Option<ColorBy>,
+    /// Also check out the PR's base branch in its shared worktree
+    with_base: bool,
+    checkout: &'a CheckoutArgs,
+}
+
+/// What `--worktree-color-by` hashes into a color.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ColorBy {
+    /// The PR author, so one person's PRs share a color
+    Author,
+    /// The branch the PR merges into
+    Base,
+    /// The PR number
+    Pr,
+}
+
+/// Where a checkout's worktree comes from.
+#[derive(Clone, Debug, PartialEq)]
+enum Source {
+    /// A PR's head branch, checked out detached at `origin/<branch>`.
+    /// `head_oid` is the tip GitHub reports, when known. A `fork` branch isn't
+    /// on origin, so `pull/<n>/head` is checked out instead.
+    RemoteBranch { branch: String, pr_number: u64, head_oid: Option<String>, fork: bool },
+    /// A new local branch started from `base` (default: `origin/master`)
+    NewBranch { branch: String, base: Option<String> },
+}
+
+impl Source {
+    fn branch(&self) -> &str {
+        match self {
+            Source::RemoteBranch { branch, .. } | Source::NewBranch { branch, .. } => branch,
+        }
+    }
 
-            match kill_result {
-                Ok(s) if s.success() => println!("{}", "done".green()),
-                _ => println!("{}", "failed (may have already exited)".yellow()),
+    fn pr_number(&self) -> Option<u64> {
+        match self {
+            Source::RemoteBranch { pr_number, .. } => Some(*pr_number),
+            Source::NewBranch { .. } => None,
+        }
+    }
+
+    fn create(
+        &self,
+        repo_root: &PathBuf,
+        worktree_dir: &PathBuf,
+        worktree_path: &PathBuf,
+        checkout: &CheckoutArgs,
+    ) -> Result<(), String> {
+        std::fs::create_dir_all(worktree_dir)
+            .map_err(|e| format!("Failed to create worktrees dir: {}", e))?;
+        match self {
+            Source::RemoteBranch { branch, pr_number, head_oid, fork } => create_new_worktree_from_remote(
+                repo_root,
+                worktree_path,
+                branch,
+                *pr_number,
+                head_oid.as_deref(),
+                *fork,
+                checkout,
+            ),
+            Source::NewBranch { branch, base } => {
+                create_new_worktree_new_branch(repo_root, worktree_path, branch, base.as_deref(), checkout)
             }
         }
+    }
 
-        print!("{} Removing {}... ", "→".blue().bold(), dir_name.cyan());
-        std::io::stdout().flush().ok();
-
-        let repo_str = repo_root.to_string_lossy();
-        let wt_str = wt.path.to_string_lossy();
-        // Always --force: the caller has already confirmed removal, and without
-        // it git refuses to delete directories containing gitignored files
-        // (e.g. .DS_Store, build artifacts) even though they show as "clean".
-        let args = vec!["-C", &repo_str, "worktree", "remove", "--force", &wt_str];
-
-        let output = Command::new("git")
-            .args(&args)
-            .output()
-            .map_err(|e| format!("Failed to remove worktree: {}", e))?;
-
-        if output.status.success() {
-            let color_file = worktree_color_file(&wt.path);
-            let _ = fs::remove_file(color_file);
-            let _ = fs::remove_file(session_name_file(&wt.path));
-            let _ = fs::remove_file(worktree_iterm_session_file(&wt.path));
-            remove_session_pid(&wt.path);
-            remove_bazel_output_base(&wt.path);
-
-            println!("{}", "done".green());
-            removed_count += 1;
-        } else {
-            // `git worktree remove` is not atomic: it drops the worktree's admin
-            // registration (`.git/worktrees/<id>`) *before* recursively deleting
-            // the working directory. If that delete fails (commonly "Directory not
-            // empty" — a race with a process writing into a monorepo checkout, e.g.
-            // watchman/bazel/an IDE), git leaves an orphaned directory that it will
-            // no longer recognize ("is not a working tree"), so a retry can never
-            // recover it. Fall back to deleting the directory ourselves and pruning
-            // the (possibly stale) registration.
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let error_msg = stderr.trim();
+    /// What a reused PR worktree is updated from: the same ref a new one is
+    /// created from
+    fn upstream(&self) -> Option<Upstream<'_>> {
+        match self {
+            Source::RemoteBranch { pr_number, fork, .. } if uses_pull_ref(*fork) => Some(Upstream::PullRef(*pr_number)),
+            Source::RemoteBranch { branch, .. } => Some(Upstream::Branch(branch)),
+            Source::NewBranch { .. } => None,
+        }
+    }
 
-            let recovered = if wt.path.exists() {
-                match fs::remove_dir_all(&wt.path) {
-                    Ok(()) => true,
-                    Err(e) => {
-                        println!("{}", "failed".red());
-                        if !error_msg.is_empty() {
-                            println!("    {} {}", "error:".red(), error_msg);
-                        }
-                        println!("    {} manual cleanup failed: {}", "error:".red(), e);
-                        false
-                    }
+    /// `--force-update`: reset a reused PR worktree to the remote branch without
+    /// prompting. Branch worktrees are left alone, as in `refresh_existing`.
+    fn force_refresh(&self, worktree_path: &Path, on_dirty: OnDirty, no_fetch: bool) -> Result<(), String> {
+        match (self, self.upstream()) {
+            (Source::RemoteBranch { branch, pr_number, .. }, Some(upstream)) => {
+                if let Some(current) = git::current_branch(worktree_path)?.filter(|current| current != branch) {
+                    return Err(format!(
+                        "Worktree is on branch {}, but PR #{} is {}; refusing to reset it unattended",
+                        current, pr_number, branch
+                    ));
                 }
-            } else {
-                // Directory already gone; git just needs to prune stale metadata.
-                true
-            };
-
-            if recovered {
-                // Prune the stale registration git left behind, then run the same
-                // per-worktree cleanup the success path does.
-                let _ = Command::new("git")
-                    .args(["-C", &repo_str, "worktree", "prune"])
-                    .output();
-                let color_file = worktree_color_file(&wt.path);
-                let _ = fs::remove_file(color_file);
-                let _ = fs::remove_file(session_name_file(&wt.path));
-                let _ = fs::remove_file(worktree_iterm_session_file(&wt.path));
-                remove_session_pid(&wt.path);
-                remove_bazel_output_base(&wt.path);
-
-                println!("{}", "done (manual cleanup)".green());
-                removed_count += 1;
-            } else {
-                failed.push(dir_name);
+                force_update_worktree(worktree_path, upstream, on_dirty, no_fetch)
             }
+            _ => Ok(()),
         }
     }
 
-    if removed_count > 0 {
-        println!(
-            "{} Removed {} worktree(s)",
-            "✓".green().bold(),
-            removed_count
-        );
-    }
-
-    if !failed.is_empty() {
-        println!(
-            "{} Failed to remove {} worktree(s): {}",
-            "✗".red().bold(),
-            failed.len(),
-            failed.join(", ")
-        );
+    /// Bring a reused worktree back in line with the source. A PR worktree is
+    /// checked against the PR branch and updated to the remote; a branch
+    /// worktree is the branch itself, so there is nothing to sync.
+    fn refresh_existing(&self, worktree_path: &PathBuf, no_fetch: bool) -> Result<(), String> {
+        let (Source::RemoteBranch { branch, pr_number, .. }, Some(upstream)) = (self, self.upstream()) else {
+            return Ok(());
+        };
+        match confirm_worktree_branch(worktree_path, *pr_number, branch)? {
+            BranchMismatchAction::SwitchBack => {
+                progress!("{} Switching back to {}... ", "→".blue().bold(), branch.yellow());
+                std::io::stdout().flush().ok();
+                git::switch_to_branch(worktree_path, branch)?;
+                progressln!("{}", "done".green());
+            }
+            action => {
+                if let BranchMismatchAction::FollowRename { local } = action {
+                    progress!("{} Renaming {} to {}... ", "→".blue().bold(), local.yellow(), branch.yellow());
+                    std::io::stdout().flush().ok();
+                    git::rename_branch(worktree_path, &local, branch)?;
+                    track_pr_branch(worktree_path, branch, branch)?;
+                    progressln!("{}", "done".green());
+                }
+                progress!("{} Updating to latest... ", "→".blue().bold());
+                std::io::stdout().flush().ok();
+                match update_worktree(worktree_path, upstream, LocalCommits::Prompt, no_fetch) {
+                    Ok(()) => progressln!("{}", "done".green()),
+                    Err(e) => sayln!("{}\n  {} {}", "skipped".yellow(), "⚠".yellow().bold(), e.dimmed()),
+                }
+            }
+        }
+        Ok(())
     }
+}
 
-    Ok(())
+/// Initial instructions for a new agent session.
+#[derive(Clone, Debug, PartialEq)]
+enum AgentPrompt {
+    None,
+    /// A skill invocation such as `/checkout:checkout-pr 123`: shown when
+    /// spawning, and not repeated when resuming a session
+    Skill(String),
+    /// User-supplied text (`--prompt`): sent to new and resumed sessions alike
+    Text(String),
 }
 
-fn run_clean(repo: Option<PathBuf>, skip_confirm: bool) -> Result<(), String> {
-    timing!("run_clean");
-    let repo_root = repo.unwrap_or_else(default_repo_root);
+/// What `execute_checkout` should produce. `checkout pr` and `checkout
+/// branch` differ only in how they build this.
+struct CheckoutPlan {
+    repo_root: PathBuf,
+    /// Directory name for a new worktree, e.g. `pr-123-fix-login`
+    worktree_name: String,
+    source: Source,
+    /// A worktree already checked out for this source, offered for reuse
+    existing: Option<PathBuf>,
+    /// PR title, recorded for `checkout last`
+    title: Option<String>,
+    /// Key the background color is remembered under, if any
+    color_key: Option<ColorKey>,
+    prompt: AgentPrompt,
+}
+
+/// Per-invocation switches for `execute_checkout`.
+#[derive(Clone, Copy, Default)]
+struct CheckoutOptions<'a> {
+    no_agent: bool,
+    /// Resume the existing worktree session without prompting
+    resume_existing: bool,
+    /// Use the existing worktree without the resume/use/create prompt
+    use_existing: bool,
+    /// Use the existing worktree as it is, without updating it or prompting
+    allow_dirty: bool,
+    /// `existing` is the current worktree, already moved to the PR
+    into_current: bool,
+    /// Update the existing worktree without prompts, handling uncommitted
+    /// changes per the policy, then print the resulting HEAD
+    force_update: Option<OnDirty>,
+    /// Push the branch and set its upstream before the ready banner
+    push: bool,
+    /// After pushing, open a draft PR (adds an empty commit to a new worktree)
+    open_pr: bool,
+    /// Print a diff stat against this base branch after the ready banner; the
+    /// default branch when it's empty
+    base_compare: Option<&'a str>,
+    /// Show the diff since the PR head recorded at the last checkout
+    since_commit: bool,
+    /// Mark the PR's files as viewed on GitHub after checkout
+    mark_viewed: bool,
+    /// Create a new worktree next to the repo rather than in the worktree dir
+    worktree_in_repo: bool,
+    /// The PR argument as given (number or URL), remembered for `status`
+    pr_input: Option<&'a str>,
+    /// Base branch to also check out in its shared `base-` worktree
+    with_base: Option<&'a str>,
+    /// Hashed into the palette for a new worktree's color, instead of taking
+    /// the first unused one
+    color_seed: Option<&'a str>,
+    checkout: &'a CheckoutArgs,
+}
+
+fn pr_checkout_plan(
+    repo_root: PathBuf,
+    pr_number: u64,
+    pr_details: &PrDetails,
+    existing: Option<PathBuf>,
+    initial_prompt: &str,
+    chained_skill: Option<&str>,
+) -> CheckoutPlan {
+    let prompt = match chained_skill {
+        Some(skill) => format!("{} {}\n\nAfter completing the above, run: {}", initial_prompt, pr_number, skill),
+        None => format!("{} {}", initial_prompt, pr_number),
+    };
+    CheckoutPlan {
+        repo_root,
+        worktree_name: format!("{}{}", pr_dir_prefix(pr_number), create_slug(&pr_details.title)),
+        source: Source::RemoteBranch {
+            branch: pr_details.head_ref_name.clone(),
+            pr_number,
+            head_oid: (!pr_details.head_ref_oid.is_empty()).then(|| pr_details.head_ref_oid.clone()),
+            fork: pr_details.is_cross_repository,
+        },
+        existing,
+        title: Some(pr_details.title.clone()),
+        color_key: Some(ColorKey::Pr(pr_number)),
+        prompt: AgentPrompt::Skill(prompt),
+    }
+}
 
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+fn branch_checkout_plan(
+    repo_root: PathBuf,
+    branch: &str,
+    base: Option<&str>,
+    no_prefix: bool,
+    existing: Option<PathBuf>,
+    prompt: Option<String>,
+) -> CheckoutPlan {
+    // Strip any prefix like darren/ for the directory name, unless the
+    // branch was asked for verbatim
+    let slug = branch.rsplit('/').next().unwrap_or(branch);
+    let worktree_name = if no_prefix { verbatim_branch_dir_name(branch) } else { branch_dir_name(branch) };
+    CheckoutPlan {
+        repo_root,
+        worktree_name,
+        source: Source::NewBranch { branch: branch.to_string(), base: base.map(str::to_string) },
+        existing,
+        title: None,
+        // Randomly named `checkout new` branches are never checked out twice,
+        // so remembering their colors would only grow the metadata file.
+        color_key: (!is_checkout_new_worktree(slug)).then(|| ColorKey::Branch(branch.to_string())),
+        prompt: prompt.map_or(AgentPrompt::None, AgentPrompt::Text),
+    }
+}
+
+/// Create or reuse the worktree described by `plan`, run the shared setup
+/// steps, and launch (or resume) the agent in it.
+/// Carry out a checkout plan, returning the worktree it ended up in.
+fn execute_checkout(plan: CheckoutPlan, agent: Agent, options: CheckoutOptions<'_>) -> Result<PathBuf, String> {
+    timing!("execute_checkout");
+    let CheckoutPlan { repo_root, worktree_name, source, existing, title, color_key, prompt } = plan;
+    // A batch runs checkouts one after another; each banner counts its own
+    SETUP_WARNINGS.with_borrow_mut(Vec::clear);
+    let worktree_dir = if options.worktree_in_repo { sibling_worktree_dir(&repo_root) } else { default_worktree_dir(&repo_root) };
+    let checkout = options.checkout;
+    if options.open_pr && checkout.no_checkout {
+        // The empty commit would be made against an empty index
+        return Err("--pr needs the worktree's files; drop --no-checkout".to_string());
     }
 
-    let worktrees = get_all_worktrees(&repo_root)?;
+    let mut resume_target = None;
+    let mut is_new_worktree = false;
 
-    if worktrees.is_empty() {
-        println!("{} No worktrees found", "→".blue().bold());
-        return Ok(());
-    }
+    let final_path = if options.into_current {
+        existing.ok_or_else(|| "--into-current found no current worktree".to_string())?
+    } else if let Some(existing_path) = existing {
+        sayln!(
+            "\n{} Worktree already exists at {}",
+            "!".yellow().bold(),
+            existing_path.display().to_string().cyan()
+        );
 
-    // Orphaned processes are removable because remove_worktrees terminates them.
-    let removable_worktrees: Vec<_> = worktrees.iter().filter(|w| !w.has_changes && !w.has_active_session).collect();
-    let modified_worktrees: Vec<_> = worktrees.iter().filter(|w| w.has_changes && !w.has_active_session).collect();
-    let active_worktrees: Vec<_> = worktrees.iter().filter(|w| w.has_active_session).collect();
+        let available_resume = find_worktree_resume_target(&existing_path);
+        let action = if options.resume_existing {
+            available_resume
+                .map(ExistingWorktreeAction::ResumeSession)
+                .unwrap_or(ExistingWorktreeAction::UseExisting)
+        } else if options.use_existing {
+            ExistingWorktreeAction::UseExisting
+        } else if options.allow_dirty {
+            ExistingWorktreeAction::UseAsIs
+        } else {
+            let changes_handle = {
+                let path = existing_path.clone();
+                thread::spawn(move || get_uncommitted_status(&path))
+            };
+            prompt_existing_worktree_action(&existing_path, changes_handle, agent, available_resume)?
+        };
 
-    // Among removable worktrees, keep the N most recently exited reusable ones
-    // so `checkout begin` can reuse them instead of creating new worktrees.
-    const REUSABLE_POOL_SIZE: usize = 5;
+        match action {
+            ExistingWorktreeAction::ResumeSession(target) => {
+                resume_target = Some(target);
+                existing_path
+            }
+            ExistingWorktreeAction::UseExisting => {
+                if let Some(on_dirty) = options.force_update {
+                    source.force_refresh(&existing_path, on_dirty, checkout.no_fetch)?;
+                } else if options.use_existing {
+                    // The prompt that normally confirms discarding changes was skipped
+                    if let Some(changes) = get_uncommitted_status(&existing_path)? {
+                        confirm_discard_changes(&existing_path, &changes)?;
+                    }
+                    source.refresh_existing(&existing_path, checkout.no_fetch)?;
+                } else if !options.resume_existing {
+                    source.refresh_existing(&existing_path, checkout.no_fetch)?;
+                }
+                existing_path
+            }
+            ExistingWorktreeAction::UseAsIs => {
+                progressln!("{} Using the worktree as it is, without updating", "→".blue().bold());
+                existing_path
+            }
+            ExistingWorktreeAction::CreateNew => {
+                let new_path = find_next_worktree_path(&worktree_dir, &worktree_name)?;
+                source.create(&repo_root, &worktree_dir, &new_path, checkout)?;
+                is_new_worktree = true;
+                new_path
+            }
+        }
+    } else {
+        let worktree_path = worktree_dir.join(&worktree_name);
+        source.create(&repo_root, &worktree_dir, &worktree_path, checkout)?;
+        is_new_worktree = true;
+        worktree_path
+    };
 
-    let kept_for_reuse: HashSet<PathBuf> = {
-        let mut reusable: Vec<(u64, &PathBuf)> = removable_worktrees.iter()
-            .filter(|w| {
-                let dir_name = w.path.file_name().and_then(|s| s.to_str()).unwrap_or("");
-                is_checkout_new_worktree(dir_name)
-            })
-            .map(|w| {
-                let ts = fs::read_to_string(session_exited_file(&w.path))
-                    .ok()
-                    .and_then(|c| c.lines().next().and_then(|l| l.trim().parse::<u64>().ok()))
-                    .unwrap_or(0);
-                (ts, &w.path)
-            })
-            .collect();
-        // Most recent exits first
-        reusable.sort_by(|a, b| b.0.cmp(&a.0));
-        reusable.into_iter().take(REUSABLE_POOL_SIZE).map(|(_, p)| p.clone()).collect()
+    let checked_out = !(is_new_worktree && checkout.no_checkout);
+    let bg_handle = if is_new_worktree && checked_out {
+        Some(start_new_worktree_setup(final_path.clone(), repo_root.clone(), checkout.strict)?)
+    } else {
+        None
     };
 
-    let actually_removing: Vec<_> = removable_worktrees.iter().filter(|w| !kept_for_reuse.contains(&w.path)).collect();
-    let kept_worktrees: Vec<_> = removable_worktrees.iter().filter(|w| kept_for_reuse.contains(&w.path)).collect();
+    if options.force_update.is_some() {
+        sayln!("{} HEAD {}", "✓".green().bold(), git::short_sha(&final_path, "HEAD")?);
+    }
 
-    if !actually_removing.is_empty() {
-        println!(
-            "{} Removing {} worktree(s):\n",
-            "→".blue().bold(),
-            actually_removing.len()
-        );
+    if options.push {
+        publish_branch(&final_path, source.branch(), options.open_pr && is_new_worktree)?;
+    }
+    if options.open_pr {
+        create_draft_pr(&final_path, source.branch())?;
+    }
 
-        for wt in &actually_removing {
-            let dir_name = wt.path.file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| wt.path.display().to_string());
+    let launch_agent = resume_target.as_ref().map_or(agent, |target| target.agent);
+    if checked_out {
+        prepare_agent_worktree(launch_agent, &final_path, &repo_root, checkout.strict)?;
+    }
 
-            let orphan_note = if !wt.orphaned_pids.is_empty() {
-                format!(
-                    " {} {}",
-                    format!("(killing {} orphaned agent process{})", wt.orphaned_pids.len(),
-                        if wt.orphaned_pids.len() == 1 { "" } else { "es" }).yellow(),
-                    format!("pid {}", wt.orphaned_pids.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")).dimmed()
-                )
-            } else {
-                String::new()
-            };
+    let base_worktree = options.with_base.map(|base| ensure_base_worktree(&repo_root, base, checkout)).transpose()?;
 
-            println!(
-                "  {} {} {}{}",
-                format!("[{}]", "remove".red()),
-                dir_name.cyan(),
-                format!("({})", wt.branch).dimmed(),
-                orphan_note
-            );
-        }
+    print_ready_banner(&final_path);
+    write_worktree_path(&final_path)?;
+    if let Some((base_path, _)) = &base_worktree {
+        sayln!("  {} {}", "base:".dimmed(), path_link(base_path, base_path.display().to_string().cyan()));
     }
 
-    if !kept_worktrees.is_empty() {
-        if !actually_removing.is_empty() {
-            println!();
+    if let Some(base) = options.base_compare {
+        print_base_compare(&repo_root, &final_path, base, checkout.no_fetch);
+    }
+    if let Some(pr_number) = source.pr_number() {
+        let origin_branch = match &source {
+            Source::RemoteBranch { fork: true, .. } => None,
+            _ => Some(source.branch()),
+        };
+        remember_pr(&repo_root, pr_number, origin_branch, options.pr_input);
+        remember_pr_base(&repo_root, pr_number, options.with_base);
+        report_review_progress(&repo_root, &final_path, pr_number, options.since_commit);
+        if options.mark_viewed {
+            mark_pr_files_viewed(&repo_root, pr_number);
         }
-        println!(
-            "{} Keeping {} worktree(s) for reuse:\n",
-            "→".blue().bold(),
-            kept_worktrees.len()
+    }
+
+    if !checked_out {
+        print_no_checkout_note(&final_path);
+    }
+    if options.no_agent || !checked_out {
+        record_last_checkout(
+            &final_path,
+            source.branch(),
+            source.pr_number(),
+            title.as_deref(),
+            get_worktree_color(&final_path),
+        );
+        print_enter_hint(&final_path, launch_agent);
+    } else {
+        let key = color_key.as_ref().map(|key| (repo_root.as_path(), key));
+        let bg_color = assign_worktree_color(&final_path, key, options.color_seed)?;
+        record_last_checkout(
+            &final_path,
+            source.branch(),
+            source.pr_number(),
+            title.as_deref(),
+            Some(bg_color.clone()),
         );
+        record_current_iterm_session(&final_path)?;
+        let session_name = session_name_from_branch(source.branch());
 
-        for wt in &kept_worktrees {
-            let dir_name = wt.path.file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| wt.path.display().to_string());
+        // Guard ensures terminal settings are reset even on Ctrl+C or panic
+        let tab_title = terminal_title(&session_name, &repo_root);
+        let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, terminal_guard_options(checkout));
+        run_on_enter_script(&final_path, &bg_color, &session_name);
 
-            println!(
-                "  {} {} {}",
-                format!("[{}]", "reuse".green().bold()),
-                dir_name.cyan(),
-                format!("({})", wt.branch).dimmed()
+        let system_prompt = build_worktree_system_prompt();
+
+        if let Some(target) = &resume_target {
+            sayln!();
+            progressln!(
+                "{} Resuming last {} session...",
+                "→".blue().bold(),
+                target.agent.display_name(),
             );
+            sayln!();
+            let resume_prompt = match &prompt {
+                AgentPrompt::Text(text) => Some(text.as_str()),
+                AgentPrompt::Skill(_) | AgentPrompt::None => None,
+            };
+            spawn_agent_continue(
+                target.agent,
+                &final_path,
+                resume_prompt,
+                Some(&system_prompt),
+                target.resume_id.as_deref(),
+                &session_name,
+                &checkout.env,
+            )?;
+        } else {
+            let prompt = match (prompt, source.pr_number()) {
+                (AgentPrompt::Skill(skill), Some(pr_number)) if agent == Agent::Claude => {
+                    AgentPrompt::Skill(claude_pr_prompt(&skill, pr_number, &final_path))
+                }
+                (prompt, _) => prompt,
+            };
+            sayln!();
+            match &prompt {
+                AgentPrompt::Skill(skill) => progressln!(
+                    "{} Spawning {} with {}...",
+                    "→".blue().bold(),
+                    agent.display_name(),
+                    skill.cyan()
+                ),
+                AgentPrompt::Text(_) | AgentPrompt::None => progressln!(
+                    "{} Spawning {}...",
+                    "→".blue().bold(),
+                    agent.display_name(),
+                ),
+            }
+            sayln!();
+
+            match &prompt {
+                AgentPrompt::Skill(text) | AgentPrompt::Text(text) => spawn_agent_with_prompt(
+                    agent,
+                    &final_path,
+                    text,
+                    Some(&system_prompt),
+                    &session_name,
+                    &checkout.env,
+                )?,
+                AgentPrompt::None => {
+                    spawn_agent(agent, &final_path, Some(&system_prompt), &session_name, &checkout.env)?
+                }
+            }
         }
     }
 
-    if !active_worktrees.is_empty() {
-        if !actually_removing.is_empty() || !kept_worktrees.is_empty() {
-            println!();
-        }
-        println!(
-            "{} Keeping {} worktree(s) with active agent sessions:\n",
-            "→".blue().bold(),
-            active_worktrees.len()
-        );
+    if let Some(handle) = bg_handle {
+        let _ = handle.join();
+    }
+    if let Some((_, Some(handle))) = base_worktree {
+        let _ = handle.join();
+    }
 
-        for wt in &active_worktrees {
-            let dir_name = wt.path.file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| wt.path.display().to_string());
-            let active_status = format!("[{}]", "active".blue().bold());
-            let branch_status = format!("({})", wt.branch).dimmed();
+    Ok(final_path)
+}
 
-            println!(
-                "  {} {} {} {}",
-                active_status,
-                dir_name.cyan(),
-                branch_status,
-                wt.active_agent.unwrap_or_default().display_name().dimmed()
-            );
-        }
+/// `checkout pr` for a single PR, returning the worktree it checked out.
+fn run_pr(
+    pr: &str,
+    no_agent: bool,
+    repo: Option<PathBuf>,
+    initial_prompt: &str,
+    chained_skill: Option<&str>,
+    agent: Agent,
+    flags: PrFlags<'_>,
+) -> Result<PathBuf, String> {
+    timing!("run_pr");
+    let pr_number = parse_pr_arg(pr)?;
+    progressln!(
+        "{} PR #{}",
+        "→".blue().bold(),
+        pr_number.to_string().cyan()
+    );
+
+    let repo_root = resolve_checkout_repo_root(repo, flags.checkout);
+
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
     }
 
-    if !modified_worktrees.is_empty() {
-        if !actually_removing.is_empty() || !kept_worktrees.is_empty() || !active_worktrees.is_empty() {
-            println!();
-        }
-        println!(
-            "{} {} worktree(s) with uncommitted changes (will prompt individually):\n",
-            "→".blue().bold(),
-            modified_worktrees.len()
+    progress!("{} Fetching PR details... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    let pr_details = fetch_pr_details(pr_number, &repo_root)?;
+    progressln!("{}", "done".green());
+
+    progressln!(
+        "  {} {}",
+        "title:".dimmed(),
+        pr_details.title.white().bold()
+    );
+    match (&pr_details.head_repository_owner, pr_details.is_cross_repository) {
+        (Some(owner), true) => progressln!(
+            "  {} {}:{} {}",
+            "branch:".dimmed(),
+            owner.login,
+            pr_details.head_ref_name.yellow(),
+            "(fork)".dimmed()
+        ),
+        _ => progressln!("  {} {}", "branch:".dimmed(), pr_details.head_ref_name.yellow()),
+    }
+    if let Some(author) = &pr_details.author {
+        progressln!("  {} @{}", "author:".dimmed(), author.login);
+    }
+    if !pr_details.base_ref_name.is_empty() {
+        // A release-branch PR may not merge cleanly onto the default branch;
+        // say nothing when origin/HEAD isn't known locally
+        let not_default = git::default_branch(&repo_root, git::REMOTE)
+            .ok()
+            .flatten()
+            .is_some_and(|default| default != pr_details.base_ref_name);
+        progressln!(
+            "  {} {}{}",
+            "base:".dimmed(),
+            pr_details.base_ref_name.yellow(),
+            if not_default { format!(" {}", "(not default)".dimmed()) } else { String::new() }
         );
+    }
+    confirm_inactive_pr(pr_number, &pr_details.state, flags.force)?;
+    if flags.with_base && pr_details.base_ref_name.is_empty() {
+        return Err(format!("GitHub didn't report a base branch for PR #{}", pr_number));
+    }
 
-        for wt in &modified_worktrees {
-            let dir_name = wt.path.file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| wt.path.display().to_string());
+    let existing = if flags.into_current {
+        Some(move_current_worktree_to_pr(&repo_root, pr_number, &pr_details, flags.checkout.no_fetch)?)
+    } else {
+        match find_pr_worktree(&repo_root, pr_number, pr_details.origin_branch(), true)? {
+            Some(path) if !is_pr_worktree_dir(&path, pr_number) => {
+                confirm_branch_worktree_reuse(&path, &pr_details.head_ref_name, flags)?.then_some(path)
+            }
+            other => other,
+        }
+    };
 
-            println!(
-                "  {} {} {}",
-                format!("[{}]", "modified".yellow().bold()),
-                dir_name.cyan(),
-                format!("({})", wt.branch).dimmed()
-            );
+    let plan = pr_checkout_plan(repo_root, pr_number, &pr_details, existing, initial_prompt, chained_skill);
+    let color_seed = flags.color_by.and_then(|by| pr_color_seed(by, pr_number, &pr_details));
+    let options = CheckoutOptions {
+        no_agent,
+        resume_existing: flags.resume_existing,
+        use_existing: flags.use_existing,
+        allow_dirty: flags.allow_dirty,
+        into_current: flags.into_current,
+        force_update: flags.force_update,
+        base_compare: flags.base_compare.then_some(pr_details.base_ref_name.as_str()),
+        since_commit: flags.since_commit,
+        mark_viewed: flags.mark_viewed,
+        worktree_in_repo: flags.worktree_in_repo,
+        pr_input: Some(pr),
+        with_base: flags.with_base.then_some(pr_details.base_ref_name.as_str()),
+        color_seed: color_seed.as_deref(),
+        checkout: flags.checkout,
+        ..CheckoutOptions::default()
+    };
+    execute_checkout(plan, agent, options)
+}
+
+/// `checkout pr --into-current`: move the worktree the shell is in, which
+/// must be one checkout created, to the PR's head (detached, like a new PR
+/// worktree) rather than creating another directory. A branch it was on is
+/// left where it was, and the directory is renamed to the PR's so later
+/// `checkout pr` runs find it.
+fn move_current_worktree_to_pr(
+    repo_root: &PathBuf,
+    pr_number: u64,
+    pr_details: &PrDetails,
+    no_fetch: bool,
+) -> Result<PathBuf, String> {
+    let cwd = env::current_dir().map_err(|e| format!("Failed to read the current directory: {}", e))?;
+    let current = git::toplevel(&cwd)?;
+    let current = current.canonicalize().unwrap_or(current);
+    let new_path = into_current_target(repo_root, &current, pr_number, pr_details)?;
+    if let Some(changes) = get_uncommitted_status(&current)? {
+        confirm_discard_changes(&current, &changes)?;
+    }
+
+    let branch = &pr_details.head_ref_name;
+    let remote_ref = format!("origin/{}", branch);
+    let previous = git::current_branch(&current)?;
+    if previous.is_none() {
+        // A detached HEAD's own commits are only reachable from HEAD
+        let commits = git::unreferenced_commits(&current)?;
+        if !commits.is_empty() {
+            confirm_drop_local_commits(&current, &remote_ref, &commits, LocalCommits::Prompt)?;
         }
     }
+    if use_local_ref(repo_root, branch, no_fetch) {
+        print_using_local_ref(&remote_ref, "--no-fetch");
+    } else {
+        progress!("{} Fetching {}... ", "→".blue().bold(), branch.yellow());
+        io::stdout().flush().ok();
+        fetch_branch(repo_root, branch)?;
+        progressln!("{}", "done".green());
+    }
+    git::force_detach_at(&current, &remote_ref)?;
+    if new_path != current {
+        git::move_worktree(repo_root, &current, &new_path)?;
+        move_worktree_state(std::slice::from_ref(&current), &new_path)?;
+    }
+    progressln!(
+        "{} Moved {} to PR #{}{}",
+        "→".blue().bold(),
+        worktree_dir_name(&current).cyan(),
+        pr_number,
+        previous.map_or_else(String::new, |branch| format!(" {}", format!("(branch {} is left as it was)", branch).dimmed()))
+    );
+    if new_path != current {
+        progressln!("{} Renamed it to {}", "→".blue().bold(), worktree_dir_name(&new_path).cyan());
+    }
+    Ok(new_path)
+}
 
-    // Partition into owned vecs for removal, excluding those kept for reuse
-    let (removable, modified): (Vec<_>, Vec<_>) = worktrees.into_iter()
-        .filter(|w| !w.has_active_session && !kept_for_reuse.contains(&w.path))
-        .partition(|w| !w.has_changes);
-
-    if removable.is_empty() && modified.is_empty() {
-        println!("\n{} Nothing to remove", "→".blue().bold());
-        return Ok(());
+/// Check that `--into-current` may move `current` to PR `pr_number`, and
+/// return where the worktree ends up: renamed to the PR's directory name
+/// unless it already has one.
+fn into_current_target(
+    repo_root: &PathBuf,
+    current: &PathBuf,
+    pr_number: u64,
+    pr_details: &PrDetails,
+) -> Result<PathBuf, String> {
+    if current == repo_root {
+        return Err(format!("--into-current moves a worktree to the PR, but {} is the main repo", current.display()));
     }
+    if !git::list_worktrees(repo_root)?.iter().any(|entry| entry.path == *current) {
+        return Err(format!(
+            "{} isn't a worktree of {} (use --repo for another repo)",
+            current.display(),
+            repo_root.display()
+        ));
+    }
+    let [worktree_dir, canonical_worktree_dir] = owned_worktree_dirs(repo_root);
+    let managed = current.starts_with(&worktree_dir)
+        || current.starts_with(&canonical_worktree_dir)
+        || current.parent() == Some(sibling_worktree_dir(repo_root).as_path());
+    if !managed {
+        return Err(format!(
+            "{} wasn't created by checkout (it's outside {}); leaving it alone",
+            current.display(),
+            worktree_dir.display()
+        ));
+    }
+    if pr_details.is_cross_repository {
+        return Err(format!("PR #{} is from a fork; check it out into its own worktree instead", pr_number));
+    }
+    if let Some(other) = find_pr_worktree(repo_root, pr_number, pr_details.origin_branch(), true)?.filter(|path| path != current) {
+        return Err(format!(
+            "PR #{} is already checked out in {}; `checkout pr {}` opens it",
+            pr_number,
+            worktree_dir_name(&other),
+            pr_number
+        ));
+    }
+    if is_pr_worktree_dir(current, pr_number) {
+        return Ok(current.clone());
+    }
+    let new_path = current.with_file_name(format!("{}{}", pr_dir_prefix(pr_number), create_slug(&pr_details.title)));
+    if new_path.exists() {
+        return Err(format!("{} already exists; remove it or check the PR out normally", new_path.display()));
+    }
+    Ok(new_path)
+}
 
-    // Kick off `git status --short` for each modified worktree in the background
-    // so the detailed file list is ready by the time we prompt. Prompting is
-    // sequential and gated on user input, so these run for free while the UI
-    // (the clean-worktree batch prompt and earlier per-worktree prompts) waits.
-    let mut status_handles: HashMap<PathBuf, thread::JoinHandle<Result<Option<String>, String>>> =
-        HashMap::new();
-    for w in &modified {
-        let path = w.path.clone();
-        status_handles.insert(w.path.clone(), thread::spawn(move || get_uncommitted_status(&path)));
+/// What `--worktree-color-by` hashes for this PR, prefixed with the kind so an
+/// author and a branch of the same name don't collide. PRs whose author is
+/// unknown fall back to the usual first unused color.
+fn pr_color_seed(by: ColorBy, pr_number: u64, pr_details: &PrDetails) -> Option<String> {
+    match by {
+        ColorBy::Author => pr_details.author.as_ref().map(|author| format!("author:{}", author.login)),
+        ColorBy::Base => {
+            (!pr_details.base_ref_name.is_empty()).then(|| format!("base:{}", pr_details.base_ref_name))
+        }
+        ColorBy::Pr => Some(format!("pr:{}", pr_number)),
     }
-    let modified_paths = modified.iter().map(|worktree| worktree.path.clone()).collect();
-    let agent_session_times = find_agent_session_times(&modified_paths);
+}
 
-    // Collect all confirmations upfront before any removals
-    let mut all_to_remove: Vec<WorktreeInfo> = Vec::new();
+/// Split `checkout pr`'s arguments into PRs and the optional trailing skill:
+/// a last argument starting with `/` or `$`, when it follows at least one.
+/// Anything else is a PR, so a mistyped one is reported rather than dropped.
+fn split_pr_args(mut args: Vec<String>) -> (Vec<String>, Option<String>) {
+    let skill = match args.as_slice() {
+        [_, .., last] if last.starts_with(['/', '$']) => args.pop(),
+        _ => None,
+    };
+    (args, skill)
+}
 
-    // Batch-confirm clean worktrees
-    if !removable.is_empty() {
-        if !skip_confirm {
+/// `checkout pr 1 2 3`: check out each PR in turn without starting an agent,
+/// carrying on past failures, then list where each one ended up.
+fn run_pr_batch(
+    prs: &[String],
+    repo: Option<PathBuf>,
+    initial_prompt: &str,
+    chained_skill: Option<&str>,
+    agent: Agent,
+    flags: PrFlags<'_>,
+) -> Result<(), String> {
+    let mut results: Vec<(&str, Result<PathBuf, String>)> = Vec::new();
+    for (i, pr) in prs.iter().enumerate() {
+        if i > 0 {
             println!();
-            print!(
-                "{} Remove {} clean worktree(s)? [y/N]: ",
-                "?".magenta().bold(),
-                removable.len()
-            );
-            io::stdout().flush().map_err(|e| e.to_string())?;
-
-            let mut input = String::new();
-            io::stdin()
-                .read_line(&mut input)
-                .map_err(|e| format!("Failed to read input: {}", e))?;
-
-            if input.trim().to_lowercase() == "y" {
-                all_to_remove.extend(removable);
-            }
-        } else {
-            all_to_remove.extend(removable);
         }
+        println!("{}", format!("[{}/{}]", i + 1, prs.len()).dimmed());
+        let result = run_pr(pr, true, repo.clone(), initial_prompt, chained_skill, agent, flags);
+        // Ctrl+C stops the whole batch; a declined PR is only skipped
+        if result.is_err() && Interrupt::take() == Some(Interrupt::Cancelled) {
+            return Err(Interrupt::Cancelled.raise());
+        }
+        if let Err(e) = &result {
+            println!("{} {}", "✗".red().bold(), e);
+        }
+        results.push((pr, result));
     }
 
-    // Prompt individually for modified worktrees
-    if !modified.is_empty() {
-        println!();
-        for wt in modified {
-            let dir_name = wt.path.file_name()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| wt.path.display().to_string());
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    let labels: Vec<String> = results
+        .iter()
+        .map(|(pr, _)| extract_pr_number(pr).map_or_else(|_| pr.to_string(), |n| format!("#{}", n)))
+        .collect();
+    let width = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+    println!();
+    println!("{} Checked out {} of {} PR(s):", "→".blue().bold(), results.len() - failed, results.len());
+    for (label, (_, result)) in labels.iter().zip(&results) {
+        match result {
+            Ok(path) => println!(
+                "  {} {:<width$}  {}",
+                "✓".green().bold(),
+                label,
+                path_link(path, path.display().to_string().cyan())
+            ),
+            Err(e) => println!("  {} {:<width$}  {}", "✗".red().bold(), label, e.dimmed()),
+        }
+    }
+    if failed > 0 {
+        return Err(format!("Failed to check out {} PR(s)", failed));
+    }
+    Ok(())
+}
 
-            // Worktree creation time (birthtime on macOS; falls back to mtime).
-            let created_str = fs::metadata(&wt.path)
-                .and_then(|m| m.created().or_else(|_| m.modified()))
-                .map(format_time_ago)
-                .unwrap_or_else(|_| "unknown".to_string());
+/// Options for how `run_branch` creates and publishes the branch.
+#[derive(Clone, Copy, Default)]
+struct BranchSetup<'a> {
+    /// Start point for a new branch (default: origin/master)
+    base: Option<&'a str>,
+    /// Push the branch and set its upstream
+    push: bool,
+    /// After pushing, open a draft PR (adds an empty commit to a new branch)
+    open_pr: bool,
+    /// Name the worktree after the full branch name, not its last component
+    no_prefix: bool,
+    checkout: &'a CheckoutArgs,
+}
 
-            let session_str = agent_session_times
-                .get(&wt.path)
-                .copied()
-                .map(format_time_ago)
-                .unwrap_or_else(|| "none".to_string());
+fn run_branch(
+    name: &str,
+    no_agent: bool,
+    prompt: Option<String>,
+    repo: Option<PathBuf>,
+    agent: Agent,
+    resume_existing: bool,
+    setup: BranchSetup<'_>,
+) -> Result<(), String> {
+    timing!("run_branch");
+    progressln!(
+        "{} Branch {}",
+        "→".blue().bold(),
+        name.cyan()
+    );
 
-            println!(
-                "{} {} {}",
-                "→".blue().bold(),
-                dir_name.cyan(),
-                "(has uncommitted changes)".yellow()
-            );
-            println!(
-                "    {}",
-                format!("created {} · last agent session {}", created_str, session_str).dimmed()
-            );
-            let status_result = status_handles.remove(&wt.path)
-                .and_then(|h| h.join().ok())
-                .unwrap_or_else(|| get_uncommitted_status(&wt.path));
-            if let Ok(Some(status)) = status_result {
-                let lines: Vec<&str> = status.lines().collect();
-                const MAX_SHOWN: usize = 10;
-                for line in lines.iter().take(MAX_SHOWN) {
-                    println!("    {}", line.dimmed());
-                }
-                if lines.len() > MAX_SHOWN {
-                    println!("    {}", format!("... and {} more", lines.len() - MAX_SHOWN).dimmed());
-                }
-            }
+    let repo_root = resolve_checkout_repo_root(repo, setup.checkout);
 
-            print!(
-                "{} Remove {}? [y/N]: ",
-                "?".magenta().bold(),
-                dir_name.cyan()
-            );
-            io::stdout().flush().map_err(|e| e.to_string())?;
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
 
-            let mut input = String::new();
-            io::stdin()
-                .read_line(&mut input)
-                .map_err(|e| format!("Failed to read input: {}", e))?;
+    let existing = if setup.no_prefix {
+        find_existing_worktree(&repo_root, DirPattern::Name(&verbatim_branch_dir_name(name)), true)?
+            .map_or_else(|| find_worktree_by_branch(&repo_root, name), |path| Ok(Some(path)))?
+    } else {
+        find_branch_worktree(&repo_root, name, true)?
+    };
+    let plan = branch_checkout_plan(repo_root, name, setup.base, setup.no_prefix, existing, prompt);
+    let options = CheckoutOptions {
+        no_agent,
+        resume_existing,
+        push: setup.push,
+        open_pr: setup.open_pr,
+        checkout: setup.checkout,
+        ..CheckoutOptions::default()
+    };
+    execute_checkout(plan, agent, options).map(drop)
+}
 
-            if input.trim().to_lowercase() == "y" {
-                all_to_remove.push(wt);
-            }
-        }
+/// Resolve `sha` locally, fetching the default branch once if it's missing
+/// (the usual case for a recent master commit).
+fn resolve_commit_fetching(repo_root: &PathBuf, sha: &str) -> Result<String, String> {
+    if let Some(commit) = git::resolve_commit(repo_root, sha)? {
+        return Ok(commit);
     }
+    let default_branch = git::default_branch(repo_root, git::REMOTE)?.unwrap_or_else(|| "master".to_string());
+    progress!(
+        "{} Commit not found locally, fetching {}... ",
+        "→".blue().bold(),
+        default_branch.yellow()
+    );
+    std::io::stdout().flush().ok();
+    fetch_branch(repo_root, &default_branch)?;
+    progressln!("{}", "done".green());
+    git::resolve_commit(repo_root, sha)?.ok_or_else(|| {
+        format!("Commit '{}' not found, even after fetching {}/{}", sha, git::REMOTE, default_branch)
+    })
+}
 
-    // Remove everything that was confirmed
-    if !all_to_remove.is_empty() {
-        println!();
-        remove_worktrees(&all_to_remove, &repo_root)?;
+fn run_commit(
+    sha: &str,
+    name: Option<&str>,
+    no_agent: bool,
+    repo: Option<PathBuf>,
+    agent: Agent,
+    checkout: &CheckoutArgs,
+) -> Result<(), String> {
+    timing!("run_commit");
+    progressln!("{} Commit {}", "→".blue().bold(), sha.cyan());
+
+    let repo_root = resolve_checkout_repo_root(repo, checkout);
+
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
     }
 
-    // Clean up stale workspace branches (darren/<adj>-<noun>, older than 7 days,
-    // not currently checked out by any worktree). These accumulate because
-    // `git worktree remove` doesn't delete the branch.
-    const STALE_BRANCH_AGE_DAYS: u64 = 7;
-    let active_branches: HashSet<String> = get_all_worktrees(&repo_root)?
-        .into_iter()
-        .map(|w| w.branch)
-        .collect();
-    let stale = find_stale_workspace_branches(&repo_root, &active_branches, STALE_BRANCH_AGE_DAYS)?;
-    if !stale.is_empty() {
-        println!();
-        println!(
-            "{} Found {} stale workspace branch(es) (older than {} days):\n",
-            "→".blue().bold(),
-            stale.len(),
-            STALE_BRANCH_AGE_DAYS,
-        );
-        for (name, age_days) in &stale {
-            println!(
-                "  {} {} {}",
-                format!("[{}]", "stale".red()),
-                name.cyan(),
-                format!("({} days old)", age_days).dimmed(),
-            );
-        }
+    let commit = resolve_commit_fetching(&repo_root, sha)?;
+    let short_sha = git::short_sha(&repo_root, &commit)?;
+    let dir_name = name.map(str::to_string).unwrap_or_else(|| format!("commit-{}", short_sha));
+    checkout_detached(&repo_root, &commit, &dir_name, &short_sha, no_agent, agent, checkout)
+}
 
-        let confirmed = if skip_confirm {
-            true
-        } else {
-            println!();
-            print!(
-                "{} Delete {} stale branch(es)? [y/N]: ",
-                "?".magenta().bold(),
-                stale.len(),
-            );
-            io::stdout().flush().map_err(|e| e.to_string())?;
-            let mut input = String::new();
-            io::stdin()
-                .read_line(&mut input)
-                .map_err(|e| format!("Failed to read input: {}", e))?;
-            input.trim().to_lowercase() == "y"
-        };
+/// `tag-<tag>` with anything but letters, digits, `-` and `_` turned into
+/// dashes, e.g. `v1.2.0` -> `tag-v1-2-0`.
+fn tag_worktree_name(tag: &str) -> String {
+    format!("tag-{}", ref_dir_slug(tag))
+}
 
-        if confirmed {
-            println!();
-            let mut deleted = 0usize;
-            let mut failed: Vec<String> = Vec::new();
-            for (name, _) in &stale {
-                print!("{} Deleting {}... ", "→".blue().bold(), name.cyan());
-                io::stdout().flush().ok();
-                let output = Command::new("git")
-                    .args(["-C", &repo_root.to_string_lossy(), "branch", "-D", name])
-                    .output()
-                    .map_err(|e| format!("Failed to spawn git branch -D: {}", e))?;
-                if output.status.success() {
-                    println!("{}", "done".green());
-                    deleted += 1;
-                } else {
-                    println!("{}", "failed".red());
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let msg = stderr.trim();
-                    if !msg.is_empty() {
-                        println!("    {} {}", "error:".red(), msg);
-                    }
-                    failed.push(name.clone());
-                }
-            }
-            if deleted > 0 {
-                println!(
-                    "{} Deleted {} stale branch(es)",
-                    "✓".green().bold(),
-                    deleted,
-                );
-            }
-            if !failed.is_empty() {
-                println!(
-                    "{} Failed to delete {} branch(es): {}",
-                    "✗".red().bold(),
-                    failed.len(),
-                    failed.join(", "),
-                );
-            }
-        }
-    }
+/// `base-<branch>`, slugged like tags: the worktree `--with-base` shares
+/// between PRs with the same base, e.g. `release/1.2` -> `base-release-1-2`
+fn base_worktree_name(branch: &str) -> String {
+    format!("base-{}", ref_dir_slug(branch))
+}
 
-    Ok(())
+fn ref_dir_slug(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
 }
 
-/// Trust mise configs before the agent starts, then run non-critical setup in
-/// the background. Trust is path-based, so this must also run after an idle
-/// worktree is moved to a new workspace path.
-fn start_new_worktree_setup(
-    worktree_path: PathBuf,
-    repo_root: PathBuf,
-) -> Result<thread::JoinHandle<()>, String> {
-    if which_mise().is_some() {
-        run_mise_trust(&worktree_path)?;
+/// Make sure the shared worktree for base branch `base` exists and, unless it
+/// has local work, sits at the latest `origin/<base>`. It gets dependency
+/// links for running the app but no mise or agent trust, since no agent runs
+/// there. Returns its path and the background setup of a new one.
+fn ensure_base_worktree(
+    repo_root: &PathBuf,
+    base: &str,
+    checkout: &CheckoutArgs,
+) -> Result<(PathBuf, Option<thread::JoinHandle<()>>), String> {
+    let remote_ref = format!("origin/{}", base);
+    if use_local_ref(repo_root, base, checkout.no_fetch) {
+        print_using_local_ref(&remote_ref, "--no-fetch");
+    } else {
+        progress!("{} Fetching base {}... ", "→".blue().bold(), base.yellow());
+        io::stdout().flush().ok();
+        fetch_branch(repo_root, base)?;
+        progressln!("{}", "done".green());
+    }
+
+    let worktree_dir = default_worktree_dir(repo_root);
+    let path = worktree_dir.join(base_worktree_name(base));
+    if git::list_worktrees(repo_root)?.iter().any(|entry| entry.path == path) {
+        let has_changes = get_uncommitted_status(&path)?.is_some();
+        let local_commits = git::commit_count(&path, &format!("{}..HEAD", remote_ref))?;
+        if has_changes || local_commits > 0 {
+            sayln!(
+                "{} Leaving base worktree {} as is {}",
+                "!".yellow().bold(),
+                worktree_dir_name(&path).cyan(),
+                "(it has local changes)".dimmed()
+            );
+        } else {
+            git::detach_at(&path, &remote_ref)?;
+        }
+        return Ok((path, None));
+    }
+    if path.exists() {
+        return Err(format!("{} exists but isn't a worktree of this repo", path.display()));
     }
 
-    Ok(spawn_background_setup(worktree_path, repo_root))
+    fs::create_dir_all(&worktree_dir).map_err(|e| format!("Failed to create worktrees dir: {}", e))?;
+    progressln!(
+        "{} Creating base worktree at {}",
+        "→".blue().bold(),
+        path.display().to_string().cyan()
+    );
+    create_worktree_from_ref(repo_root, &path, &remote_ref, None, checkout.no_checkout)?;
+    progressln!("  {}", "done".green());
+    let setup = (!checkout.no_checkout).then(|| spawn_background_setup(path.clone(), repo_root.clone()));
+    Ok((path, setup))
+}
+
+/// Record which base branch PR `pr_number` was last checked out against with
+/// `--with-base`, or that it no longer uses one. Base branches stay listed
+/// once seen so `status` keeps flagging their worktree.
+fn remember_pr_base(repo_root: &Path, pr_number: u64, base: Option<&str>) {
+    let _ = metadata::update(&get_data_dir(), |metadata| {
+        let pr_bases = metadata.pr_bases.entry(metadata::repo_key(repo_root)).or_default();
+        for prs in pr_bases.values_mut() {
+            prs.remove(&pr_number);
+        }
+        if let Some(base) = base {
+            pr_bases.entry(base.to_string()).or_default().insert(pr_number);
+        }
+    });
 }
 
-/// Spawn non-critical worktree setup steps in the background so the agent can
-/// start sooner. Errors are logged to stderr but otherwise ignored.
-fn spawn_background_setup(
-    worktree_path: PathBuf,
-    repo_root: PathBuf,
-) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        if let Err(e) = symlink_node_modules(&worktree_path, &repo_root) {
-            eprintln!("background: symlink_node_modules failed: {}", e);
-        }
+/// PRs last checked out `--with-base` against each of the repo's base branches
+fn repo_pr_bases(repo_root: &Path) -> BTreeMap<String, BTreeSet<u64>> {
+    metadata::load(&get_data_dir()).pr_bases.remove(&metadata::repo_key(repo_root)).unwrap_or_default()
+}
 
-        if let Err(e) = symlink_vendor_bundle(&worktree_path, &repo_root) {
-            eprintln!("background: symlink_vendor_bundle failed: {}", e);
-        }
+/// Base branch of each `--with-base` worktree, keyed by directory name
+fn base_worktree_branches(pr_bases: &BTreeMap<String, BTreeSet<u64>>) -> HashMap<String, &str> {
+    pr_bases.keys().map(|base| (base_worktree_name(base), base.as_str())).collect()
+}
 
-        // Validate the bundle against this checkout's Gemfile.lock now that the
-        // vendor/ cache is linked, so the first commit's Ruby hooks don't fail.
-        if let Err(e) = run_bundle_install(&worktree_path, &repo_root) {
-            eprintln!("background: run_bundle_install failed: {}", e);
-        }
-    })
+/// Base worktrees that a PR worktree still references, with those PRs. The
+/// rest can go whenever they're clean.
+fn base_worktree_users(
+    worktrees: &[WorktreeInfo],
+    pr_bases: &BTreeMap<String, BTreeSet<u64>>,
+) -> HashMap<PathBuf, Vec<u64>> {
+    let pr_worktrees: HashSet<u64> =
+        worktrees.iter().filter_map(|wt| ui::pr_number_from_dir(&worktree_dir_name(&wt.path))).collect();
+    let bases = base_worktree_branches(pr_bases);
+    worktrees
+        .iter()
+        .filter_map(|wt| {
+            let base = bases.get(&worktree_dir_name(&wt.path))?;
+            let users: Vec<u64> = pr_bases[*base].iter().copied().filter(|pr| pr_worktrees.contains(pr)).collect();
+            (!users.is_empty()).then(|| (wt.path.clone(), users))
+        })
+        .collect()
 }
 
-fn create_new_worktree_from_remote(
-    repo_root: &PathBuf,
-    worktree_dir: &PathBuf,
-    worktree_path: &PathBuf,
-    branch: &str,
-    pr_number: u64,
+/// The commit `tag` marks, fetching the tag from origin if it isn't local.
+/// Annotated tags are peeled to their commit.
+fn resolve_tag_fetching(repo_root: &PathBuf, tag: &str) -> Result<String, String> {
+    let tag_ref = format!("refs/tags/{}", tag);
+    if !git::ref_exists(repo_root, &tag_ref)? {
+        progress!("{} Tag not found locally, fetching {}... ", "→".blue().bold(), tag.yellow());
+        std::io::stdout().flush().ok();
+        fetch_branch(repo_root, &git::tag_refspec(tag))
+            .map_err(|e| format!("Tag '{}' not found locally or on {}: {}", tag, git::REMOTE, e))?;
+        progressln!("{}", "done".green());
+    }
+    git::rev_parse(repo_root, &tag_ref)
+}
+
+fn run_tag(
+    tag: &str,
+    name: Option<&str>,
+    no_agent: bool,
+    repo: Option<PathBuf>,
+    agent: Agent,
+    checkout: &CheckoutArgs,
 ) -> Result<(), String> {
-    timing!("create_new_worktree_from_remote");
-    std::fs::create_dir_all(worktree_dir)
-        .map_err(|e| format!("Failed to create worktrees dir: {}", e))?;
+    timing!("run_tag");
+    progressln!("{} Tag {}", "→".blue().bold(), tag.cyan());
 
-    print!(
-        "{} Fetching branch {}... ",
-        "→".blue().bold(),
-        branch.yellow()
-    );
-    std::io::stdout().flush().ok();
-    if fetch_branch(repo_root, branch).is_err() {
-        // Branch may have been deleted after merge — fetch the PR head ref instead
-        let pr_ref = format!("pull/{}/head", pr_number);
-        fetch_branch(repo_root, &pr_ref)?;
-    }
-    println!("{}", "done".green());
+    let repo_root = resolve_checkout_repo_root(repo, checkout);
 
-    println!(
-        "{} Creating worktree at {}",
-        "→".blue().bold(),
-        worktree_path.display().to_string().cyan()
-    );
-    create_worktree_from_ref(repo_root, worktree_path, &format!("origin/{}", branch))?;
-    if let Some(count) = count_worktree_files(worktree_path) {
-        println!("  {} ({} files)", "done".green(), count.to_string().yellow());
-    } else {
-        println!("  {}", "done".green());
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
     }
 
-    Ok(())
+    let commit = resolve_tag_fetching(&repo_root, tag)?;
+    let dir_name = name.map(str::to_string).unwrap_or_else(|| tag_worktree_name(tag));
+    checkout_detached(&repo_root, &commit, &dir_name, tag, no_agent, agent, checkout)
 }
 
-fn create_new_worktree_new_branch(
+/// Create (or reuse) a detached worktree named `dir_name` at `commit`, then
+/// launch the agent. `label` is what `checkout last` records as the branch.
+fn checkout_detached(
     repo_root: &PathBuf,
-    worktree_dir: &PathBuf,
-    worktree_path: &PathBuf,
-    branch: &str,
+    commit: &str,
+    dir_name: &str,
+    label: &str,
+    no_agent: bool,
+    agent: Agent,
+    checkout: &CheckoutArgs,
 ) -> Result<(), String> {
-    timing!("create_new_worktree_new_branch");
-    std::fs::create_dir_all(worktree_dir)
-        .map_err(|e| format!("Failed to create worktrees dir: {}", e))?;
+    let worktree_dir = default_worktree_dir(repo_root);
+    let worktree_path = worktree_dir.join(dir_name);
 
-    // Fetch latest master
-    print!("{} Fetching latest master... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    fetch_branch(repo_root, "master")?;
-    println!("{}", "done".green());
+    let existing = git::list_worktrees(repo_root)?
+        .into_iter()
+        .find(|entry| entry.path == worktree_path);
+    let is_new_worktree = match existing {
+        Some(entry) if entry.head == commit => {
+            sayln!(
+                "\n{} Worktree already exists at {}",
+                "!".yellow().bold(),
+                worktree_path.display().to_string().cyan()
+            );
+            false
+        }
+        Some(_) => {
+            return Err(format!(
+                "{} already exists at a different commit. Pass --name to pick another directory.",
+                worktree_path.display()
+            ));
+        }
+        None => {
+            std::fs::create_dir_all(&worktree_dir)
+                .map_err(|e| format!("Failed to create worktrees dir: {}", e))?;
+            progressln!(
+                "{} Creating worktree at {}",
+                "→".blue().bold(),
+                worktree_path.display().to_string().cyan()
+            );
+            create_worktree_from_ref(repo_root, &worktree_path, commit, None, checkout.no_checkout)?;
+            if let Some(count) = count_worktree_files(&worktree_path, checkout.no_checkout) {
+                progressln!("  {} ({} files)", "done".green(), count.to_string().yellow());
+            } else {
+                progressln!("  {}", "done".green());
+            }
+            true
+        }
+    };
 
-    println!(
-        "{} Creating worktree with new branch {}",
-        "→".blue().bold(),
-        branch.yellow()
-    );
-    create_worktree_new_branch(repo_root, worktree_path, branch)?;
-    if let Some(count) = count_worktree_files(worktree_path) {
-        println!("  {} ({} files)", "done".green(), count.to_string().yellow());
+    // Detached by design, so there's nothing to track with Graphite
+    let checked_out = !(is_new_worktree && checkout.no_checkout);
+    let bg_handle = if is_new_worktree && checked_out {
+        Some(start_new_worktree_setup(worktree_path.clone(), repo_root.clone(), checkout.strict)?)
     } else {
-        println!("  {}", "done".green());
-    }
-
-    // Track with graphite
-    print!("{} Tracking with Graphite... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    run_gt_track(worktree_path)?;
-    println!("{}", "done".green());
+        None
+    };
 
-    Ok(())
-}
+    if checked_out {
+        prepare_agent_worktree(agent, &worktree_path, repo_root, checkout.strict)?;
+    }
 
-/// Returns the short status output if there are uncommitted changes, None otherwise.
-fn get_uncommitted_status(worktree_path: &PathBuf) -> Result<Option<String>, String> {
-    timing!("get_uncommitted_status");
-    let output = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "status", "--short"])
-        .output()
-        .map_err(|e| format!("Failed to check git status: {}", e))?;
+    print_ready_banner(&worktree_path);
+    write_worktree_path(&worktree_path)?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if stdout.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(stdout))
+    if !checked_out {
+        print_no_checkout_note(&worktree_path);
     }
-}
-
-fn prompt_existing_worktree_action(
-    changes_handle: thread::JoinHandle<Result<Option<String>, String>>,
-    selected_agent: Agent,
-    mut resume_target: Option<ResumeTarget>,
-) -> Result<ExistingWorktreeAction, String> {
-    println!();
-    let (use_existing_choice, create_new_choice, valid_choices) = if let Some(target) = &resume_target {
-        println!(
-            "  {} {}",
-            "[1]".cyan().bold(),
-            resume_option_label(selected_agent, target)
-        );
-        println!(
-            "  {} Use existing worktree",
-            "[2]".cyan().bold()
-        );
-        println!(
-            "  {} Create new worktree",
-            "[3]".cyan().bold()
-        );
-        ("2", "3", "1/2/3")
+    if no_agent || !checked_out {
+        record_last_checkout(&worktree_path, label, None, None, get_worktree_color(&worktree_path));
+        print_enter_hint(&worktree_path, agent);
     } else {
-        println!(
-            "  {} Use existing worktree {}",
-            "[1]".cyan().bold(),
-            "(no session found to resume)".dimmed()
-        );
-        println!(
-            "  {} Create new worktree",
-            "[2]".cyan().bold()
-        );
-        ("1", "2", "1/2")
-    };
-    println!();
+        let bg_color = assign_worktree_color(&worktree_path, None, None)?;
+        record_last_checkout(&worktree_path, label, None, None, Some(bg_color.clone()));
+        record_current_iterm_session(&worktree_path)?;
 
-    loop {
-        print!(
-            "{} Choose an option [{}]: ",
-            "?".magenta().bold(),
-            valid_choices
-        );
-        io::stdout().flush().map_err(|e| e.to_string())?;
+        // Guard ensures terminal settings are reset even on Ctrl+C or panic
+        let tab_title = terminal_title(dir_name, repo_root);
+        let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, terminal_guard_options(checkout));
+        run_on_enter_script(&worktree_path, &bg_color, dir_name);
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .map_err(|e| format!("Failed to read input: {}", e))?;
-
-        match input.trim() {
-            "1" if resume_target.is_some() => {
-                return Ok(ExistingWorktreeAction::ResumeSession(
-                    resume_target.take().unwrap(),
-                ));
-            }
-            choice if choice == use_existing_choice => {
-                let status = changes_handle
-                    .join()
-                    .map_err(|_| "Failed to check git status".to_string())??;
-                if let Some(changes) = status {
-                    // Separate tracked changes from untracked files
-                    let tracked: Vec<&str> = changes.lines().filter(|l| !l.starts_with("??")).collect();
-                    let untracked: Vec<&str> = changes.lines().filter(|l| l.starts_with("??")).collect();
-
-                    if !untracked.is_empty() {
-                        println!();
-                        println!(
-                            "{} Worktree has untracked files {}:",
-                            "!".yellow().bold(),
-                            "(will be kept)".dimmed()
-                        );
-                        for line in &untracked {
-                            println!("  {}", line.dimmed());
-                        }
-                    }
+        let system_prompt = build_worktree_system_prompt();
+        sayln!();
+        progressln!("{} Spawning {}...", "→".blue().bold(), agent.display_name());
+        sayln!();
+        spawn_agent(agent, &worktree_path, Some(&system_prompt), dir_name, &checkout.env)?;
+    }
 
-                    if !tracked.is_empty() {
-                        println!();
-                        println!(
-                            "{} Worktree has uncommitted changes:",
-                            "!".yellow().bold()
-                        );
-                        for line in &tracked {
-                            println!("  {}", line.dimmed());
-                        }
-                        println!();
-                        print!(
-                            "{} Discard these changes? [y/N]: ",
-                            "!".yellow().bold()
-                        );
-                        io::stdout().flush().map_err(|e| e.to_string())?;
-
-                        let mut confirm = String::new();
-                        io::stdin()
-                            .read_line(&mut confirm)
-                            .map_err(|e| format!("Failed to read input: {}", e))?;
-
-                        if confirm.trim().to_lowercase() != "y" {
-                            println!("{} Cancelled", "→".blue().bold());
-                            std::process::exit(0);
-                        }
-                    }
-                }
-                return Ok(ExistingWorktreeAction::UseExisting);
-            }
-            choice if choice == create_new_choice => return Ok(ExistingWorktreeAction::CreateNew),
-            _ => {
-                println!(
-                    "{} Invalid option, please enter {}",
-                    "!".red().bold(),
-                    valid_choices.replace('/', ", ")
-                );
-            }
-        }
+    if let Some(handle) = bg_handle {
+        let _ = handle.join();
     }
-}
 
-fn resume_option_label(selected_agent: Agent, target: &ResumeTarget) -> String {
-    if selected_agent == target.agent {
-        format!(
-            "Resume last {} session (keep changes, skip update)",
-            target.agent.display_name()
-        )
-    } else {
-        format!(
-            "Resume last {} session (switch from selected {}; keep changes, skip update)",
-            target.agent.display_name(),
-            selected_agent.display_name()
-        )
-    }
+    Ok(())
 }
 
-/// Rename a worktree's metadata directory under `.git/worktrees/` to match its
-/// new working-dir name. `git worktree move` only renames the working dir; the
-/// metadata dir keeps its original name, so a recycled worktree ends up with
-/// `branch-coy-oak/.git` pointing at `.git/worktrees/branch-rusty-marsh`.
-fn rename_worktree_metadata(worktree_path: &Path, new_metadata_name: &str) -> Result<(), String> {
-    let dot_git = worktree_path.join(".git");
-    let pointer = fs::read_to_string(&dot_git)
-        .map_err(|e| format!("Failed to read {}: {}", dot_git.display(), e))?;
-    let old_metadata_path = pointer
-        .lines()
-        .next()
-        .and_then(|l| l.strip_prefix("gitdir:"))
-        .ok_or_else(|| format!("{} is not a gitdir pointer file", dot_git.display()))?
-        .trim();
-    let old_metadata_path = PathBuf::from(old_metadata_path);
+const ADJECTIVES: &[&str] = &[
+    "azure", "bold", "calm", "deft", "eager", "fair", "glad", "hale",
+    "keen", "lush", "mild", "neat", "open", "pure", "quick", "rare",
+    "sage", "tame", "vast", "warm", "zesty", "brave", "crisp", "dense",
+    "fond", "grim", "hazy", "idle", "just", "kind", "lean", "mute",
+    "agile", "apt", "arid", "ashen", "avid", "blunt", "brisk", "coral",
+    "coy", "damp", "dim", "dry", "dusky", "elfin", "even", "faint",
+    "fell", "firm", "flat", "fleet", "flush", "frail", "fresh", "frost",
+    "full", "gaunt", "glib", "gold", "grand", "gray", "green", "gruff",
+    "gusty", "hardy", "hazel", "hex", "high", "hoary", "hush", "icy",
+    "inky", "ivory", "jet", "jolly", "lax", "light", "lithe", "live",
+    "lone", "lucid", "lunar", "meek", "misty", "mossy", "new", "numb",
+    "oaken", "opal", "pale", "peak", "plaid", "plum", "polar", "proud",
+    "quiet", "rapid", "raw", "regal", "rigid", "rocky", "rosy", "rough",
+    "rusty", "sandy", "sharp", "sheer", "shy", "silky", "slim", "snowy",
+    "solar", "solid", "spare", "stark", "steep", "still", "stony", "stout",
+    "swift", "terse", "thin", "tidal", "tiny", "trim", "true", "twin",
+    "vivid", "wary", "wavy", "white", "whole", "wide", "wild", "wiry",
+];
 
-    let metadata_parent = old_metadata_path
-        .parent()
-        .ok_or_else(|| format!("metadata path {} has no parent", old_metadata_path.display()))?;
-    let new_metadata_path = metadata_parent.join(new_metadata_name);
+const NOUNS: &[&str] = &[
+    "brook", "cedar", "dune", "elm", "flint", "grove", "heron", "iris",
+    "jade", "knoll", "lark", "moss", "nova", "oak", "pine", "quartz",
+    "reef", "slate", "thorn", "vale", "wren", "birch", "cliff", "delta",
+    "fern", "glade", "hawk", "isle", "junco", "kelp", "lynx", "marsh",
+    "alder", "amber", "anvil", "aspen", "basil", "bay", "blaze", "bloom",
+    "bolt", "brine", "cairn", "cave", "clam", "cloud", "coal", "colt",
+    "cone", "coral", "cove", "crane", "creek", "crest", "crow", "dale",
+    "dawn", "doe", "dove", "drift", "dusk", "ember", "finch", "fjord",
+    "flame", "flax", "foam", "fog", "forge", "fox", "frost", "gale",
+    "gem", "glen", "glow", "goose", "grain", "hare", "hazel", "heath",
+    "hedge", "herb", "hive", "holly", "inlet", "ivy", "jay", "kite",
+    "lake", "larch", "leaf", "ledge", "lily", "lime", "lodge", "maple",
+    "mesa", "mink", "mint", "mist", "moon", "moor", "moth", "nectar",
+    "nest", "oat", "olive", "orca", "otter", "owl", "pear", "plum",
+    "pond", "poppy", "rain", "ridge", "robin", "root", "rose", "rush",
+    "sage", "seal", "seed", "shade", "shell", "shore", "shrub", "smoke",
+    "snow", "spark", "spire", "spruce", "star", "stem", "stone", "storm",
+    "stork", "swift", "thyme", "tide", "trail", "trout", "tulip", "vine",
+    "wave", "wheat", "willow", "wolf", "yew",
+];
 
-    if old_metadata_path == new_metadata_path {
-        return Ok(());
-    }
-    if new_metadata_path.exists() {
-        return Err(format!(
-            "Cannot rename worktree metadata: {} already exists",
-            new_metadata_path.display()
-        ));
+fn generate_workspace_name(existing: &HashSet<String>) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as usize;
+
+    let total = ADJECTIVES.len() * NOUNS.len();
+    for offset in 0..total {
+        let idx = (nanos + offset) % total;
+        let adj = ADJECTIVES[idx % ADJECTIVES.len()];
+        let noun = NOUNS[idx / ADJECTIVES.len()];
+        let name = format!("{}-{}", adj, noun);
+        if !existing.contains(&name) {
+            return name;
+        }
     }
 
-    fs::rename(&old_metadata_path, &new_metadata_path).map_err(|e| {
-        format!(
-            "Failed to rename worktree metadata {} → {}: {}",
-            old_metadata_path.display(),
-            new_metadata_path.display(),
-            e
-        )
-    })?;
+    // Fallback: all names taken (very unlikely), append timestamp
+    let adj = ADJECTIVES[nanos % ADJECTIVES.len()];
+    let noun = NOUNS[(nanos / ADJECTIVES.len()) % NOUNS.len()];
+    format!("{}-{}-{}", adj, noun, nanos)
+}
 
-    let new_pointer = format!("gitdir: {}\n", new_metadata_path.display());
-    fs::write(&dot_git, new_pointer)
-        .map_err(|e| format!("Failed to update {}: {}", dot_git.display(), e))?;
+/// Check if a worktree directory name matches the adjective-noun pattern from `checkout new`.
+fn is_checkout_new_worktree(dir_name: &str) -> bool {
+    let slug = dir_name.strip_prefix(config::branch_dir_prefix()).unwrap_or(dir_name);
+    let parts: Vec<&str> = slug.splitn(2, '-').collect();
+    if parts.len() != 2 {
+        return false;
+    }
+    ADJECTIVES.contains(&parts[0]) && NOUNS.contains(&parts[1])
+}
 
+/// Find local `darren/<adj>-<noun>` branches older than `max_age_days` that
+/// aren't currently checked out by any worktree. Returns `(branch, age_days)`
+/// sorted oldest-first.
+fn find_stale_workspace_branches(
+    repo_root: &Path,
+    worktrees: &Worktrees,
+    max_age_days: u64,
+) -> Result<Vec<(String, u64)>, String> {
     let output = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "rev-parse", "--git-dir"])
-        .output()
-        .map_err(|e| format!("Failed to verify worktree after metadata rename: {}", e))?;
+        .args([
+            "-C", &repo_root.to_string_lossy(),
+            "for-each-ref",
+            "--format=%(refname:short) %(committerdate:unix)",
+            "refs/heads/darren/",
+        ])
+        .traced_output()
+        .map_err(|e| format!("Failed to list branches: {}", e))?;
     if !output.status.success() {
         return Err(format!(
-            "git rev-parse failed after metadata rename:\n{}",
+            "git for-each-ref failed: {}",
             String::from_utf8_lossy(&output.stderr).trim()
         ));
     }
 
-    Ok(())
-}
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let max_age_secs = max_age_days * 24 * 60 * 60;
 
-fn find_next_worktree_path(worktree_dir: &PathBuf, base_name: &str) -> Result<PathBuf, String> {
-    let mut suffix = 2;
-    loop {
-        let candidate = worktree_dir.join(format!("{}-{}", base_name, suffix));
-        if !candidate.exists() {
-            return Ok(candidate);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut stale: Vec<(String, u64)> = Vec::new();
+    for line in stdout.lines() {
+        let mut parts = line.splitn(2, ' ');
+        let name = parts.next().unwrap_or("").trim();
+        let ts: u64 = parts.next().unwrap_or("0").trim().parse().unwrap_or(0);
+        if name.is_empty() || worktrees.by_branch(name).is_some() {
+            continue;
         }
-        suffix += 1;
-        if suffix > 100 {
-            return Err("Too many worktrees".to_string());
+        let slug = name.strip_prefix(config::branch_prefix()).unwrap_or(name);
+        if !is_checkout_new_worktree(slug) {
+            continue;
         }
-    }
-}
-
-fn extract_pr_number(input: &str) -> Result<u64, String> {
-    if let Ok(num) = input.parse::<u64>() {
-        return Ok(num);
-    }
-
-    let re = Regex::new(r"/pull/(\d+)").unwrap();
-    if let Some(caps) = re.captures(input) {
-        if let Some(m) = caps.get(1) {
-            return m
-                .as_str()
-                .parse()
-                .map_err(|_| "Failed to parse PR number".to_string());
+        let age = now.saturating_sub(ts);
+        if age >= max_age_secs {
+            stale.push((name.to_string(), age / 86_400));
         }
     }
-
-    Err(format!(
-        "Could not parse PR number from '{}'. Expected a number or GitHub PR URL.",
-        input
-    ))
-}
-
-fn fetch_pr_details(pr_number: u64, repo_root: &PathBuf) -> Result<PrDetails, String> {
-    timing!("fetch_pr_details");
-    let output = Command::new("gh")
-        .args(["pr", "view", &pr_number.to_string(), "--json", "headRefName,title"])
-        .current_dir(repo_root)
-        .output()
-        .map_err(|e| format!("Failed to run gh: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("gh pr view failed: {}", stderr.trim()));
-    }
-
-    serde_json::from_slice(&output.stdout)
-        .map_err(|e| format!("Failed to parse PR details: {}", e))
+    stale.sort_by(|a, b| b.1.cmp(&a.1));
+    Ok(stale)
 }
 
-fn create_slug(title: &str) -> String {
-    let without_prefix = if let Some(idx) = title.find(": ") {
-        &title[idx + 2..]
-    } else {
-        title
-    };
-
-    let slug: String = without_prefix
-        .to_lowercase()
-        .chars()
-        .map(|c| if c.is_alphanumeric() { c } else { '-' })
-        .collect();
-
-    let re = Regex::new(r"-+").unwrap();
-    let cleaned = re.replace_all(&slug, "-");
-    let trimmed = cleaned.trim_matches('-');
+/// Find the oldest idle worktree created by `checkout new`: no alive session
+/// PID, and has an `.exited` marker that says "clean". Legacy worktrees closed
+/// before the SIGHUP fix won't be reused until they're closed once with the
+/// fixed binary. `git status` is deliberately not used here — it's slow enough
+/// that running it on tens of worktrees noticeably delays `checkout begin`.
+fn find_reusable_worktree(repo_root: &PathBuf) -> Result<Option<PathBuf>, String> {
+    timing!("find_reusable_worktree");
+    let entries = list_worktree_paths(repo_root)?;
+    // A note marks a worktree as one to keep, like it does for `clean`
+    let notes = metadata::load(&get_data_dir()).notes;
 
-    trimmed
-        .split('-')
-        .filter(|s| !s.is_empty())
-        .take(4)
-        .collect::<Vec<_>>()
-        .join("-")
-}
+    let mut candidates: Vec<(u64, PathBuf)> = Vec::new();
 
-fn find_existing_worktree(repo_root: &PathBuf, pattern: &str) -> Result<Option<PathBuf>, String> {
-    timing!(&format!("find_existing_worktree({})", pattern));
-    let output = Command::new("git")
-        .args(["-C", &repo_root.to_string_lossy(), "worktree", "list"])
-        .output()
-        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
+    for (path, _branch) in &entries {
+        let dir_name = match path.file_name().and_then(|s| s.to_str()) {
+            Some(n) => n,
+            None => continue,
+        };
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+        if !is_checkout_new_worktree(dir_name) || notes.contains_key(&note_key(path)) {
+            continue;
+        }
 
-    for line in stdout.lines() {
-        if line.contains(pattern) {
-            if let Some(path) = line.split_whitespace().next() {
-                return Ok(Some(PathBuf::from(path)));
+        if let Some(pid) = read_session_pid(path) {
+            if is_pid_alive(pid) {
+                continue;
             }
         }
-    }
 
-    Ok(None)
-}
+        // Must have an `.exited` marker that recorded "clean" at close time.
+        let content = match fs::read_to_string(session_exited_file(path)) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        let Some(exited) = parse_exited_session(&content) else {
+            continue;
+        };
+        if !exited.clean {
+            continue;
+        }
 
-/// Count files in a directory (non-recursively counts all entries via `git ls-files`)
-fn count_worktree_files(worktree_path: &PathBuf) -> Option<usize> {
-    let output = Command::new("git")
-        .args(["-C", &worktree_path.to_string_lossy(), "ls-files"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .ok()?;
-    if output.status.success() {
-        let count = output.stdout.iter().filter(|&&b| b == b'\n').count();
-        Some(count)
-    } else {
-        None
+        candidates.push((exited.timestamp, path.clone()));
     }
-}
 
-/// Run a git command with a spinner showing elapsed time.
-/// `label` is the prefix already printed (e.g. "→ Creating worktree...").
-/// Returns the command's exit status.
-fn run_git_with_spinner(args: &[&str]) -> Result<std::process::ExitStatus, String> {
-    let args_owned: Vec<String> = args.iter().map(|s| s.to_string()).collect();
-    let handle = thread::spawn(move || {
-        Command::new("git")
-            .args(&args_owned)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-    });
-
-    let spinner_chars = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
-    let start = Instant::now();
-    let mut i = 0;
-
-    while !handle.is_finished() {
-        let elapsed = start.elapsed().as_secs();
-        let spinner = spinner_chars[i % spinner_chars.len()];
-        print!("\r\x1b[K  {} {}s", spinner, elapsed);
-        std::io::stdout().flush().ok();
-        i += 1;
-        thread::sleep(Duration::from_millis(100));
-    }
-
-    // Clear the spinner line
-    print!("\r\x1b[K");
-    std::io::stdout().flush().ok();
-
-    handle
-        .join()
-        .map_err(|_| "git command thread panicked".to_string())?
-        .map_err(|e| format!("Failed to run git command: {}", e))
+    // Pick the oldest (earliest exit timestamp) so freshly-closed worktrees
+    // remain easy to re-enter.
+    candidates.sort_by_key(|(ts, _)| *ts);
+    Ok(candidates.into_iter().next().map(|(_, path)| path))
 }
 
-fn fetch_branch(repo_root: &PathBuf, branch: &str) -> Result<(), String> {
-    timing!(&format!("fetch_branch({})", branch));
-    let max_retries = 3;
-    for attempt in 1..=max_retries {
-        let status = Command::new("git")
-            .args(["-C", &repo_root.to_string_lossy(), "fetch", "origin", branch])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .map_err(|e| format!("Failed to fetch: {}", e))?;
-
-        if status.success() {
-            return Ok(());
+/// Remove stale `*.lock` files in the worktree's per-worktree git dir.
+///
+/// A crashed prior session can leave `index.lock` (or similar) behind, which
+/// then blocks every git operation in the recycled worktree with a confusing
+/// "Another git process seems to be running" error. We only call this when
+/// recycling an idle worktree — no live session should be holding a real lock.
+fn clear_stale_worktree_locks(worktree_path: &Path) {
+    let output = match Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "rev-parse", "--git-dir"])
+        .traced_output()
+    {
+        Ok(o) if o.status.success() => o,
+        _ => return,
+    };
+    let git_dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let entries = match fs::read_dir(&git_dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_lock = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.ends_with(".lock"))
+            .unwrap_or(false);
+        if !is_lock {
+            continue;
         }
-
-        if attempt < max_retries {
-            thread::sleep(Duration::from_secs(1));
+        // Only remove locks older than 60s as a paranoia belt — a live process
+        // would still be actively touching it.
+        let too_old = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok())
+            .map(|d| d > Duration::from_secs(60))
+            .unwrap_or(false);
+        if !too_old {
+            continue;
+        }
+        if fs::remove_file(&path).is_ok() {
+            progressln!(
+                "{} Cleared stale lock {}",
+                "→".blue().bold(),
+                path.display().to_string().dimmed()
+            );
         }
     }
-
-    Err("git fetch failed after 3 attempts".to_string())
 }
 
-fn create_worktree_from_ref(repo_root: &PathBuf, worktree_path: &PathBuf, git_ref: &str) -> Result<(), String> {
-    let repo_str = repo_root.to_string_lossy().to_string();
-    let wt_str = worktree_path.to_string_lossy().to_string();
-
-    let status = run_git_with_spinner(&["-C", &repo_str, "worktree", "add", &wt_str, git_ref])?;
-
-    if !status.success() {
-        // Try with FETCH_HEAD if branch is checked out elsewhere
-        let status = run_git_with_spinner(&["-C", &repo_str, "worktree", "add", &wt_str, "FETCH_HEAD"])?;
+fn reset_worktree_to_master(worktree_path: &PathBuf) -> Result<(), String> {
+    timing!("reset_worktree_to_master");
+    clear_stale_worktree_locks(worktree_path);
+    // Fetch latest master
+    progress!("{} Fetching latest master... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "fetch", "origin", "master"])
+        .traced_output()
+        .map_err(|e| format!("Failed to spawn git fetch: {}", e))?;
+    if !output.status.success() {
+        sayln!("{}", "error".red());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "git fetch origin master failed in {} (exit {}):\n{}",
+            worktree_path.display(),
+            output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
+            stderr.trim()
+        ));
+    }
+    progressln!("{}", "done".green());
 
-        if !status.success() {
-            return Err("git worktree add failed".to_string());
-        }
+    // Reset branch to origin/master
+    progress!("{} Resetting to latest master... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "reset", "--hard", "origin/master"])
+        .traced_output()
+        .map_err(|e| format!("Failed to spawn git reset: {}", e))?;
+    if !output.status.success() {
+        sayln!("{}", "error".red());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(format!(
+            "git reset --hard origin/master failed in {} (exit {}):\n{}{}{}",
+            worktree_path.display(),
+            output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
+            stderr.trim(),
+            if !stderr.trim().is_empty() && !stdout.trim().is_empty() { "\n" } else { "" },
+            stdout.trim()
+        ));
     }
+    progressln!("{}", "done".green());
 
     Ok(())
 }
 
-fn create_worktree_new_branch(repo_root: &PathBuf, worktree_path: &PathBuf, branch: &str) -> Result<(), String> {
-    timing!("create_worktree_new_branch (git worktree add)");
-    let repo_str = repo_root.to_string_lossy().to_string();
-    let wt_str = worktree_path.to_string_lossy().to_string();
+fn run_new(
+    no_agent: bool,
+    prompt: Option<String>,
+    repo: Option<PathBuf>,
+    agent: Agent,
+    checkout: &CheckoutArgs,
+) -> Result<(), String> {
+    timing!("run_new");
+    let repo_root = resolve_checkout_repo_root(repo.clone(), checkout);
 
-    let status = run_git_with_spinner(&["-C", &repo_str, "worktree", "add", "-b", branch, &wt_str, "origin/master"])?;
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
 
-    if !status.success() {
-        // Branch may already exist from a previous attempt, try checking it out directly
-        let status = run_git_with_spinner(&["-C", &repo_str, "worktree", "add", &wt_str, branch])?;
+    // Collect existing worktree names so we don't generate a collision
+    let existing_names: HashSet<String> = list_worktree_paths(&repo_root)?
+        .iter()
+        .filter_map(|(path, _)| {
+            path.file_name()
+                .and_then(|f| f.to_str())
+                .and_then(|s| s.strip_prefix(config::branch_dir_prefix()))
+                .map(|s| s.to_string())
+        })
+        .collect();
 
-        if !status.success() {
-            return Err("git worktree add failed".to_string());
-        }
-    }
+    // Try to reuse an idle scratch worktree. Its files are already checked
+    // out, so --no-checkout always gets a fresh one instead.
+    let reusable = if checkout.no_checkout { None } else { find_reusable_worktree(&repo_root)? };
+    if let Some(reusable) = reusable {
+        let workspace_name = generate_workspace_name(&existing_names);
+        let branch_name = format!("{}{}", config::branch_prefix(), workspace_name);
 
-    Ok(())
-}
+        let old_dir = reusable.file_name().unwrap().to_string_lossy().to_string();
+        let old_name = old_dir.strip_prefix(config::branch_dir_prefix()).unwrap_or(&old_dir);
 
-fn update_worktree(worktree_path: &PathBuf, branch: &str) -> Result<(), String> {
-    timing!("update_worktree");
-    let max_retries = 3;
-    let mut last_stderr = String::new();
-    for attempt in 1..=max_retries {
-        let output = Command::new("git")
-            .args(["-C", &worktree_path.to_string_lossy(), "fetch", "origin", branch])
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| format!("Failed to fetch: {}", e))?;
+        progressln!(
+            "{} Recycling idle workspace {} {} {}",
+            "→".blue().bold(),
+            old_name.dimmed(),
+            "→".dimmed(),
+            workspace_name.cyan()
+        );
 
-        if output.status.success() {
-            last_stderr.clear();
-            break;
+        reset_worktree_to_master(&reusable)?;
+
+        // Create a new branch for this workspace
+        let output = Command::new("git")
+            .args(["-C", &reusable.to_string_lossy(), "checkout", "-b", &branch_name])
+            .traced_output()
+            .map_err(|e| format!("Failed to spawn git checkout: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "git checkout -b {} failed in {} (exit {}):\n{}",
+                branch_name,
+                reusable.display(),
+                output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
 
-        last_stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-        if attempt < max_retries {
-            thread::sleep(Duration::from_secs(1));
+        // Rename the worktree directory to match the new workspace name
+        let new_path = reusable.parent().unwrap().join(branch_dir_name(&workspace_name));
+        let output = Command::new("git")
+            .args([
+                "-C", &repo_root.to_string_lossy(),
+                "worktree", "move",
+                &reusable.to_string_lossy(),
+                &new_path.to_string_lossy(),
+            ])
+            .traced_output()
+            .map_err(|e| format!("Failed to spawn git worktree move: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "git worktree move {} → {} failed (exit {}):\n{}",
+                reusable.display(),
+                new_path.display(),
+                output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "?".into()),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
-    }
 
-    if !last_stderr.is_empty() {
-        return Err(format!("git fetch failed after {} attempts: {}", max_retries, last_stderr));
-    }
+        // `git worktree move` doesn't rename the metadata dir under
+        // `.git/worktrees/`. Rename it to match so that the working-dir name
+        // and the metadata-dir name stay in sync across recycles.
+        let new_metadata_name = branch_dir_name(&workspace_name);
+        rename_worktree_metadata(&new_path, &new_metadata_name)?;
 
-    let ref_name = format!("origin/{}", branch);
-    let output = Command::new("git")
-        .args([
-            "-C",
-            &worktree_path.to_string_lossy(),
-            "reset",
-            "--hard",
-            &ref_name,
-        ])
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped())
-        .output()
-        .map_err(|e| format!("Failed to reset: {}", e))?;
+        let bg_handle = start_new_worktree_setup(new_path.clone(), repo_root.clone(), checkout.strict)?;
+        prepare_agent_worktree(agent, &new_path, &repo_root, checkout.strict)?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("git reset failed: {}", stderr.trim()));
-    }
+        print_ready_banner(&new_path);
+        write_worktree_path(&new_path)?;
 
-    Ok(())
-}
+        if no_agent {
+            print_enter_hint(&new_path, agent);
+        } else {
+            let bg_color = assign_worktree_color(&new_path, None, None)?;
+            record_current_iterm_session(&new_path)?;
+            let session_name = session_name_from_branch(&branch_name);
 
-fn which_mise() -> Option<PathBuf> {
-    Command::new("which")
-        .arg("mise")
-        .output()
-        .ok()
-        .filter(|o| o.status.success())
-        .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
-}
+            let tab_title = terminal_title(&session_name, &repo_root);
+            let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, terminal_guard_options(checkout));
 
-fn run_mise_trust(worktree_path: &PathBuf) -> Result<(), String> {
-    let status = Command::new("mise")
-        .args(["trust", "--all"])
-        .current_dir(worktree_path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map_err(|e| format!("Failed to run mise trust: {}", e))?;
+            run_on_enter_script(&new_path, &bg_color, &session_name);
 
-    if !status.success() {
-        return Err("mise trust failed".to_string());
-    }
+            let system_prompt = build_worktree_system_prompt();
 
-    Ok(())
-}
+            println!();
+            progressln!(
+                "{} Spawning {}...",
+                "→".blue().bold(),
+                agent.display_name(),
+            );
+            println!();
+
+            if let Some(prompt) = &prompt {
+                spawn_agent_with_prompt(
+                    agent,
+                    &new_path,
+                    prompt,
+                    Some(&system_prompt),
+                    &session_name,
+                    &checkout.env,
+                )?;
+            } else {
+                spawn_agent(agent, &new_path, Some(&system_prompt), &session_name, &checkout.env)?;
+            }
+        }
+
+        let _ = bg_handle.join();
 
-/// Validate/install the gem bundle for a freshly created worktree so the
-/// pre-commit Ruby hooks (which run `bundle exec`) don't blow up on a bundle
-/// that doesn't match this checkout's Gemfile.lock — the failure that otherwise
-/// forces `git commit --no-verify`.
-///
-/// Mirrors `bin/create_worktree.sh`'s optimized path: point BUNDLE_PATH at the
-/// main repo's `vendor/bundle` cache (which we've already symlinked in) and run
-/// `bundle install --local` in frozen mode (via `BUNDLE_FROZEN=true` in the env,
-/// not the `--frozen` flag, so Bundler doesn't persist it into the tracked
-/// `.bundle/config`). When the worktree's lock matches the cache — the common
-/// case for a branch off master — this is a fast no-op validation (~0.5s, no
-/// network). Only when the branch's Gemfile.lock needs a gem that isn't cached
-/// do we fall back to a networked `bundle install`.
-///
-/// Runs in the background thread after `symlink_vendor_bundle`, so it never
-/// blocks time-to-prompt and finishes well before the user's first commit.
-fn run_bundle_install(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
-    // Nothing to do unless this is a Bundler project with a usable gem cache.
-    if !worktree_path.join("Gemfile").is_file() {
-        return Ok(());
-    }
-    let cache_bundle = repo_root.join("vendor/bundle");
-    if !cache_bundle.is_dir() {
         return Ok(());
     }
 
-    let run = |args: &[&str]| -> Result<Option<bool>, String> {
-        match Command::new("bundle")
-            .args(args)
-            .current_dir(worktree_path)
-            .env("BUNDLE_PATH", &cache_bundle)
-            // Enforce frozen mode via the environment rather than the `--frozen`
-            // CLI flag: Bundler "remembers" the flag by persisting
-            // `BUNDLE_FROZEN: "true"` into the tracked `.bundle/config`, which
-            // leaves every worktree permanently dirty. The env var is not
-            // persisted, so worktrees stay clean.
-            .env("BUNDLE_FROZEN", "true")
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-        {
-            Ok(status) => Ok(Some(status.success())),
-            // Bundler isn't installed — nothing we can or should do.
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(format!("Failed to run bundle install: {}", e)),
-        }
-    };
+    // No reusable worktree, create a new one
+    let workspace_name = generate_workspace_name(&existing_names);
+    let branch_name = format!("{}{}", config::branch_prefix(), workspace_name);
 
-    // Fast path: validate against the cached gems with no network access.
-    match run(&["install", "--local"])? {
-        None => return Ok(()),       // bundle not available
-        Some(true) => return Ok(()), // bundle satisfied from cache
-        Some(false) => {}            // fall through to networked install
-    }
+    progressln!(
+        "{} New workspace {}",
+        "→".blue().bold(),
+        workspace_name.cyan()
+    );
 
-    // Fallback: the branch's lock needs a gem the cache doesn't have.
-    match run(&["install"])? {
-        Some(false) => Err("bundle install failed".to_string()),
-        _ => Ok(()),
-    }
+    run_branch(&branch_name, no_agent, prompt, repo, agent, false, BranchSetup { checkout, ..BranchSetup::default() })
 }
 
-fn run_gt_track(worktree_path: &PathBuf) -> Result<(), String> {
-    timing!("run_gt_track");
-    let status = Command::new("gt")
-        .args(["track", "--no-interactive", "--parent", "master"])
-        .current_dir(worktree_path)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map_err(|e| format!("Failed to run gt track: {}", e))?;
-
-    if !status.success() {
-        return Err("gt track failed".to_string());
-    }
+#[derive(Clone)]
+struct WorktreeInfo {
+    path: PathBuf,
+    branch: String,
+    /// Checked-out commit SHA
+    head: String,
+    /// A tag pointing at `head`, shown instead of the SHA for detached worktrees
+    tag: Option<String>,
+    has_changes: bool,
+    /// Commits on HEAD that aren't pushed; see `git::unpushed_commit_count`.
+    /// `None` when they couldn't be counted, which counts as local work so
+    /// nothing is removed on a guess
+    unpushed_commits: Option<usize>,
+    has_active_session: bool,
+    active_agent: Option<Agent>,
+    orphaned_pids: Vec<u32>,
+    /// One-liner from `checkout note`
+    note: Option<String>,
+    /// Last `pr`/`branch`/`new` checkout into this worktree, or open or resume
+    last_used: Option<SystemTime>,
+    /// For a branch worktree tracked by Graphite, the branch it's stacked on
+    stack_parent: Option<StackParent>,
+}
 
-    Ok(())
+/// A Graphite parent branch and the commits HEAD has on top of it
+#[derive(Clone, Debug, PartialEq)]
+struct StackParent {
+    branch: String,
+    ahead: usize,
 }
 
-/// Find all node_modules directories in repo_root and symlink them into
-/// the worktree. Skips any nested inside other node_modules since those
-/// are already contained within the parent symlink. Returns the number
-/// of symlinks created.
-fn symlink_node_modules(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<usize, String> {
-    let output = Command::new("find")
-        .args([
-            repo_root.to_string_lossy().as_ref(),
-            "-name", "node_modules",
-            "-type", "d",
-            "-not", "-path", "*/node_modules/*/node_modules",
-            "-prune",
-        ])
-        .output()
-        .map_err(|e| format!("Failed to find node_modules: {}", e))?;
+/// The Graphite parent of the branch checked out in `worktree_path`, compared
+/// with the local parent branch (or its origin copy if there's none locally).
+/// `None` whenever gt or git can't say.
+fn stack_parent(worktree_path: &Path) -> Option<StackParent> {
+    let branch = gt::parent(worktree_path)?;
+    let parent_ref = if git::local_branch_exists(worktree_path, &branch).unwrap_or(false) {
+        branch.clone()
+    } else {
+        format!("{}/{}", git::REMOTE, branch)
+    };
+    let ahead = git::commit_count(worktree_path, &format!("{}..HEAD", parent_ref)).ok()?;
+    Some(StackParent { branch, ahead })
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut count = 0;
+/// Whether `git status --porcelain` output shows changes to tracked files.
+/// Untracked files alone don't count, and neither do paths matching the
+/// `status_ignore` config.
+fn has_tracked_changes(porcelain: &str) -> bool {
+    has_tracked_changes_ignoring(porcelain, &config::get().status_ignore)
+}
 
-    for line in stdout.lines() {
-        let source = PathBuf::from(line.trim());
-        if !source.is_dir() || source.is_symlink() {
-            continue;
-        }
+fn has_tracked_changes_ignoring(porcelain: &str, ignore: &[String]) -> bool {
+    porcelain
+        .lines()
+        .any(|l| !l.trim().is_empty() && !l.starts_with("??") && !porcelain_entry_ignored(l, ignore))
+}
 
-        let rel = source.strip_prefix(repo_root)
-            .map_err(|_| format!("Path {} not under repo root", source.display()))?;
-        let dest = worktree_path.join(rel);
+/// Whether every path in a porcelain line (`XY path`, or `XY old -> new` for
+/// renames and copies) matches one of the `ignore` globs.
+fn porcelain_entry_ignored(line: &str, ignore: &[String]) -> bool {
+    if ignore.is_empty() {
+        return false;
+    }
+    let Some(paths) = line.get(3..) else {
+        return false;
+    };
+    paths.split(" -> ").all(|path| {
+        // Paths with special characters are quoted
+        let path = path.trim_matches('"');
+        ignore.iter().any(|pattern| glob_match(pattern, path))
+    })
+}
 
-        if dest.exists() || dest.is_symlink() {
-            continue;
+impl WorktreeInfo {
+    /// The branch, or for a detached worktree (PR, commit and tag worktrees)
+    /// its tag or short SHA
+    fn branch_label(&self) -> &str {
+        if self.branch != "(detached)" {
+            &self.branch
+        } else if let Some(tag) = &self.tag {
+            tag
+        } else if self.head.len() >= 7 {
+            &self.head[..7]
+        } else {
+            &self.branch
         }
+    }
 
-        // Ensure parent directory exists in the worktree
-        if let Some(parent) = dest.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)
-                    .map_err(|e| format!("Failed to create parent dir {}: {}", parent.display(), e))?;
-            }
+    /// ` last used 3d ago`, dimmed, or nothing when it's never been recorded
+    fn last_used_label(&self) -> String {
+        match self.last_used {
+            Some(time) => format!(" {}", format!("last used {}", format_time_ago(time)).dimmed()),
+            None => String::new(),
         }
+    }
 
-        std::os::unix::fs::symlink(&source, &dest)
-            .map_err(|e| format!("Failed to symlink {}: {}", rel.display(), e))?;
-        count += 1;
+    /// Uncommitted changes or unpushed commits: work that removing the
+    /// worktree would lose
+    fn has_local_work(&self) -> bool {
+        self.has_changes || self.unpushed_commits != Some(0)
     }
 
-    Ok(count)
+    /// `active`, `modified`, `unpushed`, `orphaned` or `clean`, in that
+    /// precedence
+    fn state(&self) -> &'static str {
+        if self.has_active_session {
+            "active"
+        } else if self.has_changes {
+            "modified"
+        } else if self.unpushed_commits.is_some_and(|count| count > 0) {
+            "unpushed"
+        } else if !self.orphaned_pids.is_empty() {
+            "orphaned"
+        } else {
+            "clean"
+        }
+    }
 }
 
-/// Symlink the main repo's `vendor/` directory into the worktree so Bundler
-/// can find installed gems (e.g. `numo-narray`) without re-running
-/// `bundle install` per worktree. The repo's `Gemfile`/`Gemfile.lock` are
-/// already tracked in git, so they show up in the worktree on their own —
-/// only the gitignored `vendor/bundle` install output needs help.
-///
-/// No-ops if the main repo has no `vendor/`, or if the worktree already has
-/// one (real dir or existing symlink).
-fn symlink_vendor_bundle(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
-    let source = repo_root.join("vendor");
-    if !source.is_dir() || source.is_symlink() {
-        return Ok(());
+/// A repo's linked worktrees with their status, listed once per invocation
+/// so a command can query them without re-running `git worktree list` and
+/// `git status` in every worktree.
+struct Worktrees {
+    list: Vec<WorktreeInfo>,
+}
+
+impl Worktrees {
+    fn iter(&self) -> std::slice::Iter<'_, WorktreeInfo> {
+        self.list.iter()
     }
 
-    let dest = worktree_path.join("vendor");
-    if dest.exists() || dest.symlink_metadata().is_ok() {
-        return Ok(());
+    fn is_empty(&self) -> bool {
+        self.list.is_empty()
     }
 
-    std::os::unix::fs::symlink(&source, &dest)
-        .map_err(|e| format!("Failed to symlink vendor: {}", e))?;
+    fn into_vec(self) -> Vec<WorktreeInfo> {
+        self.list
+    }
 
-    Ok(())
-}
+    /// The worktree with `branch` checked out
+    fn by_branch(&self, branch: &str) -> Option<&WorktreeInfo> {
+        self.list.iter().find(|wt| wt.branch == branch)
+    }
 
-fn prepare_agent_worktree(
-    agent: Agent,
-    worktree_path: &PathBuf,
-    repo_root: &PathBuf,
-) -> Result<(), String> {
-    if agent != Agent::Claude {
-        return Ok(());
+    /// Inactive worktrees that can be removed without losing work
+    fn clean(&self) -> impl Iterator<Item = &WorktreeInfo> {
+        self.list.iter().filter(|wt| !wt.has_active_session && !wt.has_local_work())
     }
 
-    print!("{} Copying Claude settings... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    symlink_claude_settings(worktree_path, repo_root)?;
-    println!("{}", "done".green());
+    /// Inactive worktrees with uncommitted changes or unpushed commits
+    fn dirty(&self) -> impl Iterator<Item = &WorktreeInfo> {
+        self.list.iter().filter(|wt| !wt.has_active_session && wt.has_local_work())
+    }
 
-    print!("{} Adding Claude trust... ", "→".blue().bold());
-    std::io::stdout().flush().ok();
-    add_claude_trust(worktree_path, repo_root)?;
-    println!("{}", "done".green());
+    /// The worktrees that remain once `removed` are gone
+    fn without(&self, removed: &[WorktreeInfo]) -> Worktrees {
+        let list = self.list.iter().filter(|wt| !removed.iter().any(|r| r.path == wt.path)).cloned().collect();
+        Worktrees { list }
+    }
 
-    Ok(())
+    fn sort(&mut self, sort: WorktreeSort) {
+        match sort {
+            WorktreeSort::Path => self.list.sort_by(|a, b| a.path.cmp(&b.path)),
+            // Worktrees of unknown age go last, so `clean --limit` never
+            // mistakes them for the oldest
+            WorktreeSort::Age => self.list.sort_by_cached_key(|wt| {
+                let created = worktree_created(&wt.path);
+                (created.is_none(), created)
+            }),
+        }
+    }
 }
 
-fn symlink_claude_settings(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
-    timing!("symlink_claude_settings");
-    // Symlink the main repo's .claude/settings.local.json into the worktree
-    // This contains MCP server configurations and other local settings
-    let source = repo_root.join(".claude/settings.local.json");
+/// When the worktree was added (or last moved): git writes its `.git` file
+/// then and leaves it alone otherwise.
+fn worktree_created(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path.join(".git")).and_then(|meta| meta.modified()).ok()
+}
 
-    let resolved_source = if !source.exists() {
-        // Fall back to global settings if repo-specific doesn't exist
-        let home = env::var("HOME").map_err(|_| "HOME not set")?;
-        let global_source = PathBuf::from(format!("{}/.claude/settings.local.json", home));
+fn get_all_worktrees(repo_root: &PathBuf) -> Result<Worktrees, String> {
+    // First pass: collect paths, branches and HEADs
+    let entries: Vec<(PathBuf, String, String)> = git::list_worktrees(repo_root)?
+        .into_iter()
+        .filter(|entry| entry.path != *repo_root)
+        .map(|entry| (entry.path, entry.branch.unwrap_or_else(|| "(detached)".to_string()), entry.head))
+        .collect();
 
-        if !global_source.exists() {
-            return Ok(());
-        }
+    // Check active sessions first (fast PID file reads) so we can skip
+    // expensive git-status calls for worktrees we're keeping anyway.
+    let session_status: Vec<bool> = entries
+        .iter()
+        .map(|(path, _, _)| {
+            if let Some(pid) = read_session_pid(path) {
+                if is_pid_alive(pid) {
+                    true
+                } else {
+                    remove_session_pid(path); // stale PID file from a crash
+                    false
+                }
+            } else {
+                false
+            }
+        })
+        .collect();
 
-        global_source
-    } else {
-        source
-    };
+    // Status, unpushed commits and a branch's Graphite parent are read side
+    // by side, at most --parallel worktrees at a time, and only for inactive
+    // worktrees
+    let local_work: Vec<(bool, Option<usize>, Option<StackParent>)> = thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .iter()
+            .zip(&session_status)
+            .map(|((path, branch, _), &active)| {
+                (!active).then(|| {
+                    scope.spawn(move || {
+                        let _slot = batch_slot();
+                        let has_changes = Command::new("git")
+                            .args(["-C", &path.to_string_lossy(), "status", "--porcelain"])
+                            .stderr(Stdio::null())
+                            .traced_output()
+                            .map(|o| has_tracked_changes(&String::from_utf8_lossy(&o.stdout)))
+                            .unwrap_or(false);
+                        let stack_parent = (branch != "(detached)").then(|| stack_parent(path)).flatten();
+                        (has_changes, git::unpushed_commit_count(path).ok(), stack_parent)
+                    })
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.and_then(|h| h.join().ok()).unwrap_or((false, None, None)))
+            .collect()
+    });
 
-    let dest_dir = worktree_path.join(".claude");
-    fs::create_dir_all(&dest_dir)
-        .map_err(|e| format!("Failed to create .claude dir: {}", e))?;
+    let tags = git::tags_by_commit(repo_root).unwrap_or_default();
+    let metadata::Metadata { mut notes, last_used, .. } = metadata::load(&get_data_dir());
 
-    let dest = dest_dir.join("settings.local.json");
+    // Collect results
+    let mut worktrees: Vec<WorktreeInfo> = entries
+        .into_iter()
+        .zip(local_work)
+        .zip(session_status)
+        .map(|(((path, branch, head), (has_changes, unpushed_commits, stack_parent)), has_active_session)| {
+            let active_agent = has_active_session
+                .then(|| read_session_agent(&path).unwrap_or(Agent::Claude));
+            let orphaned_pids: Vec<u32> = Vec::new();
+            let tag = if branch == "(detached)" { tags.get(&head).cloned() } else { None };
+            let note = notes.remove(&note_key(&path));
+            let last_used = last_used.get(&note_key(&path)).map(|&secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            WorktreeInfo {
+                path,
+                branch,
+                head,
+                tag,
+                has_changes,
+                unpushed_commits,
+                has_active_session,
+                active_agent,
+                orphaned_pids,
+                note,
+                last_used,
+                stack_parent,
+            }
+        })
+        .collect();
 
-    // Remove existing file/symlink if present so we can create a fresh symlink
-    if dest.exists() || dest.symlink_metadata().is_ok() {
-        fs::remove_file(&dest)
-            .map_err(|e| format!("Failed to remove existing settings file: {}", e))?;
-    }
+    // Sort by path for consistent output
+    worktrees.sort_by(|a, b| a.path.cmp(&b.path));
 
-    std::os::unix::fs::symlink(&resolved_source, &dest)
-        .map_err(|e| format!("Failed to symlink claude settings: {}", e))?;
+    Ok(Worktrees { list: worktrees })
+}
 
-    Ok(())
+/// Branches checked out by more than one worktree, with the worktrees on each.
+/// Git normally refuses this, but `--force` or manual edits can get there, and
+/// the next `git worktree add` for that branch then fails confusingly.
+fn find_duplicate_branches(worktrees: &[WorktreeInfo]) -> Vec<(&str, Vec<&Path>)> {
+    let mut by_branch: BTreeMap<&str, Vec<&Path>> = BTreeMap::new();
+    for wt in worktrees.iter().filter(|w| w.branch != "(detached)") {
+        by_branch.entry(wt.branch.as_str()).or_default().push(&wt.path);
+    }
+    by_branch.into_iter().filter(|(_, paths)| paths.len() > 1).collect()
 }
 
-fn add_claude_trust(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
-    timing!("add_claude_trust");
-    let home = env::var("HOME").map_err(|_| "HOME not set")?;
-    let claude_json_path = PathBuf::from(format!("{}/.claude.json", home));
+fn warn_duplicate_branches(out: &mut String, worktrees: &[WorktreeInfo]) {
+    for (branch, paths) in find_duplicate_branches(worktrees) {
+        let names: Vec<String> = paths.iter().map(|p| worktree_dir_name(p)).collect();
+        let _ = writeln!(
+            out,
+            "{} Branch {} is checked out in {} worktrees: {}",
+            "⚠".yellow(),
+            branch.yellow(),
+            paths.len(),
+            names.join(", ").cyan()
+        );
+        let _ = writeln!(
+            out,
+            "  {}",
+            "Switch or remove all but one, or later checkouts of this branch will fail".dimmed()
+        );
+    }
+}
 
-    // Read existing file or create empty object
-    let mut data: Value = if claude_json_path.exists() {
-        let content = fs::read_to_string(&claude_json_path)
-            .map_err(|e| format!("Failed to read .claude.json: {}", e))?;
-        serde_json::from_str(&content)
-            .map_err(|e| format!("Failed to parse .claude.json: {}", e))?
-    } else {
-        serde_json::json!({})
+/// Print `text`, through `$PAGER` (default `less`) when `force` is set or when
+/// stdout is a terminal too short to show it all. ANSI colors are preserved.
+fn print_paged(text: &str, force: bool) {
+    let fits = terminal_size()
+        .map_or(true, |(_, rows)| text.lines().count() < rows as usize);
+    if !io::stdout().is_terminal() || (fits && !force) {
+        print!("{}", text);
+        return;
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let child = Command::new("sh")
+        .args(["-c", &pager])
+        // Like git: let less pass colors through, quit if one screen, keep output
+        .env("LESS", env::var("LESS").unwrap_or_else(|_| "FRX".to_string()))
+        .stdin(Stdio::piped())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The pager closing early (e.g. `q`) is not an error
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+        }
+        Err(_) => print!("{}", text),
+    }
+}
+
+/// Output format for `checkout status`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum StatusFormat {
+    /// Colored summary
+    #[default]
+    Human,
+    /// One absolute worktree path per line
+    Path,
+    /// One worktree directory name per line
+    Name,
+    /// JSON array with each worktree's branch, HEAD and state
+    Json,
+}
+
+/// Undecorated `status` output for scripts, one worktree per line (or a JSON
+/// array). Empty input yields no lines, or `[]` for JSON. `None` for the
+/// human format, which `run_status` renders itself.
+/// PR page URLs by worktree path, for PRs checked out from a URL
+fn worktree_pr_urls(repo_root: &Path, worktrees: &[WorktreeInfo]) -> HashMap<PathBuf, String> {
+    let inputs = metadata::load(&get_data_dir()).pr_inputs;
+    worktrees
+        .iter()
+        .filter_map(|wt| {
+            let pr_number = ui::pr_number_from_dir(&worktree_dir_name(&wt.path))?;
+            Some((wt.path.clone(), pr_input_url(inputs.get(&metadata::pr_key(repo_root, pr_number))?)?))
+        })
+        .collect()
+}
+
+fn format_status_plain(
+    worktrees: &[WorktreeInfo],
+    pr_urls: &HashMap<PathBuf, String>,
+    format: StatusFormat,
+) -> Option<String> {
+    let out = match format {
+        StatusFormat::Human => return None,
+        StatusFormat::Path => worktrees
+            .iter()
+            .map(|wt| format!("{}\n", wt.path.display()))
+            .collect(),
+        StatusFormat::Name => worktrees
+            .iter()
+            .map(|wt| format!("{}\n", worktree_dir_name(&wt.path)))
+            .collect(),
+        StatusFormat::Json => {
+            let entries: Vec<Value> = worktrees
+                .iter()
+                .map(|wt| {
+                    serde_json::json!({
+                        "path": wt.path,
+                        "name": worktree_dir_name(&wt.path),
+                        "branch": (wt.branch != "(detached)").then_some(&wt.branch),
+                        "head": wt.head,
+                        "tag": wt.tag,
+                        "state": wt.state(),
+                        "unpushedCommits": wt.unpushed_commits,
+                        "activeAgent": wt.active_agent.filter(|_| wt.has_active_session).map(Agent::command),
+                        "orphanedPids": wt.orphaned_pids,
+                        "note": wt.note,
+                        "stackParent": wt.stack_parent.as_ref().map(|parent| serde_json::json!({
+                            "branch": parent.branch,
+                            "ahead": parent.ahead,
+                        })),
+                        "lastUsed": wt.last_used.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+                        "prUrl": pr_urls.get(&wt.path),
+                    })
+                })
+                .collect();
+            format!("{}\n", Value::Array(entries))
+        }
     };
+    Some(out)
+}
 
-    // Ensure projects object exists
-    if data.get("projects").is_none() {
-        data["projects"] = serde_json::json!({});
+fn last_fetched_label(repo_root: &Path) -> String {
+    match git::last_fetch_time(repo_root) {
+        Some(time) => format!("last fetched {}", format_time_ago(time)),
+        None => "never fetched".to_string(),
     }
+}
 
-    let worktree_path_str = worktree_path.to_string_lossy().to_string();
-    let repo_root_str = repo_root.to_string_lossy().to_string();
+/// `status --fetch`: one fetch in the main repo updates every worktree, since
+/// they share its object store and refs. Offline is fine; the numbers are
+/// just as stale as before.
+fn fetch_for_status(repo_root: &Path, format: StatusFormat) {
+    let previous_time = git::last_fetch_time(repo_root);
+    let previous = last_fetched_label(repo_root);
+    let repo_str = repo_root.to_string_lossy();
+    let args = ["-C", &repo_str, "fetch", "--prune", git::REMOTE];
+    let result = if format == StatusFormat::Human {
+        println!("{} Fetching {} {}", "→".blue().bold(), git::REMOTE, format!("({})", previous).dimmed());
+        run_git_with_spinner(&args)
+    } else {
+        // Keep stdout clean for scripts
+        Command::new("git")
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .traced_status()
+            .map_err(|e| format!("Failed to run git fetch: {}", e))
+    };
+    let error = match result {
+        Ok(status) if status.success() => return,
+        Ok(_) => "git fetch failed".to_string(),
+        Err(e) => e,
+    };
+    if let Some(time) = previous_time {
+        git::restore_last_fetch_time(repo_root, time);
+    }
+    eprintln!("{} {}; showing data as of the previous fetch ({})", "⚠".yellow(), error, previous);
+}
 
-    // Try to copy settings from the main repo, or use defaults
-    let base_settings = data
-        .get("projects")
-        .and_then(|p| p.get(&repo_root_str))
-        .cloned()
-        .unwrap_or_else(|| serde_json::json!({
-            "allowedTools": [],
-            "mcpContextUris": [],
-            "mcpServers": {},
-            "enabledMcpjsonServers": [],
-            "disabledMcpjsonServers": [],
-            "projectOnboardingSeenCount": 0,
-            "hasClaudeMdExternalIncludesApproved": false,
-            "hasClaudeMdExternalIncludesWarningShown": false,
-            "reactVulnerabilityCache": {
-                "detected": false,
-                "package": null,
-                "packageName": null,
-                "version": null,
-                "packageManager": null
-            }
-        }));
+fn run_status(
+    repo: Option<PathBuf>,
+    pager: bool,
+    format: StatusFormat,
+    fetch: bool,
+    usage: bool,
+    watch: Option<Duration>,
+    order: WorktreeOrderArgs,
+) -> Result<(), String> {
+    timing!("run_status");
+    let repo_root = resolve_repo_root(repo);
 
-    // Create new project entry based on main repo settings
-    let mut new_project = base_settings.clone();
-    // Always ensure trust is accepted for new worktrees
-    new_project["hasTrustDialogAccepted"] = serde_json::json!(true);
-    // Reset session-specific fields
-    new_project.as_object_mut().map(|obj| {
-        obj.remove("lastAPIDuration");
-        obj.remove("lastAPIDurationWithoutRetries");
-        obj.remove("lastCost");
-        obj.remove("lastDuration");
-        obj.remove("lastLinesAdded");
-        obj.remove("lastLinesRemoved");
-        obj.remove("lastModelUsage");
-        obj.remove("lastSessionId");
-        obj.remove("lastToolDuration");
-        obj.remove("lastTotalCacheCreationInputTokens");
-        obj.remove("lastTotalCacheReadInputTokens");
-        obj.remove("lastTotalInputTokens");
-        obj.remove("lastTotalOutputTokens");
-        obj.remove("lastTotalWebSearchRequests");
-        obj.remove("exampleFiles");
-        obj.remove("exampleFilesGeneratedAt");
-    });
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
 
-    data["projects"][&worktree_path_str] = new_project;
+    if fetch {
+        fetch_for_status(&repo_root, format);
+    }
 
-    // Write back to file
-    let content = serde_json::to_string_pretty(&data)
-        .map_err(|e| format!("Failed to serialize .claude.json: {}", e))?;
-    fs::write(&claude_json_path, content)
-        .map_err(|e| format!("Failed to write .claude.json: {}", e))?;
+    if let Some(interval) = watch {
+        return run_status_watch(&repo_root, usage, order, interval);
+    }
+
+    let (worktrees, total) = gather_status(&repo_root, order)?;
+    let pr_urls = worktree_pr_urls(&repo_root, &worktrees);
+    if let Some(out) = format_status_plain(&worktrees, &pr_urls, format) {
+        print!("{}", out);
+        return Ok(());
+    }
+
+    if worktrees.is_empty() {
+        println!("{} No worktrees found", "→".blue().bold());
+        return Ok(());
+    }
 
+    print_paged(&format_status_human(&repo_root, &worktrees, total, &pr_urls, usage), pager);
     Ok(())
 }
 
-fn build_worktree_system_prompt() -> String {
-    "IMPORTANT: This is a git worktree. The node_modules directories are symlinked \
-     from the main repo. NEVER run `pnpm install` in this worktree — it will corrupt \
-     the shared node_modules symlinks with absolute paths pointing at this worktree, \
-     breaking the main repo and all other worktrees. If you need to test dependency \
-     changes, first `rm` the node_modules symlink to create an isolated copy."
-        .to_string()
+/// The worktrees `status` shows, in `order`, with the total before `--limit`
+fn gather_status(repo_root: &PathBuf, order: WorktreeOrderArgs) -> Result<(Vec<WorktreeInfo>, usize), String> {
+    let mut worktrees = get_all_worktrees(repo_root)?;
+    worktrees.sort(order.sort);
+    let mut worktrees = worktrees.into_vec();
+    let total = worktrees.len();
+    if let Some(limit) = order.limit {
+        worktrees.truncate(limit);
+    }
+    Ok((worktrees, total))
 }
 
-fn codex_session_snapshot(worktree_path: &Path) -> HashMap<String, SystemTime> {
-    let mut files = Vec::new();
-    collect_jsonl_files(&get_codex_session_dir(), &mut files);
+fn format_status_human(
+    repo_root: &Path,
+    worktrees: &[WorktreeInfo],
+    total: usize,
+    pr_urls: &HashMap<PathBuf, String>,
+    usage: bool,
+) -> String {
+    let mut out = String::new();
+    let shown = if worktrees.len() < total {
+        format!("showing {}, ", worktrees.len())
+    } else {
+        String::new()
+    };
+    let _ = writeln!(
+        out,
+        "{} {} worktree(s) found {}:\n",
+        "→".blue().bold(),
+        total,
+        format!("({}{} {})", shown, git::REMOTE, last_fetched_label(repo_root)).dimmed()
+    );
 
-    let mut sessions: HashMap<String, SystemTime> = HashMap::new();
-    for path in files {
-        let Some((cwd, session_id)) = codex_session_metadata(&path) else {
-            continue;
+    let pr_bases = repo_pr_bases(repo_root);
+    let base_branches = base_worktree_branches(&pr_bases);
+    // Notes sit indented on their own line and are cut to fit it
+    let note_width = crossterm::terminal::size().map_or(100, |(cols, _)| cols as usize).saturating_sub(6);
+    for wt in worktrees {
+        let status = match wt.state() {
+            "active" => format!(
+                "active {}",
+                wt.active_agent.unwrap_or_default().command()
+            )
+            .blue()
+            .bold(),
+            "modified" => "modified".yellow().bold(),
+            "unpushed" => "unpushed".yellow(),
+            "orphaned" => "orphaned".magenta().bold(),
+            state => state.green(),
         };
-        if cwd != worktree_path {
-            continue;
-        }
-        let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
-            continue;
+
+        let dir_name = wt.path.file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| wt.path.display().to_string());
+
+        // Shown even when modified, so both kinds of local work are visible
+        let unpushed = match wt.unpushed_commits {
+            Some(0) => String::new(),
+            Some(count) => format!(" {}", format!("↑{}", count).yellow()),
+            // Active worktrees aren't checked
+            None if wt.has_active_session => String::new(),
+            None => format!(" {}", "↑?".yellow()),
         };
-        sessions
-            .entry(session_id)
-            .and_modify(|current| *current = (*current).max(modified))
-            .or_insert(modified);
-    }
-    sessions
-}
 
-fn wait_for_codex_session_id(
-    worktree_path: &Path,
-    before: &HashMap<String, SystemTime>,
-    timeout: Duration,
-) -> Option<String> {
-    let deadline = Instant::now() + timeout;
-    let mut delay = Duration::from_millis(100);
-    while Instant::now() < deadline {
-        let changed = codex_session_snapshot(worktree_path)
-            .into_iter()
-            .filter(|(session_id, modified)| {
-                before
-                    .get(session_id)
-                    .is_none_or(|previous| modified > previous)
-            })
-            .max_by_key(|(_, modified)| *modified)
-            .map(|(session_id, _)| session_id);
-        if changed.is_some() {
-            return changed;
+        let pr_link = match (pr_urls.get(&wt.path), ui::pr_number_from_dir(&dir_name)) {
+            (Some(url), Some(pr_number)) => format!(" {}", url_link(url, format!("#{}", pr_number).blue())),
+            _ => String::new(),
+        };
+        let label = match base_branches.get(&dir_name) {
+            Some(base) => format!("(base: {} @ {})", base, wt.branch_label()),
+            None => format!("({})", wt.branch_label()),
+        };
+
+        let stack_parent = match &wt.stack_parent {
+            Some(parent) => format!(" {}", format!("{} ahead of {}", parent.ahead, parent.branch).dimmed()),
+            None => String::new(),
+        };
+        let _ = writeln!(
+            out,
+            "  [{}] {} {}{}{}{}{}",
+            status,
+            path_link(&wt.path, dir_name.cyan()),
+            label.dimmed(),
+            pr_link,
+            unpushed,
+            stack_parent,
+            wt.last_used_label()
+        );
+        if let Some(note) = &wt.note {
+            let _ = writeln!(out, "      {}", truncate_chars(note, note_width).dimmed());
         }
-        thread::sleep(delay);
-        delay = (delay * 2).min(Duration::from_secs(1));
     }
-    None
-}
 
-enum CodexRenameWorkerMessage {
-    Started(u32),
-    Finished(Result<(), String>),
+    if !find_duplicate_branches(worktrees).is_empty() {
+        out.push('\n');
+        warn_duplicate_branches(&mut out, worktrees);
+    }
+
+    if usage {
+        let paths: Vec<PathBuf> = worktrees.iter().map(|wt| wt.path.clone()).collect();
+        out.push('\n');
+        out.push_str(&format_usage_report(&claude_usage_report(&read_claude_json(), &paths, &owned_worktree_dirs(repo_root))));
+    }
+
+    out
 }
 
-fn read_codex_app_server_response(
-    reader: &mut impl BufRead,
-    request_id: u64,
-) -> Result<Value, String> {
-    let mut line = String::new();
+/// `status --watch`: redraw from the top of the screen every `interval`, like
+/// watch(1). Line wrap is off while it runs, so a frame is exactly as tall as
+/// its line count and a resize can't leave a scrolled-off copy behind.
+fn run_status_watch(
+    repo_root: &PathBuf,
+    usage: bool,
+    order: WorktreeOrderArgs,
+    interval: Duration,
+) -> Result<(), String> {
+    if !io::stdout().is_terminal() {
+        return Err("--watch needs a terminal; drop it to print the status once".to_string());
+    }
+    enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+    let _guard = WatchGuard;
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\x1b[?25l\x1b[?7l");
+
+    let footer = format!("Every {} · q to quit", format_duration_ms_precise(interval.as_millis() as u64))
+        .dimmed()
+        .to_string();
     loop {
-        line.clear();
-        let bytes = reader
-            .read_line(&mut line)
-            .map_err(|e| format!("Failed to read Codex app server response: {}", e))?;
-        if bytes == 0 {
-            return Err(format!(
-                "Codex app server exited before answering request {}",
-                request_id
-            ));
-        }
-        let Ok(response) = serde_json::from_str::<Value>(&line) else {
-            continue;
-        };
-        if response.get("id").and_then(Value::as_u64) == Some(request_id) {
-            return Ok(response);
+        // Each frame asks gt afresh, so a restacked branch shows its new parent
+        gt::forget_parents();
+        let (worktrees, total) = gather_status(repo_root, order)?;
+        let pr_urls = worktree_pr_urls(repo_root, &worktrees);
+        let body = format_status_human(repo_root, &worktrees, total, &pr_urls, usage);
+        // Some ptys report a size of 0x0
+        let rows = terminal_size().ok().filter(|&(_, rows)| rows > 0).map_or(24, |(_, rows)| rows as usize);
+        let _ = write!(stdout, "\x1b[H\x1b[2J{}", watch_frame(&body, rows, &footer));
+        let _ = stdout.flush();
+
+        let next = Instant::now() + interval;
+        loop {
+            let remaining = next.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !event::poll(remaining).unwrap_or(false) {
+                break;
+            }
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    // Raw mode turns Ctrl+C into a key press rather than SIGINT
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    _ => {}
+                },
+                Ok(Event::Resize(..)) => break,
+                _ => {}
+            }
         }
     }
 }
 
-fn run_codex_rename_worker(
-    app_server_args: Vec<String>,
-    thread_id: String,
-    name: String,
-    messages: mpsc::Sender<CodexRenameWorkerMessage>,
-) -> Result<(), String> {
-    let mut child = Command::new("codex")
-        .args(&app_server_args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .map_err(|e| format!("Failed to start codex {}: {}", app_server_args.join(" "), e))?;
-    let _ = messages.send(CodexRenameWorkerMessage::Started(child.id()));
+/// Puts the terminal back however `status --watch` exits, leaving the last
+/// frame on screen
+struct WatchGuard;
 
-    let result = (|| {
-        let mut stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| "Codex app server stdin was unavailable".to_string())?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| "Codex app server stdout was unavailable".to_string())?;
-        let mut reader = io::BufReader::new(stdout);
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let mut stdout = io::stdout();
+        let _ = writeln!(stdout, "\x1b[?7h\x1b[?25h");
+        let _ = stdout.flush();
+    }
+}
 
-        writeln!(stdin, "{}", serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "initialize",
-            "params": {
-                "clientInfo": {
-                    "name": "checkout",
-                    "title": "Checkout",
-                    "version": env!("CARGO_PKG_VERSION")
-                }
+/// One `--watch` frame: `body` cut to leave room for `footer` on the last of
+/// `rows` lines, with raw mode's `\r\n` line endings
+fn watch_frame(body: &str, rows: usize, footer: &str) -> String {
+    let mut lines: Vec<&str> = body.lines().take(rows.saturating_sub(2)).collect();
+    lines.push("");
+    lines.push(footer);
+    lines.join("\r\n")
+}
+
+fn remove_worktrees(worktrees: &[WorktreeInfo], repo_root: &PathBuf) -> Result<(), String> {
+    let mut removed_count = 0;
+    let mut failed: Vec<String> = Vec::new();
+
+    for wt in worktrees {
+        let dir_name = wt.path.file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| wt.path.display().to_string());
+
+        for pid in &wt.orphaned_pids {
+            print!(
+                "{} Killing orphaned agent process (pid {})... ",
+                "→".blue().bold(),
+                pid.to_string().dimmed()
+            );
+            std::io::stdout().flush().ok();
+
+            let kill_result = Command::new("kill")
+                .arg(pid.to_string())
+                .traced_status();
+
+            match kill_result {
+                Ok(s) if s.success() => println!("{}", "done".green()),
+                _ => println!("{}", "failed (may have already exited)".yellow()),
             }
-        })).map_err(|e| format!("Failed to initialize Codex app server: {}", e))?;
-        stdin.flush()
-            .map_err(|e| format!("Failed to flush Codex initialize request: {}", e))?;
+        }
 
-        let initialize = read_codex_app_server_response(&mut reader, 1)?;
-        if let Some(error) = initialize.get("error") {
-            return Err(format!("Codex app server initialize failed: {}", error));
+        // --force below would throw uncommitted changes away with the directory
+        if wt.has_changes {
+            if let Err(e) = back_up_changes(&wt.path) {
+                println!("{} Keeping {}: {}", "✗".red().bold(), dir_name.cyan(), e);
+                failed.push(dir_name);
+                continue;
+            }
         }
 
-        writeln!(stdin, "{}", serde_json::json!({
-            "jsonrpc": "2.0",
-            "method": "initialized"
-        })).map_err(|e| format!("Failed to acknowledge Codex initialization: {}", e))?;
-        writeln!(stdin, "{}", serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": 2,
-            "method": "thread/name/set",
-            "params": {"threadId": thread_id, "name": name}
-        })).map_err(|e| format!("Failed to send Codex rename request: {}", e))?;
-        stdin.flush()
-            .map_err(|e| format!("Failed to flush Codex rename request: {}", e))?;
+        print!("{} Removing {}... ", "→".blue().bold(), dir_name.cyan());
+        std::io::stdout().flush().ok();
 
-        let rename = read_codex_app_server_response(&mut reader, 2)?;
-        if let Some(error) = rename.get("error") {
-            return Err(format!("Codex thread rename failed: {}", error));
-        }
-        Ok(())
-    })();
+        let repo_str = repo_root.to_string_lossy();
+        let wt_str = wt.path.to_string_lossy();
+        // Always --force: the caller has already confirmed removal, and without
+        // it git refuses to delete directories containing gitignored files
+        // (e.g. .DS_Store, build artifacts) even though they show as "clean".
+        let args = vec!["-C", &repo_str, "worktree", "remove", "--force", &wt_str];
 
-    let _ = child.kill();
-    let _ = child.wait();
-    result
-}
+        let output = Command::new("git")
+            .args(&args)
+            .traced_output()
+            .map_err(|e| format!("Failed to remove worktree: {}", e))?;
 
-fn set_codex_thread_name_with_timeout(
-    app_server_args: &[&str],
-    thread_id: &str,
-    name: &str,
-) -> Result<(), String> {
-    let (worker_sender, receiver) = mpsc::channel();
-    let args = app_server_args.iter().map(|argument| argument.to_string()).collect();
-    let thread_id = thread_id.to_string();
-    let name = name.to_string();
-    thread::spawn(move || {
-        let result = run_codex_rename_worker(args, thread_id, name, worker_sender.clone());
-        let _ = worker_sender.send(CodexRenameWorkerMessage::Finished(result));
-    });
+        if output.status.success() {
+            remove_worktree_color(&wt.path);
+            let _ = fs::remove_file(session_name_file(&wt.path));
+            let _ = fs::remove_file(worktree_iterm_session_file(&wt.path));
+            remove_session_pid(&wt.path);
+            remove_worktree_note(&wt.path);
+            remove_bazel_output_base(&wt.path);
+            remove_pr_fetch_ref(repo_root, &wt.path);
 
-    let deadline = Instant::now() + Duration::from_secs(10);
-    let mut child_pid: Option<u32> = None;
-    loop {
-        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
-            if let Some(pid) = child_pid {
-                let _ = Command::new("kill")
-                    .args(["-TERM", &pid.to_string()])
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .status();
-            }
-            return Err("Codex app server rename timed out".to_string());
-        };
-        match receiver.recv_timeout(remaining) {
-            Ok(CodexRenameWorkerMessage::Started(pid)) => child_pid = Some(pid),
-            Ok(CodexRenameWorkerMessage::Finished(result)) => return result,
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                if let Some(pid) = child_pid {
-                    let _ = Command::new("kill")
-                        .args(["-TERM", &pid.to_string()])
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .status();
+            println!("{}", "done".green());
+            removed_count += 1;
+        } else {
+            // `git worktree remove` is not atomic: it drops the worktree's admin
+            // registration (`.git/worktrees/<id>`) *before* recursively deleting
+            // the working directory. If that delete fails (commonly "Directory not
+            // empty" — a race with a process writing into a monorepo checkout, e.g.
+            // watchman/bazel/an IDE), git leaves an orphaned directory that it will
+            // no longer recognize ("is not a working tree"), so a retry can never
+            // recover it. Fall back to deleting the directory ourselves and pruning
+            // the (possibly stale) registration.
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let error_msg = stderr.trim();
+
+            let recovered = if wt.path.exists() {
+                match fs::remove_dir_all(&wt.path) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("{}", "failed".red());
+                        if !error_msg.is_empty() {
+                            println!("    {} {}", "error:".red(), error_msg);
+                        }
+                        println!("    {} manual cleanup failed: {}", "error:".red(), e);
+                        false
+                    }
                 }
-                return Err("Codex app server rename timed out".to_string());
-            }
-            Err(mpsc::RecvTimeoutError::Disconnected) => {
-                return Err("Codex app server rename worker exited".to_string());
+            } else {
+                // Directory already gone; git just needs to prune stale metadata.
+                true
+            };
+
+            if recovered {
+                // Prune the stale registration git left behind, then run the same
+                // per-worktree cleanup the success path does.
+                let _ = Command::new("git")
+                    .args(["-C", &repo_str, "worktree", "prune"])
+                    .traced_output();
+                remove_worktree_color(&wt.path);
+                let _ = fs::remove_file(session_name_file(&wt.path));
+                let _ = fs::remove_file(worktree_iterm_session_file(&wt.path));
+                remove_session_pid(&wt.path);
+                remove_worktree_note(&wt.path);
+                remove_bazel_output_base(&wt.path);
+                remove_pr_fetch_ref(repo_root, &wt.path);
+
+                println!("{}", "done (manual cleanup)".green());
+                removed_count += 1;
+            } else {
+                failed.push(dir_name);
             }
         }
     }
+
+    if removed_count > 0 {
+        println!(
+            "{} Removed {} worktree(s)",
+            "✓".green().bold(),
+            removed_count
+        );
+    }
+
+    if !failed.is_empty() {
+        println!(
+            "{} Failed to remove {} worktree(s): {}",
+            "✗".red().bold(),
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+
+    Ok(())
 }
 
-fn set_codex_thread_name(thread_id: &str, name: &str) -> Result<(), String> {
-    let mut errors = Vec::new();
-    for args in [
-        &["app-server", "--stdio"][..],
-        &["app-server", "proxy"][..],
-    ] {
-        match set_codex_thread_name_with_timeout(args, thread_id, name) {
-            Ok(()) => return Ok(()),
-            Err(error) => errors.push(format!("codex {}: {}", args.join(" "), error)),
+/// "uncommitted changes", "2 unpushed commit(s)" or both, for worktrees
+/// with local work
+fn local_work_summary(wt: &WorktreeInfo) -> Option<String> {
+    let mut parts = Vec::new();
+    if wt.has_changes {
+        parts.push("uncommitted changes".to_string());
+    }
+    match wt.unpushed_commits {
+        Some(0) => {}
+        Some(count) => parts.push(format!("{} unpushed commit(s)", count)),
+        None => parts.push("couldn't check for unpushed commits".to_string()),
+    }
+    (!parts.is_empty()).then(|| parts.join(" and "))
+}
+
+/// Match `name` against a glob where `*` matches any run of characters and
+/// `?` matches exactly one. Everything else is literal and case-sensitive.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen and the name index it was tried against
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
         }
     }
-    Err(errors.join("; "))
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
-fn spawn_codex_thread_renamer(
-    worktree_path: PathBuf,
-    session_name: String,
-    session_id: Option<String>,
-    sessions_before_launch: HashMap<String, SystemTime>,
-) {
-    thread::spawn(move || {
-        let thread_id = session_id.or_else(|| {
-            wait_for_codex_session_id(
-                &worktree_path,
-                &sessions_before_launch,
-                Duration::from_secs(10),
-            )
-        });
-        if let Some(thread_id) = thread_id {
-            // Naming is best-effort: it must never prevent the interactive
-            // coding-agent session from opening.
-            let _ = set_codex_thread_name(&thread_id, &session_name);
-        }
-    });
+/// Directory-name globs restricting which worktrees `checkout clean` considers.
+struct CleanFilter {
+    exclude: Vec<String>,
+    only: Vec<String>,
+    /// Let `clean` remove worktrees that have a note
+    include_noted: bool,
 }
 
-fn spawn_agent_with_prompt(
-    agent: Agent,
-    worktree_path: &PathBuf,
-    prompt: &str,
-    developer_instructions: Option<&str>,
-    session_name: &str,
-) -> Result<(), String> {
-    spawn_agent_process(
-        agent,
-        worktree_path,
-        Some(prompt),
-        developer_instructions,
-        None,
-        false,
-        session_name,
-    )
+impl CleanFilter {
+    /// Set aside before anything else: excluded by pattern, or noted
+    fn is_protected(&self, wt: &WorktreeInfo) -> bool {
+        self.is_excluded(&worktree_dir_name(&wt.path)) || (wt.note.is_some() && !self.include_noted)
+    }
+
+    fn is_excluded(&self, dir_name: &str) -> bool {
+        self.exclude.iter().any(|pattern| glob_match(pattern, dir_name))
+    }
+
+    fn is_selected(&self, dir_name: &str) -> bool {
+        self.only.is_empty() || self.only.iter().any(|pattern| glob_match(pattern, dir_name))
+    }
+
+    /// Exclude patterns that match none of `dir_names`, which are likely typos.
+    fn unused_excludes<'a>(&'a self, dir_names: &[&str]) -> Vec<&'a str> {
+        self.exclude
+            .iter()
+            .filter(|pattern| !dir_names.iter().any(|name| glob_match(pattern, name)))
+            .map(String::as_str)
+            .collect()
+    }
 }
 
-fn spawn_agent_continue(
-    agent: Agent,
-    worktree_path: &PathBuf,
-    prompt: Option<&str>,
-    developer_instructions: Option<&str>,
-    session_id: Option<&str>,
-    session_name: &str,
-) -> Result<(), String> {
-    spawn_agent_process(
-        agent,
-        worktree_path,
-        prompt,
-        developer_instructions,
-        session_id,
-        true,
-        session_name,
-    )
+fn worktree_dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
 }
 
-fn spawn_agent(
-    agent: Agent,
-    worktree_path: &PathBuf,
-    developer_instructions: Option<&str>,
-    session_name: &str,
-) -> Result<(), String> {
-    spawn_agent_process(
-        agent,
-        worktree_path,
-        None,
-        developer_instructions,
-        None,
-        false,
-        session_name,
-    )
-}
-
-fn spawn_agent_process(
-    agent: Agent,
-    worktree_path: &PathBuf,
-    prompt: Option<&str>,
-    developer_instructions: Option<&str>,
-    session_id: Option<&str>,
-    resume: bool,
-    session_name: &str,
-) -> Result<(), String> {
-    set_terminal_cwd(worktree_path);
-    save_session_name(worktree_path, session_name)?;
-
-    let sessions_before_launch = if agent == Agent::Codex && session_id.is_none() {
-        codex_session_snapshot(worktree_path)
-    } else {
-        HashMap::new()
-    };
-
-    let mut cmd = Command::new(agent.command());
-    cmd.args(build_agent_args(
-        agent,
-        prompt,
-        developer_instructions,
-        resume,
-        session_id,
-    )?);
-
-    let mut child = cmd
-        .current_dir(worktree_path)
-        .spawn()
-        .map_err(|e| format!("Failed to spawn {}: {}", agent.command(), e))?;
-
-    if agent == Agent::Codex {
-        spawn_codex_thread_renamer(
-            worktree_path.clone(),
-            session_name.to_string(),
-            session_id.map(str::to_string),
-            sessions_before_launch,
-        );
+/// Tidy `.git` after `clean`: prune registrations of worktrees that are gone
+/// (only worth doing after removals) and, on request, stale remote-tracking
+/// refs. Reports only what was actually freed.
+fn prune_after_clean(repo_root: &Path, removed_any: bool, prune_remote: bool) -> Result<(), String> {
+    if removed_any {
+        let pruned = git::prune_worktrees(repo_root)?;
+        if pruned > 0 {
+            println!(
+                "{} Pruned {} stale worktree registration(s)",
+                "✓".green().bold(),
+                pruned
+            );
+        }
     }
 
-    let _pid_guard = PidFileGuard::new(worktree_path, child.id(), agent);
-    let status = child
-        .wait()
-        .map_err(|e| format!("Failed to wait for {}: {}", agent.command(), e))?;
-
-    if !status.success() {
-        return Err(format!("{} exited with error", agent.command()));
+    if prune_remote {
+        print!("{} Pruning stale {} refs... ", "→".blue().bold(), git::REMOTE);
+        io::stdout().flush().ok();
+        let pruned = git::prune_remote(repo_root, git::REMOTE)?;
+        if pruned.is_empty() {
+            println!("{}", "nothing to prune".dimmed());
+        } else {
+            println!("{} ({} pruned)", "done".green(), pruned.len());
+            for name in &pruned {
+                println!("  {}", name.dimmed());
+            }
+        }
     }
 
     Ok(())
 }
 
-fn build_agent_args(
-    agent: Agent,
-    prompt: Option<&str>,
-    developer_instructions: Option<&str>,
-    resume: bool,
-    session_id: Option<&str>,
-) -> Result<Vec<String>, String> {
-    let mut args = Vec::new();
-    match agent {
-        Agent::Claude => {
-            args.extend(["--permission-mode".to_string(), "acceptEdits".to_string()]);
-            if resume {
-                args.push("--continue".to_string());
-            }
-            if let Some(prompt) = prompt {
-                args.push(prompt.to_string());
-            }
-            if let Some(instructions) = developer_instructions {
-                args.extend([
-                    "--append-system-prompt".to_string(),
-                    instructions.to_string(),
-                ]);
-            }
-        }
-        Agent::Codex => {
-            if let Some(instructions) = developer_instructions {
-                let value = serde_json::to_string(instructions)
-                    .map_err(|e| format!("Failed to encode Codex developer instructions: {}", e))?;
-                args.extend([
-                    "--config".to_string(),
-                    format!("developer_instructions={}", value),
-                ]);
-            }
-            if resume {
-                args.push("resume".to_string());
-                if let Some(session_id) = session_id {
-                    args.push(session_id.to_string());
-                } else {
-                    args.push("--last".to_string());
-                }
-            }
-            if let Some(prompt) = prompt {
-                args.push(prompt.to_string());
+/// `clean --delete-branch`: remove removed worktrees' local branches too.
+#[derive(Clone, Copy, Debug)]
+struct BranchDeletion {
+    /// Use `git branch -D`, deleting unmerged work
+    force: bool,
+}
+
+/// Branches of the removed worktrees that are safe to delete: named (not
+/// detached), not the default branch, and not checked out in any worktree
+/// that's still registered (including the main repo).
+fn branches_to_delete(removed: &[WorktreeInfo], remaining: &[git::WorktreeEntry], default_branch: &str) -> Vec<String> {
+    let checked_out: HashSet<&str> = remaining.iter().filter_map(|entry| entry.branch.as_deref()).collect();
+    let mut branches: Vec<String> = removed
+        .iter()
+        .filter(|wt| !remaining.iter().any(|entry| entry.path == wt.path))
+        .map(|wt| wt.branch.as_str())
+        .filter(|branch| *branch != "(detached)" && *branch != default_branch && !checked_out.contains(branch))
+        .map(str::to_string)
+        .collect();
+    branches.sort();
+    branches.dedup();
+    branches
+}
+
+fn delete_removed_branches(repo_root: &Path, removed: &[WorktreeInfo], deletion: BranchDeletion) -> Result<(), String> {
+    let default_branch = git::default_branch(repo_root, git::REMOTE)?.unwrap_or_else(|| "master".to_string());
+    let remaining = git::list_worktrees(repo_root)?;
+    for branch in branches_to_delete(removed, &remaining, &default_branch) {
+        print!("{} Deleting branch {}... ", "→".blue().bold(), branch.cyan());
+        io::stdout().flush().ok();
+        match git::delete_branch(repo_root, &branch, deletion.force) {
+            Ok(()) => println!("{}", "done".green()),
+            Err(e) if !deletion.force && e.contains("not fully merged") => {
+                println!("{} {}", "skipped".yellow(), "(unmerged commits; use --force)".dimmed())
             }
+            Err(e) => println!("{}\n    {} {}", "failed".red(), "error:".red(), e),
         }
     }
-    Ok(args)
+    Ok(())
 }
 
-// ---------- resume subcommand ----------
+fn run_clean(
+    repo: Option<PathBuf>,
+    skip_confirm: bool,
+    filter: &CleanFilter,
+    prune_remote: bool,
+    delete_branches: Option<BranchDeletion>,
+    order: WorktreeOrderArgs,
+) -> Result<(), String> {
+    timing!("run_clean");
+    let repo_root = resolve_repo_root(repo);
 
-struct ChatMessage {
-    role: String,   // "user" or "assistant"
-    content: String,
-}
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
 
-struct SessionInfo {
-    last_modified: SystemTime,
-    messages: Vec<ChatMessage>,
-    agent: Agent,
-    resume_id: Option<String>,
-}
+    let mut all_worktrees = get_all_worktrees(&repo_root)?;
+    all_worktrees.sort(order.sort);
 
-struct WorktreeSession {
-    worktree: WorktreeInfo,
-    session: SessionInfo,
-}
+    if all_worktrees.is_empty() {
+        println!("{} No worktrees found", "→".blue().bold());
+        return Ok(());
+    }
 
-/// Encode a worktree path into the Claude projects directory name format.
-/// `/Users/dtsung/figma-worktrees/branch-foo` → `-Users-dtsung-figma-worktrees-branch-foo`
-fn encode_project_path(path: &Path) -> String {
-    path.to_string_lossy().replace('/', "-")
-}
+    let dir_names: Vec<String> = all_worktrees.iter().map(|w| worktree_dir_name(&w.path)).collect();
+    let dir_name_refs: Vec<&str> = dir_names.iter().map(String::as_str).collect();
+    for pattern in filter.unused_excludes(&dir_name_refs) {
+        println!(
+            "{} Exclude pattern {} matched no worktrees {}",
+            "⚠".yellow(),
+            pattern.cyan(),
+            "(typo?)".dimmed()
+        );
+    }
 
-/// Read the last `max_bytes` of a file and return complete lines from that tail.
-fn tail_lines(path: &Path, max_bytes: u64) -> Result<Vec<String>, String> {
-    let meta = fs::metadata(path).map_err(|e| format!("stat {}: {}", path.display(), e))?;
-    let file_len = meta.len();
+    // Excluded worktrees, and base worktrees a PR worktree still uses, are set
+    // aside before anything else so they can never be removed or kept for
+    // reuse; active ones are simply left alone.
+    let base_users = base_worktree_users(&all_worktrees.list, &repo_pr_bases(&repo_root));
+    let (protected_worktrees, worktrees): (Vec<_>, Vec<_>) = all_worktrees
+        .iter()
+        .filter(|w| filter.is_selected(&worktree_dir_name(&w.path)))
+        .cloned()
+        .partition(|w| filter.is_protected(w) || base_users.contains_key(&w.path));
+    let worktrees = Worktrees { list: worktrees };
+    let protected_worktrees: Vec<_> = protected_worktrees.into_iter().filter(|w| !w.has_active_session).collect();
 
-    let file = fs::File::open(path).map_err(|e| format!("open {}: {}", path.display(), e))?;
+    if worktrees.is_empty() && protected_worktrees.is_empty() {
+        println!("{} No worktrees match --only", "→".blue().bold());
+        return Ok(());
+    }
 
-    let start = if file_len > max_bytes { file_len - max_bytes } else { 0 };
-    let reader = io::BufReader::new(file);
-    let mut lines: Vec<String> = Vec::new();
+    // Orphaned processes are removable because remove_worktrees terminates them.
+    let removable_worktrees: Vec<_> = worktrees.clean().collect();
+    let modified_worktrees: Vec<_> = worktrees.dirty().collect();
+    let active_worktrees: Vec<_> = worktrees.iter().filter(|w| w.has_active_session).collect();
 
-    // Skip to the start offset by reading bytes
-    use std::io::{Seek, SeekFrom};
-    let mut reader = reader;
-    reader.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    // Among removable worktrees, keep the N most recently exited reusable ones
+    // so `checkout begin` can reuse them instead of creating new worktrees.
+    const REUSABLE_POOL_SIZE: usize = 5;
 
-    // If we skipped into the middle of a file, discard the first (partial) line
-    if start > 0 {
-        let mut _discard = String::new();
-        reader.read_line(&mut _discard).ok();
-    }
+    let kept_for_reuse: HashSet<PathBuf> = {
+        let mut reusable: Vec<(u64, &PathBuf)> = removable_worktrees.iter()
+            .filter(|w| {
+                let dir_name = w.path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+                is_checkout_new_worktree(dir_name)
+            })
+            .map(|w| {
+                let exited = fs::read_to_string(session_exited_file(&w.path))
+                    .ok()
+                    .and_then(|c| c.lines().next().and_then(|l| l.trim().parse::<u64>().ok()))
+                    .unwrap_or(0);
+                let used = w.last_used.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+                (exited.max(used), &w.path)
+            })
+            .collect();
+        // Most recently exited or checked out into first
+        reusable.sort_by(|a, b| b.0.cmp(&a.0));
+        reusable.into_iter().take(REUSABLE_POOL_SIZE).map(|(_, p)| p.clone()).collect()
+    };
 
-    for line in reader.lines() {
-        match line {
-            Ok(l) => lines.push(l),
-            Err(_) => break,
+    let mut actually_removing: Vec<_> = removable_worktrees.iter().filter(|w| !kept_for_reuse.contains(&w.path)).collect();
+    // Clean worktrees past --limit are left for a later run
+    let over_limit: HashSet<PathBuf> = match order.limit {
+        Some(limit) if actually_removing.len() > limit => {
+            actually_removing.split_off(limit).into_iter().map(|w| w.path.clone()).collect()
         }
-    }
+        _ => HashSet::new(),
+    };
+    let kept_worktrees: Vec<_> = removable_worktrees.iter().filter(|w| kept_for_reuse.contains(&w.path)).collect();
 
-    Ok(lines)
-}
+    if !actually_removing.is_empty() {
+        println!(
+            "{} Removing {} worktree(s):\n",
+            "→".blue().bold(),
+            actually_removing.len()
+        );
 
-/// Parse JSONL lines to extract the last N user/assistant chat messages.
-fn parse_claude_session_messages(jsonl_path: &Path, max_messages: usize) -> Vec<ChatMessage> {
-    // Read last ~256KB — plenty for recent messages
-    let lines = match tail_lines(jsonl_path, 256 * 1024) {
-        Ok(l) => l,
-        Err(_) => return Vec::new(),
-    };
+        for wt in &actually_removing {
+            let dir_name = wt.path.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| wt.path.display().to_string());
 
-    let mut messages: Vec<ChatMessage> = Vec::new();
+            let orphan_note = if !wt.orphaned_pids.is_empty() {
+                format!(
+                    " {} {}",
+                    format!("(killing {} orphaned agent process{})", wt.orphaned_pids.len(),
+                        if wt.orphaned_pids.len() == 1 { "" } else { "es" }).yellow(),
+                    format!("pid {}", wt.orphaned_pids.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")).dimmed()
+                )
+            } else {
+                String::new()
+            };
 
-    for line in &lines {
-        let val: Value = match serde_json::from_str(line) {
-            Ok(v) => v,
-            Err(_) => continue,
-        };
+            println!(
+                "  {} {} {}{}{}",
+                format!("[{}]", "remove".red()),
+                dir_name.cyan(),
+                format!("({})", wt.branch).dimmed(),
+                wt.last_used_label(),
+                orphan_note
+            );
+        }
+        if !over_limit.is_empty() {
+            println!(
+                "  {}",
+                format!("…and {} more clean worktree(s) left by --limit", over_limit.len()).dimmed()
+            );
+        }
+    }
 
-        let msg_type = val.get("type").and_then(|v| v.as_str()).unwrap_or("");
+    if !kept_worktrees.is_empty() {
+        if !actually_removing.is_empty() {
+            println!();
+        }
+        println!(
+            "{} Keeping {} worktree(s) for reuse:\n",
+            "→".blue().bold(),
+            kept_worktrees.len()
+        );
 
-        match msg_type {
-            "user" => {
-                let content = val.get("message").and_then(|m| m.get("content"));
-                if let Some(content) = content {
-                    if let Some(text) = content.as_str() {
-                        let trimmed = text.trim_start();
-                        // Skip system/tool messages (XML tags, tool results)
-                        if !trimmed.starts_with('<') {
-                            messages.push(ChatMessage {
-                                role: "user".to_string(),
-                                content: text.to_string(),
-                            });
-                        }
-                    }
-                    // Skip tool_result arrays — those aren't human messages
-                }
-            }
-            "assistant" => {
-                let content = val.get("message").and_then(|m| m.get("content"));
-                if let Some(arr) = content.and_then(|c| c.as_array()) {
-                    // Find the first text block (skip thinking blocks)
-                    for item in arr {
-                        let block_type = item.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                        if block_type == "text" {
-                            if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
-                                messages.push(ChatMessage {
-                                    role: "assistant".to_string(),
-                                    content: text.to_string(),
-                                });
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-            _ => {}
+        for wt in &kept_worktrees {
+            let dir_name = wt.path.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| wt.path.display().to_string());
+
+            println!(
+                "  {} {} {}{}",
+                format!("[{}]", "reuse".green().bold()),
+                dir_name.cyan(),
+                format!("({})", wt.branch).dimmed(),
+                wt.last_used_label()
+            );
         }
     }
 
-    // Return the last N messages
-    let start = if messages.len() > max_messages { messages.len() - max_messages } else { 0 };
-    messages.split_off(start)
-}
+    if !active_worktrees.is_empty() {
+        if !actually_removing.is_empty() || !kept_worktrees.is_empty() {
+            println!();
+        }
+        println!(
+            "{} Keeping {} worktree(s) with active agent sessions:\n",
+            "→".blue().bold(),
+            active_worktrees.len()
+        );
 
-/// Find the most recent Claude session for a worktree path.
-fn find_claude_worktree_session(worktree_path: &Path) -> Option<SessionInfo> {
-    let home = env::var("HOME").ok()?;
-    let encoded = encode_project_path(worktree_path);
-    let project_dir = PathBuf::from(format!("{}/.claude/projects/{}", home, encoded));
+        for wt in &active_worktrees {
+            let dir_name = wt.path.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| wt.path.display().to_string());
+            let active_status = format!("[{}]", "active".blue().bold());
+            let branch_status = format!("({})", wt.branch).dimmed();
 
-    if !project_dir.is_dir() {
-        return None;
+            println!(
+                "  {} {} {} {}",
+                active_status,
+                dir_name.cyan(),
+                branch_status,
+                wt.active_agent.unwrap_or_default().display_name().dimmed()
+            );
+        }
     }
 
-    let mut best_path: Option<PathBuf> = None;
-    let mut best_time: Option<SystemTime> = None;
-
-    if let Ok(entries) = fs::read_dir(&project_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().and_then(|e| e.to_str()) == Some("jsonl") {
-                if let Ok(meta) = fs::metadata(&path) {
-                    if let Ok(modified) = meta.modified() {
-                        if best_time.is_none() || modified > best_time.unwrap() {
-                            best_time = Some(modified);
-                            best_path = Some(path);
-                        }
-                    }
-                }
-            }
+    if !modified_worktrees.is_empty() {
+        if !actually_removing.is_empty() || !kept_worktrees.is_empty() || !active_worktrees.is_empty() {
+            println!();
         }
-    }
+        println!(
+            "{} {} worktree(s) with uncommitted changes or unpushed commits (will prompt individually):\n",
+            "→".blue().bold(),
+            modified_worktrees.len()
+        );
 
-    let jsonl_path = best_path?;
-    let last_modified = best_time?;
-    let messages = parse_claude_session_messages(&jsonl_path, 50);
+        for wt in &modified_worktrees {
+            let dir_name = wt.path.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| wt.path.display().to_string());
 
-    if messages.is_empty() {
-        return None;
+            println!(
+                "  {} {} {}",
+                format!("[{}]", wt.state().yellow().bold()),
+                dir_name.cyan(),
+                format!("({})", wt.branch).dimmed()
+            );
+        }
     }
 
-    Some(SessionInfo {
-        last_modified,
-        messages,
-        agent: Agent::Claude,
-        resume_id: None,
-    })
-}
-
-/// Most-recent Claude session activity time for a worktree, based on the
-/// mtime of its session transcripts. Lighter than `find_worktree_session`
-/// since it doesn't parse message contents.
-fn find_claude_worktree_session_time(worktree_path: &Path) -> Option<SystemTime> {
-    let home = env::var("HOME").ok()?;
-    let encoded = encode_project_path(worktree_path);
-    let project_dir = PathBuf::from(format!("{}/.claude/projects/{}", home, encoded));
+    if !protected_worktrees.is_empty() {
+        if !worktrees.is_empty() {
+            println!();
+        }
+        println!(
+            "{}",
+            format!("→ Protected {} worktree(s) by exclude pattern, note or PR base:\n", protected_worktrees.len())
+                .dimmed()
+        );
 
-    let mut best: Option<SystemTime> = None;
-    for entry in fs::read_dir(&project_dir).ok()?.flatten() {
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) == Some("jsonl") {
-            if let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) {
-                if best.map_or(true, |b| modified > b) {
-                    best = Some(modified);
-                }
-            }
+        for wt in &protected_worktrees {
+            let base_of = base_users.get(&wt.path).map(|prs| {
+                let prs: Vec<String> = prs.iter().map(|pr| format!("#{}", pr)).collect();
+                format!(" · base of {}", prs.join(", "))
+            });
+            println!(
+                "  {}",
+                format!(
+                    "[protected] {} ({}){}{}{}",
+                    worktree_dir_name(&wt.path),
+                    wt.branch,
+                    local_work_summary(wt).map(|s| format!(" · {}", s)).unwrap_or_default(),
+                    base_of.unwrap_or_default(),
+                    wt.note.as_deref().map(|note| format!(" · {}", note)).unwrap_or_default()
+                )
+                .dimmed()
+            );
         }
     }
-    best
-}
 
-fn get_codex_session_dir() -> PathBuf {
-    if let Ok(codex_home) = env::var("CODEX_HOME") {
-        return PathBuf::from(codex_home).join("sessions");
-    }
-    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(home).join(".codex/sessions")
-}
+    // Partition into owned vecs for removal, excluding those kept for reuse
+    let (removable, modified): (Vec<_>, Vec<_>) = worktrees.into_vec().into_iter()
+        .filter(|w| !w.has_active_session && !kept_for_reuse.contains(&w.path) && !over_limit.contains(&w.path))
+        .partition(|w| !w.has_local_work());
 
-fn collect_jsonl_files(dir: &Path, files: &mut Vec<PathBuf>) {
-    let Ok(entries) = fs::read_dir(dir) else {
-        return;
-    };
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.is_dir() {
-            collect_jsonl_files(&path, files);
-        } else if path.extension().and_then(|ext| ext.to_str()) == Some("jsonl") {
-            files.push(path);
-        }
+    if removable.is_empty() && modified.is_empty() {
+        println!("\n{} Nothing to remove", "→".blue().bold());
+        return prune_after_clean(&repo_root, false, prune_remote);
     }
-}
 
-fn codex_session_metadata(jsonl_path: &Path) -> Option<(PathBuf, String)> {
-    let file = fs::File::open(jsonl_path).ok()?;
-    let first_line = io::BufReader::new(file).lines().next()?.ok()?;
-    let value: Value = serde_json::from_str(&first_line).ok()?;
-    if value.get("type").and_then(Value::as_str) != Some("session_meta") {
-        return None;
-    }
-    let payload = value.get("payload")?;
-    // Checkout launches top-level CLI sessions. Excluding subagent transcripts
-    // prevents a worker's partial conversation from replacing the parent session.
-    if payload.get("source").and_then(Value::as_str) != Some("cli") {
-        return None;
+    // Kick off `git status --short` for each modified worktree in the background
+    // so the detailed file list is ready by the time we prompt. Prompting is
+    // sequential and gated on user input, so these run for free while the UI
+    // (the clean-worktree batch prompt and earlier per-worktree prompts) waits.
+    let mut status_handles: HashMap<PathBuf, thread::JoinHandle<Result<Option<String>, String>>> =
+        HashMap::new();
+    for w in &modified {
+        let path = w.path.clone();
+        let handle = thread::spawn(move || {
+            let _slot = batch_slot();
+            get_uncommitted_status(&path)
+        });
+        status_handles.insert(w.path.clone(), handle);
     }
-    let worktree = payload.get("cwd").and_then(Value::as_str).map(PathBuf::from)?;
-    let session_id = payload.get("id").and_then(Value::as_str)?.to_string();
-    Some((worktree, session_id))
-}
+    let modified_paths = modified.iter().map(|worktree| worktree.path.clone()).collect();
+    let agent_session_times = find_agent_session_times(&modified_paths);
 
-fn latest_codex_session_files(
-    worktree_paths: &HashSet<PathBuf>,
-) -> HashMap<PathBuf, (SystemTime, PathBuf, String)> {
-    let mut files = Vec::new();
-    collect_jsonl_files(&get_codex_session_dir(), &mut files);
+    // Collect all confirmations upfront before any removals
+    let mut all_to_remove: Vec<WorktreeInfo> = Vec::new();
 
-    let mut latest: HashMap<PathBuf, (SystemTime, PathBuf, String)> = HashMap::new();
-    for path in files {
-        let Some((worktree_path, session_id)) = codex_session_metadata(&path) else {
-            continue;
-        };
-        if !worktree_paths.contains(&worktree_path) {
-            continue;
-        }
-        let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
-            continue;
-        };
-        let replace = latest
-            .get(&worktree_path)
-            .is_none_or(|(current, _, _)| modified > *current);
-        if replace {
-            latest.insert(worktree_path, (modified, path, session_id));
-        }
-    }
-    latest
-}
+    // Batch-confirm clean worktrees, or pick a subset when there are several
+    // and a terminal to show the checklist on
+    if !removable.is_empty() {
+        if !skip_confirm && removable.len() > 1 && io::stdin().is_terminal() && io::stdout().is_terminal() {
+            let labels: Vec<String> = removable
+                .iter()
+                .map(|wt| format!("{} ({})", worktree_dir_name(&wt.path), wt.branch_label()))
+                .collect();
+            let title = format!("checkout clean — Select clean worktrees to remove ({})", labels.len());
+            let selected: HashSet<usize> = ui::multi_select(&title, &labels)?.unwrap_or_default().into_iter().collect();
+            println!();
+            println!(
+                "{} Selected {} of {} clean worktree(s)",
+                "→".blue().bold(),
+                selected.len(),
+                removable.len()
+            );
+            all_to_remove.extend(
+                removable.into_iter().enumerate().filter(|(i, _)| selected.contains(i)).map(|(_, wt)| wt),
+            );
+        } else if !skip_confirm {
+            println!();
+            print!(
+                "{} Remove {} clean worktree(s)? [y/N]: ",
+                "?".magenta().bold(),
+                removable.len()
+            );
+            io::stdout().flush().map_err(|e| e.to_string())?;
 
-fn choose_resume_target(
-    codex: Option<ResumeTarget>,
-    claude: Option<ResumeTarget>,
-) -> Option<ResumeTarget> {
-    match (codex, claude) {
-        (Some(codex), Some(claude)) => {
-            if codex.last_modified >= claude.last_modified {
-                Some(codex)
-            } else {
-                Some(claude)
+            let input = read_confirmation()?;
+
+            if input.trim().to_lowercase() == "y" {
+                all_to_remove.extend(removable);
             }
+        } else {
+            all_to_remove.extend(removable);
         }
-        (Some(codex), None) => Some(codex),
-        (None, Some(claude)) => Some(claude),
-        (None, None) => None,
     }
-}
 
-fn find_worktree_resume_target(worktree_path: &Path) -> Option<ResumeTarget> {
-    let claude = find_claude_worktree_session_time(worktree_path).map(|last_modified| ResumeTarget {
-        last_modified,
-        agent: Agent::Claude,
-        resume_id: None,
-    });
-
-    let mut worktree_paths = HashSet::new();
-    worktree_paths.insert(worktree_path.to_path_buf());
-    let codex = latest_codex_session_files(&worktree_paths)
-        .remove(worktree_path)
-        .map(|(last_modified, _, resume_id)| ResumeTarget {
-            last_modified,
-            agent: Agent::Codex,
-            resume_id: Some(resume_id),
-        });
+    // Prompt individually for modified worktrees
+    if !modified.is_empty() {
+        println!();
+        for wt in modified {
+            let dir_name = wt.path.file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| wt.path.display().to_string());
 
-    choose_resume_target(codex, claude)
-}
+            // Worktree creation time (birthtime on macOS; falls back to mtime).
+            let created_str = fs::metadata(&wt.path)
+                .and_then(|m| m.created().or_else(|_| m.modified()))
+                .map(format_time_ago)
+                .unwrap_or_else(|_| "unknown".to_string());
 
-fn find_codex_worktree_session_id(worktree_path: &Path) -> Option<String> {
-    let mut worktree_paths = HashSet::new();
-    worktree_paths.insert(worktree_path.to_path_buf());
-    latest_codex_session_files(&worktree_paths)
-        .remove(worktree_path)
-        .map(|(_, _, session_id)| session_id)
-}
+            let session_str = agent_session_times
+                .get(&wt.path)
+                .copied()
+                .map(format_time_ago)
+                .unwrap_or_else(|| "none".to_string());
 
-fn parse_codex_session_messages(jsonl_path: &Path, max_messages: usize) -> Vec<ChatMessage> {
-    let lines = match tail_lines(jsonl_path, 256 * 1024) {
-        Ok(lines) => lines,
-        Err(_) => return Vec::new(),
-    };
-    let mut messages = Vec::new();
+            println!(
+                "{} {} {}",
+                "→".blue().bold(),
+                dir_name.cyan(),
+                format!("(has {})", local_work_summary(&wt).unwrap_or_default()).yellow()
+            );
+            println!(
+                "    {}",
+                format!("created {} · last agent session {}", created_str, session_str).dimmed()
+            );
+            let status_result = status_handles.remove(&wt.path)
+                .and_then(|h| h.join().ok())
+                .unwrap_or_else(|| get_uncommitted_status(&wt.path));
+            if let Ok(Some(status)) = status_result {
+                let lines: Vec<&str> = status.lines().collect();
+                const MAX_SHOWN: usize = 10;
+                for line in lines.iter().take(MAX_SHOWN) {
+                    println!("    {}", line.dimmed());
+                }
+                if lines.len() > MAX_SHOWN {
+                    println!("    {}", format!("... and {} more", lines.len() - MAX_SHOWN).dimmed());
+                }
+            }
 
-    for line in lines {
-        let Ok(value) = serde_json::from_str::<Value>(&line) else {
-            continue;
-        };
-        if value.get("type").and_then(Value::as_str) != Some("response_item") {
-            continue;
+            print!(
+                "{} Remove {}? [y/N]: ",
+                "?".magenta().bold(),
+                dir_name.cyan()
+            );
+            io::stdout().flush().map_err(|e| e.to_string())?;
+
+            let input = read_confirmation()?;
+
+            if input.trim().to_lowercase() == "y" {
+                all_to_remove.push(wt);
+            }
         }
-        let Some(payload) = value.get("payload") else {
-            continue;
-        };
-        if payload.get("type").and_then(Value::as_str) != Some("message") {
-            continue;
+    }
+
+    // Remove everything that was confirmed
+    if !all_to_remove.is_empty() {
+        println!();
+        remove_worktrees(&all_to_remove, &repo_root)?;
+    }
+    prune_after_clean(&repo_root, !all_to_remove.is_empty(), prune_remote)?;
+    if let Some(deletion) = delete_branches.filter(|_| !all_to_remove.is_empty()) {
+        delete_removed_branches(&repo_root, &all_to_remove, deletion)?;
+    }
+
+    // Clean up stale workspace branches (darren/<adj>-<noun>, older than 7 days,
+    // not currently checked out by any worktree). These accumulate because
+    // `git worktree remove` doesn't delete the branch.
+    const STALE_BRANCH_AGE_DAYS: u64 = 7;
+    let remaining = all_worktrees.without(&all_to_remove);
+    let stale = find_stale_workspace_branches(&repo_root, &remaining, STALE_BRANCH_AGE_DAYS)?;
+    if !stale.is_empty() {
+        println!();
+        println!(
+            "{} Found {} stale workspace branch(es) (older than {} days):\n",
+            "→".blue().bold(),
+            stale.len(),
+            STALE_BRANCH_AGE_DAYS,
+        );
+        for (name, age_days) in &stale {
+            println!(
+                "  {} {} {}",
+                format!("[{}]", "stale".red()),
+                name.cyan(),
+                format!("({} days old)", age_days).dimmed(),
+            );
         }
-        let Some(role) = payload.get("role").and_then(Value::as_str) else {
-            continue;
+
+        let confirmed = if skip_confirm {
+            true
+        } else {
+            println!();
+            print!(
+                "{} Delete {} stale branch(es)? [y/N]: ",
+                "?".magenta().bold(),
+                stale.len(),
+            );
+            io::stdout().flush().map_err(|e| e.to_string())?;
+            let input = read_confirmation()?;
+            input.trim().to_lowercase() == "y"
         };
-        if role != "user" && role != "assistant" {
-            continue;
-        }
 
-        let expected_type = if role == "user" { "input_text" } else { "output_text" };
-        let content = payload
-            .get("content")
-            .and_then(Value::as_array)
-            .into_iter()
-            .flatten()
-            .filter(|part| part.get("type").and_then(Value::as_str) == Some(expected_type))
-            .filter_map(|part| part.get("text").and_then(Value::as_str))
-            .filter(|text| role != "user" || !text.trim_start().starts_with('<'))
-            .collect::<Vec<_>>()
-            .join("\n");
-        if !content.trim().is_empty() {
-            messages.push(ChatMessage {
-                role: role.to_string(),
-                content,
-            });
+        if confirmed {
+            println!();
+            let mut deleted = 0usize;
+            let mut failed: Vec<String> = Vec::new();
+            for (name, _) in &stale {
+                print!("{} Deleting {}... ", "→".blue().bold(), name.cyan());
+                io::stdout().flush().ok();
+                let output = Command::new("git")
+                    .args(["-C", &repo_root.to_string_lossy(), "branch", "-D", name])
+                    .traced_output()
+                    .map_err(|e| format!("Failed to spawn git branch -D: {}", e))?;
+                if output.status.success() {
+                    println!("{}", "done".green());
+                    deleted += 1;
+                } else {
+                    println!("{}", "failed".red());
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let msg = stderr.trim();
+                    if !msg.is_empty() {
+                        println!("    {} {}", "error:".red(), msg);
+                    }
+                    failed.push(name.clone());
+                }
+            }
+            if deleted > 0 {
+                println!(
+                    "{} Deleted {} stale branch(es)",
+                    "✓".green().bold(),
+                    deleted,
+                );
+            }
+            if !failed.is_empty() {
+                println!(
+                    "{} Failed to delete {} branch(es): {}",
+                    "✗".red().bold(),
+                    failed.len(),
+                    failed.join(", "),
+                );
+            }
         }
     }
 
-    let start = messages.len().saturating_sub(max_messages);
-    messages.split_off(start)
+    Ok(())
 }
 
-fn find_agent_sessions(
-    agent: Agent,
-    entries: Vec<(PathBuf, String)>,
-) -> Vec<WorktreeSession> {
-    match agent {
-        Agent::Claude => entries
-            .into_iter()
-            .filter_map(|(path, branch)| {
-                let session = find_claude_worktree_session(&path)?;
-                Some(WorktreeSession {
-                    worktree: WorktreeInfo {
-                        path,
-                        branch,
-                        has_changes: false,
-                        has_active_session: false,
-                        active_agent: None,
-                        orphaned_pids: Vec::new(),
-                    },
-                    session,
-                })
-            })
-            .collect(),
-        Agent::Codex => {
-            let branches: HashMap<PathBuf, String> = entries.into_iter().collect();
-            let worktree_paths = branches.keys().cloned().collect();
-            latest_codex_session_files(&worktree_paths)
-                .into_iter()
-                .filter_map(|(path, (last_modified, jsonl_path, session_id))| {
-                    let messages = parse_codex_session_messages(&jsonl_path, 50);
-                    let branch = branches.get(&path)?.clone();
-                    Some(WorktreeSession {
-                        worktree: WorktreeInfo {
-                            path,
-                            branch,
-                            has_changes: false,
-                            has_active_session: false,
-                            active_agent: None,
-                            orphaned_pids: Vec::new(),
-                        },
-                        session: SessionInfo {
-                            last_modified,
-                            messages,
-                            agent: Agent::Codex,
-                            resume_id: Some(session_id),
-                        },
-                    })
-                })
-                .collect()
-        }
-    }
+struct UninstallOptions {
+    dry_run: bool,
+    delete_branches: bool,
+    /// Remove worktrees with local work and delete unmerged branches
+    force: bool,
+    yes: bool,
 }
 
-fn find_all_agent_sessions(entries: Vec<(PathBuf, String)>) -> Vec<WorktreeSession> {
-    let mut sessions = find_agent_sessions(Agent::Codex, entries.clone());
-    sessions.extend(find_agent_sessions(Agent::Claude, entries));
-    sessions
+/// File count and total bytes under `path`, without following symlinks
+/// (worktrees link `node_modules` from the main repo). Subtrees in `skip`
+/// are left out.
+fn dir_usage(path: &Path, skip: &[&Path]) -> (usize, u64) {
+    if skip.contains(&path) {
+        return (0, 0);
+    }
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return (0, 0);
+    };
+    if !meta.is_dir() {
+        return (1, meta.len());
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return (0, 0);
+    };
+    entries
+        .flatten()
+        .map(|entry| dir_usage(&entry.path(), skip))
+        .fold((0, 0), |(files, bytes), (f, b)| (files + f, bytes + b))
 }
 
-fn find_agent_session_times(worktree_paths: &HashSet<PathBuf>) -> HashMap<PathBuf, SystemTime> {
-    let mut times = HashMap::new();
-    for path in worktree_paths {
-        if let Some(modified) = find_claude_worktree_session_time(path) {
-            times.insert(path.clone(), modified);
-        }
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
     }
-    for (path, (modified, _, _)) in latest_codex_session_files(worktree_paths) {
-        times
-            .entry(path)
-            .and_modify(|current| *current = (*current).max(modified))
-            .or_insert(modified);
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
     }
-    times
+    format!("{:.1} {}", size, UNITS[unit])
 }
 
-/// Format a `SystemTime` as a human-readable "time ago" string.
-fn format_time_ago(time: SystemTime) -> String {
-    let elapsed = time.elapsed().unwrap_or_default();
-    let secs = elapsed.as_secs();
+fn claude_json_path() -> Result<PathBuf, String> {
+    let home = env::var("HOME").map_err(|_| "HOME not set")?;
+    Ok(PathBuf::from(format!("{}/.claude.json", home)))
+}
 
-    if secs < 60 {
-        return "just now".to_string();
-    }
-    let mins = secs / 60;
-    if mins < 60 {
-        return format!("{}m ago", mins);
+/// `projects` keys in `~/.claude.json` for paths under one of `dirs`: the
+/// entries `add_claude_trust` wrote for checkout's worktrees.
+fn claude_project_entries(claude_json: &Value, dirs: &[&Path]) -> Vec<String> {
+    claude_json
+        .get("projects")
+        .and_then(Value::as_object)
+        .map(|projects| {
+            projects
+                .keys()
+                .filter(|key| dirs.iter().any(|dir| Path::new(key.as_str()).starts_with(dir)))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn remove_claude_project_entries(keys: &[String]) -> Result<(), String> {
+    let path = claude_json_path()?;
+    let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read .claude.json: {}", e))?;
+    let mut data: Value =
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse .claude.json: {}", e))?;
+    if let Some(projects) = data.get_mut("projects").and_then(Value::as_object_mut) {
+        for key in keys {
+            projects.remove(key);
+        }
     }
-    let hours = mins / 60;
-    if hours < 24 {
-        return format!("{}h ago", hours);
+    let content = serde_json::to_string_pretty(&data)
+        .map_err(|e| format!("Failed to serialize .claude.json: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write .claude.json: {}", e))
+}
+
+/// `~/.claude.json`, or an empty object if it's missing or unreadable
+fn read_claude_json() -> Value {
+    claude_json_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_else(|| serde_json::json!({}))
+}
+
+/// The worktree dir, as configured and canonicalized: git reports canonical
+/// paths, while .claude.json keeps them as created.
+fn owned_worktree_dirs(repo_root: &Path) -> [PathBuf; 2] {
+    let worktree_dir = default_worktree_dir(repo_root);
+    let canonical = worktree_dir.canonicalize().unwrap_or_else(|_| worktree_dir.clone());
+    [worktree_dir, canonical]
+}
+
+/// Stats Claude Code keeps in a `~/.claude.json` project entry about its last
+/// session. The schema isn't ours, so every field is optional.
+#[derive(Debug, Default, PartialEq)]
+struct ClaudeUsage {
+    cost: Option<f64>,
+    duration_ms: Option<u64>,
+    lines_added: Option<u64>,
+    lines_removed: Option<u64>,
+    models: Vec<String>,
+}
+
+impl ClaudeUsage {
+    /// The usage recorded in a project entry, if it has any
+    fn from_project(project: &Value) -> Option<Self> {
+        let usage = ClaudeUsage {
+            cost: project.get("lastCost").and_then(Value::as_f64),
+            duration_ms: project.get("lastDuration").and_then(Value::as_u64),
+            lines_added: project.get("lastLinesAdded").and_then(Value::as_u64),
+            lines_removed: project.get("lastLinesRemoved").and_then(Value::as_u64),
+            models: project
+                .get("lastModelUsage")
+                .and_then(Value::as_object)
+                .map(|models| models.keys().cloned().collect())
+                .unwrap_or_default(),
+        };
+        (usage != ClaudeUsage::default()).then_some(usage)
     }
-    let days = hours / 24;
-    if days < 30 {
-        return format!("{}d ago", days);
+
+    /// Add `other`'s numbers to these; models aren't totalled
+    fn add(&mut self, other: &ClaudeUsage) {
+        fn sum<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            }
+        }
+        self.cost = sum(self.cost, other.cost);
+        self.duration_ms = sum(self.duration_ms, other.duration_ms);
+        self.lines_added = sum(self.lines_added, other.lines_added);
+        self.lines_removed = sum(self.lines_removed, other.lines_removed);
     }
-    let months = days / 30;
-    format!("{}mo ago", months)
 }
 
-/// Lightweight worktree listing: just parses `git worktree list --porcelain`
-/// without spawning any git-status or process-detection subcommands.
-fn list_worktree_paths(repo_root: &PathBuf) -> Result<Vec<(PathBuf, String)>, String> {
-    timing!("list_worktree_paths");
-    let output = Command::new("git")
-        .args(["-C", &repo_root.to_string_lossy(), "worktree", "list", "--porcelain"])
-        .output()
-        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut entries: Vec<(PathBuf, String)> = Vec::new();
-    let mut current_path: Option<PathBuf> = None;
-    let mut current_branch: Option<String> = None;
+/// Last-session usage of checkout's worktrees, by directory name, and the
+/// project entries left behind by worktrees that no longer exist
+#[derive(Debug, Default)]
+struct UsageReport {
+    worktrees: Vec<(String, ClaudeUsage)>,
+    reclaimable: Vec<(String, Option<ClaudeUsage>)>,
+}
 
-    for line in stdout.lines() {
-        if let Some(path_str) = line.strip_prefix("worktree ") {
-            current_path = Some(PathBuf::from(path_str));
-        } else if let Some(branch_str) = line.strip_prefix("branch refs/heads/") {
-            current_branch = Some(branch_str.to_string());
-        } else if line.is_empty() {
-            if let Some(path) = current_path.take() {
-                if path != *repo_root {
-                    let branch = current_branch.take().unwrap_or_else(|| "(detached)".to_string());
-                    entries.push((path, branch));
+fn claude_usage_report(claude_json: &Value, worktrees: &[PathBuf], owned_dirs: &[PathBuf]) -> UsageReport {
+    let mut report = UsageReport::default();
+    let Some(projects) = claude_json.get("projects").and_then(Value::as_object) else {
+        return report;
+    };
+    for (key, project) in projects {
+        let path = Path::new(key);
+        // One filesystem lookup per project, not one per worktree compared
+        let canonical = path.canonicalize().ok();
+        let live = worktrees.iter().find(|wt| wt.as_path() == path || canonical.as_ref() == Some(*wt));
+        match live {
+            Some(wt) => {
+                if let Some(usage) = ClaudeUsage::from_project(project) {
+                    report.worktrees.push((worktree_dir_name(wt), usage));
                 }
             }
-            current_branch = None;
+            None if owned_dirs.iter().any(|dir| path.starts_with(dir)) && !path.exists() => {
+                report.reclaimable.push((key.clone(), ClaudeUsage::from_project(project)));
+            }
+            None => {}
         }
     }
-
-    Ok(entries)
+    report.worktrees.sort_by(|a, b| a.0.cmp(&b.0));
+    report
 }
 
-fn run_resume(repo: Option<PathBuf>) -> Result<(), String> {
-    timing!("run_resume");
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-
-    if !repo_root.exists() {
-        return Err(format!("Repo not found at {}", repo_root.display()));
+/// `1h 5m`, `14m 2s` or `42s`
+fn format_duration_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
     }
+}
 
-    // Fast path: just list worktree paths (single git command, no status checks)
-    print!("{} Listing worktrees... ", "→".blue().bold());
-    io::stdout().flush().ok();
-    let entries = list_worktree_paths(&repo_root)?;
-    println!("{} ({} found)", "done".green(), entries.len());
+fn format_usage_row(label: &str, width: usize, usage: &ClaudeUsage) -> String {
+    let cost = usage.cost.map_or("-".to_string(), |cost| format!("${:.2}", cost));
+    let duration = usage.duration_ms.map_or("-".to_string(), format_duration_ms);
+    let lines = match (usage.lines_added, usage.lines_removed) {
+        (None, None) => String::new(),
+        (added, removed) => format!(
+            "{} {}",
+            format!("+{}", added.unwrap_or(0)).green(),
+            format!("-{}", removed.unwrap_or(0)).red()
+        ),
+    };
+    format!(
+        "  {} {} {:>8} {} {}",
+        format!("{:<width$}", label, width = width).cyan(),
+        format!("{:>8}", cost).yellow(),
+        duration,
+        lines,
+        usage.models.join(", ").dimmed()
+    )
+    .trim_end()
+    .to_string()
+}
 
-    if entries.is_empty() {
-        println!("{} No worktrees found", "→".blue().bold());
-        return Ok(());
+fn format_usage_report(report: &UsageReport) -> String {
+    let mut out = String::new();
+    if report.worktrees.is_empty() {
+        let _ = writeln!(out, "{} No Claude usage recorded for any worktree", "→".blue().bold());
+    } else {
+        let _ = writeln!(
+            out,
+            "{} Last Claude session of {} worktree(s):\n",
+            "→".blue().bold(),
+            report.worktrees.len()
+        );
+        let width = report.worktrees.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0).max(5);
+        let mut total = ClaudeUsage::default();
+        for (name, usage) in &report.worktrees {
+            let _ = writeln!(out, "{}", format_usage_row(name, width, usage));
+            total.add(usage);
+        }
+        let _ = writeln!(out, "{}", format_usage_row("total", width, &total).bold());
     }
 
-    // Find sessions via filesystem reads only — skip worktrees without sessions
-    print!("{} Finding agent sessions... ", "→".blue().bold());
-    io::stdout().flush().ok();
-    let mut sessions = find_all_agent_sessions(entries);
-    println!("{} ({} with sessions)", "done".green(), sessions.len());
+    if !report.reclaimable.is_empty() {
+        let _ = writeln!(
+            out,
+            "\n{} {} .claude.json entr{} for removed worktrees (reclaimable):\n",
+            "→".blue().bold(),
+            report.reclaimable.len(),
+            if report.reclaimable.len() == 1 { "y" } else { "ies" }
+        );
+        let width = report.reclaimable.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+        for (key, usage) in &report.reclaimable {
+            match usage {
+                Some(usage) => {
+                    let _ = writeln!(out, "{}", format_usage_row(key, width, usage));
+                }
+                None => {
+                    let _ = writeln!(out, "  {}", key.dimmed());
+                }
+            }
+        }
+    }
+    out
+}
 
-    if sessions.is_empty() {
-        println!("{} No worktrees with agent sessions found", "→".blue().bold());
-        return Ok(());
+fn run_usage(repo: Option<PathBuf>) -> Result<(), String> {
+    timing!("run_usage");
+    let repo_root = resolve_repo_root(repo);
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
     }
+    let paths: Vec<PathBuf> = get_all_worktrees(&repo_root)?.iter().map(|wt| wt.path.clone()).collect();
+    let report = claude_usage_report(&read_claude_json(), &paths, &owned_worktree_dirs(&repo_root));
+    print!("{}", format_usage_report(&report));
+    Ok(())
+}
 
-    // Sort by most recent session first
-    sessions.sort_by(|a, b| b.session.last_modified.cmp(&a.session.last_modified));
+/// Why `uninstall` has to leave a worktree in place, if it does. `--force`
+/// overrides everything except a running agent.
+fn uninstall_blocker(wt: &WorktreeInfo, force: bool) -> Option<String> {
+    if wt.has_active_session {
+        return Some("active agent session".to_string());
+    }
+    if force {
+        return None;
+    }
+    local_work_summary(wt)
+}
 
-    // Run the TUI and get the selected index
-    let selected = run_resume_tui(&sessions)?;
+fn print_uninstall_category(label: &str, detail: String) {
+    println!("\n{} {} {}", "→".blue().bold(), label.bold(), format!("({})", detail).dimmed());
+}
 
-    let Some(idx) = selected else {
-        return Ok(());
-    };
+/// How `migrate` brings a worktree from the old dir to the new one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MigrateStep {
+    /// Still at the old path: `git worktree move` it
+    Move,
+    /// Already moved by hand: `git worktree repair` git's records
+    Repair,
+}
 
-    let ws = &sessions[idx];
-    let worktree_path = &ws.worktree.path;
-    let agent = ws.session.agent;
+/// What to do for a worktree given whether its old and new paths exist
+fn migrate_step(old_exists: bool, new_exists: bool) -> Result<MigrateStep, &'static str> {
+    match (old_exists, new_exists) {
+        (true, false) => Ok(MigrateStep::Move),
+        (false, true) => Ok(MigrateStep::Repair),
+        (true, true) => Err("exists at both the old and the new path"),
+        (false, false) => Err("found at neither the old nor the new path"),
+    }
+}
 
-    prepare_agent_worktree(agent, worktree_path, &repo_root)?;
-    let bg_color = pick_available_color(worktree_path);
-    save_worktree_color(worktree_path, &bg_color)?;
-    record_current_iterm_session(worktree_path)?;
-    let session_name = session_name_for_resume(worktree_path, &ws.worktree.branch);
+fn run_migrate(from: &Path, to: &Path, dry_run: bool, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let to = config::expand_tilde(to);
+    // git may report the old dir as given or canonicalized, and .claude.json
+    // keeps paths as they were created
+    let mut from_dirs = vec![config::expand_tilde(from)];
+    if let Ok(canonical) = from_dirs[0].canonicalize() {
+        if canonical != from_dirs[0] {
+            from_dirs.push(canonical);
+        }
+    }
 
-    let _iterm_guard = ItermGuard::new(&bg_color, &session_name);
+    // Each worktree's old path as git reports it, then its other spellings
+    let moved: Vec<(Vec<PathBuf>, PathBuf)> = git::list_worktrees(&repo_root)?
+        .into_iter()
+        .filter(|entry| entry.path != repo_root)
+        .filter_map(|entry| {
+            let relative = from_dirs.iter().find_map(|dir| entry.path.strip_prefix(dir).ok())?.to_path_buf();
+            let mut old_paths = vec![entry.path.clone()];
+            old_paths.extend(from_dirs.iter().map(|dir| dir.join(&relative)).filter(|path| *path != entry.path));
+            Some((old_paths, to.join(relative)))
+        })
+        .collect();
+    if moved.is_empty() {
+        println!("{} No worktrees registered under {}", "→".blue().bold(), from.display());
+        return Ok(());
+    }
 
     println!(
-        "\n{} Resuming session in {}...\n",
+        "{} {} {} worktree(s) from {} to {}",
         "→".blue().bold(),
-        worktree_path.display().to_string().cyan()
+        if dry_run { "Would migrate" } else { "Migrating" },
+        moved.len(),
+        from.display().to_string().dimmed(),
+        to.display().to_string().cyan()
     );
+    check_worktree_state()?;
+    let mut failed = 0;
+    for (old_paths, new_path) in &moved {
+        let old_path = &old_paths[0];
+        let name = worktree_dir_name(new_path);
+        let step = match migrate_step(old_path.exists(), new_path.exists()) {
+            Ok(step) => step,
+            Err(reason) => {
+                println!("  {} {}: {}", "✗".red(), name.cyan(), reason);
+                failed += 1;
+                continue;
+            }
+        };
+        let action = match step {
+            MigrateStep::Move => "move",
+            MigrateStep::Repair => "repair",
+        };
+        if dry_run {
+            println!("  {} {}: would {}, then update its color, note and .claude.json entry", "→".blue(), name.cyan(), action);
+            continue;
+        }
+        let result = match step {
+            MigrateStep::Move => new_path
+                .parent()
+                .map_or(Ok(()), |parent| {
+                    fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))
+                })
+                .and_then(|()| git::move_worktree(&repo_root, old_path, new_path)),
+            MigrateStep::Repair => git::repair_worktree(&repo_root, new_path),
+        };
+        // Only a worktree git now knows at its new path gets its state moved.
+        // If that fails, the state that did move goes back, and so does a
+        // worktree this run moved; one moved by hand stays where it is.
+        let result = result.and_then(|()| {
+            move_worktree_state(old_paths, new_path).map_err(|e| {
+                let _ = move_worktree_state(std::slice::from_ref(new_path), old_path);
+                match step {
+                    MigrateStep::Move => match git::move_worktree(&repo_root, new_path, old_path) {
+                        Ok(()) => format!("{}; moved it back to the old path", e),
+                        Err(back) => format!("moved, but {}; moving it back failed too: {}", e, back),
+                    },
+                    MigrateStep::Repair => format!("repaired, but {}", e),
+                }
+            })
+        });
+        match result {
+            Ok(()) => println!("  {} {}: {}", "✓".green(), name.cyan(), if step == MigrateStep::Move { "moved" } else { "repaired" }),
+            Err(e) => {
+                println!("  {} {}: {}", "✗".red(), name.cyan(), e);
+                failed += 1;
+            }
+        }
+    }
 
-    let developer_instructions = build_worktree_system_prompt();
-    spawn_agent_continue(
-        agent,
-        worktree_path,
-        None,
-        Some(&developer_instructions),
-        ws.session.resume_id.as_deref(),
-        &session_name,
-    )?;
-
+    let configured = env::var_os("CHECKOUT_WORKTREE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| config::get().worktree_dir.as_deref().map(config::expand_tilde));
+    if configured.is_some_and(|dir| from_dirs.contains(&dir)) {
+        println!(
+            "{} worktree_dir still points at the old dir; run `checkout config set worktree_dir {}` (or update $CHECKOUT_WORKTREE_DIR)",
+            "!".yellow().bold(),
+            to.display()
+        );
+    }
+    if failed > 0 {
+        return Err(format!("Failed to migrate {} worktree(s)", failed));
+    }
     Ok(())
 }
 
-fn run_resume_last(repo: Option<PathBuf>, agent: Agent) -> Result<(), String> {
-    timing!("run_resume_last");
-    let repo_root = repo.unwrap_or_else(default_repo_root);
-
+fn run_uninstall(repo: Option<PathBuf>, options: &UninstallOptions) -> Result<(), String> {
+    timing!("run_uninstall");
+    let repo_root = resolve_repo_root(repo);
     if !repo_root.exists() {
         return Err(format!("Repo not found at {}", repo_root.display()));
     }
 
-    // Read all .exited files, find the most recent one whose worktree still exists
-    // and doesn't have an active session
+    let data_dir = get_data_dir();
+    let color_dir = get_color_dir();
     let session_dir = get_session_dir();
-    let entries = fs::read_dir(&session_dir)
-        .map_err(|e| format!("Failed to read session dir: {}", e))?;
+    let home = PathBuf::from(env::var("HOME").unwrap_or_default());
+    for dir in [&data_dir, &color_dir, &session_dir] {
+        check_uninstall_dir(dir, &home, &config::config_path(), &repo_root)?;
+    }
 
-    let mut best: Option<(u64, PathBuf)> = None;
+    // Worktrees are checkout's to remove when they live in its worktree dir
+    let [worktree_dir, canonical_worktree_dir] = owned_worktree_dirs(&repo_root);
+    let owned_dirs = [worktree_dir.as_path(), canonical_worktree_dir.as_path()];
+    let (to_remove, kept): (Vec<_>, Vec<_>) = get_all_worktrees(&repo_root)?
+        .into_vec()
+        .into_iter()
+        .filter(|wt| owned_dirs.iter().any(|dir| wt.path.starts_with(dir)))
+        .map(|wt| {
+            let blocker = uninstall_blocker(&wt, options.force);
+            (wt, blocker)
+        })
+        .partition(|(_, blocker)| blocker.is_none());
+    let to_remove: Vec<WorktreeInfo> = to_remove.into_iter().map(|(wt, _)| wt).collect();
 
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) != Some("exited") {
-            continue;
-        }
+    let claude_entries: Vec<String> = claude_json_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok())
+        .map(|data| claude_project_entries(&data, &owned_dirs))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|key| !kept.iter().any(|(wt, _)| wt.path == Path::new(key)))
+        .collect();
 
-        let content = match fs::read_to_string(&path) {
-            Ok(c) => c,
-            Err(_) => continue,
-        };
+    let branches = if options.delete_branches {
+        let default_branch = git::default_branch(&repo_root, git::REMOTE)?.unwrap_or_else(|| "master".to_string());
+        let remaining: Vec<_> = git::list_worktrees(&repo_root)?
+            .into_iter()
+            .filter(|entry| !to_remove.iter().any(|wt| wt.path == entry.path))
+            .collect();
+        branches_to_delete(&to_remove, &remaining, &default_branch)
+    } else {
+        Vec::new()
+    };
 
-        let Some(exited) = parse_exited_session(&content) else {
-            continue;
-        };
-        if exited.agent != agent {
-            continue;
-        }
-        let timestamp = exited.timestamp;
-        let worktree_path = exited.worktree_path;
+    // Records left behind by worktrees that are already gone; the ones being
+    // removed now are cleaned up with them
+    let kept_paths: Vec<&Path> = kept.iter().map(|(wt, _)| wt.path.as_path()).collect();
+    let stale_records: Vec<String> = uninstall_records(&metadata::load(&data_dir), &owned_dirs, &kept_paths)
+        .into_iter()
+        .filter(|key| !to_remove.iter().any(|wt| wt.path == Path::new(key)))
+        .collect();
+    // Per-PR and per-branch records aren't tied to a worktree, so they only go when
+    // none of the repo's worktrees stay
+    let pr_records = if kept.is_empty() { metadata::load(&data_dir).remove_repo_records(&repo_root) } else { 0 };
+    let last_checkout = last_checkout::load(&data_dir)
+        .ok()
+        .flatten()
+        .filter(|last| owned_dirs.iter().any(|dir| last.path.starts_with(dir)) && !kept_paths.contains(&last.path.as_path()));
 
-        // Skip if worktree no longer exists
-        if !worktree_path.exists() {
-            let _ = fs::remove_file(&path);
-            continue;
-        }
+    let worktree_usage: Vec<(usize, u64)> = to_remove.iter().map(|wt| dir_usage(&wt.path, &[])).collect();
+    let total_bytes = worktree_usage.iter().map(|(_, bytes)| bytes).sum::<u64>();
 
-        // Skip if there's an active session
-        if let Some(pid) = read_session_pid(&worktree_path) {
-            if is_pid_alive(pid) {
-                continue;
-            }
-        }
+    println!(
+        "{} {} everything checkout created for {}",
+        "→".blue().bold(),
+        if options.dry_run { "Would remove" } else { "Removing" },
+        repo_root.display().to_string().cyan()
+    );
 
-        if best.as_ref().map_or(true, |(ts, _)| timestamp > *ts) {
-            best = Some((timestamp, worktree_path));
+    print_uninstall_category(
+        "Worktrees",
+        format!("{}, {}", to_remove.len(), format_size(worktree_usage.iter().map(|(_, bytes)| bytes).sum())),
+    );
+    for (wt, (_, bytes)) in to_remove.iter().zip(&worktree_usage) {
+        println!(
+            "  {} {} {}",
+            worktree_dir_name(&wt.path).cyan(),
+            format!("({})", wt.branch_label()).dimmed(),
+            format_size(*bytes).dimmed()
+        );
+    }
+    print_uninstall_category("Claude project entries", format!("{}", claude_entries.len()));
+    for key in &claude_entries {
+        println!("  {}", key.dimmed());
+    }
+    print_uninstall_category("Notes and colors of removed worktrees", format!("{}", stale_records.len()));
+    for key in &stale_records {
+        println!("  {}", key.dimmed());
+    }
+    if pr_records > 0 {
+        print_uninstall_category("PR and branch records (branches, URLs, reviews, colors)", format!("{}", pr_records));
+    }
+    if let Some(last) = &last_checkout {
+        print_uninstall_category("Last checkout record", worktree_dir_name(&last.path));
+    }
+    if options.delete_branches {
+        print_uninstall_category("Local branches", format!("{}", branches.len()));
+        for branch in &branches {
+            println!("  {}", branch.cyan());
         }
     }
 
-    let Some((_timestamp, worktree_path)) = best else {
-        println!("{} No recently exited sessions found", "!".yellow().bold());
-        return Ok(());
-    };
-
-    let branch = worktree_path
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
+    if !kept.is_empty() {
+        println!(
+            "\n{} Keeping {} worktree(s):",
+            "⚠".yellow(),
+            kept.len()
+        );
+        for (wt, blocker) in &kept {
+            println!(
+                "  {} {}",
+                worktree_dir_name(&wt.path).cyan(),
+                format!("({})", blocker.as_deref().unwrap_or_default()).yellow()
+            );
+        }
+        if !options.force && kept.iter().any(|(wt, _)| !wt.has_active_session) {
+            println!("  {}", "use --force to remove worktrees with local work".dimmed());
+        }
+    }
 
     println!(
-        "{} Resuming last session in {}",
+        "\n{} Total: {} · the main repo {} is never touched",
         "→".blue().bold(),
-        worktree_path.display().to_string().cyan()
+        format_size(total_bytes).bold(),
+        repo_root.display()
+    );
+    println!(
+        "  {}",
+        format!(
+            "Backups, traces and other repos' state in {} are left in place",
+            data_dir.display()
+        )
+        .dimmed()
     );
 
-    prepare_agent_worktree(agent, &worktree_path, &repo_root)?;
-    let bg_color = pick_available_color(&worktree_path);
-    save_worktree_color(&worktree_path, &bg_color)?;
-    record_current_iterm_session(&worktree_path)?;
-    let session_name = session_name_for_resume(&worktree_path, &branch);
+    if options.dry_run {
+        println!("{} Dry run: nothing removed", "✓".green().bold());
+        return Ok(());
+    }
 
-    let _iterm_guard = ItermGuard::new(&bg_color, &session_name);
+    if !options.yes {
+        print!("\n{} Remove all of the above? [y/N]: ", "?".magenta().bold());
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let input = read_confirmation()?;
+        if input.trim().to_lowercase() != "y" {
+            return Err(Interrupt::Declined.raise());
+        }
+    }
 
-    let system_prompt = build_worktree_system_prompt();
-    let resume_id = if agent == Agent::Codex {
-        find_codex_worktree_session_id(&worktree_path)
-    } else {
-        None
-    };
+    if !to_remove.is_empty() {
+        println!();
+        remove_worktrees(&to_remove, &repo_root)?;
+        prune_after_clean(&repo_root, true, false)?;
+        if options.delete_branches {
+            delete_removed_branches(&repo_root, &to_remove, BranchDeletion { force: options.force })?;
+        }
+    }
 
-    println!();
-    println!(
-        "{} Resuming {} session...",
-        "→".blue().bold(),
-        agent.display_name(),
-    );
-    println!();
+    if !claude_entries.is_empty() {
+        print!("{} Removing {} Claude project entries... ", "→".blue().bold(), claude_entries.len());
+        io::stdout().flush().ok();
+        match remove_claude_project_entries(&claude_entries) {
+            Ok(()) => println!("{}", "done".green()),
+            Err(e) => println!("{}\n    {} {}", "failed".red(), "error:".red(), e),
+        }
+    }
 
-    spawn_agent_continue(
-        agent,
-        &worktree_path,
-        None,
-        Some(&system_prompt),
-        resume_id.as_deref(),
-        &session_name,
-    )?;
+    if !stale_records.is_empty() || pr_records > 0 {
+        for key in &stale_records {
+            let _ = fs::remove_file(worktree_color_file(Path::new(key)));
+        }
+        metadata::update(&data_dir, |metadata| {
+            for key in &stale_records {
+                metadata.notes.remove(key);
+                metadata.last_used.remove(key);
+            }
+            if pr_records > 0 {
+                metadata.remove_repo_records(&repo_root);
+            }
+        })?;
+    }
+    if last_checkout.is_some() {
+        let _ = fs::remove_file(last_checkout::last_checkout_file(&data_dir));
+    }
+    // Only files checkout created were removed above; drop the directories
+    // too if nothing else is left in them
+    for dir in [&color_dir, &session_dir, &data_dir] {
+        let _ = fs::remove_dir(dir);
+    }
 
+    println!(
+        "\n{} Uninstalled from {}; {} left in place",
+        "✓".green().bold(),
+        repo_root.display(),
+        config::config_path().display()
+    );
     Ok(())
 }
 
-/// Parse inline markdown (`**bold**`, `*italic*`, `` `code` ``) into styled spans.
-fn parse_inline_markdown(text: &str, base_style: Style) -> Vec<(String, Style)> {
-    let mut segments: Vec<(String, Style)> = Vec::new();
-    let mut current = String::new();
-    let mut bold = false;
-    let mut italic = false;
-    let mut in_code = false;
-    let chars: Vec<char> = text.chars().collect();
-    let len = chars.len();
-    let mut i = 0;
-
-    let code_style = base_style.fg(Color::Cyan);
-
-    let make_style = |base: Style, b: bool, it: bool| -> Style {
-        let mut s = base;
-        if b { s = s.add_modifier(Modifier::BOLD); }
-        if it { s = s.add_modifier(Modifier::ITALIC); }
-        s
+/// Refuse to clean up state in `dir` when it is the home directory, holds the
+/// config file, or holds the main repo: a misconfigured `data_dir` must never
+/// point uninstall at files checkout doesn't own.
+fn check_uninstall_dir(dir: &Path, home: &Path, config_path: &Path, repo_root: &Path) -> Result<(), String> {
+    let reason = if dir.as_os_str().is_empty() || dir == Path::new("/") || dir == home {
+        "it is the home directory"
+    } else if config_path.starts_with(dir) {
+        "it contains the config file"
+    } else if repo_root.starts_with(dir) {
+        "it contains the main repo"
+    } else {
+        return Ok(());
     };
+    Err(format!("Refusing to uninstall from {}: {}", dir.display(), reason))
+}
+
+/// Worktree paths in the owned dirs that still have a note or last-use
+/// record, other than the worktrees being kept.
+fn uninstall_records(metadata: &metadata::Metadata, owned_dirs: &[&Path], kept: &[&Path]) -> Vec<String> {
+    metadata
+        .notes
+        .keys()
+        .chain(metadata.last_used.keys())
+        .filter(|key| {
+            let path = Path::new(key.as_str());
+            owned_dirs.iter().any(|dir| path.starts_with(dir)) && !kept.contains(&path)
+        })
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
 
-    while i < len {
-        if chars[i] == '`' {
-            if !current.is_empty() {
-                let style = if in_code { code_style } else { make_style(base_style, bold, italic) };
-                segments.push((std::mem::take(&mut current), style));
-            }
-            in_code = !in_code;
-            i += 1;
-        } else if in_code {
-            // Inside backticks — no markdown parsing
-            current.push(chars[i]);
-            i += 1;
-        } else if i + 1 < len && chars[i] == '*' && chars[i + 1] == '*' {
-            if !current.is_empty() {
-                segments.push((std::mem::take(&mut current), make_style(base_style, bold, italic)));
-            }
-            bold = !bold;
-            i += 2;
-        } else if chars[i] == '*' {
-            if !current.is_empty() {
-                segments.push((std::mem::take(&mut current), make_style(base_style, bold, italic)));
-            }
-            italic = !italic;
-            i += 1;
-        } else {
-            current.push(chars[i]);
-            i += 1;
-        }
-    }
+/// Explain what `--no-checkout` skipped and how to materialize the files.
+fn print_no_checkout_note(worktree_path: &Path) {
+    sayln!(
+        "{} Files not checked out (--no-checkout); skipped mise, dependency links and the agent",
+        "!".yellow().bold()
+    );
+    sayln!(
+        "  {}",
+        format!(
+            "Populate it with `git sparse-checkout set <dirs>`, or `git reset --hard` for every file, in {}",
+            worktree_path.display()
+        )
+        .dimmed()
+    );
+}
 
-    if !current.is_empty() {
-        let style = if in_code { code_style } else { make_style(base_style, bold, italic) };
-        segments.push((current, style));
+/// Trust mise configs before the agent starts, then run non-critical setup in
+/// the background. Trust is path-based, so this must also run after an idle
+/// worktree is moved to a new workspace path.
+fn start_new_worktree_setup(
+    worktree_path: PathBuf,
+    repo_root: PathBuf,
+    strict: bool,
+) -> Result<thread::JoinHandle<()>, String> {
+    if which_mise().is_some() {
+        best_effort("mise trust", run_mise_trust(&worktree_path), strict)?;
     }
 
-    segments
+    Ok(spawn_background_setup(worktree_path, repo_root))
 }
 
-/// Wrap pre-styled segments into display lines, breaking at word boundaries.
-fn wrap_styled_segments<'a>(segments: &[(String, Style)], width: usize) -> Vec<Vec<Span<'a>>> {
-    // Flatten into a list of (char, style) pairs
-    let mut styled_chars: Vec<(char, Style)> = Vec::new();
-    for (text, style) in segments {
-        for ch in text.chars() {
-            styled_chars.push((ch, *style));
+/// Spawn non-critical worktree setup steps in the background so the agent can
+/// start sooner. Errors are logged to stderr but otherwise ignored.
+fn spawn_background_setup(
+    worktree_path: PathBuf,
+    repo_root: PathBuf,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = symlink_node_modules(&worktree_path, &repo_root) {
+            eprintln!("background: symlink_node_modules failed: {}", e);
         }
-    }
 
-    if styled_chars.is_empty() {
-        return Vec::new();
-    }
-
-    let mut result: Vec<Vec<Span<'a>>> = Vec::new();
-    let total = styled_chars.len();
-    let mut pos = 0;
-
-    while pos < total {
-        let line_end = (pos + width).min(total);
-
-        // If the whole remainder fits, take it all
-        if line_end == total {
-            let line = build_spans_from_styled_chars(&styled_chars[pos..total]);
-            result.push(line);
-            break;
+        if let Err(e) = symlink_vendor_bundle(&worktree_path, &repo_root) {
+            eprintln!("background: symlink_vendor_bundle failed: {}", e);
         }
 
-        // Look for a space to break at (scan backwards from the cut point)
-        let mut break_at = line_end;
-        let mut found_space = false;
-        for i in (pos..line_end).rev() {
-            if styled_chars[i].0 == ' ' {
-                break_at = i + 1; // break after the space
-                found_space = true;
-                break;
-            }
+        // Validate the bundle against this checkout's Gemfile.lock now that the
+        // vendor/ cache is linked, so the first commit's Ruby hooks don't fail.
+        if let Err(e) = run_bundle_install(&worktree_path, &repo_root) {
+            eprintln!("background: run_bundle_install failed: {}", e);
         }
+    })
+}
 
-        // If no space found, hard-break at width
-        if !found_space {
-            break_at = line_end;
+fn create_new_worktree_from_remote(
+    repo_root: &PathBuf,
+    worktree_path: &PathBuf,
+    branch: &str,
+    pr_number: u64,
+    head_oid: Option<&str>,
+    fork: bool,
+    checkout: &CheckoutArgs,
+) -> Result<(), String> {
+    timing!("create_new_worktree_from_remote");
+    let use_pull_ref = uses_pull_ref(fork);
+    let start_ref = if use_pull_ref { pr_fetch_ref(pr_number) } else { format!("origin/{}", branch) };
+    if use_pull_ref {
+        fetch_pull_ref(repo_root, pr_number, head_oid, checkout.no_fetch)?;
+    } else if use_local_ref(repo_root, branch, checkout.no_fetch) {
+        print_using_local_ref(&start_ref, "--no-fetch");
+    } else if head_oid.is_some_and(|oid| git::ref_points_at(repo_root, &start_ref, oid)) {
+        // Re-checking out a PR whose objects the main repo still has
+        print_using_local_ref(&start_ref, "up to date");
+    } else {
+        progress!(
+            "{} Fetching branch {}... ",
+            "→".blue().bold(),
+            branch.yellow()
+        );
+        note_no_fetch_fallback(branch, checkout.no_fetch);
+        std::io::stdout().flush().ok();
+        if fetch_branch(repo_root, branch).is_err() {
+            // Branch may have been deleted after merge — fetch the PR head ref instead
+            let pr_ref = format!("pull/{}/head", pr_number);
+            fetch_branch(repo_root, &pr_ref)?;
         }
+        progressln!("{}", "done".green());
+    }
 
-        let line = build_spans_from_styled_chars(&styled_chars[pos..break_at]);
-        result.push(line);
-        pos = break_at;
+    progressln!(
+        "{} Creating worktree at {}",
+        "→".blue().bold(),
+        worktree_path.display().to_string().cyan()
+    );
+    create_worktree_from_ref(repo_root, worktree_path, &start_ref, Some("FETCH_HEAD"), checkout.no_checkout)?;
+    if let Some(count) = count_worktree_files(worktree_path, checkout.no_checkout) {
+        progressln!("  {} ({} files)", "done".green(), count.to_string().yellow());
+    } else {
+        progressln!("  {}", "done".green());
     }
 
-    result
+    Ok(())
 }
 
-/// Convert a slice of (char, style) pairs into coalesced Spans.
-fn build_spans_from_styled_chars<'a>(chars: &[(char, Style)]) -> Vec<Span<'a>> {
-    let mut spans: Vec<Span<'a>> = Vec::new();
-    if chars.is_empty() {
-        return spans;
+/// Whether a PR is fetched from `pull/<n>/head` rather than its branch.
+/// `origin/<branch>` is missing or unrelated for a fork, so its PR always
+/// comes from the head ref GitHub keeps on the base repo.
+fn uses_pull_ref(fork: bool) -> bool {
+    fork || config::get().pr_fetch == config::PrFetch::PullRef
+}
+
+/// Local ref that `pull/<n>/head` is fetched into
+fn pr_fetch_ref(pr_number: u64) -> String {
+    format!("refs/checkout/pr-{}", pr_number)
+}
+
+/// Fetch `pull/<n>/head` into `pr_fetch_ref`, unless it's already at the
+/// reported head or `--no-fetch` finds it locally.
+fn fetch_pull_ref(repo_root: &PathBuf, pr_number: u64, head_oid: Option<&str>, no_fetch: bool) -> Result<(), String> {
+    let local = pr_fetch_ref(pr_number);
+    if no_fetch && git::ref_exists(repo_root, &local).unwrap_or(false) {
+        print_using_local_ref(&local, "--no-fetch");
+        return Ok(());
     }
+    if head_oid.is_some_and(|oid| git::ref_points_at(repo_root, &local, oid)) {
+        print_using_local_ref(&local, "up to date");
+        return Ok(());
+    }
+    let pull_ref = format!("pull/{}/head", pr_number);
+    progress!("{} Fetching {}... ", "→".blue().bold(), pull_ref.yellow());
+    std::io::stdout().flush().ok();
+    // Forced: the PR head moves backwards when its branch is force-pushed
+    fetch_branch(repo_root, &format!("+{}:{}", pull_ref, local))?;
+    progressln!("{}", "done".green());
+    Ok(())
+}
 
-    let mut current_text = String::new();
-    let mut current_style = chars[0].1;
+/// Drop the `pull/<n>/head` ref fetched for a removed PR worktree, so it
+/// doesn't keep the PR's objects from being garbage-collected. Another
+/// worktree of the same PR (`pr-<n>-2`) still resets to it, so it stays
+/// until the last one is gone.
+fn remove_pr_fetch_ref(repo_root: &Path, worktree_path: &Path) {
+    let Some(pr_number) = ui::pr_number_from_dir(&worktree_dir_name(worktree_path)) else {
+        return;
+    };
+    let Ok(worktrees) = git::list_worktrees(repo_root) else {
+        return;
+    };
+    let still_used = worktrees.iter().any(|entry| {
+        entry.path != worktree_path && ui::pr_number_from_dir(&worktree_dir_name(&entry.path)) == Some(pr_number)
+    });
+    if !still_used {
+        let _ = git::delete_ref(repo_root, &pr_fetch_ref(pr_number));
+    }
+}
 
-    for &(ch, style) in chars {
-        if style == current_style {
-            current_text.push(ch);
-        } else {
-            if !current_text.is_empty() {
-                spans.push(Span::styled(std::mem::take(&mut current_text), current_style));
+fn create_new_worktree_new_branch(
+    repo_root: &PathBuf,
+    worktree_path: &PathBuf,
+    branch: &str,
+    base: Option<&str>,
+    checkout: &CheckoutArgs,
+) -> Result<(), String> {
+    timing!("create_new_worktree_new_branch");
+    let start_point = match base {
+        Some(base) => {
+            progress!("{} Resolving base {}... ", "→".blue().bold(), base.yellow());
+            std::io::stdout().flush().ok();
+            let start_point = git::resolve_base(repo_root, base, git::REMOTE, |branch| {
+                if use_local_ref(repo_root, branch, checkout.no_fetch) {
+                    Ok(())
+                } else {
+                    fetch_branch(repo_root, branch)
+                }
+            })?;
+            println!("{}", start_point.green());
+            start_point
+        }
+        None => {
+            // Fetch latest master
+            if use_local_ref(repo_root, "master", checkout.no_fetch) {
+                print_using_local_ref("origin/master", "--no-fetch");
+            } else {
+                progress!("{} Fetching latest master... ", "→".blue().bold());
+                note_no_fetch_fallback("master", checkout.no_fetch);
+                std::io::stdout().flush().ok();
+                fetch_branch(repo_root, "master")?;
+                progressln!("{}", "done".green());
             }
-            current_style = style;
-            current_text.push(ch);
+            "origin/master".to_string()
         }
+    };
+
+    progressln!(
+        "{} Creating worktree with new branch {}",
+        "→".blue().bold(),
+        branch.yellow()
+    );
+    create_worktree_new_branch(repo_root, worktree_path, branch, &start_point, checkout.no_checkout)?;
+    if let Some(count) = count_worktree_files(worktree_path, checkout.no_checkout) {
+        progressln!("  {} ({} files)", "done".green(), count.to_string().yellow());
+    } else {
+        progressln!("  {}", "done".green());
     }
 
-    if !current_text.is_empty() {
-        spans.push(Span::styled(current_text, current_style));
+    // Track with graphite. Stacks are parented on master, so a branch cut
+    // from another base is left for the user to track.
+    if base.is_some() {
+        progressln!(
+            "{} Skipping Graphite tracking {}",
+            "→".blue().bold(),
+            "(custom base)".dimmed()
+        );
+    } else {
+        progress!("{} Tracking with Graphite... ", "→".blue().bold());
+        std::io::stdout().flush().ok();
+        finish_step("Graphite tracking", run_gt_track(worktree_path), checkout.strict)?;
     }
 
-    spans
+    Ok(())
 }
 
-/// Render messages for a session into display lines, fitting within a line budget.
-fn render_session_messages(ws: &WorktreeSession, max_lines: usize, msg_width: usize) -> Vec<Line<'static>> {
-    let prefix_width = 14; // "    ┃ <agent> " with a six-character label
-    let effective_msg_width = if msg_width > prefix_width + 4 { msg_width - prefix_width } else { 40 };
-    let agent_label = ws.session.agent.display_name();
+/// Returns the short status output if there are uncommitted changes, None otherwise.
+fn get_uncommitted_status(worktree_path: &PathBuf) -> Result<Option<String>, String> {
+    timing!("get_uncommitted_status");
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "status", "--short"])
+        .traced_output()
+        .map_err(|e| format!("Failed to check git status: {}", e))?;
 
-    let mut all_msg_lines: Vec<Vec<Line>> = Vec::new();
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if stdout.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(stdout))
+    }
+}
 
-    for (msg_idx, msg) in ws.session.messages.iter().enumerate() {
-        let (label, label_style, text_style) = if msg.role == "user" {
-            ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
-             Style::default().fg(Color::White))
-        } else {
-            (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD),
-             Style::default().fg(Color::Gray))
-        };
+/// Show a reused worktree's changes and confirm discarding the tracked ones
+/// (untracked files survive the reset), backing them up once confirmed.
+/// Exits if the user declines.
+fn confirm_discard_changes(worktree_path: &Path, changes: &str) -> Result<(), String> {
+    // Separate tracked changes from untracked files
+    let tracked: Vec<&str> = changes.lines().filter(|l| !l.starts_with("??")).collect();
+    let untracked: Vec<&str> = changes.lines().filter(|l| l.starts_with("??")).collect();
 
-        let mut msg_lines: Vec<Line> = Vec::new();
+    if !untracked.is_empty() {
+        sayln!();
+        sayln!(
+            "{} Worktree has untracked files {}:",
+            "!".yellow().bold(),
+            "(will be kept)".dimmed()
+        );
+        for line in &untracked {
+            sayln!("  {}", line.dimmed());
+        }
+    }
 
-        if msg_idx > 0 {
-            msg_lines.push(Line::from(vec![
-                Span::styled("    ┃", Style::default().fg(Color::DarkGray)),
-            ]));
+    if !tracked.is_empty() {
+        sayln!();
+        sayln!(
+            "{} Worktree has uncommitted changes:",
+            "!".yellow().bold()
+        );
+        for line in &tracked {
+            sayln!("  {}", line.dimmed());
         }
+        sayln!();
+        say!(
+            "{} Discard these changes? [y/N]: ",
+            "!".yellow().bold()
+        );
+        io::stdout().flush().map_err(|e| e.to_string())?;
 
-        let mut first_line = true;
+        let confirm = read_confirmation()?;
 
-        for text_line in msg.content.split('\n') {
-            if text_line.is_empty() {
-                if first_line {
-                    msg_lines.push(Line::from(vec![
-                        Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
-                        Span::styled(label, label_style),
-                    ]));
-                    first_line = false;
-                } else {
-                    msg_lines.push(Line::from(vec![
-                        Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
-                    ]));
-                }
-                continue;
-            }
+        if confirm.trim().to_lowercase() != "y" {
+            return Err(Interrupt::Declined.raise());
+        }
+        back_up_changes(worktree_path)?;
+    }
+    Ok(())
+}
 
-            let segments = parse_inline_markdown(text_line, text_style);
-            let wrapped = wrap_styled_segments(&segments, effective_msg_width);
+/// Save the worktree's uncommitted changes and untracked files before they're
+/// discarded; a failed backup stops the discard.
+fn back_up_changes(worktree_path: &Path) -> Result<(), String> {
+    let Some(patch) = backup::create(&get_data_dir(), worktree_path)? else {
+        return Ok(());
+    };
+    sayln!(
+        "{} Backed up the changes to {} {}",
+        "→".blue().bold(),
+        patch.display(),
+        "(checkout backups restore <file> re-applies them)".dimmed()
+    );
+    Ok(())
+}
 
-            for display_spans in wrapped {
-                let mut line_spans = vec![
-                    Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
-                ];
-                if first_line {
-                    line_spans.push(Span::styled(label, label_style));
-                    line_spans.push(Span::raw(" "));
-                    first_line = false;
-                } else {
-                    line_spans.push(Span::raw("       "));
-                }
-                line_spans.extend(display_spans);
-                msg_lines.push(Line::from(line_spans));
+/// What `pr --force-update` does with uncommitted changes to tracked files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OnDirty {
+    /// Stop with an error, leaving the worktree untouched
+    #[default]
+    Fail,
+    /// `git stash` them before updating
+    Stash,
+    /// Let the reset throw them away, along with any unpushed commits
+    Discard,
+}
+
+/// Reset a worktree to `origin/<branch>` without prompting, first dealing
+/// with uncommitted changes per `on_dirty`. Untracked files are always kept.
+/// Unpushed commits are only reset away with `OnDirty::Discard`.
+fn force_update_worktree(
+    worktree_path: &Path,
+    upstream: Upstream,
+    on_dirty: OnDirty,
+    no_fetch: bool,
+) -> Result<(), String> {
+    let dirty = get_uncommitted_status(&worktree_path.to_path_buf())?.is_some_and(|status| has_tracked_changes(&status));
+    if dirty {
+        match on_dirty {
+            OnDirty::Fail => {
+                return Err(format!(
+                    "{} has uncommitted changes; pass --on-dirty stash or --on-dirty discard to update anyway",
+                    worktree_path.display()
+                ));
+            }
+            OnDirty::Stash => {
+                progress!("{} Stashing uncommitted changes... ", "→".blue().bold());
+                io::stdout().flush().ok();
+                git::stash_push(worktree_path, &format!("checkout: before updating to {}", upstream.ref_name()))?;
+                progressln!("{} {}", "done".green(), "(git stash pop to restore)".dimmed());
+            }
+            OnDirty::Discard => {
+                back_up_changes(worktree_path)?;
+                progressln!("{} Discarding uncommitted changes", "→".blue().bold());
             }
         }
-
-        all_msg_lines.push(msg_lines);
     }
 
-    // Walk backwards from newest, fitting whole messages
-    let mut budget = max_lines;
-    let mut start_msg = all_msg_lines.len();
+    progress!("{} Updating to latest... ", "→".blue().bold());
+    io::stdout().flush().ok();
+    let local_commits = match on_dirty {
+        OnDirty::Discard => LocalCommits::Discard,
+        OnDirty::Fail | OnDirty::Stash => LocalCommits::Refuse,
+    };
+    update_worktree(&worktree_path.to_path_buf(), upstream, local_commits, no_fetch)?;
+    progressln!("{}", "done".green());
+    Ok(())
+}
 
-    for (i, msg_lines) in all_msg_lines.iter().enumerate().rev() {
-        if msg_lines.len() <= budget {
-            budget -= msg_lines.len();
-            start_msg = i;
-        } else {
-            break;
-        }
-    }
+fn prompt_existing_worktree_action(
+    worktree_path: &Path,
+    changes_handle: thread::JoinHandle<Result<Option<String>, String>>,
+    selected_agent: Agent,
+    mut resume_target: Option<ResumeTarget>,
+) -> Result<ExistingWorktreeAction, String> {
+    sayln!();
+    let (use_existing_choice, use_as_is_choice, create_new_choice, valid_choices) = if let Some(target) = &resume_target {
+        sayln!(
+            "  {} {}",
+            "[1]".cyan().bold(),
+            resume_option_label(selected_agent, target)
+        );
+        sayln!(
+            "  {} Use existing worktree",
+            "[2]".cyan().bold()
+        );
+        sayln!(
+            "  {} Use as-is, don't update {}",
+            "[3]".cyan().bold(),
+            "(keeps uncommitted changes)".dimmed()
+        );
+        sayln!(
+            "  {} Create new worktree",
+            "[4]".cyan().bold()
+        );
+        ("2", "3", "4", "1/2/3/4")
+    } else {
+        sayln!(
+            "  {} Use existing worktree {}",
+            "[1]".cyan().bold(),
+            "(no session found to resume)".dimmed()
+        );
+        sayln!(
+            "  {} Use as-is, don't update {}",
+            "[2]".cyan().bold(),
+            "(keeps uncommitted changes)".dimmed()
+        );
+        sayln!(
+            "  {} Create new worktree",
+            "[3]".cyan().bold()
+        );
+        ("1", "2", "3", "1/2/3")
+    };
+    sayln!();
 
-    let mut lines: Vec<Line> = Vec::new();
+    loop {
+        say!(
+            "{} Choose an option [{}]: ",
+            "?".magenta().bold(),
+            valid_choices
+        );
+        io::stdout().flush().map_err(|e| e.to_string())?;
 
-    // If there's remaining budget and an older message that didn't fit whole,
-    // show its tail to fill the panel
-    if budget > 0 && start_msg > 0 {
-        let partial_idx = start_msg - 1;
-        let partial = &all_msg_lines[partial_idx];
-        let partial_msg = &ws.session.messages[partial_idx];
+        let input = read_input()?;
 
-        // Reserve 1 line for the "..." label
-        let tail_budget = budget.saturating_sub(1);
-        if tail_budget > 0 {
-            let (label, label_style) = if partial_msg.role == "user" {
-                ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
-            } else {
-                (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))
-            };
-            lines.push(Line::from(vec![
-                Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
-                Span::styled(label, label_style),
-                Span::styled(" ...", Style::default().fg(Color::DarkGray)),
-            ]));
-            let skip = partial.len().saturating_sub(tail_budget);
-            lines.extend(partial[skip..].iter().cloned());
+        match input.trim() {
+            "1" if resume_target.is_some() => {
+                return Ok(ExistingWorktreeAction::ResumeSession(
+                    resume_target.take().unwrap(),
+                ));
+            }
+            choice if choice == use_existing_choice => {
+                let status = changes_handle
+                    .join()
+                    .map_err(|_| "Failed to check git status".to_string())??;
+                if let Some(changes) = status {
+                    confirm_discard_changes(worktree_path, &changes)?;
+                }
+                return Ok(ExistingWorktreeAction::UseExisting);
+            }
+            choice if choice == use_as_is_choice => return Ok(ExistingWorktreeAction::UseAsIs),
+            choice if choice == create_new_choice => return Ok(ExistingWorktreeAction::CreateNew),
+            _ => {
+                sayln!(
+                    "{} Invalid option, please enter {}",
+                    "!".red().bold(),
+                    valid_choices.replace('/', ", ")
+                );
+            }
         }
     }
+}
 
-    if start_msg < all_msg_lines.len() {
-        for msg_lines in &all_msg_lines[start_msg..] {
-            lines.extend(msg_lines.iter().cloned());
-        }
-    } else if all_msg_lines.len() == 1 {
-        // Only one message and it's too large — show label + tail
-        let newest_lines = &all_msg_lines[0];
-        let newest_msg = &ws.session.messages[0];
-        let (label, label_style) = if newest_msg.role == "user" {
-            ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
-        } else {
-            (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))
-        };
-        lines.push(Line::from(vec![
-            Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
-            Span::styled(label, label_style),
-            Span::styled(" ...", Style::default().fg(Color::DarkGray)),
-        ]));
-        let tail_budget = max_lines.saturating_sub(1);
-        let skip = newest_lines.len().saturating_sub(tail_budget);
-        lines.extend(newest_lines[skip..].iter().cloned());
-    }
+enum BranchMismatchAction {
+    Update,
+    SwitchBack,
+    /// Follow a head branch renamed on GitHub: rename the local branch to the
+    /// PR's branch and track it on origin, then update
+    FollowRename { local: String },
+}
 
-    lines
+/// A local branch whose upstream is a different `origin` branch than the
+/// PR's. gh keeps the PR number when the author renames the head branch, so
+/// this is what a worktree branch left over from before the rename looks like.
+#[derive(Debug, PartialEq)]
+struct StaleUpstream {
+    local: String,
+    tracked: String,
 }
 
-fn run_resume_tui(sessions: &[WorktreeSession]) -> Result<Option<usize>, String> {
-    enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
-    let mut stdout = io::stdout();
-    stdout.execute(EnterAlternateScreen).map_err(|e| format!("Failed to enter alternate screen: {}", e))?;
+fn stale_upstream(current: Option<&str>, upstream: Option<&git::Upstream>, expected_branch: &str) -> Option<StaleUpstream> {
+    let (local, upstream) = (current?, upstream?);
+    (upstream.remote == git::REMOTE && upstream.branch != expected_branch).then(|| StaleUpstream {
+        local: local.to_string(),
+        tracked: upstream.branch.clone(),
+    })
+}
 
-    let original_hook = std::panic::take_hook();
-    std::panic::set_hook(Box::new(move |info| {
-        let _ = disable_raw_mode();
-        let _ = io::stdout().execute(LeaveAlternateScreen);
-        original_hook(info);
-    }));
+/// Guard against `update_worktree` hard-resetting a worktree that was manually
+/// switched to another branch. PR worktrees are created on a detached HEAD, so
+/// only a named branch that differs from the PR's counts as a mismatch. A
+/// branch still tracking the PR's old head branch is offered a rename.
+fn confirm_worktree_branch(
+    worktree_path: &Path,
+    pr_number: u64,
+    expected_branch: &str,
+) -> Result<BranchMismatchAction, String> {
+    let Some(current) = git::current_branch(worktree_path)? else {
+        return Ok(BranchMismatchAction::Update);
+    };
+    let upstream = git::upstream(worktree_path, &current)?;
+    let stale = stale_upstream(Some(&current), upstream.as_ref(), expected_branch);
+
+    if current == expected_branch {
+        if let Some(stale) = stale {
+            sayln!(
+                "{} Branch {} tracks origin/{}; pointing it at origin/{}",
+                "!".yellow().bold(),
+                current.yellow(),
+                stale.tracked,
+                expected_branch
+            );
+            track_pr_branch(worktree_path, &current, expected_branch)?;
+        }
+        return Ok(BranchMismatchAction::Update);
+    }
+
+    sayln!();
+    match &stale {
+        Some(stale) => {
+            sayln!(
+                "{} Worktree is on branch {} tracking origin/{}, but PR #{} is now {}",
+                "!".yellow().bold(),
+                current.yellow(),
+                stale.tracked,
+                pr_number,
+                expected_branch.yellow()
+            );
+            sayln!("  {}", "The PR's head branch may have been renamed on GitHub".dimmed());
+        }
+        None => sayln!(
+            "{} Worktree is on branch {}, but PR #{} is {}",
+            "!".yellow().bold(),
+            current.yellow(),
+            pr_number,
+            expected_branch.yellow()
+        ),
+    }
+    sayln!();
+    let mut actions = Vec::new();
+    if stale.is_some() {
+        actions.push((
+            format!(
+                "Rename {} to {} and track origin/{}, then update {}",
+                current,
+                expected_branch,
+                expected_branch,
+                "(git branch -m; discards uncommitted changes)".dimmed()
+            ),
+            Some(BranchMismatchAction::FollowRename { local: current.clone() }),
+        ));
+    }
+    actions.push((
+        format!(
+            "Reset to origin/{} anyway {}",
+            expected_branch,
+            format!("(moves {} there and discards uncommitted changes)", current).dimmed()
+        ),
+        Some(BranchMismatchAction::Update),
+    ));
+    actions.push((
+        format!("Switch back to {} without resetting", expected_branch),
+        Some(BranchMismatchAction::SwitchBack),
+    ));
+    actions.push(("Cancel".to_string(), None));
+    for (index, (label, _)) in actions.iter().enumerate() {
+        sayln!("  {} {}", format!("[{}]", index + 1).cyan().bold(), label);
+    }
+    sayln!();
+
+    let choices: Vec<String> = (1..=actions.len()).map(|n| n.to_string()).collect();
+    loop {
+        say!("{} Choose an option [{}]: ", "?".magenta().bold(), choices.join("/"));
+        io::stdout().flush().map_err(|e| e.to_string())?;
 
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).map_err(|e| format!("Failed to create terminal: {}", e))?;
+        let input = read_input()?;
 
-    let mut selected: usize = 0;
-    let mut scroll_offset: usize = 0;
-    let visible_branches: usize = 10;
-    let scroll_margin: usize = 2;
+        let choice = input.trim().parse::<usize>().ok().filter(|n| (1..=actions.len()).contains(n));
+        match choice {
+            Some(n) => match actions.swap_remove(n - 1).1 {
+                Some(action) => return Ok(action),
+                None => {
+                    return Err(Interrupt::Declined.raise());
+                }
+            },
+            None => sayln!(
+                "{} Invalid option, please enter {}",
+                "!".red().bold(),
+                choices.join(", ")
+            ),
+        }
+    }
+}
 
-    let result = loop {
-        terminal.draw(|frame| {
-            let area = frame.area();
+/// Make `local` track `origin/<pr_branch>`.
+fn track_pr_branch(worktree_path: &Path, local: &str, pr_branch: &str) -> Result<(), String> {
+    git::set_upstream(
+        worktree_path,
+        local,
+        &git::Upstream { remote: git::REMOTE.to_string(), branch: pr_branch.to_string() },
+    )
+}
 
-            // Clamp visible branches to what fits
-            let branch_height = visible_branches.min(sessions.len()) as u16;
+fn resume_option_label(selected_agent: Agent, target: &ResumeTarget) -> String {
+    if selected_agent == target.agent {
+        format!(
+            "Resume last {} session (keep changes, skip update)",
+            target.agent.display_name()
+        )
+    } else {
+        format!(
+            "Resume last {} session (switch from selected {}; keep changes, skip update)",
+            target.agent.display_name(),
+            selected_agent.display_name()
+        )
+    }
+}
 
-            // Layout: title | messages | separator | branches | footer
+/// Rename a worktree's metadata directory under `.git/worktrees/` to match its
+/// new working-dir name. `git worktree move` only renames the working dir; the
+/// metadata dir keeps its original name, so a recycled worktree ends up with
+/// `branch-coy-oak/.git` pointing at `.git/worktrees/branch-rusty-marsh`.
+fn rename_worktree_metadata(worktree_path: &Path, new_metadata_name: &str) -> Result<(), String> {
+    let dot_git = worktree_path.join(".git");
+    let pointer = fs::read_to_string(&dot_git)
+        .map_err(|e| format!("Failed to read {}: {}", dot_git.display(), e))?;
+    let old_metadata_path = pointer
+        .lines()
+        .next()
+        .and_then(|l| l.strip_prefix("gitdir:"))
+        .ok_or_else(|| format!("{} is not a gitdir pointer file", dot_git.display()))?
+        .trim();
+    let old_metadata_path = PathBuf::from(old_metadata_path);
+
+    let metadata_parent = old_metadata_path
+        .parent()
+        .ok_or_else(|| format!("metadata path {} has no parent", old_metadata_path.display()))?;
+    let new_metadata_path = metadata_parent.join(new_metadata_name);
+
+    if old_metadata_path == new_metadata_path {
+        return Ok(());
+    }
+    if new_metadata_path.exists() {
+        return Err(format!(
+            "Cannot rename worktree metadata: {} already exists",
+            new_metadata_path.display()
+        ));
+    }
+
+    fs::rename(&old_metadata_path, &new_metadata_path).map_err(|e| {
+        format!(
+            "Failed to rename worktree metadata {} → {}: {}",
+            old_metadata_path.display(),
+            new_metadata_path.display(),
+            e
+        )
+    })?;
+
+    let new_pointer = format!("gitdir: {}\n", new_metadata_path.display());
+    fs::write(&dot_git, new_pointer)
+        .map_err(|e| format!("Failed to update {}: {}", dot_git.display(), e))?;
+
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "rev-parse", "--git-dir"])
+        .traced_output()
+        .map_err(|e| format!("Failed to verify worktree after metadata rename: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git rev-parse failed after metadata rename:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Candidate directory name for the `attempt`-th retry (0-based) of `base_name`.
+fn collision_candidate(
+    strategy: config::CollisionStrategy,
+    base_name: &str,
+    attempt: u32,
+    now: Duration,
+) -> String {
+    match strategy {
+        config::CollisionStrategy::Numeric => format!("{}-{}", base_name, attempt + 2),
+        config::CollisionStrategy::Timestamp => {
+            format!("{}-{}", base_name, now.as_millis() + attempt as u128)
+        }
+        config::CollisionStrategy::Hash => {
+            let seed = format!("{}:{}:{}", std::process::id(), now.as_nanos(), attempt);
+            let digest = format!("{:x}", md5::compute(seed.as_bytes()));
+            format!("{}-{}", base_name, &digest[..7])
+        }
+    }
+}
+
+fn find_next_worktree_path(worktree_dir: &Path, base_name: &str) -> Result<PathBuf, String> {
+    let strategy = config::get().collision_strategy;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        let candidate = worktree_dir.join(collision_candidate(strategy, base_name, attempt, now));
+        if !candidate.exists() {
+            return Ok(candidate);
+        }
+        attempt += 1;
+        // Only the numeric scheme can run out; the others always produce a fresh name.
+        if strategy == config::CollisionStrategy::Numeric && attempt > 98 {
+            return Err("Too many worktrees".to_string());
+        }
+    }
+}
+
+/// GitHub stores issue and PR numbers as 32-bit signed integers.
+const MAX_PR_NUMBER: u64 = i32::MAX as u64;
+
+/// Parse a duration such as `500ms`, `2s`, `5m`, `1h` or `7d`; a bare number
+/// is seconds.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration '{}': expected e.g. 500ms, 2s or 1m", input);
+    let trimmed = input.trim();
+    let (digits, unit) = trimmed.split_at(trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len()));
+    let value: u64 = digits.parse().map_err(|_| invalid())?;
+    let millis_per = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return Err(invalid()),
+    };
+    match value.checked_mul(millis_per) {
+        Some(0) | None => Err(invalid()),
+        Some(millis) => Ok(Duration::from_millis(millis)),
+    }
+}
+
+/// Value parser for `--parallel`: a positive count, since zero slots would
+/// leave every batch operation waiting forever
+fn parse_parallel(input: &str) -> Result<usize, String> {
+    match input.parse() {
+        Ok(0) | Err(_) => Err(format!("Invalid --parallel '{}': expected a positive number", input)),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// The CPU count, capped so a many-core machine doesn't flood the network and
+/// disk with fetches of a big repo
+fn default_parallelism() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get()).min(8)
+}
+
+/// Held while a batch git operation runs; waits while `--parallel` others are
+/// already running. Threads take one each, so batches stay bounded however
+/// many threads they spawn.
+struct BatchSlot;
+
+fn batch_slot() -> BatchSlot {
+    let limit = match PARALLEL.load(Ordering::Relaxed) {
+        0 => default_parallelism(),
+        n => n,
+    };
+    let mut running = BATCH_RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    while *running >= limit {
+        running = BATCH_SLOT_FREED.wait(running).unwrap_or_else(|e| e.into_inner());
+    }
+    *running += 1;
+    BatchSlot
+}
+
+impl Drop for BatchSlot {
+    fn drop(&mut self) {
+        *BATCH_RUNNING.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        BATCH_SLOT_FREED.notify_one();
+    }
+}
+
+/// Parse a `--env KEY=VAL` argument. The value may be empty or contain `=`;
+/// the key must be a shell-style name so it can't be mistaken for a typo.
+fn parse_env_var(input: &str) -> Result<(String, String), String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid --env '{}': expected KEY=VAL", input))?;
+    let valid_key = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_key {
+        return Err(format!(
+            "Invalid --env '{}': '{}' is not a valid variable name",
+            input, key
+        ));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse a PR reference: `123`, `#123`, `pull/123` or a PR URL such as
+/// `https://github.com/owner/repo/pull/123/files`.
+fn extract_pr_number(input: &str) -> Result<u64, String> {
+    let max = config::get().max_pr_number.map_or(MAX_PR_NUMBER, |max| max.min(MAX_PR_NUMBER));
+    extract_pr_number_up_to(input, max)
+}
+
+/// `extract_pr_number`, rejecting numbers above `max` (the `max_pr_number` config)
+fn extract_pr_number_up_to(input: &str, max: u64) -> Result<u64, String> {
+    let Some(digits) = PR_NUMBER
+        .captures(input.trim())
+        .and_then(|caps| caps.get(1).or_else(|| caps.get(2)))
+    else {
+        return Err(format!(
+            "Could not parse PR number from '{}'. Expected one of: 123, #123, pull/123, \
+             https://github.com/owner/repo/pull/123",
+            input
+        ));
+    };
+    let digits = digits.as_str();
+    if digits.bytes().all(|digit| digit == b'0') {
+        return Err(format!("Invalid PR number '{}' in '{}': PR numbers start at 1", digits, input));
+    }
+    if digits.starts_with('0') {
+        return Err(format!(
+            "Invalid PR number '{}' in '{}': PR numbers start at 1 and have no leading zeros",
+            digits, input
+        ));
+    }
+    match digits.parse::<u64>() {
+        Ok(number) if number <= max => Ok(number),
+        Ok(number) if number <= MAX_PR_NUMBER => Err(format!(
+            "PR number {} in '{}' is above max_pr_number ({}); raise it in the config if that's a real PR",
+            digits, input, max
+        )),
+        _ => Err(format!("PR number {} in '{}' is out of range", digits, input)),
+    }
+}
+
+/// A parsed PR reference. `host` is set only for a PR URL on a host other
+/// than github.com, i.e. GitHub Enterprise.
+#[derive(Debug, PartialEq)]
+struct PrRef {
+    number: u64,
+    host: Option<String>,
+}
+
+fn parse_pr_ref(input: &str) -> Result<PrRef, String> {
+    let number = extract_pr_number(input)?;
+    let host = PR_URL
+        .captures(input.trim())
+        .map(|caps| caps[2].to_lowercase())
+        .filter(|host| host != "github.com" && host != "www.github.com");
+    Ok(PrRef { number, host })
+}
+
+/// Parse the PR argument of a command that talks to GitHub. A PR URL on an
+/// Enterprise host routes this process's `gh` calls to that host.
+fn parse_pr_arg(input: &str) -> Result<u64, String> {
+    let pr_ref = parse_pr_ref(input)?;
+    if let Some(host) = pr_ref.host {
+        if let Ok(mut gh_host) = GH_HOST.lock() {
+            *gh_host = Some(host);
+        }
+    }
+    Ok(pr_ref.number)
+}
+
+/// `gh`, with `GH_HOST` set when a PR URL named an Enterprise host. Without
+/// it gh infers the host from the repo's remotes.
+fn gh_command() -> Command {
+    let mut command = Command::new("gh");
+    if let Some(host) = GH_HOST.lock().ok().and_then(|host| host.clone()) {
+        command.env("GH_HOST", host);
+    }
+    command
+}
+
+fn fetch_pr_details(pr_number: u64, repo_root: &PathBuf) -> Result<PrDetails, String> {
+    timing!("fetch_pr_details");
+    let output = gh_command()
+        .args(["pr", "view", &pr_number.to_string(), "--json", &PrDetails::JSON_FIELDS.join(",")])
+        .current_dir(repo_root)
+        .traced_output()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("gh pr view failed: {}", stderr.trim()));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse PR details: {}", e))
+}
+
+#[derive(Deserialize)]
+struct PrAuthor {
+    login: String,
+}
+
+/// One row of `gh pr list --json number,title,headRefName,author`.
+#[derive(Deserialize)]
+struct PrSummary {
+    number: u64,
+    title: String,
+    #[serde(rename = "headRefName")]
+    head_ref_name: String,
+    author: PrAuthor,
+}
+
+fn pr_list_args(picker: &PrPickerArgs) -> Vec<String> {
+    let mut args: Vec<String> = ["pr", "list", "--json", "number,title,headRefName,author", "--limit"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    args.push(picker.limit.to_string());
+    if let Some(author) = &picker.author {
+        args.extend(["--author".to_string(), author.clone()]);
+    }
+    if let Some(assignee) = &picker.assignee {
+        args.extend(["--assignee".to_string(), assignee.clone()]);
+    }
+    args
+}
+
+fn list_open_prs(repo_root: &Path, picker: &PrPickerArgs) -> Result<Vec<PrSummary>, String> {
+    timing!("list_open_prs");
+    let output = gh_command()
+        .args(pr_list_args(picker))
+        .current_dir(repo_root)
+        .traced_output()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("gh pr list failed: {}", stderr.trim()));
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("Failed to parse PR list: {}", e))
+}
+
+/// List open PRs and ask which one to check out.
+fn pick_open_pr(repo_root: &Path, picker: &PrPickerArgs) -> Result<u64, String> {
+    say!("{} Listing open PRs... ", "→".blue().bold());
+    io::stdout().flush().ok();
+    let prs = list_open_prs(repo_root, picker)?;
+    sayln!("{}", "done".green());
+
+    if prs.is_empty() {
+        return Err("No open PRs match".to_string());
+    }
+
+    sayln!();
+    let width = prs.len().to_string().len();
+    for (i, pr) in prs.iter().enumerate() {
+        sayln!(
+            "  {:>width$}) {} {} {} {}",
+            i + 1,
+            format!("#{}", pr.number).cyan(),
+            pr.title.white().bold(),
+            format!("({})", pr.head_ref_name).dimmed(),
+            format!("@{}", pr.author.login).dimmed(),
+            width = width
+        );
+    }
+    sayln!();
+
+    loop {
+        say!("{} Choose a PR [1-{}] (Enter to cancel): ", "?".magenta().bold(), prs.len());
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let input = read_input()?;
+
+        let input = input.trim();
+        if input.is_empty() || input == "q" {
+            return Err(Interrupt::Declined.raise());
+        }
+        match input.parse::<usize>() {
+            Ok(choice) if (1..=prs.len()).contains(&choice) => return Ok(prs[choice - 1].number),
+            _ => sayln!("{} Invalid option, please enter 1-{}", "!".red().bold(), prs.len()),
+        }
+    }
+}
+
+/// Push a branch worktree's branch and set its upstream. `initial_commit`
+/// first adds an empty commit, since GitHub won't open a PR for a branch
+/// with no commits of its own.
+fn publish_branch(worktree_path: &Path, branch: &str, initial_commit: bool) -> Result<(), String> {
+    if initial_commit {
+        progress!("{} Creating initial commit... ", "→".blue().bold());
+        std::io::stdout().flush().ok();
+        git::commit_empty(worktree_path, branch)?;
+        progressln!("{}", "done".green());
+    }
+    progress!("{} Pushing {} to {}... ", "→".blue().bold(), branch.yellow(), git::REMOTE);
+    std::io::stdout().flush().ok();
+    git::push_set_upstream(worktree_path, git::REMOTE, branch)?;
+    progressln!("{}", "done".green());
+    Ok(())
+}
+
+fn create_draft_pr(worktree_path: &Path, branch: &str) -> Result<(), String> {
+    print!("{} Opening draft PR... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    let output = gh_command()
+        .args(["pr", "create", "--draft", "--fill", "--head", branch])
+        .current_dir(worktree_path)
+        .stdin(Stdio::null())
+        .traced_output()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("gh pr create failed: {}", stderr.trim()));
+    }
+    println!("{}", String::from_utf8_lossy(&output.stdout).trim().cyan());
+    Ok(())
+}
+
+/// The first four words of a PR title, minus any conventional-commit type,
+/// for its worktree directory name. A colon later in the title is kept.
+fn create_slug(title: &str) -> String {
+    let without_prefix = match COMMIT_TYPE_PREFIX.find(title) {
+        Some(prefix) if prefix.end() < title.len() => &title[prefix.end()..],
+        _ => title,
+    };
+
+    let slug: String = without_prefix
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+
+    let cleaned = DASH_RUNS.replace_all(&slug, "-");
+    let trimmed = cleaned.trim_matches('-');
+
+    trimmed
+        .split('-')
+        .filter(|s| !s.is_empty())
+        .take(4)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Which worktree directory names `find_existing_worktree` accepts
+#[derive(Clone, Copy, Debug)]
+enum DirPattern<'a> {
+    /// Exactly this name, or it plus a collision suffix from
+    /// `find_next_worktree_path` (`branch-auth-2`, but not `branch-auth-tokens`)
+    Name(&'a str),
+    /// Any name starting with this, e.g. `pr-12-` for PR 12 under any title
+    Prefix(&'a str),
+}
+
+impl DirPattern<'_> {
+    fn matches(&self, dir_name: &str) -> bool {
+        match *self {
+            DirPattern::Name(base) => dir_name == base
+                || dir_name
+                    .strip_prefix(base)
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .is_some_and(is_collision_suffix),
+            DirPattern::Prefix(prefix) => dir_name.starts_with(prefix),
+        }
+    }
+}
+
+/// Whether `suffix` could come from `collision_candidate`: a number (numeric
+/// and timestamp strategies) or a 7-digit hex digest (hash).
+fn is_collision_suffix(suffix: &str) -> bool {
+    !suffix.is_empty()
+        && (suffix.bytes().all(|b| b.is_ascii_digit())
+            || (suffix.len() == 7 && suffix.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))))
+}
+
+/// Worktrees other than the main checkout whose directory name matches
+/// `pattern`, in `git worktree list` order.
+fn matching_worktrees(entries: Vec<git::WorktreeEntry>, repo_root: &Path, pattern: DirPattern) -> Vec<git::WorktreeEntry> {
+    entries
+        .into_iter()
+        .filter(|entry| entry.path != repo_root && pattern.matches(&worktree_dir_name(&entry.path)))
+        .collect()
+}
+
+/// Find the worktree whose directory matches `pattern`, asking which one
+/// when several do.
+/// The worktree matching `pattern`, asking which one when several do. Without
+/// `interactive` (JSON output) or a terminal to ask on, several matches are
+/// an error instead.
+fn find_existing_worktree(repo_root: &PathBuf, pattern: DirPattern, interactive: bool) -> Result<Option<PathBuf>, String> {
+    timing!(&format!("find_existing_worktree({:?})", pattern));
+    let mut candidates = matching_worktrees(git::list_worktrees(repo_root)?, repo_root, pattern);
+    if candidates.len() <= 1 {
+        return Ok(candidates.pop().map(|entry| entry.path));
+    }
+    if !interactive || !io::stdin().is_terminal() {
+        let names: Vec<String> = candidates.iter().map(|entry| worktree_dir_name(&entry.path)).collect();
+        return Err(format!("Several worktrees match: {}", names.join(", ")));
+    }
+    pick_worktree(&candidates).map(Some)
+}
+
+fn pick_worktree(candidates: &[git::WorktreeEntry]) -> Result<PathBuf, String> {
+    sayln!("\n{} Several worktrees match:\n", "!".yellow().bold());
+    let width = candidates.len().to_string().len();
+    for (i, entry) in candidates.iter().enumerate() {
+        sayln!(
+            "  {:>width$}) {} {}",
+            i + 1,
+            worktree_dir_name(&entry.path).cyan(),
+            format!("({})", entry.branch.as_deref().unwrap_or("detached")).dimmed(),
+            width = width
+        );
+    }
+    sayln!();
+
+    loop {
+        say!("{} Choose a worktree [1-{}] (Enter to cancel): ", "?".magenta().bold(), candidates.len());
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let input = read_input()?;
+
+        let input = input.trim();
+        if input.is_empty() || input == "q" {
+            return Err(Interrupt::Declined.raise());
+        }
+        match input.parse::<usize>() {
+            Ok(choice) if (1..=candidates.len()).contains(&choice) => return Ok(candidates[choice - 1].path.clone()),
+            _ => sayln!("{} Invalid option, please enter 1-{}", "!".red().bold(), candidates.len()),
+        }
+    }
+}
+
+/// Count files in a directory (non-recursively counts all entries via `git ls-files`)
+fn count_worktree_files(worktree_path: &PathBuf, no_checkout: bool) -> Option<usize> {
+    // The index is empty until the files are checked out
+    if no_checkout {
+        return None;
+    }
+    let output = Command::new("git")
+        .args(["-C", &worktree_path.to_string_lossy(), "ls-files"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .traced_output()
+        .ok()?;
+    if output.status.success() {
+        let count = output.stdout.iter().filter(|&&b| b == b'\n').count();
+        Some(count)
+    } else {
+        None
+    }
+}
+
+/// Run a git command with a spinner showing elapsed time.
+/// `label` is the prefix already printed (e.g. "→ Creating worktree...").
+/// Returns the command's exit status.
+fn run_git_with_spinner(args: &[&str]) -> Result<std::process::ExitStatus, String> {
+    if GIT_OUTPUT_SHOWN.load(Ordering::Relaxed) {
+        return Command::new("git")
+            .args(args)
+            .stdout(say_stdio())
+            .traced_status()
+            .map_err(|e| format!("Failed to run git command: {}", e));
+    }
+    let args_owned: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+    let handle = thread::spawn(move || {
+        // stderr is captured rather than discarded, for `--trace`
+        Command::new("git")
+            .args(&args_owned)
+            .stdout(Stdio::null())
+            .traced_output()
+            .map(|output| output.status)
+    });
+
+    let spinner_chars = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+    let start = Instant::now();
+    let mut i = 0;
+
+    while !handle.is_finished() && !QUIET.load(Ordering::Relaxed) {
+        let elapsed = start.elapsed().as_secs();
+        let spinner = spinner_chars[i % spinner_chars.len()];
+        say!("\r\x1b[K  {} {}s", spinner, elapsed);
+        std::io::stdout().flush().ok();
+        i += 1;
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Clear the spinner line
+    progress!("\r\x1b[K");
+    std::io::stdout().flush().ok();
+
+    handle
+        .join()
+        .map_err(|_| "git command thread panicked".to_string())?
+        .map_err(|e| format!("Failed to run git command: {}", e))
+}
+
+/// With `--no-fetch`, whether `origin/<branch>` is already available locally
+/// so the fetch can be skipped.
+fn use_local_ref(repo: &Path, branch: &str, no_fetch: bool) -> bool {
+    no_fetch && git::ref_exists(repo, &format!("origin/{}", branch)).unwrap_or(false)
+}
+
+fn print_using_local_ref(git_ref: &str, reason: &str) {
+    progressln!(
+        "{} Using local {} {}",
+        "→".blue().bold(),
+        git_ref.yellow(),
+        format!("({})", reason).dimmed()
+    );
+}
+
+/// Called when about to fetch; explains why `--no-fetch` didn't apply.
+fn note_no_fetch_fallback(branch: &str, no_fetch: bool) {
+    if no_fetch {
+        say!("{} ", format!("(origin/{} not found locally)", branch).dimmed());
+    }
+}
+
+fn fetch_branch(repo_root: &PathBuf, branch: &str) -> Result<(), String> {
+    timing!(&format!("fetch_branch({})", branch));
+    let shown = GIT_OUTPUT_SHOWN.load(Ordering::Relaxed);
+    if shown {
+        // Start git's progress on a line of its own
+        progressln!();
+    }
+    let max_retries = 3;
+    for attempt in 1..=max_retries {
+        // Hidden stderr is captured rather than discarded, for `--trace`
+        let status = Command::new("git")
+            .args(["-C", &repo_root.to_string_lossy(), "fetch", "origin", branch])
+            .stdout(Stdio::null())
+            .stderr(if shown { Stdio::inherit() } else { Stdio::piped() })
+            .traced_output()
+            .map(|output| output.status)
+            .map_err(|e| format!("Failed to fetch: {}", e))?;
+
+        if status.success() {
+            return Ok(());
+        }
+
+        if attempt < max_retries {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    Err("git fetch failed after 3 attempts".to_string())
+}
+
+/// `git -C <repo> worktree add [--no-checkout] <args>` with a spinner. With
+/// `--no-checkout`, new worktrees are registered with an empty working tree,
+/// for users who set up a sparse checkout before materializing files.
+fn run_worktree_add(repo_str: &str, args: &[&str], no_checkout: bool) -> Result<std::process::ExitStatus, String> {
+    run_git_with_spinner(&worktree_add_args(repo_str, args, no_checkout))
+}
+
+/// `run_worktree_add`'s git arguments, given `--no-checkout`'s setting
+fn worktree_add_args<'a>(repo_str: &'a str, args: &[&'a str], no_checkout: bool) -> Vec<&'a str> {
+    let mut git_args = vec!["-C", repo_str, "worktree", "add"];
+    if no_checkout {
+        git_args.push("--no-checkout");
+    }
+    git_args.extend_from_slice(args);
+    git_args
+}
+
+/// Add a detached worktree at `git_ref`, retrying at `fallback_ref` (e.g.
+/// `FETCH_HEAD` when the branch is checked out elsewhere) if that fails.
+fn create_worktree_from_ref(
+    repo_root: &PathBuf,
+    worktree_path: &PathBuf,
+    git_ref: &str,
+    fallback_ref: Option<&str>,
+    no_checkout: bool,
+) -> Result<(), String> {
+    let repo_str = repo_root.to_string_lossy().to_string();
+    let wt_str = worktree_path.to_string_lossy().to_string();
+
+    let status = run_worktree_add(&repo_str, &["--detach", &wt_str, git_ref], no_checkout)?;
+    if status.success() {
+        return Ok(());
+    }
+
+    if let Some(fallback_ref) = fallback_ref {
+        let status = run_worktree_add(&repo_str, &["--detach", &wt_str, fallback_ref], no_checkout)?;
+        if status.success() {
+            return Ok(());
+        }
+    }
+
+    Err("git worktree add failed".to_string())
+}
+
+fn create_worktree_new_branch(
+    repo_root: &PathBuf,
+    worktree_path: &PathBuf,
+    branch: &str,
+    start_point: &str,
+    no_checkout: bool,
+) -> Result<(), String> {
+    timing!("create_worktree_new_branch (git worktree add)");
+    let repo_str = repo_root.to_string_lossy().to_string();
+    let wt_str = worktree_path.to_string_lossy().to_string();
+
+    let status = run_worktree_add(&repo_str, &["-b", branch, &wt_str, start_point], no_checkout)?;
+
+    if !status.success() {
+        // Branch may already exist from a previous attempt, try checking it out directly
+        let status = run_worktree_add(&repo_str, &[&wt_str, branch], no_checkout)?;
+
+        if !status.success() {
+            return Err("git worktree add failed".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// What `update_worktree` does when HEAD has commits that its upstream ref
+/// doesn't, e.g. work committed in the worktree but never pushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LocalCommits {
+    /// List them and ask before resetting
+    Prompt,
+    /// Stop with an error, leaving the worktree untouched
+    Refuse,
+    /// Reset anyway, printing the old HEAD so they can be recovered
+    Discard,
+}
+
+/// Where a PR worktree is updated from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Upstream<'a> {
+    /// The PR's head branch on origin
+    Branch(&'a str),
+    /// `pull/<n>/head`, fetched into `pr_fetch_ref`: a fork's branch isn't
+    /// on origin
+    PullRef(u64),
+}
+
+impl Upstream<'_> {
+    /// The ref a worktree is reset to
+    fn ref_name(&self) -> String {
+        match self {
+            Upstream::Branch(branch) => format!("origin/{}", branch),
+            Upstream::PullRef(pr_number) => pr_fetch_ref(*pr_number),
+        }
+    }
+
+    fn refspec(&self) -> String {
+        match self {
+            Upstream::Branch(branch) => branch.to_string(),
+            // Forced: the PR head moves backwards when its branch is force-pushed
+            Upstream::PullRef(pr_number) => format!("+pull/{}/head:{}", pr_number, pr_fetch_ref(*pr_number)),
+        }
+    }
+
+    /// With --no-fetch, whether the ref is already there to reset to
+    fn use_local(&self, repo: &Path, no_fetch: bool) -> bool {
+        match self {
+            Upstream::Branch(branch) => use_local_ref(repo, branch, no_fetch),
+            Upstream::PullRef(pr_number) => {
+                no_fetch && git::ref_exists(repo, &pr_fetch_ref(*pr_number)).unwrap_or(false)
+            }
+        }
+    }
+}
+
+/// Reset `worktree_path` to the freshly fetched `upstream`, first checking
+/// `local_commits` if that would drop commits only HEAD has.
+fn update_worktree(
+    worktree_path: &PathBuf,
+    upstream: Upstream,
+    local_commits: LocalCommits,
+    no_fetch: bool,
+) -> Result<(), String> {
+    timing!("update_worktree");
+    // With --no-fetch, reset straight to the local ref if it exists
+    if !upstream.use_local(worktree_path, no_fetch) {
+        if no_fetch {
+            say!("{} ", format!("({} not found locally)", upstream.ref_name()).dimmed());
+        }
+        let max_retries = 3;
+        let mut last_stderr = String::new();
+        for attempt in 1..=max_retries {
+            let output = Command::new("git")
+                .args(["-C", &worktree_path.to_string_lossy(), "fetch", "origin", &upstream.refspec()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .traced_output()
+                .map_err(|e| format!("Failed to fetch: {}", e))?;
+
+            if output.status.success() {
+                last_stderr.clear();
+                break;
+            }
+
+            last_stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if attempt < max_retries {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+
+        if !last_stderr.is_empty() {
+            return Err(format!("git fetch failed after {} attempts: {}", max_retries, last_stderr));
+        }
+    }
+
+    let ref_name = upstream.ref_name();
+    let unpushed = git::log_oneline(worktree_path, &format!("{}..HEAD", ref_name))?;
+    if !unpushed.is_empty() {
+        confirm_drop_local_commits(worktree_path, &ref_name, &unpushed, local_commits)?;
+    }
+    let output = Command::new("git")
+        .args([
+            "-C",
+            &worktree_path.to_string_lossy(),
+            "reset",
+            "--hard",
+            &ref_name,
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .traced_output()
+        .map_err(|e| format!("Failed to reset: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git reset failed: {}", stderr.trim()));
+    }
+
+    Ok(())
+}
+
+/// Apply the `LocalCommits` policy to `commits` (oneline, newest first) that
+/// a reset to `ref_name` would drop. `Ok` means go ahead with the reset.
+fn confirm_drop_local_commits(
+    worktree_path: &Path,
+    ref_name: &str,
+    commits: &[String],
+    policy: LocalCommits,
+) -> Result<(), String> {
+    let head = git::short_sha(worktree_path, "HEAD")?;
+    let summary = format!("HEAD has {} commit(s) that {} doesn't", commits.len(), ref_name);
+    match policy {
+        LocalCommits::Refuse => {
+            return Err(format!("{}; push them or pass --on-dirty discard to reset anyway", summary));
+        }
+        LocalCommits::Discard => {
+            sayln!(
+                "\n{} Discarding {} {}",
+                "⚠".yellow(),
+                summary,
+                format!("(recover with git reset --hard {})", head).dimmed()
+            );
+            return Ok(());
+        }
+        LocalCommits::Prompt => {}
+    }
+
+    sayln!();
+    sayln!("{} {}:", "!".yellow().bold(), summary);
+    for line in commits {
+        sayln!("  {}", line.dimmed());
+    }
+    sayln!();
+    say!(
+        "{} Reset to {} anyway? {} [y/N]: ",
+        "?".magenta().bold(),
+        ref_name,
+        format!("(recover with git reset --hard {})", head).dimmed()
+    );
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let confirm = read_confirmation()?;
+    if confirm.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err(format!("kept {} unpushed commit(s) at {}", commits.len(), head))
+    }
+}
+
+fn which_mise() -> Option<PathBuf> {
+    Command::new("which")
+        .arg("mise")
+        .traced_output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
+}
+
+fn run_mise_trust(worktree_path: &PathBuf) -> Result<(), String> {
+    let status = Command::new("mise")
+        .args(["trust", "--all"])
+        .current_dir(worktree_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .traced_status()
+        .map_err(|e| format!("Failed to run mise trust: {}", e))?;
+
+    if !status.success() {
+        return Err("mise trust failed".to_string());
+    }
+
+    Ok(())
+}
+
+/// Validate/install the gem bundle for a freshly created worktree so the
+/// pre-commit Ruby hooks (which run `bundle exec`) don't blow up on a bundle
+/// that doesn't match this checkout's Gemfile.lock — the failure that otherwise
+/// forces `git commit --no-verify`.
+///
+/// Mirrors `bin/create_worktree.sh`'s optimized path: point BUNDLE_PATH at the
+/// main repo's `vendor/bundle` cache (which we've already symlinked in) and run
+/// `bundle install --local` in frozen mode (via `BUNDLE_FROZEN=true` in the env,
+/// not the `--frozen` flag, so Bundler doesn't persist it into the tracked
+/// `.bundle/config`). When the worktree's lock matches the cache — the common
+/// case for a branch off master — this is a fast no-op validation (~0.5s, no
+/// network). Only when the branch's Gemfile.lock needs a gem that isn't cached
+/// do we fall back to a networked `bundle install`.
+///
+/// Runs in the background thread after `symlink_vendor_bundle`, so it never
+/// blocks time-to-prompt and finishes well before the user's first commit.
+fn run_bundle_install(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
+    // Nothing to do unless this is a Bundler project with a usable gem cache.
+    if !worktree_path.join("Gemfile").is_file() {
+        return Ok(());
+    }
+    let cache_bundle = repo_root.join("vendor/bundle");
+    if !cache_bundle.is_dir() {
+        return Ok(());
+    }
+
+    let run = |args: &[&str]| -> Result<Option<bool>, String> {
+        match Command::new("bundle")
+            .args(args)
+            .current_dir(worktree_path)
+            .env("BUNDLE_PATH", &cache_bundle)
+            // Enforce frozen mode via the environment rather than the `--frozen`
+            // CLI flag: Bundler "remembers" the flag by persisting
+            // `BUNDLE_FROZEN: "true"` into the tracked `.bundle/config`, which
+            // leaves every worktree permanently dirty. The env var is not
+            // persisted, so worktrees stay clean.
+            .env("BUNDLE_FROZEN", "true")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .traced_status()
+        {
+            Ok(status) => Ok(Some(status.success())),
+            // Bundler isn't installed — nothing we can or should do.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to run bundle install: {}", e)),
+        }
+    };
+
+    // Fast path: validate against the cached gems with no network access.
+    match run(&["install", "--local"])? {
+        None => return Ok(()),       // bundle not available
+        Some(true) => return Ok(()), // bundle satisfied from cache
+        Some(false) => {}            // fall through to networked install
+    }
+
+    // Fallback: the branch's lock needs a gem the cache doesn't have.
+    match run(&["install"])? {
+        Some(false) => Err("bundle install failed".to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn run_gt_track(worktree_path: &PathBuf) -> Result<(), String> {
+    timing!("run_gt_track");
+    let status = Command::new("gt")
+        .args(["track", "--no-interactive", "--parent", "master"])
+        .current_dir(worktree_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .traced_status()
+        .map_err(|e| format!("Failed to run gt track: {}", e))?;
+
+    if !status.success() {
+        return Err("gt track failed".to_string());
+    }
+
+    Ok(())
+}
+
+/// Find all node_modules directories in repo_root and symlink them into
+/// the worktree. Skips any nested inside other node_modules since those
+/// are already contained within the parent symlink. Returns the number
+/// of symlinks created.
+fn symlink_node_modules(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<usize, String> {
+    let output = Command::new("find")
+        .args([
+            repo_root.to_string_lossy().as_ref(),
+            "-name", "node_modules",
+            "-type", "d",
+            "-not", "-path", "*/node_modules/*/node_modules",
+            "-prune",
+        ])
+        .traced_output()
+        .map_err(|e| format!("Failed to find node_modules: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut count = 0;
+
+    for line in stdout.lines() {
+        let source = PathBuf::from(line.trim());
+        if !source.is_dir() || source.is_symlink() {
+            continue;
+        }
+
+        let rel = source.strip_prefix(repo_root)
+            .map_err(|_| format!("Path {} not under repo root", source.display()))?;
+        let dest = worktree_path.join(rel);
+
+        if dest.exists() || dest.is_symlink() {
+            continue;
+        }
+
+        // Ensure parent directory exists in the worktree
+        if let Some(parent) = dest.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create parent dir {}: {}", parent.display(), e))?;
+            }
+        }
+
+        std::os::unix::fs::symlink(&source, &dest)
+            .map_err(|e| format!("Failed to symlink {}: {}", rel.display(), e))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Symlink the main repo's `vendor/` directory into the worktree so Bundler
+/// can find installed gems (e.g. `numo-narray`) without re-running
+/// `bundle install` per worktree. The repo's `Gemfile`/`Gemfile.lock` are
+/// already tracked in git, so they show up in the worktree on their own —
+/// only the gitignored `vendor/bundle` install output needs help.
+///
+/// No-ops if the main repo has no `vendor/`, or if the worktree already has
+/// one (real dir or existing symlink).
+fn symlink_vendor_bundle(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<(), String> {
+    let source = repo_root.join("vendor");
+    if !source.is_dir() || source.is_symlink() {
+        return Ok(());
+    }
+
+    let dest = worktree_path.join("vendor");
+    if dest.exists() || dest.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+
+    std::os::unix::fs::symlink(&source, &dest)
+        .map_err(|e| format!("Failed to symlink vendor: {}", e))?;
+
+    Ok(())
+}
+
+/// Decide what a failed best-effort step means: the error itself under
+/// `--strict`, otherwise a warning to carry on with.
+fn tolerate_step_failure(step: &str, result: Result<(), String>, strict: bool) -> Result<Option<String>, String> {
+    match result {
+        Ok(()) => Ok(None),
+        Err(e) if strict => Err(format!("{} failed: {}", step, e)),
+        Err(e) => Ok(Some(format!("{} failed: {}", step, e))),
+    }
+}
+
+/// Run the outcome of a step the worktree works without (mise, Graphite,
+/// Claude setup) through `--strict`: by default a failure is warned about,
+/// collected for the ready banner, and the checkout goes on.
+fn best_effort(step: &str, result: Result<(), String>, strict: bool) -> Result<(), String> {
+    if let Some(warning) = tolerate_step_failure(step, result, strict)? {
+        eprintln!("{} {}", "⚠".yellow(), warning);
+        SETUP_WARNINGS.with_borrow_mut(|warnings| warnings.push(warning));
+    }
+    Ok(())
+}
+
+/// Finish a best-effort step's `... ` progress line with `done` or `failed`
+fn finish_step(step: &str, result: Result<(), String>, strict: bool) -> Result<(), String> {
+    match result {
+        Ok(()) => progressln!("{}", "done".green()),
+        Err(_) => progressln!("{}", "failed".yellow()),
+    }
+    best_effort(step, result, strict)
+}
+
+/// `✓ Worktree ready`, or a warning count when best-effort setup steps failed
+fn print_ready_banner(path: &Path) {
+    let warnings = SETUP_WARNINGS.with_borrow(Vec::clone);
+    let location = path_link(path, path.display().to_string().cyan().bold());
+    sayln!();
+    if warnings.is_empty() {
+        sayln!("{} Worktree ready at {}", "✓".green().bold(), location);
+        return;
+    }
+    sayln!(
+        "{} Worktree ready at {} {}",
+        "⚠".yellow().bold(),
+        location,
+        format!(
+            "(completed with {} warning{})",
+            warnings.len(),
+            if warnings.len() == 1 { "" } else { "s" }
+        )
+        .yellow()
+    );
+    for warning in &warnings {
+        sayln!("  {}", warning.dimmed());
+    }
+}
+
+fn prepare_agent_worktree(
+    agent: Agent,
+    worktree_path: &PathBuf,
+    repo_root: &PathBuf,
+    strict: bool,
+) -> Result<(), String> {
+    if agent != Agent::Claude {
+        return Ok(());
+    }
+
+    progress!("{} Copying Claude settings... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    match symlink_claude_settings(worktree_path, repo_root) {
+        Ok(SettingsChange::KeptNewer(_)) => sayln!("{}", "kept the worktree's newer copy".yellow()),
+        result => finish_step("Copying Claude settings", result.map(|_| ()), strict)?,
+    }
+
+    progress!("{} Adding Claude trust... ", "→".blue().bold());
+    std::io::stdout().flush().ok();
+    finish_step("Adding Claude trust", add_claude_trust(worktree_path, repo_root).map(|_| ()), strict)?;
+
+    Ok(())
+}
+
+/// What linking `.claude/settings.local.json` into a worktree did
+#[derive(Debug, PartialEq)]
+enum SettingsChange {
+    /// No settings file in the repo or home directory to link
+    NoSource,
+    /// Already a link to the source
+    Unchanged(PathBuf),
+    /// Created the link
+    Linked(PathBuf),
+    /// Replaced an older file or a link elsewhere with the link
+    Relinked(PathBuf),
+    /// Left a worktree-local file alone because it's newer than the source
+    KeptNewer(PathBuf),
+}
+
+fn symlink_claude_settings(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<SettingsChange, String> {
+    timing!("symlink_claude_settings");
+    // Symlink the main repo's .claude/settings.local.json into the worktree
+    // This contains MCP server configurations and other local settings
+    let source = repo_root.join(".claude/settings.local.json");
+
+    let resolved_source = if !source.exists() {
+        // Fall back to global settings if repo-specific doesn't exist
+        let home = env::var("HOME").map_err(|_| "HOME not set")?;
+        let global_source = PathBuf::from(format!("{}/.claude/settings.local.json", home));
+
+        if !global_source.exists() {
+            return Ok(SettingsChange::NoSource);
+        }
+
+        global_source
+    } else {
+        source
+    };
+
+    link_claude_settings(&resolved_source, &worktree_path.join(".claude/settings.local.json"))
+}
+
+/// Point `dest` at `source`. Safe to re-run on a reused worktree: an existing
+/// link to `source` is left as is, and a regular file edited in the worktree
+/// after `source` last changed is kept rather than clobbered.
+fn link_claude_settings(source: &Path, dest: &Path) -> Result<SettingsChange, String> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let replacing = match dest.symlink_metadata() {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            if fs::read_link(dest).is_ok_and(|target| target == source) {
+                return Ok(SettingsChange::Unchanged(source.to_path_buf()));
+            }
+            true
+        }
+        Ok(_) => {
+            if let (Some(local), Some(shared)) = (modified(dest), modified(source)) {
+                if local > shared {
+                    return Ok(SettingsChange::KeptNewer(source.to_path_buf()));
+                }
+            }
+            true
+        }
+        Err(_) => false,
+    };
+
+    if let Some(dest_dir) = dest.parent() {
+        fs::create_dir_all(dest_dir).map_err(|e| format!("Failed to create .claude dir: {}", e))?;
+    }
+    if replacing {
+        fs::remove_file(dest).map_err(|e| format!("Failed to remove existing settings file: {}", e))?;
+    }
+    std::os::unix::fs::symlink(source, dest)
+        .map_err(|e| format!("Failed to symlink claude settings: {}", e))?;
+
+    Ok(if replacing {
+        SettingsChange::Relinked(source.to_path_buf())
+    } else {
+        SettingsChange::Linked(source.to_path_buf())
+    })
+}
+
+/// What trusting a worktree in `~/.claude.json` did
+#[derive(Debug, PartialEq)]
+enum TrustChange {
+    /// Added a project entry, based on the main repo's
+    Added,
+    /// The entry existed; only `hasTrustDialogAccepted` was set
+    Accepted,
+    /// The entry existed and was already trusted
+    Unchanged,
+}
+
+fn add_claude_trust(worktree_path: &PathBuf, repo_root: &PathBuf) -> Result<TrustChange, String> {
+    timing!("add_claude_trust");
+    trust_claude_project(&claude_json_path()?, worktree_path, repo_root)
+}
+
+/// Trust the worktree in the `.claude.json` at `claude_json_path`, writing
+/// the file only when something changed
+fn trust_claude_project(claude_json_path: &Path, worktree_path: &Path, repo_root: &Path) -> Result<TrustChange, String> {
+    // Read existing file or create empty object
+    let mut data: Value = if claude_json_path.exists() {
+        let content = fs::read_to_string(claude_json_path)
+            .map_err(|e| format!("Failed to read .claude.json: {}", e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse .claude.json: {}", e))?
+    } else {
+        serde_json::json!({})
+    };
+
+    let change = ensure_project_trust(
+        &mut data,
+        &worktree_path.to_string_lossy(),
+        &repo_root.to_string_lossy(),
+    );
+    if change == TrustChange::Unchanged {
+        return Ok(change);
+    }
+
+    // Write back to file
+    let content = serde_json::to_string_pretty(&data)
+        .map_err(|e| format!("Failed to serialize .claude.json: {}", e))?;
+    fs::write(claude_json_path, content)
+        .map_err(|e| format!("Failed to write .claude.json: {}", e))?;
+
+    Ok(change)
+}
+
+/// Make sure `.claude.json` data trusts `worktree`. An existing entry may
+/// carry the user's own changes, so only its trust flag is touched.
+fn ensure_project_trust(data: &mut Value, worktree: &str, repo_root: &str) -> TrustChange {
+    // Ensure projects object exists
+    if data.get("projects").is_none() {
+        data["projects"] = serde_json::json!({});
+    }
+
+    if let Some(project) = data["projects"].get_mut(worktree).filter(|project| project.is_object()) {
+        if project.get("hasTrustDialogAccepted") == Some(&Value::Bool(true)) {
+            return TrustChange::Unchanged;
+        }
+        project["hasTrustDialogAccepted"] = serde_json::json!(true);
+        return TrustChange::Accepted;
+    }
+
+    // Try to copy settings from the main repo, or use defaults
+    let base_settings = data
+        .get("projects")
+        .and_then(|p| p.get(repo_root))
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!({
+            "allowedTools": [],
+            "mcpContextUris": [],
+            "mcpServers": {},
+            "enabledMcpjsonServers": [],
+            "disabledMcpjsonServers": [],
+            "projectOnboardingSeenCount": 0,
+            "hasClaudeMdExternalIncludesApproved": false,
+            "hasClaudeMdExternalIncludesWarningShown": false,
+            "reactVulnerabilityCache": {
+                "detected": false,
+                "package": null,
+                "packageName": null,
+                "version": null,
+                "packageManager": null
+            }
+        }));
+
+    // Create new project entry based on main repo settings
+    let mut new_project = base_settings.clone();
+    // Always ensure trust is accepted for new worktrees
+    new_project["hasTrustDialogAccepted"] = serde_json::json!(true);
+    // Reset session-specific fields
+    new_project.as_object_mut().map(|obj| {
+        obj.remove("lastAPIDuration");
+        obj.remove("lastAPIDurationWithoutRetries");
+        obj.remove("lastCost");
+        obj.remove("lastDuration");
+        obj.remove("lastLinesAdded");
+        obj.remove("lastLinesRemoved");
+        obj.remove("lastModelUsage");
+        obj.remove("lastSessionId");
+        obj.remove("lastToolDuration");
+        obj.remove("lastTotalCacheCreationInputTokens");
+        obj.remove("lastTotalCacheReadInputTokens");
+        obj.remove("lastTotalInputTokens");
+        obj.remove("lastTotalOutputTokens");
+        obj.remove("lastTotalWebSearchRequests");
+        obj.remove("exampleFiles");
+        obj.remove("exampleFilesGeneratedAt");
+    });
+
+    data["projects"][worktree] = new_project;
+    TrustChange::Added
+}
+
+/// Link Claude settings into and trust a worktree, e.g. one created outside
+/// checkout or where those steps failed, and say what changed
+fn run_trust(query: &str, repo: Option<PathBuf>) -> Result<(), String> {
+    let repo_root = resolve_repo_root(repo);
+    let worktree = resolve_worktree_query(&repo_root, query)?;
+    let name = worktree_dir_name(&worktree);
+    let settings = "settings.local.json".cyan();
+
+    match symlink_claude_settings(&worktree, &repo_root)? {
+        SettingsChange::NoSource => println!(
+            "{} No {} in the repo or ~/.claude to link",
+            "→".blue().bold(),
+            settings
+        ),
+        SettingsChange::Unchanged(source) => {
+            println!("{} {} already links to {}", "→".blue().bold(), settings, source.display())
+        }
+        SettingsChange::Linked(source) => {
+            println!("{} Linked {} to {}", "✓".green().bold(), settings, source.display())
+        }
+        SettingsChange::Relinked(source) => println!(
+            "{} Replaced {} with a link to {}",
+            "✓".green().bold(),
+            settings,
+            source.display()
+        ),
+        SettingsChange::KeptNewer(source) => println!(
+            "{} Kept {}: it was edited after {}",
+            "!".yellow().bold(),
+            settings,
+            source.display()
+        ),
+    }
+
+    match add_claude_trust(&worktree, &repo_root)? {
+        TrustChange::Added => println!("{} Added {} to ~/.claude.json as trusted", "✓".green().bold(), name.cyan()),
+        TrustChange::Accepted => println!("{} Accepted the trust dialog for {}", "✓".green().bold(), name.cyan()),
+        TrustChange::Unchanged => println!("{} {} is already trusted", "→".blue().bold(), name.cyan()),
+    }
+    Ok(())
+}
+
+fn build_worktree_system_prompt() -> String {
+    "IMPORTANT: This is a git worktree. The node_modules directories are symlinked \
+     from the main repo. NEVER run `pnpm install` in this worktree — it will corrupt \
+     the shared node_modules symlinks with absolute paths pointing at this worktree, \
+     breaking the main repo and all other worktrees. If you need to test dependency \
+     changes, first `rm` the node_modules symlink to create an isolated copy."
+        .to_string()
+}
+
+fn codex_session_snapshot(worktree_path: &Path) -> HashMap<String, SystemTime> {
+    let mut files = Vec::new();
+    collect_jsonl_files(&get_codex_session_dir(), &mut files);
+
+    let mut sessions: HashMap<String, SystemTime> = HashMap::new();
+    for path in files {
+        let Some((cwd, session_id)) = codex_session_metadata(&path) else {
+            continue;
+        };
+        if cwd != worktree_path {
+            continue;
+        }
+        let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        sessions
+            .entry(session_id)
+            .and_modify(|current| *current = (*current).max(modified))
+            .or_insert(modified);
+    }
+    sessions
+}
+
+fn wait_for_codex_session_id(
+    worktree_path: &Path,
+    before: &HashMap<String, SystemTime>,
+    timeout: Duration,
+) -> Option<String> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(100);
+    while Instant::now() < deadline {
+        let changed = codex_session_snapshot(worktree_path)
+            .into_iter()
+            .filter(|(session_id, modified)| {
+                before
+                    .get(session_id)
+                    .is_none_or(|previous| modified > previous)
+            })
+            .max_by_key(|(_, modified)| *modified)
+            .map(|(session_id, _)| session_id);
+        if changed.is_some() {
+            return changed;
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(Duration::from_secs(1));
+    }
+    None
+}
+
+enum CodexRenameWorkerMessage {
+    Started(u32),
+    Finished(Result<(), String>),
+}
+
+fn read_codex_app_server_response(
+    reader: &mut impl BufRead,
+    request_id: u64,
+) -> Result<Value, String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let bytes = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("Failed to read Codex app server response: {}", e))?;
+        if bytes == 0 {
+            return Err(format!(
+                "Codex app server exited before answering request {}",
+                request_id
+            ));
+        }
+        let Ok(response) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if response.get("id").and_then(Value::as_u64) == Some(request_id) {
+            return Ok(response);
+        }
+    }
+}
+
+fn run_codex_rename_worker(
+    app_server_args: Vec<String>,
+    thread_id: String,
+    name: String,
+    messages: mpsc::Sender<CodexRenameWorkerMessage>,
+) -> Result<(), String> {
+    let mut child = Command::new("codex")
+        .args(&app_server_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to start codex {}: {}", app_server_args.join(" "), e))?;
+    let _ = messages.send(CodexRenameWorkerMessage::Started(child.id()));
+
+    let result = (|| {
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| "Codex app server stdin was unavailable".to_string())?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "Codex app server stdout was unavailable".to_string())?;
+        let mut reader = io::BufReader::new(stdout);
+
+        writeln!(stdin, "{}", serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {
+                "clientInfo": {
+                    "name": "checkout",
+                    "title": "Checkout",
+                    "version": env!("CARGO_PKG_VERSION")
+                }
+            }
+        })).map_err(|e| format!("Failed to initialize Codex app server: {}", e))?;
+        stdin.flush()
+            .map_err(|e| format!("Failed to flush Codex initialize request: {}", e))?;
+
+        let initialize = read_codex_app_server_response(&mut reader, 1)?;
+        if let Some(error) = initialize.get("error") {
+            return Err(format!("Codex app server initialize failed: {}", error));
+        }
+
+        writeln!(stdin, "{}", serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "initialized"
+        })).map_err(|e| format!("Failed to acknowledge Codex initialization: {}", e))?;
+        writeln!(stdin, "{}", serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "thread/name/set",
+            "params": {"threadId": thread_id, "name": name}
+        })).map_err(|e| format!("Failed to send Codex rename request: {}", e))?;
+        stdin.flush()
+            .map_err(|e| format!("Failed to flush Codex rename request: {}", e))?;
+
+        let rename = read_codex_app_server_response(&mut reader, 2)?;
+        if let Some(error) = rename.get("error") {
+            return Err(format!("Codex thread rename failed: {}", error));
+        }
+        Ok(())
+    })();
+
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
+
+fn set_codex_thread_name_with_timeout(
+    app_server_args: &[&str],
+    thread_id: &str,
+    name: &str,
+) -> Result<(), String> {
+    let (worker_sender, receiver) = mpsc::channel();
+    let args = app_server_args.iter().map(|argument| argument.to_string()).collect();
+    let thread_id = thread_id.to_string();
+    let name = name.to_string();
+    thread::spawn(move || {
+        let result = run_codex_rename_worker(args, thread_id, name, worker_sender.clone());
+        let _ = worker_sender.send(CodexRenameWorkerMessage::Finished(result));
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut child_pid: Option<u32> = None;
+    loop {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            if let Some(pid) = child_pid {
+                let _ = Command::new("kill")
+                    .args(["-TERM", &pid.to_string()])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .traced_status();
+            }
+            return Err("Codex app server rename timed out".to_string());
+        };
+        match receiver.recv_timeout(remaining) {
+            Ok(CodexRenameWorkerMessage::Started(pid)) => child_pid = Some(pid),
+            Ok(CodexRenameWorkerMessage::Finished(result)) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some(pid) = child_pid {
+                    let _ = Command::new("kill")
+                        .args(["-TERM", &pid.to_string()])
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::null())
+                        .traced_status();
+                }
+                return Err("Codex app server rename timed out".to_string());
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err("Codex app server rename worker exited".to_string());
+            }
+        }
+    }
+}
+
+fn set_codex_thread_name(thread_id: &str, name: &str) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for args in [
+        &["app-server", "--stdio"][..],
+        &["app-server", "proxy"][..],
+    ] {
+        match set_codex_thread_name_with_timeout(args, thread_id, name) {
+            Ok(()) => return Ok(()),
+            Err(error) => errors.push(format!("codex {}: {}", args.join(" "), error)),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+fn spawn_codex_thread_renamer(
+    worktree_path: PathBuf,
+    session_name: String,
+    session_id: Option<String>,
+    sessions_before_launch: HashMap<String, SystemTime>,
+) {
+    thread::spawn(move || {
+        let thread_id = session_id.or_else(|| {
+            wait_for_codex_session_id(
+                &worktree_path,
+                &sessions_before_launch,
+                Duration::from_secs(10),
+            )
+        });
+        if let Some(thread_id) = thread_id {
+            // Naming is best-effort: it must never prevent the interactive
+            // coding-agent session from opening.
+            let _ = set_codex_thread_name(&thread_id, &session_name);
+        }
+    });
+}
+
+fn spawn_agent_with_prompt(
+    agent: Agent,
+    worktree_path: &PathBuf,
+    prompt: &str,
+    developer_instructions: Option<&str>,
+    session_name: &str,
+    env: &[(String, String)],
+) -> Result<(), String> {
+    let args = build_agent_args(agent, Some(prompt), developer_instructions, false, None)?;
+    spawn_agent_process(agent, worktree_path, args, None, session_name, env)
+}
+
+fn spawn_agent_continue(
+    agent: Agent,
+    worktree_path: &PathBuf,
+    prompt: Option<&str>,
+    developer_instructions: Option<&str>,
+    session_id: Option<&str>,
+    session_name: &str,
+    env: &[(String, String)],
+) -> Result<(), String> {
+    let args = build_agent_args(agent, prompt, developer_instructions, true, session_id)?;
+    spawn_agent_process(agent, worktree_path, args, session_id, session_name, env)
+}
+
+fn spawn_agent(
+    agent: Agent,
+    worktree_path: &PathBuf,
+    developer_instructions: Option<&str>,
+    session_name: &str,
+    env: &[(String, String)],
+) -> Result<(), String> {
+    let args = build_agent_args(agent, None, developer_instructions, false, None)?;
+    spawn_agent_process(agent, worktree_path, args, None, session_name, env)
+}
+
+/// Run the agent with `args` in `worktree_path` until it exits. `env` (from
+/// `--env`) is applied after the `CHECKOUT_*` variables, so it can override them.
+fn spawn_agent_process(
+    agent: Agent,
+    worktree_path: &PathBuf,
+    args: Vec<String>,
+    session_id: Option<&str>,
+    session_name: &str,
+    env: &[(String, String)],
+) -> Result<(), String> {
+    set_terminal_cwd(worktree_path);
+    save_session_name(worktree_path, session_name)?;
+
+    let sessions_before_launch = if agent == Agent::Codex && session_id.is_none() {
+        codex_session_snapshot(worktree_path)
+    } else {
+        HashMap::new()
+    };
+
+    let mut cmd = Command::new(agent.command());
+    if let Ok(env) = CHECKOUT_ENV.lock() {
+        cmd.envs(env.iter().map(|(key, value)| (*key, value)));
+    }
+    cmd.envs(env.iter().cloned());
+    cmd.args(args);
+
+    let mut child = cmd
+        .current_dir(worktree_path)
+        .spawn()
+        .map_err(|e| format!("Failed to spawn {}: {}", agent.command(), e))?;
+
+    if agent == Agent::Codex {
+        spawn_codex_thread_renamer(
+            worktree_path.clone(),
+            session_name.to_string(),
+            session_id.map(str::to_string),
+            sessions_before_launch,
+        );
+    }
+
+    let _pid_guard = PidFileGuard::new(worktree_path, child.id(), agent);
+    let status = child
+        .wait()
+        .map_err(|e| format!("Failed to wait for {}: {}", agent.command(), e))?;
+
+    if !status.success() {
+        return Err(format!("{} exited with error", agent.command()));
+    }
+
+    Ok(())
+}
+
+fn build_agent_args(
+    agent: Agent,
+    prompt: Option<&str>,
+    developer_instructions: Option<&str>,
+    resume: bool,
+    session_id: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    match agent {
+        Agent::Claude => {
+            args.extend(["--permission-mode".to_string(), "acceptEdits".to_string()]);
+            if resume {
+                args.push("--continue".to_string());
+            }
+            if let Some(prompt) = prompt {
+                args.push(prompt.to_string());
+            }
+            if let Some(instructions) = developer_instructions {
+                args.extend([
+                    "--append-system-prompt".to_string(),
+                    instructions.to_string(),
+                ]);
+            }
+        }
+        Agent::Codex => {
+            if let Some(instructions) = developer_instructions {
+                let value = serde_json::to_string(instructions)
+                    .map_err(|e| format!("Failed to encode Codex developer instructions: {}", e))?;
+                args.extend([
+                    "--config".to_string(),
+                    format!("developer_instructions={}", value),
+                ]);
+            }
+            if resume {
+                args.push("resume".to_string());
+                if let Some(session_id) = session_id {
+                    args.push(session_id.to_string());
+                } else {
+                    args.push("--last".to_string());
+                }
+            }
+            if let Some(prompt) = prompt {
+                args.push(prompt.to_string());
+            }
+        }
+    }
+    Ok(args)
+}
+
+// ---------- resume subcommand ----------
+
+struct ChatMessage {
+    role: String,   // "user" or "assistant"
+    content: String,
+}
+
+struct SessionInfo {
+    last_modified: SystemTime,
+    messages: Vec<ChatMessage>,
+    agent: Agent,
+    resume_id: Option<String>,
+}
+
+struct WorktreeSession {
+    worktree: WorktreeInfo,
+    session: SessionInfo,
+}
+
+/// Encode a worktree path into the Claude projects directory name format.
+/// `/Users/dtsung/figma-worktrees/branch-foo` → `-Users-dtsung-figma-worktrees-branch-foo`
+fn encode_project_path(path: &Path) -> String {
+    path.to_string_lossy().replace('/', "-")
+}
+
+/// Read the last `max_bytes` of a file and return complete lines from that tail.
+fn tail_lines(path: &Path, max_bytes: u64) -> Result<Vec<String>, String> {
+    let meta = fs::metadata(path).map_err(|e| format!("stat {}: {}", path.display(), e))?;
+    let file_len = meta.len();
+
+    let file = fs::File::open(path).map_err(|e| format!("open {}: {}", path.display(), e))?;
+
+    let start = if file_len > max_bytes { file_len - max_bytes } else { 0 };
+    let reader = io::BufReader::new(file);
+    let mut lines: Vec<String> = Vec::new();
+
+    // Skip to the start offset by reading bytes
+    use std::io::{Seek, SeekFrom};
+    let mut reader = reader;
+    reader.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+
+    // If we skipped into the middle of a file, discard the first (partial) line
+    if start > 0 {
+        let mut _discard = String::new();
+        reader.read_line(&mut _discard).ok();
+    }
+
+    for line in reader.lines() {
+        match line {
+            Ok(l) => lines.push(l),
+            Err(_) => break,
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Parse JSONL lines to extract the last N user/assistant chat messages.
+fn parse_claude_session_messages(jsonl_path: &Path, max_messages: usize) -> Vec<ChatMessage> {
+    // Read last ~256KB — plenty for recent messages
+    let lines = match tail_lines(jsonl_path, 256 * 1024) {
+        Ok(l) => l,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut messages: Vec<ChatMessage> = Vec::new();
+
+    for line in &lines {
+        let val: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let msg_type = val.get("type").and_then(|v| v.as_str()).unwrap_or("");
+
+        match msg_type {
+            "user" => {
+                let content = val.get("message").and_then(|m| m.get("content"));
+                if let Some(content) = content {
+                    if let Some(text) = content.as_str() {
+                        let trimmed = text.trim_start();
+                        // Skip system/tool messages (XML tags, tool results)
+                        if !trimmed.starts_with('<') {
+                            messages.push(ChatMessage {
+                                role: "user".to_string(),
+                                content: text.to_string(),
+                            });
+                        }
+                    }
+                    // Skip tool_result arrays — those aren't human messages
+                }
+            }
+            "assistant" => {
+                let content = val.get("message").and_then(|m| m.get("content"));
+                if let Some(arr) = content.and_then(|c| c.as_array()) {
+                    // Find the first text block (skip thinking blocks)
+                    for item in arr {
+                        let block_type = item.get("type").and_then(|t| t.as_str()).unwrap_or("");
+                        if block_type == "text" {
+                            if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
+                                messages.push(ChatMessage {
+                                    role: "assistant".to_string(),
+                                    content: text.to_string(),
+                                });
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Return the last N messages
+    let start = if messages.len() > max_messages { messages.len() - max_messages } else { 0 };
+    messages.split_off(start)
+}
+
+/// Find the most recent Claude session for a worktree path.
+fn find_claude_worktree_session(worktree_path: &Path) -> Option<SessionInfo> {
+    let home = env::var("HOME").ok()?;
+    let encoded = encode_project_path(worktree_path);
+    let project_dir = PathBuf::from(format!("{}/.claude/projects/{}", home, encoded));
+
+    if !project_dir.is_dir() {
+        return None;
+    }
+
+    let mut best_path: Option<PathBuf> = None;
+    let mut best_time: Option<SystemTime> = None;
+
+    if let Ok(entries) = fs::read_dir(&project_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("jsonl") {
+                if let Ok(meta) = fs::metadata(&path) {
+                    if let Ok(modified) = meta.modified() {
+                        if best_time.is_none() || modified > best_time.unwrap() {
+                            best_time = Some(modified);
+                            best_path = Some(path);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let jsonl_path = best_path?;
+    let last_modified = best_time?;
+    let messages = parse_claude_session_messages(&jsonl_path, 50);
+
+    if messages.is_empty() {
+        return None;
+    }
+
+    Some(SessionInfo {
+        last_modified,
+        messages,
+        agent: Agent::Claude,
+        resume_id: None,
+    })
+}
+
+/// Most-recent Claude session activity time for a worktree, based on the
+/// mtime of its session transcripts. Lighter than `find_worktree_session`
+/// since it doesn't parse message contents.
+fn find_claude_worktree_session_time(worktree_path: &Path) -> Option<SystemTime> {
+    let home = env::var("HOME").ok()?;
+    let encoded = encode_project_path(worktree_path);
+    let project_dir = PathBuf::from(format!("{}/.claude/projects/{}", home, encoded));
+
+    let mut best: Option<SystemTime> = None;
+    for entry in fs::read_dir(&project_dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("jsonl") {
+            if let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) {
+                if best.map_or(true, |b| modified > b) {
+                    best = Some(modified);
+                }
+            }
+        }
+    }
+    best
+}
+
+fn get_codex_session_dir() -> PathBuf {
+    if let Ok(codex_home) = env::var("CODEX_HOME") {
+        return PathBuf::from(codex_home).join("sessions");
+    }
+    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".codex/sessions")
+}
+
+fn collect_jsonl_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_jsonl_files(&path, files);
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("jsonl") {
+            files.push(path);
+        }
+    }
+}
+
+fn codex_session_metadata(jsonl_path: &Path) -> Option<(PathBuf, String)> {
+    let file = fs::File::open(jsonl_path).ok()?;
+    let first_line = io::BufReader::new(file).lines().next()?.ok()?;
+    let value: Value = serde_json::from_str(&first_line).ok()?;
+    if value.get("type").and_then(Value::as_str) != Some("session_meta") {
+        return None;
+    }
+    let payload = value.get("payload")?;
+    // Checkout launches top-level CLI sessions. Excluding subagent transcripts
+    // prevents a worker's partial conversation from replacing the parent session.
+    if payload.get("source").and_then(Value::as_str) != Some("cli") {
+        return None;
+    }
+    let worktree = payload.get("cwd").and_then(Value::as_str).map(PathBuf::from)?;
+    let session_id = payload.get("id").and_then(Value::as_str)?.to_string();
+    Some((worktree, session_id))
+}
+
+fn latest_codex_session_files(
+    worktree_paths: &HashSet<PathBuf>,
+) -> HashMap<PathBuf, (SystemTime, PathBuf, String)> {
+    let mut files = Vec::new();
+    collect_jsonl_files(&get_codex_session_dir(), &mut files);
+
+    let mut latest: HashMap<PathBuf, (SystemTime, PathBuf, String)> = HashMap::new();
+    for path in files {
+        let Some((worktree_path, session_id)) = codex_session_metadata(&path) else {
+            continue;
+        };
+        if !worktree_paths.contains(&worktree_path) {
+            continue;
+        }
+        let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        let replace = latest
+            .get(&worktree_path)
+            .is_none_or(|(current, _, _)| modified > *current);
+        if replace {
+            latest.insert(worktree_path, (modified, path, session_id));
+        }
+    }
+    latest
+}
+
+fn choose_resume_target(
+    codex: Option<ResumeTarget>,
+    claude: Option<ResumeTarget>,
+) -> Option<ResumeTarget> {
+    match (codex, claude) {
+        (Some(codex), Some(claude)) => {
+            if codex.last_modified >= claude.last_modified {
+                Some(codex)
+            } else {
+                Some(claude)
+            }
+        }
+        (Some(codex), None) => Some(codex),
+        (None, Some(claude)) => Some(claude),
+        (None, None) => None,
+    }
+}
+
+fn find_worktree_resume_target(worktree_path: &Path) -> Option<ResumeTarget> {
+    let claude = find_claude_worktree_session_time(worktree_path).map(|last_modified| ResumeTarget {
+        last_modified,
+        agent: Agent::Claude,
+        resume_id: None,
+    });
+
+    let mut worktree_paths = HashSet::new();
+    worktree_paths.insert(worktree_path.to_path_buf());
+    let codex = latest_codex_session_files(&worktree_paths)
+        .remove(worktree_path)
+        .map(|(last_modified, _, resume_id)| ResumeTarget {
+            last_modified,
+            agent: Agent::Codex,
+            resume_id: Some(resume_id),
+        });
+
+    choose_resume_target(codex, claude)
+}
+
+fn find_codex_worktree_session_id(worktree_path: &Path) -> Option<String> {
+    let mut worktree_paths = HashSet::new();
+    worktree_paths.insert(worktree_path.to_path_buf());
+    latest_codex_session_files(&worktree_paths)
+        .remove(worktree_path)
+        .map(|(_, _, session_id)| session_id)
+}
+
+fn parse_codex_session_messages(jsonl_path: &Path, max_messages: usize) -> Vec<ChatMessage> {
+    let lines = match tail_lines(jsonl_path, 256 * 1024) {
+        Ok(lines) => lines,
+        Err(_) => return Vec::new(),
+    };
+    let mut messages = Vec::new();
+
+    for line in lines {
+        let Ok(value) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if value.get("type").and_then(Value::as_str) != Some("response_item") {
+            continue;
+        }
+        let Some(payload) = value.get("payload") else {
+            continue;
+        };
+        if payload.get("type").and_then(Value::as_str) != Some("message") {
+            continue;
+        }
+        let Some(role) = payload.get("role").and_then(Value::as_str) else {
+            continue;
+        };
+        if role != "user" && role != "assistant" {
+            continue;
+        }
+
+        let expected_type = if role == "user" { "input_text" } else { "output_text" };
+        let content = payload
+            .get("content")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter(|part| part.get("type").and_then(Value::as_str) == Some(expected_type))
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .filter(|text| role != "user" || !text.trim_start().starts_with('<'))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !content.trim().is_empty() {
+            messages.push(ChatMessage {
+                role: role.to_string(),
+                content,
+            });
+        }
+    }
+
+    let start = messages.len().saturating_sub(max_messages);
+    messages.split_off(start)
+}
+
+fn find_agent_sessions(
+    agent: Agent,
+    entries: Vec<(PathBuf, String)>,
+) -> Vec<WorktreeSession> {
+    match agent {
+        Agent::Claude => entries
+            .into_iter()
+            .filter_map(|(path, branch)| {
+                let session = find_claude_worktree_session(&path)?;
+                Some(WorktreeSession {
+                    worktree: WorktreeInfo {
+                        path,
+                        branch,
+                        head: String::new(),
+                        tag: None,
+                        has_changes: false,
+                        unpushed_commits: Some(0),
+                        has_active_session: false,
+                        active_agent: None,
+                        orphaned_pids: Vec::new(),
+                        note: None,
+                        last_used: None,
+                        stack_parent: None,
+                    },
+                    session,
+                })
+            })
+            .collect(),
+        Agent::Codex => {
+            let branches: HashMap<PathBuf, String> = entries.into_iter().collect();
+            let worktree_paths = branches.keys().cloned().collect();
+            latest_codex_session_files(&worktree_paths)
+                .into_iter()
+                .filter_map(|(path, (last_modified, jsonl_path, session_id))| {
+                    let messages = parse_codex_session_messages(&jsonl_path, 50);
+                    let branch = branches.get(&path)?.clone();
+                    Some(WorktreeSession {
+                        worktree: WorktreeInfo {
+                            path,
+                            branch,
+                            head: String::new(),
+                            tag: None,
+                            has_changes: false,
+                            unpushed_commits: Some(0),
+                            has_active_session: false,
+                            active_agent: None,
+                            orphaned_pids: Vec::new(),
+                            note: None,
+                            last_used: None,
+                            stack_parent: None,
+                        },
+                        session: SessionInfo {
+                            last_modified,
+                            messages,
+                            agent: Agent::Codex,
+                            resume_id: Some(session_id),
+                        },
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+fn find_all_agent_sessions(entries: Vec<(PathBuf, String)>) -> Vec<WorktreeSession> {
+    let mut sessions = find_agent_sessions(Agent::Codex, entries.clone());
+    sessions.extend(find_agent_sessions(Agent::Claude, entries));
+    sessions
+}
+
+fn find_agent_session_times(worktree_paths: &HashSet<PathBuf>) -> HashMap<PathBuf, SystemTime> {
+    let mut times = HashMap::new();
+    for path in worktree_paths {
+        if let Some(modified) = find_claude_worktree_session_time(path) {
+            times.insert(path.clone(), modified);
+        }
+    }
+    for (path, (modified, _, _)) in latest_codex_session_files(worktree_paths) {
+        times
+            .entry(path)
+            .and_modify(|current| *current = (*current).max(modified))
+            .or_insert(modified);
+    }
+    times
+}
+
+/// Format a `SystemTime` as a human-readable "time ago" string.
+fn format_time_ago(time: SystemTime) -> String {
+    let elapsed = time.elapsed().unwrap_or_default();
+    let secs = elapsed.as_secs();
+
+    if secs < 60 {
+        return "just now".to_string();
+    }
+    let mins = secs / 60;
+    if mins < 60 {
+        return format!("{}m ago", mins);
+    }
+    let hours = mins / 60;
+    if hours < 24 {
+        return format!("{}h ago", hours);
+    }
+    let days = hours / 24;
+    if days < 30 {
+        return format!("{}d ago", days);
+    }
+    let months = days / 30;
+    format!("{}mo ago", months)
+}
+
+/// Lightweight worktree listing: just parses `git worktree list --porcelain`
+/// without spawning any git-status or process-detection subcommands.
+fn list_worktree_paths(repo_root: &PathBuf) -> Result<Vec<(PathBuf, String)>, String> {
+    timing!("list_worktree_paths");
+    let output = Command::new("git")
+        .args(["-C", &repo_root.to_string_lossy(), "worktree", "list", "--porcelain"])
+        .traced_output()
+        .map_err(|e| format!("Failed to list worktrees: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut entries: Vec<(PathBuf, String)> = Vec::new();
+    let mut current_path: Option<PathBuf> = None;
+    let mut current_branch: Option<String> = None;
+
+    for line in stdout.lines() {
+        if let Some(path_str) = line.strip_prefix("worktree ") {
+            current_path = Some(PathBuf::from(path_str));
+        } else if let Some(branch_str) = line.strip_prefix("branch refs/heads/") {
+            current_branch = Some(branch_str.to_string());
+        } else if line.is_empty() {
+            if let Some(path) = current_path.take() {
+                if path != *repo_root {
+                    let branch = current_branch.take().unwrap_or_else(|| "(detached)".to_string());
+                    entries.push((path, branch));
+                }
+            }
+            current_branch = None;
+        }
+    }
+
+    Ok(entries)
+}
+
+fn run_resume(repo: Option<PathBuf>) -> Result<(), String> {
+    timing!("run_resume");
+    let repo_root = resolve_repo_root(repo);
+
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
+
+    // Fast path: just list worktree paths (single git command, no status checks)
+    print!("{} Listing worktrees... ", "→".blue().bold());
+    io::stdout().flush().ok();
+    let entries = list_worktree_paths(&repo_root)?;
+    println!("{} ({} found)", "done".green(), entries.len());
+
+    if entries.is_empty() {
+        println!("{} No worktrees found", "→".blue().bold());
+        return Ok(());
+    }
+
+    // Find sessions via filesystem reads only — skip worktrees without sessions
+    print!("{} Finding agent sessions... ", "→".blue().bold());
+    io::stdout().flush().ok();
+    let mut sessions = find_all_agent_sessions(entries);
+    println!("{} ({} with sessions)", "done".green(), sessions.len());
+
+    if sessions.is_empty() {
+        println!("{} No worktrees with agent sessions found", "→".blue().bold());
+        return Ok(());
+    }
+
+    // Sort by most recent session first
+    sessions.sort_by(|a, b| b.session.last_modified.cmp(&a.session.last_modified));
+
+    // Run the TUI and get the selected index
+    let selected = run_resume_tui(&sessions)?;
+
+    let Some(idx) = selected else {
+        return Ok(());
+    };
+
+    let ws = &sessions[idx];
+    let worktree_path = &ws.worktree.path;
+    let agent = ws.session.agent;
+    touch_last_used(worktree_path);
+    write_worktree_path(worktree_path)?;
+
+    prepare_agent_worktree(agent, worktree_path, &repo_root, false)?;
+    let bg_color = assign_worktree_color(worktree_path, None, None)?;
+    record_current_iterm_session(worktree_path)?;
+    let session_name = session_name_for_resume(worktree_path, &ws.worktree.branch);
+
+    let tab_title = terminal_title(&session_name, &repo_root);
+    let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, terminal_guard_options(&CheckoutArgs::default()));
+
+    run_on_enter_script(worktree_path, &bg_color, &session_name);
+
+    println!(
+        "\n{} Resuming session in {}...\n",
+        "→".blue().bold(),
+        worktree_path.display().to_string().cyan()
+    );
+
+    let developer_instructions = build_worktree_system_prompt();
+    spawn_agent_continue(
+        agent,
+        worktree_path,
+        None,
+        Some(&developer_instructions),
+        ws.session.resume_id.as_deref(),
+        &session_name,
+        &[],
+    )?;
+
+    Ok(())
+}
+
+fn run_resume_last(repo: Option<PathBuf>, agent: Agent) -> Result<(), String> {
+    timing!("run_resume_last");
+    let repo_root = resolve_repo_root(repo);
+
+    if !repo_root.exists() {
+        return Err(format!("Repo not found at {}", repo_root.display()));
+    }
+
+    // Read all .exited files, find the most recent one whose worktree still exists
+    // and doesn't have an active session
+    let session_dir = get_session_dir();
+    let entries = fs::read_dir(&session_dir)
+        .map_err(|e| format!("Failed to read session dir: {}", e))?;
+
+    let mut best: Option<(u64, PathBuf)> = None;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("exited") {
+            continue;
+        }
+
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        let Some(exited) = parse_exited_session(&content) else {
+            continue;
+        };
+        if exited.agent != agent {
+            continue;
+        }
+        let timestamp = exited.timestamp;
+        let worktree_path = exited.worktree_path;
+
+        // Skip if worktree no longer exists
+        if !worktree_path.exists() {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+
+        // Skip if there's an active session
+        if let Some(pid) = read_session_pid(&worktree_path) {
+            if is_pid_alive(pid) {
+                continue;
+            }
+        }
+
+        if best.as_ref().map_or(true, |(ts, _)| timestamp > *ts) {
+            best = Some((timestamp, worktree_path));
+        }
+    }
+
+    let Some((_timestamp, worktree_path)) = best else {
+        println!("{} No recently exited sessions found", "!".yellow().bold());
+        return Ok(());
+    };
+
+    let branch = worktree_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!(
+        "{} Resuming last session in {}",
+        "→".blue().bold(),
+        worktree_path.display().to_string().cyan()
+    );
+    touch_last_used(&worktree_path);
+    write_worktree_path(&worktree_path)?;
+
+    prepare_agent_worktree(agent, &worktree_path, &repo_root, false)?;
+    let bg_color = assign_worktree_color(&worktree_path, None, None)?;
+    record_current_iterm_session(&worktree_path)?;
+    let session_name = session_name_for_resume(&worktree_path, &branch);
+
+    let tab_title = terminal_title(&session_name, &repo_root);
+    let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, terminal_guard_options(&CheckoutArgs::default()));
+
+    run_on_enter_script(&worktree_path, &bg_color, &session_name);
+
+    let system_prompt = build_worktree_system_prompt();
+    let resume_id = if agent == Agent::Codex {
+        find_codex_worktree_session_id(&worktree_path)
+    } else {
+        None
+    };
+
+    println!();
+    println!(
+        "{} Resuming {} session...",
+        "→".blue().bold(),
+        agent.display_name(),
+    );
+    println!();
+
+    spawn_agent_continue(
+        agent,
+        &worktree_path,
+        None,
+        Some(&system_prompt),
+        resume_id.as_deref(),
+        &session_name,
+        &[],
+    )?;
+
+    Ok(())
+}
+
+/// Parse inline markdown (`**bold**`, `*italic*`, `` `code` ``) into styled spans.
+fn parse_inline_markdown(text: &str, base_style: Style) -> Vec<(String, Style)> {
+    let mut segments: Vec<(String, Style)> = Vec::new();
+    let mut current = String::new();
+    let mut bold = false;
+    let mut italic = false;
+    let mut in_code = false;
+    let chars: Vec<char> = text.chars().collect();
+    let len = chars.len();
+    let mut i = 0;
+
+    let code_style = base_style.fg(Color::Cyan);
+
+    let make_style = |base: Style, b: bool, it: bool| -> Style {
+        let mut s = base;
+        if b { s = s.add_modifier(Modifier::BOLD); }
+        if it { s = s.add_modifier(Modifier::ITALIC); }
+        s
+    };
+
+    while i < len {
+        if chars[i] == '`' {
+            if !current.is_empty() {
+                let style = if in_code { code_style } else { make_style(base_style, bold, italic) };
+                segments.push((std::mem::take(&mut current), style));
+            }
+            in_code = !in_code;
+            i += 1;
+        } else if in_code {
+            // Inside backticks — no markdown parsing
+            current.push(chars[i]);
+            i += 1;
+        } else if i + 1 < len && chars[i] == '*' && chars[i + 1] == '*' {
+            if !current.is_empty() {
+                segments.push((std::mem::take(&mut current), make_style(base_style, bold, italic)));
+            }
+            bold = !bold;
+            i += 2;
+        } else if chars[i] == '*' {
+            if !current.is_empty() {
+                segments.push((std::mem::take(&mut current), make_style(base_style, bold, italic)));
+            }
+            italic = !italic;
+            i += 1;
+        } else {
+            current.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    if !current.is_empty() {
+        let style = if in_code { code_style } else { make_style(base_style, bold, italic) };
+        segments.push((current, style));
+    }
+
+    segments
+}
+
+/// Wrap pre-styled segments into display lines, breaking at word boundaries.
+fn wrap_styled_segments<'a>(segments: &[(String, Style)], width: usize) -> Vec<Vec<Span<'a>>> {
+    // Flatten into a list of (char, style) pairs
+    let mut styled_chars: Vec<(char, Style)> = Vec::new();
+    for (text, style) in segments {
+        for ch in text.chars() {
+            styled_chars.push((ch, *style));
+        }
+    }
+
+    if styled_chars.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result: Vec<Vec<Span<'a>>> = Vec::new();
+    let total = styled_chars.len();
+    let mut pos = 0;
+
+    while pos < total {
+        let line_end = (pos + width).min(total);
+
+        // If the whole remainder fits, take it all
+        if line_end == total {
+            let line = build_spans_from_styled_chars(&styled_chars[pos..total]);
+            result.push(line);
+            break;
+        }
+
+        // Look for a space to break at (scan backwards from the cut point)
+        let mut break_at = line_end;
+        let mut found_space = false;
+        for i in (pos..line_end).rev() {
+            if styled_chars[i].0 == ' ' {
+                break_at = i + 1; // break after the space
+                found_space = true;
+                break;
+            }
+        }
+
+        // If no space found, hard-break at width
+        if !found_space {
+            break_at = line_end;
+        }
+
+        let line = build_spans_from_styled_chars(&styled_chars[pos..break_at]);
+        result.push(line);
+        pos = break_at;
+    }
+
+    result
+}
+
+/// Convert a slice of (char, style) pairs into coalesced Spans.
+fn build_spans_from_styled_chars<'a>(chars: &[(char, Style)]) -> Vec<Span<'a>> {
+    let mut spans: Vec<Span<'a>> = Vec::new();
+    if chars.is_empty() {
+        return spans;
+    }
+
+    let mut current_text = String::new();
+    let mut current_style = chars[0].1;
+
+    for &(ch, style) in chars {
+        if style == current_style {
+            current_text.push(ch);
+        } else {
+            if !current_text.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut current_text), current_style));
+            }
+            current_style = style;
+            current_text.push(ch);
+        }
+    }
+
+    if !current_text.is_empty() {
+        spans.push(Span::styled(current_text, current_style));
+    }
+
+    spans
+}
+
+/// Render messages for a session into display lines, fitting within a line budget.
+fn render_session_messages(ws: &WorktreeSession, max_lines: usize, msg_width: usize) -> Vec<Line<'static>> {
+    let prefix_width = 14; // "    ┃ <agent> " with a six-character label
+    let effective_msg_width = if msg_width > prefix_width + 4 { msg_width - prefix_width } else { 40 };
+    let agent_label = ws.session.agent.display_name();
+
+    let mut all_msg_lines: Vec<Vec<Line>> = Vec::new();
+
+    for (msg_idx, msg) in ws.session.messages.iter().enumerate() {
+        let (label, label_style, text_style) = if msg.role == "user" {
+            ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
+             Style::default().fg(Color::White))
+        } else {
+            (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+             Style::default().fg(Color::Gray))
+        };
+
+        let mut msg_lines: Vec<Line> = Vec::new();
+
+        if msg_idx > 0 {
+            msg_lines.push(Line::from(vec![
+                Span::styled("    ┃", Style::default().fg(Color::DarkGray)),
+            ]));
+        }
+
+        let mut first_line = true;
+
+        for text_line in msg.content.split('\n') {
+            if text_line.is_empty() {
+                if first_line {
+                    msg_lines.push(Line::from(vec![
+                        Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
+                        Span::styled(label, label_style),
+                    ]));
+                    first_line = false;
+                } else {
+                    msg_lines.push(Line::from(vec![
+                        Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
+                    ]));
+                }
+                continue;
+            }
+
+            let segments = parse_inline_markdown(text_line, text_style);
+            let wrapped = wrap_styled_segments(&segments, effective_msg_width);
+
+            for display_spans in wrapped {
+                let mut line_spans = vec![
+                    Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
+                ];
+                if first_line {
+                    line_spans.push(Span::styled(label, label_style));
+                    line_spans.push(Span::raw(" "));
+                    first_line = false;
+                } else {
+                    line_spans.push(Span::raw("       "));
+                }
+                line_spans.extend(display_spans);
+                msg_lines.push(Line::from(line_spans));
+            }
+        }
+
+        all_msg_lines.push(msg_lines);
+    }
+
+    // Walk backwards from newest, fitting whole messages
+    let mut budget = max_lines;
+    let mut start_msg = all_msg_lines.len();
+
+    for (i, msg_lines) in all_msg_lines.iter().enumerate().rev() {
+        if msg_lines.len() <= budget {
+            budget -= msg_lines.len();
+            start_msg = i;
+        } else {
+            break;
+        }
+    }
+
+    let mut lines: Vec<Line> = Vec::new();
+
+    // If there's remaining budget and an older message that didn't fit whole,
+    // show its tail to fill the panel
+    if budget > 0 && start_msg > 0 {
+        let partial_idx = start_msg - 1;
+        let partial = &all_msg_lines[partial_idx];
+        let partial_msg = &ws.session.messages[partial_idx];
+
+        // Reserve 1 line for the "..." label
+        let tail_budget = budget.saturating_sub(1);
+        if tail_budget > 0 {
+            let (label, label_style) = if partial_msg.role == "user" {
+                ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+            } else {
+                (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))
+            };
+            lines.push(Line::from(vec![
+                Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
+                Span::styled(label, label_style),
+                Span::styled(" ...", Style::default().fg(Color::DarkGray)),
+            ]));
+            let skip = partial.len().saturating_sub(tail_budget);
+            lines.extend(partial[skip..].iter().cloned());
+        }
+    }
+
+    if start_msg < all_msg_lines.len() {
+        for msg_lines in &all_msg_lines[start_msg..] {
+            lines.extend(msg_lines.iter().cloned());
+        }
+    } else if all_msg_lines.len() == 1 {
+        // Only one message and it's too large — show label + tail
+        let newest_lines = &all_msg_lines[0];
+        let newest_msg = &ws.session.messages[0];
+        let (label, label_style) = if newest_msg.role == "user" {
+            ("You   ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+        } else {
+            (agent_label, Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD))
+        };
+        lines.push(Line::from(vec![
+            Span::styled("    ┃ ", Style::default().fg(Color::DarkGray)),
+            Span::styled(label, label_style),
+            Span::styled(" ...", Style::default().fg(Color::DarkGray)),
+        ]));
+        let tail_budget = max_lines.saturating_sub(1);
+        let skip = newest_lines.len().saturating_sub(tail_budget);
+        lines.extend(newest_lines[skip..].iter().cloned());
+    }
+
+    lines
+}
+
+fn run_resume_tui(sessions: &[WorktreeSession]) -> Result<Option<usize>, String> {
+    enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+    let mut stdout = io::stdout();
+    stdout.execute(EnterAlternateScreen).map_err(|e| format!("Failed to enter alternate screen: {}", e))?;
+
+    let original_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = io::stdout().execute(LeaveAlternateScreen);
+        original_hook(info);
+    }));
+
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend).map_err(|e| format!("Failed to create terminal: {}", e))?;
+
+    let mut selected: usize = 0;
+    let mut scroll_offset: usize = 0;
+    let visible_branches: usize = 10;
+    let scroll_margin: usize = 2;
+
+    let result = loop {
+        terminal.draw(|frame| {
+            let area = frame.area();
+
+            // Clamp visible branches to what fits
+            let branch_height = visible_branches.min(sessions.len()) as u16;
+
+            // Layout: title | messages | separator | branches | footer
             let chunks = Layout::vertical([
                 Constraint::Length(2),           // title + blank
                 Constraint::Min(3),              // message panel (flexible)
@@ -5167,580 +10944,2026 @@ fn run_resume_tui(sessions: &[WorktreeSession]) -> Result<Option<usize>, String>
                 Constraint::Length(1),            // footer
             ]).split(area);
 
-            let available_width = area.width as usize;
+            let available_width = area.width as usize;
+
+            // -- Title --
+            let title = Paragraph::new(Line::from(vec![
+                Span::styled("checkout resume", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(" — Select a worktree to resume", Style::default().fg(Color::DarkGray)),
+            ]));
+            frame.render_widget(title, chunks[0]);
+
+            // -- Message panel --
+            let msg_height = chunks[1].height as usize;
+            let msg_lines = render_session_messages(&sessions[selected], msg_height, available_width);
+            // Bottom-align: if fewer lines than panel height, pad from top
+            let mut padded: Vec<Line> = Vec::new();
+            if msg_lines.len() < msg_height {
+                for _ in 0..(msg_height - msg_lines.len()) {
+                    padded.push(Line::from(""));
+                }
+            }
+            padded.extend(msg_lines);
+            let msg_paragraph = Paragraph::new(padded);
+            frame.render_widget(msg_paragraph, chunks[1]);
+
+            // -- Separator --
+            let sep_line = "─".repeat(available_width);
+            let separator = Paragraph::new(Line::from(
+                Span::styled(sep_line, Style::default().fg(Color::DarkGray))
+            ));
+            frame.render_widget(separator, chunks[2]);
+
+            // -- Branch list --
+            let visible_count = branch_height as usize;
+            let end = (scroll_offset + visible_count).min(sessions.len());
+
+            let mut branch_lines: Vec<Line> = Vec::new();
+            for i in scroll_offset..end {
+                let ws = &sessions[i];
+                let is_sel = i == selected;
+
+                let dir_name = ws.worktree.path.file_name()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_else(|| ws.worktree.path.display().to_string());
+
+                let time_ago = format_time_ago(ws.session.last_modified);
+                let arrow = if is_sel { "▸ " } else { "  " };
+                let branch_part = format!("({})", ws.worktree.branch);
+
+                let left_len = arrow.chars().count() + dir_name.chars().count() + 1 + branch_part.chars().count();
+                let time_len = time_ago.chars().count();
+                let pad = if available_width > left_len + time_len {
+                    available_width - left_len - time_len
+                } else {
+                    1
+                };
+
+                let line_style = if is_sel {
+                    Style::default().bg(Color::Rgb(40, 40, 50))
+                } else {
+                    Style::default()
+                };
+
+                branch_lines.push(Line::from(vec![
+                    Span::styled(arrow, if is_sel { Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).bg(Color::Rgb(40, 40, 50)) } else { Style::default() }),
+                    Span::styled(dir_name, Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).patch(line_style)),
+                    Span::styled(" ", line_style),
+                    Span::styled(branch_part, Style::default().fg(Color::DarkGray).patch(line_style)),
+                    Span::styled(" ".repeat(pad), line_style),
+                    Span::styled(time_ago, Style::default().fg(Color::DarkGray).patch(line_style)),
+                ]));
+            }
+            let branch_paragraph = Paragraph::new(branch_lines);
+            frame.render_widget(branch_paragraph, chunks[3]);
+
+            // -- Footer --
+            let footer = Paragraph::new(Line::from(vec![
+                Span::styled("↑↓", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(" Navigate  ", Style::default().fg(Color::DarkGray)),
+                Span::styled("Enter", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(" Resume  ", Style::default().fg(Color::DarkGray)),
+                Span::styled("q", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(" Quit", Style::default().fg(Color::DarkGray)),
+            ]));
+            frame.render_widget(footer, chunks[4]);
+        }).map_err(|e| format!("Draw error: {}", e))?;
+
+        // Handle input
+        if event::poll(std::time::Duration::from_millis(100)).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break Ok(None),
+                    KeyCode::Enter => {
+                        break Ok(Some(selected));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        if selected > 0 {
+                            selected -= 1;
+                        }
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        if selected < sessions.len() - 1 {
+                            selected += 1;
+                        }
+                    }
+                    _ => {}
+                }
+
+                // Adjust scroll offset to keep selection visible with margin
+                let vis = visible_branches.min(sessions.len());
+                if selected < scroll_offset + scroll_margin {
+                    scroll_offset = selected.saturating_sub(scroll_margin);
+                } else if selected + scroll_margin >= scroll_offset + vis {
+                    scroll_offset = (selected + scroll_margin + 1).saturating_sub(vis);
+                }
+                scroll_offset = scroll_offset.min(sessions.len().saturating_sub(vis));
+            }
+        }
+    };
+
+    // Restore terminal
+    disable_raw_mode().map_err(|e| format!("Failed to disable raw mode: {}", e))?;
+    terminal.backend_mut().execute(LeaveAlternateScreen).map_err(|e| format!("Failed to leave alternate screen: {}", e))?;
+    terminal.show_cursor().map_err(|e| format!("Failed to show cursor: {}", e))?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::test_repo::{run_git, scratch_repo};
+
+    #[test]
+    fn no_spawn_env_values() {
+        for value in ["1", "true", "YES", "on"] {
+            assert!(is_truthy(value), "{}", value);
+        }
+        for value in ["", "0", "false", "No", " off "] {
+            assert!(!is_truthy(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn dir_only_takes_a_pr_and_a_skill() {
+        let cli = Cli::try_parse_from(["checkout", "pr", "123", "/review", "--dir-only"]).unwrap();
+        let Commands::Pr { pr, dir_only, .. } = cli.command else {
+            panic!("expected pr");
+        };
+        assert!(dir_only);
+        assert_eq!(split_pr_args(pr), (vec!["123".to_string()], Some("/review".to_string())));
+    }
+
+    #[test]
+    fn write_path_is_a_global_option() {
+        let cli = Cli::try_parse_from(["checkout", "pr", "42", "--write-path", "/tmp/cw-path"]).unwrap();
+        assert_eq!(cli.write_path, Some(PathBuf::from("/tmp/cw-path")));
+        let cli = Cli::try_parse_from(["checkout", "--write-path", "/tmp/cw-path", "new"]).unwrap();
+        assert_eq!(cli.write_path, Some(PathBuf::from("/tmp/cw-path")));
+    }
+
+    #[test]
+    fn checkout_flags_only_parse_on_checkout_commands() {
+        let cli = Cli::try_parse_from([
+            "checkout", "branch", "darren/x", "--no-fetch", "--strict", "--env", "A=1", "--clone-if-missing", "git@h:o/r",
+        ])
+        .unwrap();
+        let Commands::Branch { checkout, .. } = cli.command else {
+            panic!("expected branch");
+        };
+        assert!(checkout.no_fetch && checkout.strict);
+        assert_eq!(checkout.env, [("A".to_string(), "1".to_string())]);
+        assert_eq!(checkout.clone_if_missing.as_deref(), Some("git@h:o/r"));
+
+        for args in [
+            ["checkout", "clean", "--no-checkout"].as_slice(),
+            &["checkout", "config", "get", "repo", "--strict"],
+            &["checkout", "status", "--env", "X=1"],
+            &["checkout", "--keep-color-on-exit", "resume"],
+        ] {
+            assert!(Cli::try_parse_from(args).is_err(), "{:?} should be rejected", args);
+        }
+    }
+
+    #[test]
+    fn codex_is_the_default_agent() {
+        let cli = Cli::try_parse_from(["checkout", "new", "--no-agent"]).unwrap();
+        assert_eq!(cli.agent, Agent::Codex);
+        assert!(matches!(cli.command, Commands::New { no_agent: true, .. }));
+    }
+
+    #[test]
+    fn parses_noninteractive_resource_open_commands() {
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "open",
+            "pr",
+            "830562",
+            "--repo",
+            "/tmp/figma",
+            "--json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Open {
+                target: OpenTarget::Pr { pr, json: true, .. }
+            } if pr == "830562"
+        ));
+
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "open",
+            "statsig",
+            "my_gate",
+            "--repo",
+            "/tmp/figma",
+            "--json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Open {
+                target: OpenTarget::Statsig { gate, json: true, .. }
+            } if gate == "my_gate"
+        ));
+
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "open",
+            "workspace",
+            "--repo",
+            "/tmp/work-dash",
+            "--json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Open {
+                target: OpenTarget::Workspace { repo, json: true }
+            } if repo == PathBuf::from("/tmp/work-dash")
+        ));
+
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "session",
+            "pr",
+            "830562",
+            "--branch",
+            "darren/test",
+            "--repo",
+            "/tmp/figma",
+            "--json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Session {
+                target: SessionTarget::Pr { pr, branch: Some(branch), json: true, .. }
+            } if pr == "830562" && branch == "darren/test"
+        ));
+
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "session",
+            "workspace",
+            "--repo",
+            "/tmp/work-dash",
+            "--register-current",
+            "--json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Session {
+                target: SessionTarget::Workspace { register_current: true, json: true, .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn statsig_worktrees_are_stable_and_shell_arguments_are_quoted() {
+        assert_eq!(
+            statsig_branch_name("my_gate"),
+            "darren/statsig-my-gate"
+        );
+        assert_eq!(shell_quote("gate with ' quote"), "'gate with '\"'\"' quote'");
+        let long = statsig_branch_name("this_is_a_very_long_gate_name_that_needs_a_stable_hash_suffix");
+        assert!(long.starts_with("darren/statsig-this-is-a-"));
+        assert!(session_name_from_branch(&long).len() <= 25);
+        assert!(Regex::new(r"-[0-9a-f]{6}$").unwrap().is_match(&long));
+    }
+
+    #[test]
+    fn statsig_prompt_builds_and_maintains_a_rollout_dossier() {
+        let codex_prompt = statsig_rollout_prompt("my_gate", Agent::Codex);
+        assert!(codex_prompt.contains("$statsig-cli"));
+        assert!(codex_prompt.contains("$document"));
+        assert!(codex_prompt.contains("`my_gate`"));
+        assert!(codex_prompt.contains("~/figma/dtsung/documents/"));
+        assert!(codex_prompt.contains("introducing pull request"));
+        assert!(codex_prompt.contains("## Rollout journal"));
+        assert!(!codex_prompt.contains("## Workstreams"));
+        assert!(!codex_prompt.contains("~/figma/dtsung/workstreams/"));
+        assert!(!codex_prompt.contains("worktree root"));
+
+        let claude_prompt = statsig_rollout_prompt("my_gate", Agent::Claude);
+        assert!(claude_prompt.contains("/statsig-cli"));
+        assert!(claude_prompt.contains("/darren:document"));
+        assert!(!claude_prompt.contains("$statsig-cli"));
+        assert!(!claude_prompt.contains("$document"));
+    }
+
+    #[test]
+    fn iterm_session_mappings_use_stable_exact_ids() {
+        assert_eq!(
+            normalized_iterm_session_id("w0t1p0:ABC-123"),
+            Some("ABC-123".to_string())
+        );
+        assert_eq!(
+            normalized_iterm_session_id("ABC-123\n"),
+            Some("ABC-123".to_string())
+        );
+        assert_eq!(normalized_iterm_session_id(""), None);
+
+        let first = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-a"));
+        let repeated = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-a"));
+        let other_repo = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-b"));
+        assert_eq!(first, repeated);
+        assert_ne!(first, other_repo);
+
+        let first_worktree = worktree_iterm_session_file(Path::new("/tmp/repo-a/worktree"));
+        let repeated_worktree = worktree_iterm_session_file(Path::new("/tmp/repo-a/worktree"));
+        let same_name_other_repo = worktree_iterm_session_file(Path::new("/tmp/repo-b/worktree"));
+        assert_eq!(first_worktree, repeated_worktree);
+        assert_ne!(first_worktree, same_name_other_repo);
+    }
 
-            // -- Title --
-            let title = Paragraph::new(Line::from(vec![
-                Span::styled("checkout resume", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-                Span::styled(" — Select a worktree to resume", Style::default().fg(Color::DarkGray)),
-            ]));
-            frame.render_widget(title, chunks[0]);
+    #[test]
+    fn iterm_api_client_sends_full_resource_context() {
+        let socket_path =
+            env::temp_dir().join(format!("checkout-iterm-api-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&socket_path);
+        let listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(request["action"], "open");
+            assert_eq!(
+                request["sessionIds"],
+                serde_json::json!(["ABC-123", "DEF-456"])
+            );
+            assert_eq!(request["sessionName"], "work-dash");
+            assert_eq!(request["launchCommand"], "checkout workspace");
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"{\"ok\":true,\"exists\":true,\"sessionId\":\"ABC-123\",\"action\":\"focused\"}\n")
+                .unwrap();
+        });
+
+        let response = iterm_api_request_at(
+            &socket_path,
+            &serde_json::json!({
+                "action": "open",
+                "sessionIds": ["ABC-123", "DEF-456"],
+                "sessionName": "work-dash",
+                "launchCommand": "checkout workspace",
+            }),
+        )
+        .unwrap();
+        assert_eq!(response.session_id.as_deref(), Some("ABC-123"));
+        assert_eq!(response.action.as_deref(), Some("focused"));
+        server.join().unwrap();
+        fs::remove_file(socket_path).unwrap();
+    }
+
+    #[test]
+    fn iterm_api_client_reports_missing_helper() {
+        let socket_path = env::temp_dir().join(format!(
+            "checkout-missing-iterm-api-{}.sock",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&socket_path);
+
+        let error = iterm_api_request_at(
+            &socket_path,
+            &serde_json::json!({ "action": "status", "sessionIds": [] }),
+        )
+        .unwrap_err();
+
+        assert!(error.contains("iTerm Python API helper is unavailable"));
+    }
+
+    #[test]
+    fn claude_and_legacy_flags_remain_supported() {
+        let cli = Cli::try_parse_from([
+            "checkout",
+            "new",
+            "--agent",
+            "claude",
+            "--no-claude",
+            "--claude-prompt",
+            "/tmp/prompt.md",
+        ])
+        .unwrap();
+        assert_eq!(cli.agent, Agent::Claude);
+        assert!(matches!(
+            cli.command,
+            Commands::New {
+                no_agent: true,
+                prompt: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn translates_claude_skill_syntax_for_codex() {
+        assert_eq!(normalize_skill(Agent::Codex, "/walkthrough"), "$walkthrough");
+        assert_eq!(
+            normalize_skill(Agent::Codex, "/checkout:checkout-pr"),
+            "$checkout-pr"
+        );
+        assert_eq!(normalize_skill(Agent::Claude, "/walkthrough"), "/walkthrough");
+    }
+
+    #[test]
+    fn derives_short_session_names_from_branch_leaves() {
+        assert_eq!(session_name_from_branch("darren/fix_PR-title"), "fix-pr-title");
+        assert_eq!(
+            session_name_from_branch("dependabot/npm_and_yarn/react-19"),
+            "react-19"
+        );
+        assert_eq!(
+            session_name_from_branch("refs/heads/darren/a-very-long-branch-name-that-keeps-going"),
+            "a-very-long-branch-name"
+        );
+        assert_eq!(session_name_from_branch("---"), "worktree");
+    }
+
+    #[test]
+    fn exited_markers_are_agent_aware_and_backward_compatible() {
+        let current = parse_exited_session("123\n/tmp/branch-a\nclean\ncodex").unwrap();
+        assert_eq!(current.agent, Agent::Codex);
+        assert!(current.clean);
+
+        let legacy = parse_exited_session("456\n/tmp/branch-b\ndirty").unwrap();
+        assert_eq!(legacy.agent, Agent::Claude);
+        assert!(!legacy.clean);
+    }
+
+    #[test]
+    fn parses_codex_messages_without_injected_context() {
+        let path = env::temp_dir().join(format!(
+            "checkout-codex-session-parser-{}.jsonl",
+            std::process::id()
+        ));
+        let records = [
+            serde_json::json!({
+                "type": "response_item",
+                "payload": {
+                    "type": "message",
+                    "role": "developer",
+                    "content": [{"type": "input_text", "text": "hidden"}]
+                }
+            }),
+            serde_json::json!({
+                "type": "response_item",
+                "payload": {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{"type": "input_text", "text": "<environment_context>hidden</environment_context>"}]
+                }
+            }),
+            serde_json::json!({
+                "type": "response_item",
+                "payload": {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{"type": "input_text", "text": "Implement the change"}]
+                }
+            }),
+            serde_json::json!({
+                "type": "response_item",
+                "payload": {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "output_text", "text": "Done"}]
+                }
+            }),
+        ];
+        fs::write(
+            &path,
+            records
+                .into_iter()
+                .map(|record| record.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+        .unwrap();
+
+        let messages = parse_codex_session_messages(&path, 50);
+        let _ = fs::remove_file(path);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "user");
+        assert_eq!(messages[0].content, "Implement the change");
+        assert_eq!(messages[1].role, "assistant");
+        assert_eq!(messages[1].content, "Done");
+    }
+
+    #[test]
+    fn reads_codex_cli_session_identity() {
+        let path = env::temp_dir().join(format!(
+            "checkout-codex-session-meta-{}.jsonl",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            serde_json::json!({
+                "type": "session_meta",
+                "payload": {
+                    "id": "019f4918-33a8-7542-96cf-cfcfb2886c75",
+                    "cwd": "/tmp/branch-a",
+                    "source": "cli"
+                }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let metadata = codex_session_metadata(&path);
+        let _ = fs::remove_file(path);
+
+        assert_eq!(
+            metadata,
+            Some((
+                PathBuf::from("/tmp/branch-a"),
+                "019f4918-33a8-7542-96cf-cfcfb2886c75".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn builds_codex_launch_and_resume_arguments() {
+        assert_eq!(
+            build_agent_args(
+                Agent::Codex,
+                Some("do the thing"),
+                Some("protect the worktree"),
+                false,
+                None,
+            )
+            .unwrap(),
+            vec![
+                "--config",
+                "developer_instructions=\"protect the worktree\"",
+                "do the thing",
+            ]
+        );
+        assert_eq!(
+            build_agent_args(
+                Agent::Codex,
+                Some("refresh the rollout dossier"),
+                Some("protect the worktree"),
+                true,
+                Some("019f4918-33a8-7542-96cf-cfcfb2886c75"),
+            )
+            .unwrap(),
+            vec![
+                "--config",
+                "developer_instructions=\"protect the worktree\"",
+                "resume",
+                "019f4918-33a8-7542-96cf-cfcfb2886c75",
+                "refresh the rollout dossier",
+            ]
+        );
+    }
 
-            // -- Message panel --
-            let msg_height = chunks[1].height as usize;
-            let msg_lines = render_session_messages(&sessions[selected], msg_height, available_width);
-            // Bottom-align: if fewer lines than panel height, pad from top
-            let mut padded: Vec<Line> = Vec::new();
-            if msg_lines.len() < msg_height {
-                for _ in 0..(msg_height - msg_lines.len()) {
-                    padded.push(Line::from(""));
-                }
-            }
-            padded.extend(msg_lines);
-            let msg_paragraph = Paragraph::new(padded);
-            frame.render_widget(msg_paragraph, chunks[1]);
+    #[test]
+    fn preserves_claude_launch_arguments() {
+        assert_eq!(
+            build_agent_args(
+                Agent::Claude,
+                None,
+                Some("protect the worktree"),
+                true,
+                None,
+            )
+            .unwrap(),
+            vec![
+                "--permission-mode",
+                "acceptEdits",
+                "--continue",
+                "--append-system-prompt",
+                "protect the worktree",
+            ]
+        );
+    }
 
-            // -- Separator --
-            let sep_line = "─".repeat(available_width);
-            let separator = Paragraph::new(Line::from(
-                Span::styled(sep_line, Style::default().fg(Color::DarkGray))
-            ));
-            frame.render_widget(separator, chunks[2]);
+    #[test]
+    fn resume_target_uses_the_most_recent_sessions_agent() {
+        let older_codex = ResumeTarget {
+            last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(10),
+            agent: Agent::Codex,
+            resume_id: Some("codex-session".to_string()),
+        };
+        let newer_claude = ResumeTarget {
+            last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(20),
+            agent: Agent::Claude,
+            resume_id: None,
+        };
 
-            // -- Branch list --
-            let visible_count = branch_height as usize;
-            let end = (scroll_offset + visible_count).min(sessions.len());
+        assert_eq!(
+            choose_resume_target(Some(older_codex), Some(newer_claude)),
+            Some(ResumeTarget {
+                last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(20),
+                agent: Agent::Claude,
+                resume_id: None,
+            })
+        );
+        assert_eq!(choose_resume_target(None, None), None);
+    }
 
-            let mut branch_lines: Vec<Line> = Vec::new();
-            for i in scroll_offset..end {
-                let ws = &sessions[i];
-                let is_sel = i == selected;
+    #[test]
+    fn color_files_are_distinct_per_worktree_path() {
+        let a = color_file_name(Path::new("/src/figma-worktrees/branch-fix"));
+        let b = color_file_name(Path::new("/src/other-worktrees/branch-fix"));
+        assert!(a.starts_with("branch-fix-") && b.starts_with("branch-fix-"));
+        assert_eq!(a.len(), "branch-fix-".len() + 8);
+        assert_ne!(a, b);
+        assert_eq!(a, color_file_name(Path::new("/src/figma-worktrees/branch-fix")));
+    }
 
-                let dir_name = ws.worktree.path.file_name()
-                    .map(|s| s.to_string_lossy().to_string())
-                    .unwrap_or_else(|| ws.worktree.path.display().to_string());
+    #[test]
+    fn worktree_color_argument_accepts_hex_none_and_auto() {
+        assert_eq!(parse_worktree_color("#1E2233"), Ok("1e2233".to_string()));
+        assert_eq!(parse_worktree_color("2d1f2d"), Ok("2d1f2d".to_string()));
+        assert_eq!(parse_worktree_color("none"), Ok("none".to_string()));
+        assert_eq!(parse_worktree_color("auto"), Ok("auto".to_string()));
+        assert!(parse_worktree_color("#123").is_err());
+        assert!(parse_worktree_color("navy").is_err());
+        assert!(parse_worktree_color("#1e2233ff").is_err());
+    }
 
-                let time_ago = format_time_ago(ws.session.last_modified);
-                let arrow = if is_sel { "▸ " } else { "  " };
-                let branch_part = format!("({})", ws.worktree.branch);
+    #[test]
+    fn color_forget_targets_distinguish_prs_from_branches() {
+        assert_eq!(parse_color_key("123"), ColorKey::Pr(123));
+        assert_eq!(
+            parse_color_key("https://github.com/org/repo/pull/456"),
+            ColorKey::Pr(456)
+        );
+        assert_eq!(
+            parse_color_key("darren/auth-fix"),
+            ColorKey::Branch("darren/auth-fix".to_string())
+        );
+    }
 
-                let left_len = arrow.chars().count() + dir_name.chars().count() + 1 + branch_part.chars().count();
-                let time_len = time_ago.chars().count();
-                let pad = if available_width > left_len + time_len {
-                    available_width - left_len - time_len
-                } else {
-                    1
-                };
+    #[test]
+    fn collision_strategies_produce_expected_suffix_shapes() {
+        use config::CollisionStrategy;
+        let now = Duration::from_millis(1_760_611_200_123);
 
-                let line_style = if is_sel {
-                    Style::default().bg(Color::Rgb(40, 40, 50))
-                } else {
-                    Style::default()
-                };
+        assert_eq!(collision_candidate(CollisionStrategy::Numeric, "pr-1-fix", 0, now), "pr-1-fix-2");
+        assert_eq!(collision_candidate(CollisionStrategy::Numeric, "pr-1-fix", 3, now), "pr-1-fix-5");
 
-                branch_lines.push(Line::from(vec![
-                    Span::styled(arrow, if is_sel { Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).bg(Color::Rgb(40, 40, 50)) } else { Style::default() }),
-                    Span::styled(dir_name, Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).patch(line_style)),
-                    Span::styled(" ", line_style),
-                    Span::styled(branch_part, Style::default().fg(Color::DarkGray).patch(line_style)),
-                    Span::styled(" ".repeat(pad), line_style),
-                    Span::styled(time_ago, Style::default().fg(Color::DarkGray).patch(line_style)),
-                ]));
-            }
-            let branch_paragraph = Paragraph::new(branch_lines);
-            frame.render_widget(branch_paragraph, chunks[3]);
+        assert_eq!(
+            collision_candidate(CollisionStrategy::Timestamp, "branch-x", 0, now),
+            "branch-x-1760611200123"
+        );
+        assert_eq!(
+            collision_candidate(CollisionStrategy::Timestamp, "branch-x", 1, now),
+            "branch-x-1760611200124"
+        );
 
-            // -- Footer --
-            let footer = Paragraph::new(Line::from(vec![
-                Span::styled("↑↓", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-                Span::styled(" Navigate  ", Style::default().fg(Color::DarkGray)),
-                Span::styled("Enter", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-                Span::styled(" Resume  ", Style::default().fg(Color::DarkGray)),
-                Span::styled("q", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-                Span::styled(" Quit", Style::default().fg(Color::DarkGray)),
-            ]));
-            frame.render_widget(footer, chunks[4]);
-        }).map_err(|e| format!("Draw error: {}", e))?;
+        let first = collision_candidate(CollisionStrategy::Hash, "branch-x", 0, now);
+        let suffix = first.strip_prefix("branch-x-").unwrap();
+        assert_eq!(suffix.len(), 7);
+        assert!(suffix.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, collision_candidate(CollisionStrategy::Hash, "branch-x", 1, now));
+    }
 
-        // Handle input
-        if event::poll(std::time::Duration::from_millis(100)).unwrap_or(false) {
-            if let Ok(Event::Key(key)) = event::read() {
-                if key.kind != KeyEventKind::Press {
-                    continue;
-                }
-                match key.code {
-                    KeyCode::Char('q') | KeyCode::Esc => break Ok(None),
-                    KeyCode::Enter => {
-                        break Ok(Some(selected));
-                    }
-                    KeyCode::Up | KeyCode::Char('k') => {
-                        if selected > 0 {
-                            selected -= 1;
-                        }
-                    }
-                    KeyCode::Down | KeyCode::Char('j') => {
-                        if selected < sessions.len() - 1 {
-                            selected += 1;
-                        }
-                    }
-                    _ => {}
-                }
+    #[test]
+    fn existing_worktree_lookup_matches_whole_names_or_collision_suffixes() {
+        let entry = |path: &str, branch: Option<&str>| git::WorktreeEntry {
+            path: PathBuf::from(path),
+            head: String::new(),
+            branch: branch.map(str::to_string),
+        };
+        let entries = vec![
+            entry("/repo", Some("master")),
+            entry("/wt/branch-auth-tokens", Some("darren/auth-tokens")),
+            entry("/wt/branch-auth-2", Some("darren/auth")),
+            entry("/wt/branch-auth-1a2b3c4", Some("darren/auth")),
+            entry("/wt/pr-12-old-title", None),
+            entry("/wt/pr-123-other", None),
+            entry("/wt/pr-12-new-title-2", None),
+        ];
+        let names = |pattern| {
+            matching_worktrees(entries.clone(), Path::new("/repo"), pattern)
+                .into_iter()
+                .map(|entry| worktree_dir_name(&entry.path))
+                .collect::<Vec<_>>()
+        };
 
-                // Adjust scroll offset to keep selection visible with margin
-                let vis = visible_branches.min(sessions.len());
-                if selected < scroll_offset + scroll_margin {
-                    scroll_offset = selected.saturating_sub(scroll_margin);
-                } else if selected + scroll_margin >= scroll_offset + vis {
-                    scroll_offset = (selected + scroll_margin + 1).saturating_sub(vis);
-                }
-                scroll_offset = scroll_offset.min(sessions.len().saturating_sub(vis));
-            }
-        }
-    };
+        // The original is gone; suffixed copies match, a longer slug doesn't
+        assert_eq!(names(DirPattern::Name("branch-auth")), ["branch-auth-2", "branch-auth-1a2b3c4"]);
+        assert_eq!(names(DirPattern::Name("branch-auth-tokens")), ["branch-auth-tokens"]);
+        assert!(names(DirPattern::Name("branch-aut")).is_empty());
+        // Both titles of PR 12 are candidates, PR 123 is not
+        assert_eq!(names(DirPattern::Prefix("pr-12-")), ["pr-12-old-title", "pr-12-new-title-2"]);
+        assert!(names(DirPattern::Name("repo")).is_empty());
 
-    // Restore terminal
-    disable_raw_mode().map_err(|e| format!("Failed to disable raw mode: {}", e))?;
-    terminal.backend_mut().execute(LeaveAlternateScreen).map_err(|e| format!("Failed to leave alternate screen: {}", e))?;
-    terminal.show_cursor().map_err(|e| format!("Failed to show cursor: {}", e))?;
+        assert!(is_collision_suffix("2") && is_collision_suffix("1700000000000") && is_collision_suffix("0ab12cd"));
+        assert!(!is_collision_suffix("") && !is_collision_suffix("tokens") && !is_collision_suffix("decaf"));
+    }
 
-    result
-}
+    #[test]
+    fn clean_globs_match_directory_names() {
+        assert!(glob_match("branch-scratch*", "branch-scratch"));
+        assert!(glob_match("branch-scratch*", "branch-scratch-2"));
+        assert!(glob_match("pr-100-*", "pr-100-fix-login"));
+        assert!(!glob_match("pr-100-*", "pr-1000-fix-login"));
+        assert!(glob_match("*-baseline", "branch-perf-baseline"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert!(glob_match("pr-?-x", "pr-7-x"));
+        assert!(!glob_match("pr-?-x", "pr-77-x"));
+        assert!(!glob_match("pr-?-x", "pr--x"));
 
-    #[test]
-    fn codex_is_the_default_agent() {
-        let cli = Cli::try_parse_from(["checkout", "new", "--no-agent"]).unwrap();
-        assert_eq!(cli.agent, Agent::Codex);
-        assert!(matches!(cli.command, Commands::New { no_agent: true, .. }));
+        assert!(glob_match("branch-perf-baseline", "branch-perf-baseline"));
+        assert!(!glob_match("branch-perf-baseline", "branch-perf-baseline-2"));
+        assert!(!glob_match("Branch-scratch", "branch-scratch"));
     }
 
     #[test]
-    fn parses_noninteractive_resource_open_commands() {
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "open",
-            "pr",
-            "830562",
-            "--repo",
-            "/tmp/figma",
-            "--json",
-        ])
-        .unwrap();
-        assert!(matches!(
-            cli.command,
-            Commands::Open {
-                target: OpenTarget::Pr { pr, json: true, .. }
-            } if pr == "830562"
-        ));
-
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "open",
-            "statsig",
-            "my_gate",
-            "--repo",
-            "/tmp/figma",
-            "--json",
-        ])
-        .unwrap();
-        assert!(matches!(
-            cli.command,
-            Commands::Open {
-                target: OpenTarget::Statsig { gate, json: true, .. }
-            } if gate == "my_gate"
-        ));
-
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "open",
-            "workspace",
-            "--repo",
-            "/tmp/work-dash",
-            "--json",
-        ])
-        .unwrap();
-        assert!(matches!(
-            cli.command,
-            Commands::Open {
-                target: OpenTarget::Workspace { repo, json: true }
-            } if repo == PathBuf::from("/tmp/work-dash")
-        ));
+    fn worktree_queries_match_paths_names_or_one_unique_part() {
+        let worktrees = vec![
+            PathBuf::from("/wt/branch-foo"),
+            PathBuf::from("/wt/branch-foo-2"),
+            PathBuf::from("/wt/pr-12-fix-login"),
+        ];
+        assert_eq!(match_worktree(&worktrees, "/wt/branch-foo-2"), Ok(Some(&worktrees[1])));
+        assert_eq!(match_worktree(&worktrees, "branch-foo"), Ok(Some(&worktrees[0])));
+        assert_eq!(match_worktree(&worktrees, "login"), Ok(Some(&worktrees[2])));
+        assert!(match_worktree(&worktrees, "branch").unwrap_err().contains("branch-foo, branch-foo-2"));
+        assert_eq!(match_worktree(&worktrees, "nope"), Ok(None));
+    }
 
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "session",
-            "pr",
-            "830562",
-            "--branch",
-            "darren/test",
-            "--repo",
-            "/tmp/figma",
-            "--json",
-        ])
-        .unwrap();
-        assert!(matches!(
-            cli.command,
-            Commands::Session {
-                target: SessionTarget::Pr { pr, branch: Some(branch), json: true, .. }
-            } if pr == "830562" && branch == "darren/test"
-        ));
+    #[test]
+    fn which_lists_the_worktrees_when_nothing_matches() {
+        let (dir, work) = scratch_repo("which");
+        assert_eq!(
+            run_which("nope", Some(work.clone())),
+            Err("No worktree matches nope (there are no worktrees)".to_string())
+        );
+        for name in ["branch-foo", "pr-12-fix-login"] {
+            run_git(&work, &["worktree", "add", "-q", "--detach", &dir.join(name).to_string_lossy()]);
+        }
+        assert_eq!(
+            run_which("nope", Some(work.clone())),
+            Err("No worktree matches nope; worktrees: branch-foo, pr-12-fix-login".to_string())
+        );
+        assert_eq!(resolve_worktree_query(&work, "nope"), Err("No worktree matches nope".to_string()));
+        assert_eq!(find_worktree_query(&work, "login"), Ok(Some(dir.join("pr-12-fix-login"))));
 
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "session",
-            "workspace",
-            "--repo",
-            "/tmp/work-dash",
-            "--register-current",
-            "--json",
-        ])
-        .unwrap();
-        assert!(matches!(
-            cli.command,
-            Commands::Session {
-                target: SessionTarget::Workspace { register_current: true, json: true, .. }
-            }
-        ));
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn statsig_worktrees_are_stable_and_shell_arguments_are_quoted() {
+    fn notes_protect_worktrees_from_clean_and_truncate_to_fit() {
+        let noted = WorktreeInfo {
+            path: PathBuf::from("/wt/branch-repro"),
+            branch: "darren/repro".to_string(),
+            head: String::new(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: Some("do not delete, repro for INC-4412".to_string()),
+            last_used: None,
+            stack_parent: None,
+        };
+        let plain = WorktreeInfo { path: PathBuf::from("/wt/branch-other"), note: None, ..noted.clone() };
+        let noted = WorktreeInfo {
+            last_used: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_611_200)),
+            stack_parent: Some(StackParent { branch: "darren/repro-base".to_string(), ahead: 2 }),
+            ..noted
+        };
+        let mut filter = CleanFilter { exclude: Vec::new(), only: Vec::new(), include_noted: false };
+        assert!(filter.is_protected(&noted));
+        assert!(!filter.is_protected(&plain));
+        filter.include_noted = true;
+        assert!(!filter.is_protected(&noted));
+
+        let json: Value = serde_json::from_str(&format_status_plain(&[noted, plain], &HashMap::new(), StatusFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[0]["note"], "do not delete, repro for INC-4412");
+        assert_eq!(json[1]["note"], Value::Null);
+        assert_eq!(json[0]["lastUsed"], 1_760_611_200);
+        assert_eq!(json[1]["lastUsed"], Value::Null);
+        assert_eq!(json[0]["stackParent"], serde_json::json!({ "branch": "darren/repro-base", "ahead": 2 }));
+        assert_eq!(json[1]["stackParent"], Value::Null);
+
+        assert_eq!(truncate_chars("waiting on review", 40), "waiting on review");
+        assert_eq!(truncate_chars("waiting on review", 8), "waiting…");
+        assert_eq!(truncate_chars("ünïcödé", 4), "ünï…");
+    }
+
+    #[test]
+    fn mark_viewed_mutation_aliases_one_field_per_file() {
         assert_eq!(
-            statsig_branch_name("my_gate"),
-            "darren/statsig-my-gate"
+            mark_viewed_mutation(2),
+            "mutation($id: ID!, $p0: String!, $p1: String!) { \
+             f0: markFileAsViewed(input: {pullRequestId: $id, path: $p0}) { clientMutationId } \
+             f1: markFileAsViewed(input: {pullRequestId: $id, path: $p1}) { clientMutationId } }"
         );
-        assert_eq!(shell_quote("gate with ' quote"), "'gate with '\"'\"' quote'");
-        let long = statsig_branch_name("this_is_a_very_long_gate_name_that_needs_a_stable_hash_suffix");
-        assert!(long.starts_with("darren/statsig-this-is-a-"));
-        assert!(session_name_from_branch(&long).len() <= 25);
-        assert!(Regex::new(r"-[0-9a-f]{6}$").unwrap().is_match(&long));
     }
 
     #[test]
-    fn statsig_prompt_builds_and_maintains_a_rollout_dossier() {
-        let codex_prompt = statsig_rollout_prompt("my_gate", Agent::Codex);
-        assert!(codex_prompt.contains("$statsig-cli"));
-        assert!(codex_prompt.contains("$document"));
-        assert!(codex_prompt.contains("`my_gate`"));
-        assert!(codex_prompt.contains("~/figma/dtsung/documents/"));
-        assert!(codex_prompt.contains("introducing pull request"));
-        assert!(codex_prompt.contains("## Rollout journal"));
-        assert!(!codex_prompt.contains("## Workstreams"));
-        assert!(!codex_prompt.contains("~/figma/dtsung/workstreams/"));
-        assert!(!codex_prompt.contains("worktree root"));
+    fn migrate_moves_or_repairs_depending_on_where_the_worktree_is() {
+        assert_eq!(migrate_step(true, false), Ok(MigrateStep::Move));
+        assert_eq!(migrate_step(false, true), Ok(MigrateStep::Repair));
+        assert!(migrate_step(true, true).is_err());
+        assert!(migrate_step(false, false).is_err());
+    }
 
-        let claude_prompt = statsig_rollout_prompt("my_gate", Agent::Claude);
-        assert!(claude_prompt.contains("/statsig-cli"));
-        assert!(claude_prompt.contains("/darren:document"));
-        assert!(!claude_prompt.contains("$statsig-cli"));
-        assert!(!claude_prompt.contains("$document"));
+    #[test]
+    fn claude_projects_are_rekeyed_to_the_new_path() {
+        let mut data = serde_json::json!({
+            "projects": {
+                "/old/wt/pr-1-a": { "hasTrustDialogAccepted": true },
+                "/old/wt/pr-2-b": {},
+                "/new/wt/pr-2-b": { "lastCost": 1.5 },
+            }
+        });
+        // Matched through its second spelling, e.g. the configured dir before canonicalizing
+        let old_paths = [PathBuf::from("/private/old/wt/pr-1-a"), PathBuf::from("/old/wt/pr-1-a")];
+        assert!(rename_claude_project(&mut data, &old_paths, Path::new("/new/wt/pr-1-a")));
+        assert_eq!(data["projects"]["/new/wt/pr-1-a"]["hasTrustDialogAccepted"], true);
+        assert!(data["projects"].get("/old/wt/pr-1-a").is_none());
+
+        // An entry already at the new path wins
+        assert!(!rename_claude_project(&mut data, &[PathBuf::from("/old/wt/pr-2-b")], Path::new("/new/wt/pr-2-b")));
+        assert_eq!(data["projects"]["/new/wt/pr-2-b"]["lastCost"], 1.5);
+        assert!(!rename_claude_project(&mut serde_json::json!({}), &old_paths, Path::new("/new/wt/pr-1-a")));
     }
 
     #[test]
-    fn iterm_session_mappings_use_stable_exact_ids() {
+    fn uninstall_finds_worktree_claude_entries_and_measures_disk_usage() {
+        let claude_json = serde_json::json!({
+            "projects": {
+                "/repo": {},
+                "/wt/pr-12-fix": {},
+                "/wt/branch-foo": {},
+                "/wt-other/branch-foo": {},
+            }
+        });
+        let mut entries = claude_project_entries(&claude_json, &[Path::new("/wt")]);
+        entries.sort();
+        assert_eq!(entries, vec!["/wt/branch-foo", "/wt/pr-12-fix"]);
+        assert!(claude_project_entries(&serde_json::json!({}), &[Path::new("/wt")]).is_empty());
+
+        let mut metadata = metadata::Metadata::default();
+        metadata.notes.insert("/wt/pr-12-fix".to_string(), "wip".to_string());
+        metadata.notes.insert("/wt-other/pr-3-x".to_string(), "other repo".to_string());
+        metadata.last_used.insert("/wt/pr-12-fix".to_string(), 1);
+        metadata.last_used.insert("/wt/branch-kept".to_string(), 2);
+        metadata.last_used.insert("/wt/branch-gone".to_string(), 3);
         assert_eq!(
-            normalized_iterm_session_id("w0t1p0:ABC-123"),
-            Some("ABC-123".to_string())
+            uninstall_records(&metadata, &[Path::new("/wt")], &[Path::new("/wt/branch-kept")]),
+            vec!["/wt/branch-gone", "/wt/pr-12-fix"]
+        );
+
+        let (home, config) = (Path::new("/home/me"), Path::new("/home/me/.config/checkout/config.toml"));
+        let repo = Path::new("/home/me/code/repo");
+        assert!(check_uninstall_dir(Path::new("/home/me/.local/share/checkout"), home, config, repo).is_ok());
+        assert!(check_uninstall_dir(Path::new("/home/me"), home, config, repo).is_err());
+        assert!(check_uninstall_dir(Path::new("/"), home, config, repo).is_err());
+        assert!(check_uninstall_dir(Path::new("/home/me/.config"), home, config, repo).is_err());
+        assert!(check_uninstall_dir(Path::new("/home/me/code"), home, config, repo).is_err());
+
+        let dir = env::temp_dir().join(format!("checkout-uninstall-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("colors")).unwrap();
+        fs::write(dir.join("metadata.json"), "{}").unwrap();
+        fs::write(dir.join("colors/pr-12-fix"), "1e2233").unwrap();
+        std::os::unix::fs::symlink("/", dir.join("link")).unwrap();
+        assert_eq!(dir_usage(&dir, &[]), (3, 2 + 6 + "/".len() as u64));
+        assert_eq!(dir_usage(&dir, &[&dir.join("colors")]), (2, 2 + "/".len() as u64));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn status_ignore_filters_porcelain_entries_before_deciding_dirtiness() {
+        let ignore = vec!["gen/.buildinfo".to_string(), "*.lock.tmp".to_string()];
+        let ignored = |line: &str| porcelain_entry_ignored(line, &ignore);
+        assert!(ignored(" M gen/.buildinfo"));
+        assert!(ignored("M  yarn.lock.tmp"));
+        assert!(ignored(" M packages/web/yarn.lock.tmp"));
+        assert!(ignored(" M \"gen/.buildinfo\""));
+        assert!(!ignored(" M src/gen/.buildinfo"));
+        assert!(!ignored(" M src/main.rs"));
+        // Renames are ignored only when both sides match
+        assert!(ignored("R  a.lock.tmp -> b.lock.tmp"));
+        assert!(!ignored("R  gen/.buildinfo -> src/buildinfo.rs"));
+        assert!(!ignored("R  src/lib.rs -> gen/.buildinfo"));
+        assert!(!porcelain_entry_ignored(" M gen/.buildinfo", &[]));
+
+        assert!(!has_tracked_changes_ignoring(" M gen/.buildinfo\n M a.lock.tmp\n?? notes.txt\n", &ignore));
+        assert!(has_tracked_changes_ignoring(" M gen/.buildinfo\n M src/main.rs\n", &ignore));
+        assert!(has_tracked_changes_ignoring(" M gen/.buildinfo\n", &[]));
+        assert!(!has_tracked_changes_ignoring("?? new.rs\n", &[]));
+
+        let filter = CleanFilter {
+            exclude: vec!["branch-scratch*".to_string(), "branch-scrach".to_string()],
+            only: vec!["branch-*".to_string()],
+            include_noted: false,
+        };
+        assert!(filter.is_excluded("branch-scratch-2"));
+        assert!(!filter.is_excluded("branch-quiet-fox"));
+        assert!(filter.is_selected("branch-quiet-fox"));
+        assert!(!filter.is_selected("pr-5-fix"));
+        assert_eq!(
+            filter.unused_excludes(&["branch-scratch", "pr-5-fix"]),
+            vec!["branch-scrach"]
         );
+    }
+
+    #[test]
+    fn duplicate_branches_are_grouped_and_detached_heads_ignored() {
+        let worktree = |path: &str, branch: &str| WorktreeInfo {
+            path: PathBuf::from(path),
+            branch: branch.to_string(),
+            head: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        };
+        let worktrees = vec![
+            worktree("/wt/branch-foo", "darren/foo"),
+            worktree("/wt/pr-1-a", "(detached)"),
+            worktree("/wt/pr-2-b", "(detached)"),
+            worktree("/wt/pr-50-foo", "darren/foo"),
+            worktree("/wt/branch-bar", "darren/bar"),
+        ];
         assert_eq!(
-            normalized_iterm_session_id("ABC-123\n"),
-            Some("ABC-123".to_string())
+            find_duplicate_branches(&worktrees),
+            vec![("darren/foo", vec![Path::new("/wt/branch-foo"), Path::new("/wt/pr-50-foo")])]
         );
-        assert_eq!(normalized_iterm_session_id(""), None);
+        assert_eq!(worktrees[0].branch_label(), "darren/foo");
+        assert_eq!(worktrees[1].branch_label(), "0123456");
+        let tagged = WorktreeInfo { tag: Some("v1.2.0".to_string()), ..worktrees[1].clone() };
+        assert_eq!(tagged.branch_label(), "v1.2.0");
+        assert_eq!(tag_worktree_name("v1.2.0"), "tag-v1-2-0");
+        assert_eq!(tag_worktree_name("release/2024_10"), "tag-release-2024_10");
+    }
 
-        let first = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-a"));
-        let repeated = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-a"));
-        let other_repo = resource_iterm_session_file("pr", "42", Path::new("/tmp/repo-b"));
-        assert_eq!(first, repeated);
-        assert_ne!(first, other_repo);
+    #[test]
+    fn worktrees_sort_by_age_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("checkout-age-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let now = SystemTime::now();
+        let worktree = |name: &str, age_days: Option<u64>| {
+            let path = dir.join(name);
+            fs::create_dir_all(&path).unwrap();
+            if let Some(days) = age_days {
+                let git_file = path.join(".git");
+                fs::write(&git_file, "gitdir: /repo/.git/worktrees/x\n").unwrap();
+                let created = now - Duration::from_secs(days * 86_400);
+                fs::File::options().write(true).open(&git_file).unwrap().set_modified(created).unwrap();
+            }
+            WorktreeInfo {
+                path,
+                branch: "(detached)".to_string(),
+                head: String::new(),
+                tag: None,
+                has_changes: false,
+                unpushed_commits: Some(0),
+                has_active_session: false,
+                active_agent: None,
+                orphaned_pids: Vec::new(),
+                note: None,
+                last_used: None,
+                stack_parent: None,
+            }
+        };
+        let mut worktrees = Worktrees {
+            list: vec![worktree("a-new", Some(1)), worktree("b-unknown", None), worktree("c-old", Some(30))],
+        };
+        let names = |worktrees: &Worktrees| worktrees.iter().map(|wt| worktree_dir_name(&wt.path)).collect::<Vec<_>>();
 
-        let first_worktree = worktree_iterm_session_file(Path::new("/tmp/repo-a/worktree"));
-        let repeated_worktree = worktree_iterm_session_file(Path::new("/tmp/repo-a/worktree"));
-        let same_name_other_repo = worktree_iterm_session_file(Path::new("/tmp/repo-b/worktree"));
-        assert_eq!(first_worktree, repeated_worktree);
-        assert_ne!(first_worktree, same_name_other_repo);
+        worktrees.sort(WorktreeSort::Age);
+        assert_eq!(names(&worktrees), ["c-old", "a-new", "b-unknown"]);
+        worktrees.sort(WorktreeSort::Path);
+        assert_eq!(names(&worktrees), ["a-new", "b-unknown", "c-old"]);
+
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn iterm_api_client_sends_full_resource_context() {
-        let socket_path =
-            env::temp_dir().join(format!("checkout-iterm-api-{}.sock", std::process::id()));
-        let _ = fs::remove_file(&socket_path);
-        let listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
-        let server = thread::spawn(move || {
-            let (stream, _) = listener.accept().unwrap();
-            let mut reader = io::BufReader::new(stream);
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            let request: Value = serde_json::from_str(&line).unwrap();
-            assert_eq!(request["action"], "open");
-            assert_eq!(
-                request["sessionIds"],
-                serde_json::json!(["ABC-123", "DEF-456"])
-            );
-            assert_eq!(request["sessionName"], "work-dash");
-            assert_eq!(request["launchCommand"], "checkout workspace");
-            let mut stream = reader.into_inner();
-            stream
-                .write_all(b"{\"ok\":true,\"exists\":true,\"sessionId\":\"ABC-123\",\"action\":\"focused\"}\n")
-                .unwrap();
-        });
+    fn worktrees_query_by_branch_and_local_work() {
+        let worktree = |path: &str, branch: &str| WorktreeInfo {
+            path: PathBuf::from(path),
+            branch: branch.to_string(),
+            head: String::new(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        };
+        let worktrees = Worktrees {
+            list: vec![
+                worktree("/wt/branch-foo", "darren/foo"),
+                WorktreeInfo { has_changes: true, ..worktree("/wt/branch-bar", "darren/bar") },
+                WorktreeInfo { unpushed_commits: Some(1), ..worktree("/wt/branch-baz", "darren/baz") },
+                WorktreeInfo { has_changes: true, has_active_session: true, ..worktree("/wt/pr-1-a", "(detached)") },
+            ],
+        };
+        let paths = |list: Vec<&WorktreeInfo>| list.into_iter().map(|wt| wt.path.clone()).collect::<Vec<_>>();
 
-        let response = iterm_api_request_at(
-            &socket_path,
-            &serde_json::json!({
-                "action": "open",
-                "sessionIds": ["ABC-123", "DEF-456"],
-                "sessionName": "work-dash",
-                "launchCommand": "checkout workspace",
-            }),
-        )
-        .unwrap();
-        assert_eq!(response.session_id.as_deref(), Some("ABC-123"));
-        assert_eq!(response.action.as_deref(), Some("focused"));
-        server.join().unwrap();
-        fs::remove_file(socket_path).unwrap();
+        assert_eq!(worktrees.by_branch("darren/bar").map(|wt| wt.path.as_path()), Some(Path::new("/wt/branch-bar")));
+        assert!(worktrees.by_branch("darren/missing").is_none());
+        assert_eq!(paths(worktrees.clean().collect()), vec![PathBuf::from("/wt/branch-foo")]);
+        assert_eq!(
+            paths(worktrees.dirty().collect()),
+            vec![PathBuf::from("/wt/branch-bar"), PathBuf::from("/wt/branch-baz")]
+        );
+
+        let remaining = worktrees.without(&worktrees.list[..2]);
+        assert!(remaining.by_branch("darren/foo").is_none());
+        assert!(remaining.by_branch("darren/baz").is_some());
     }
 
     #[test]
-    fn iterm_api_client_reports_missing_helper() {
-        let socket_path = env::temp_dir().join(format!(
-            "checkout-missing-iterm-api-{}.sock",
-            std::process::id()
-        ));
-        let _ = fs::remove_file(&socket_path);
+    fn status_formats_print_paths_names_or_json() {
+        let mut active = WorktreeInfo {
+            path: PathBuf::from("/wt/pr-1-a"),
+            branch: "(detached)".to_string(),
+            head: "abc".to_string(),
+            tag: None,
+            has_changes: true,
+            unpushed_commits: Some(0),
+            has_active_session: true,
+            active_agent: Some(Agent::Claude),
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        };
+        let clean = WorktreeInfo {
+            path: PathBuf::from("/wt/branch-foo"),
+            branch: "darren/foo".to_string(),
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            ..active.clone()
+        };
+        let worktrees = vec![active.clone(), clean];
+        let pr_urls = HashMap::from([(active.path.clone(), "https://github.com/o/r/pull/1".to_string())]);
+
+        assert_eq!(format_status_plain(&worktrees, &pr_urls, StatusFormat::Path).as_deref(), Some("/wt/pr-1-a\n/wt/branch-foo\n"));
+        assert_eq!(format_status_plain(&worktrees, &pr_urls, StatusFormat::Name).as_deref(), Some("pr-1-a\nbranch-foo\n"));
+        assert_eq!(format_status_plain(&[], &HashMap::new(), StatusFormat::Path).as_deref(), Some(""));
+        assert_eq!(format_status_plain(&[], &HashMap::new(), StatusFormat::Json).as_deref(), Some("[]\n"));
+
+        let json: Value = serde_json::from_str(&format_status_plain(&worktrees, &pr_urls, StatusFormat::Json).unwrap()).unwrap();
+        assert_eq!(format_status_plain(&worktrees, &pr_urls, StatusFormat::Human), None);
+        assert_eq!(json[0]["branch"], Value::Null);
+        assert_eq!(json[0]["state"], "active");
+        assert_eq!(json[0]["activeAgent"], "claude");
+        assert_eq!(json[1]["name"], "branch-foo");
+        assert_eq!(json[1]["branch"], "darren/foo");
+        assert_eq!(json[1]["state"], "clean");
+        assert_eq!(json[1]["unpushedCommits"], 0);
+        assert_eq!(json[0]["prUrl"], "https://github.com/o/r/pull/1");
+        assert_eq!(json[1]["prUrl"], Value::Null);
+        assert_eq!(json[1]["activeAgent"], Value::Null);
+
+        active.has_active_session = false;
+        assert_eq!(active.state(), "modified");
+        active.has_changes = false;
+        active.orphaned_pids = vec![42];
+        assert_eq!(active.state(), "orphaned");
+        assert_eq!(local_work_summary(&active), None);
+
+        // Unpushed commits are local work too, so `clean` won't batch-remove them
+        active.unpushed_commits = Some(2);
+        assert_eq!(active.state(), "unpushed");
+        assert!(active.has_local_work());
+        assert_eq!(local_work_summary(&active).as_deref(), Some("2 unpushed commit(s)"));
+        active.has_changes = true;
+        assert_eq!(active.state(), "modified");
+        assert_eq!(local_work_summary(&active).as_deref(), Some("uncommitted changes and 2 unpushed commit(s)"));
+
+        // A count that failed blocks removal rather than passing as zero
+        active.has_changes = false;
+        active.unpushed_commits = None;
+        assert!(active.has_local_work());
+        assert_eq!(local_work_summary(&active).as_deref(), Some("couldn't check for unpushed commits"));
+    }
 
-        let error = iterm_api_request_at(
-            &socket_path,
-            &serde_json::json!({ "action": "status", "sessionIds": [] }),
-        )
-        .unwrap_err();
+    #[test]
+    fn delete_branch_skips_detached_default_and_checked_out_branches() {
+        let worktree = |path: &str, branch: &str| WorktreeInfo {
+            path: PathBuf::from(path),
+            branch: branch.to_string(),
+            head: String::new(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        };
+        let entry = |path: &str, branch: Option<&str>| git::WorktreeEntry {
+            path: PathBuf::from(path),
+            head: String::new(),
+            branch: branch.map(str::to_string),
+        };
+        let removed = vec![
+            worktree("/wt/branch-foo", "darren/foo"),
+            worktree("/wt/pr-1-a", "(detached)"),
+            worktree("/wt/branch-master", "master"),
+            worktree("/wt/branch-bar", "darren/bar"),
+            worktree("/wt/branch-baz", "darren/baz"),
+            worktree("/wt/branch-failed", "darren/failed"),
+        ];
+        let remaining = vec![
+            entry("/repo", Some("master")),
+            entry("/wt/branch-bar-2", Some("darren/bar")),
+            entry("/wt/branch-failed", Some("darren/failed")),
+        ];
+        assert_eq!(
+            branches_to_delete(&removed, &remaining, "master"),
+            vec!["darren/baz".to_string(), "darren/foo".to_string()]
+        );
+        assert!(Cli::try_parse_from(["checkout", "clean", "--force"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "clean", "--delete-branch", "--force"]).is_ok());
+    }
 
-        assert!(error.contains("iTerm Python API helper is unavailable"));
+    #[test]
+    fn pr_picker_filters_pass_through_to_gh() {
+        let Commands::Pr { pr, picker, .. } =
+            Cli::try_parse_from(["checkout", "pr", "--limit", "5", "--author", "@me"]).unwrap().command
+        else {
+            panic!("expected pr");
+        };
+        assert!(pr.is_empty());
+        assert_eq!(
+            pr_list_args(&picker),
+            ["pr", "list", "--json", "number,title,headRefName,author", "--limit", "5", "--author", "@me"]
+        );
+        assert!(Cli::try_parse_from(["checkout", "pr", "123", "--assignee", "@me"]).is_err());
+
+        let prs: Vec<PrSummary> = serde_json::from_str(
+            r#"[{"number":7,"title":"Fix","headRefName":"darren/fix","author":{"login":"darren","is_bot":false}}]"#,
+        )
+        .unwrap();
+        assert_eq!((prs[0].number, prs[0].author.login.as_str()), (7, "darren"));
     }
 
     #[test]
-    fn claude_and_legacy_flags_remain_supported() {
-        let cli = Cli::try_parse_from([
-            "checkout",
-            "new",
-            "--agent",
-            "claude",
-            "--no-claude",
-            "--claude-prompt",
-            "/tmp/prompt.md",
-        ])
+    fn merged_and_closed_prs_need_confirmation() {
+        assert_eq!(inactive_pr_state("MERGED"), Some("merged"));
+        assert_eq!(inactive_pr_state("CLOSED"), Some("closed"));
+        assert_eq!(inactive_pr_state("OPEN"), None);
+        assert_eq!(inactive_pr_state(""), None);
+        assert!(confirm_inactive_pr(5, "MERGED", true).is_ok());
+
+        let details: PrDetails = serde_json::from_str(r#"{"headRefName":"a","title":"b"}"#).unwrap();
+        assert_eq!(details.state, "");
+        assert!(details.author.is_none());
+        assert_eq!(details.base_ref_name, "");
+
+        let details: PrDetails = serde_json::from_str(
+            r#"{"headRefName":"a","title":"b","state":"OPEN","author":{"login":"darren"},"baseRefName":"main"}"#,
+        )
         .unwrap();
-        assert_eq!(cli.agent, Agent::Claude);
-        assert!(matches!(
-            cli.command,
-            Commands::New {
-                no_agent: true,
-                prompt: Some(_),
-                ..
-            }
-        ));
+        assert_eq!(details.author.map(|a| a.login).as_deref(), Some("darren"));
+        assert_eq!(details.base_ref_name, "main");
     }
 
     #[test]
-    fn translates_claude_skill_syntax_for_codex() {
-        assert_eq!(normalize_skill(Agent::Codex, "/walkthrough"), "$walkthrough");
+    fn pr_details_fields_match_the_gh_query_and_tolerate_extras() {
         assert_eq!(
-            normalize_skill(Agent::Codex, "/checkout:checkout-pr"),
-            "$checkout-pr"
+            PrDetails::JSON_FIELDS,
+            [
+                "headRefName",
+                "title",
+                "state",
+                "author",
+                "baseRefName",
+                "headRefOid",
+                "isCrossRepository",
+                "headRepositoryOwner"
+            ]
         );
-        assert_eq!(normalize_skill(Agent::Claude, "/walkthrough"), "/walkthrough");
+
+        // A response with every requested field, plus ones a newer query might add
+        let mut response = serde_json::Map::new();
+        for field in PrDetails::JSON_FIELDS {
+            let value = match *field {
+                "author" => serde_json::json!({ "login": "darren", "is_bot": false }),
+                "headRepositoryOwner" => serde_json::json!({ "id": "U_1", "login": "contributor" }),
+                "isCrossRepository" => Value::Bool(true),
+                _ => Value::String(format!("{}-value", field)),
+            };
+            response.insert(field.to_string(), value);
+        }
+        response.insert("files".to_string(), serde_json::json!([{ "path": "a.rs" }]));
+        response.insert("isDraft".to_string(), Value::Bool(true));
+        let details: PrDetails = serde_json::from_value(Value::Object(response)).unwrap();
+        assert_eq!(details.head_ref_name, "headRefName-value");
+        assert_eq!(details.base_ref_name, "baseRefName-value");
+        assert_eq!(details.head_ref_oid, "headRefOid-value");
+        assert_eq!(details.head_repository_owner.as_ref().map(|owner| owner.login.as_str()), Some("contributor"));
+        assert_eq!(details.origin_branch(), None);
     }
 
     #[test]
-    fn derives_short_session_names_from_branch_leaves() {
-        assert_eq!(session_name_from_branch("darren/fix_PR-title"), "fix-pr-title");
+    fn pr_and_branch_checkouts_build_plans_for_the_shared_pipeline() {
+        let details: PrDetails =
+            serde_json::from_str(r#"{"headRefName":"darren/fix","title":"Fix login bug"}"#).unwrap();
+        let plan = pr_checkout_plan(PathBuf::from("/repo"), 42, &details, None, "/checkout:checkout-pr", None);
+        assert_eq!(plan.worktree_name, "pr-42-fix-login-bug");
         assert_eq!(
-            session_name_from_branch("dependabot/npm_and_yarn/react-19"),
-            "react-19"
+            plan.source,
+            Source::RemoteBranch { branch: "darren/fix".to_string(), pr_number: 42, head_oid: None, fork: false }
         );
+        assert_eq!(plan.source.pr_number(), Some(42));
+        assert_eq!(plan.title.as_deref(), Some("Fix login bug"));
+        assert_eq!(plan.color_key, Some(ColorKey::Pr(42)));
+        assert_eq!(plan.prompt, AgentPrompt::Skill("/checkout:checkout-pr 42".to_string()));
+
+        let chained = pr_checkout_plan(PathBuf::from("/repo"), 42, &details, None, "/a:pr", Some("/a:review"));
         assert_eq!(
-            session_name_from_branch("refs/heads/darren/a-very-long-branch-name-that-keeps-going"),
-            "a-very-long-branch-name"
+            chained.prompt,
+            AgentPrompt::Skill("/a:pr 42\n\nAfter completing the above, run: /a:review".to_string())
         );
-        assert_eq!(session_name_from_branch("---"), "worktree");
+
+        let plan = branch_checkout_plan(PathBuf::from("/repo"), "darren/feature", Some("main"), false, None, None);
+        assert_eq!(plan.worktree_name, "branch-feature");
+        assert_eq!(
+            plan.source,
+            Source::NewBranch { branch: "darren/feature".to_string(), base: Some("main".to_string()) }
+        );
+        assert_eq!(plan.source.branch(), "darren/feature");
+        assert_eq!(plan.source.pr_number(), None);
+        assert_eq!(plan.title, None);
+        assert_eq!(plan.color_key, Some(ColorKey::Branch("darren/feature".to_string())));
+        assert_eq!(plan.prompt, AgentPrompt::None);
+
+        let random = format!("darren/{}-{}", ADJECTIVES[0], NOUNS[0]);
+        let plan = branch_checkout_plan(PathBuf::from("/repo"), &random, None, false, None, Some("go".to_string()));
+        assert_eq!(plan.color_key, None);
+        assert_eq!(plan.prompt, AgentPrompt::Text("go".to_string()));
+
+        let plan = branch_checkout_plan(PathBuf::from("/repo"), "release/1.2.0", None, true, None, None);
+        assert_eq!(plan.worktree_name, "branch-release-1.2.0");
+        assert_eq!(plan.source.branch(), "release/1.2.0");
+        assert_eq!(plan.color_key, Some(ColorKey::Branch("release/1.2.0".to_string())));
     }
 
     #[test]
-    fn exited_markers_are_agent_aware_and_backward_compatible() {
-        let current = parse_exited_session("123\n/tmp/branch-a\nclean\ncodex").unwrap();
-        assert_eq!(current.agent, Agent::Codex);
-        assert!(current.clean);
+    fn best_effort_steps_warn_unless_strict() {
+        assert_eq!(tolerate_step_failure("mise trust", Ok(()), false), Ok(None));
+        assert_eq!(tolerate_step_failure("mise trust", Ok(()), true), Ok(None));
+        assert_eq!(
+            tolerate_step_failure("Graphite tracking", Err("gt track failed".to_string()), false),
+            Ok(Some("Graphite tracking failed: gt track failed".to_string()))
+        );
+        assert_eq!(
+            tolerate_step_failure("Graphite tracking", Err("gt track failed".to_string()), true),
+            Err("Graphite tracking failed: gt track failed".to_string())
+        );
 
-        let legacy = parse_exited_session("456\n/tmp/branch-b\ndirty").unwrap();
-        assert_eq!(legacy.agent, Agent::Claude);
-        assert!(!legacy.clean);
+        // Without --strict the checkout carries on and the banner counts it
+        assert_eq!(best_effort("Adding Claude trust", Err("HOME not set".to_string()), false), Ok(()));
+        assert!(SETUP_WARNINGS.with_borrow(|warnings| warnings.contains(&"Adding Claude trust failed: HOME not set".to_string())));
     }
 
     #[test]
-    fn parses_codex_messages_without_injected_context() {
-        let path = env::temp_dir().join(format!(
-            "checkout-codex-session-parser-{}.jsonl",
-            std::process::id()
-        ));
-        let records = [
-            serde_json::json!({
-                "type": "response_item",
-                "payload": {
-                    "type": "message",
-                    "role": "developer",
-                    "content": [{"type": "input_text", "text": "hidden"}]
-                }
-            }),
-            serde_json::json!({
-                "type": "response_item",
-                "payload": {
-                    "type": "message",
-                    "role": "user",
-                    "content": [{"type": "input_text", "text": "<environment_context>hidden</environment_context>"}]
-                }
-            }),
-            serde_json::json!({
-                "type": "response_item",
-                "payload": {
-                    "type": "message",
-                    "role": "user",
-                    "content": [{"type": "input_text", "text": "Implement the change"}]
-                }
-            }),
-            serde_json::json!({
-                "type": "response_item",
-                "payload": {
-                    "type": "message",
-                    "role": "assistant",
-                    "content": [{"type": "output_text", "text": "Done"}]
-                }
-            }),
-        ];
+    fn no_checkout_registers_an_empty_worktree() {
+        let cli = Cli::try_parse_from(["checkout", "new", "--no-checkout"]).unwrap();
+        assert!(matches!(cli.command, Commands::New { checkout: CheckoutArgs { no_checkout: true, .. }, .. }));
+
+        let (dir, work) = scratch_repo("no-checkout");
+        fs::write(work.join("a.txt"), "1\n").unwrap();
+        run_git(&work, &["add", "a.txt"]);
+        run_git(&work, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "-m", "one"]);
+        let (repo_str, wt) = (work.to_string_lossy().to_string(), dir.join("branch-sparse"));
+        let wt_str = wt.to_string_lossy().to_string();
+        let args = worktree_add_args(&repo_str, &["-b", "sparse", &wt_str, "HEAD"], true);
+        assert_eq!(args[..5], ["-C", repo_str.as_str(), "worktree", "add", "--no-checkout"]);
+        run_git(&work, &args[2..]);
+        assert!(git::list_worktrees(&work).unwrap().iter().any(|entry| entry.path == wt));
+        assert!(!wt.join("a.txt").exists());
+        // What print_no_checkout_note suggests brings the files in
+        run_git(&wt, &["reset", "-q", "--hard"]);
+        assert!(wt.join("a.txt").exists());
+        assert_eq!(worktree_add_args(&repo_str, &["--detach", &wt_str], false)[4], "--detach");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn opening_a_worktree_counts_as_using_it() {
+        let path = std::env::temp_dir().join(format!("checkout-touch-{}/pr-3-fix", std::process::id()));
+        touch_last_used(&path);
+        let used = metadata::load(&get_data_dir()).last_used.remove(&note_key(&path)).unwrap();
+        let wt = WorktreeInfo {
+            path: path.clone(),
+            branch: "(detached)".to_string(),
+            head: String::new(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(used)),
+            stack_parent: None,
+        };
+        assert!(wt.last_used_label().contains("last used"));
+        assert_eq!(WorktreeInfo { last_used: None, ..wt }.last_used_label(), "");
+        remove_worktree_note(&path);
+    }
+
+    #[test]
+    fn base_compare_uses_the_prs_base_branch() {
+        let (dir, work) = scratch_repo("base-compare");
+        assert_eq!(base_compare_branch(&work, "release/1.2"), "release/1.2");
+        // origin/HEAD isn't set in a scratch repo
+        assert_eq!(base_compare_branch(&work, ""), "master");
+        run_git(&work, &["push", "-q", "origin", "HEAD:main"]);
+        run_git(&work, &["fetch", "-q", "origin"]);
+        run_git(&work, &["remote", "set-head", "origin", "main"]);
+        assert_eq!(base_compare_branch(&work, ""), "main");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_checkout_with_failed_setup_steps_still_succeeds() {
+        let (dir, work) = scratch_repo("setup-warnings");
+        run_git(&work, &["push", "-q", "origin", "HEAD:master"]);
+        let plan = CheckoutPlan {
+            repo_root: work.clone(),
+            worktree_name: "branch-probe".to_string(),
+            source: Source::NewBranch { branch: "probe".to_string(), base: None },
+            existing: None,
+            title: None,
+            color_key: None,
+            prompt: AgentPrompt::None,
+        };
+        // The scratch repo isn't set up for Graphite, so tracking fails
+        let options = CheckoutOptions { no_agent: true, worktree_in_repo: true, ..CheckoutOptions::default() };
+        assert_eq!(execute_checkout(plan, Agent::Codex, options), Ok(dir.join("branch-probe")));
+        assert_eq!(run_git(&dir.join("branch-probe"), &["branch", "--show-current"]), "probe");
+        let warnings = SETUP_WARNINGS.with_borrow(Vec::clone);
+        assert!(warnings.iter().any(|warning| warning.starts_with("Graphite tracking failed")), "{:?}", warnings);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn worktree_in_repo_places_worktrees_beside_the_repo() {
+        assert_eq!(sibling_worktree_dir(Path::new("/Users/d/figma")), PathBuf::from("/Users/d"));
+        assert_eq!(sibling_worktree_dir(Path::new("/")), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn on_enter_script_runs_in_worktree_with_checkout_env() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("checkout-enter-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("enter.sh");
         fs::write(
-            &path,
-            records
-                .into_iter()
-                .map(|record| record.to_string())
-                .collect::<Vec<_>>()
-                .join("\n"),
+            &script,
+            "#!/bin/sh\necho \"$(pwd) $CHECKOUT_WORKTREE $CHECKOUT_COLOR $CHECKOUT_SESSION_NAME\" > out\n",
         )
         .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
 
-        let messages = parse_codex_session_messages(&path, 50);
-        let _ = fs::remove_file(path);
+        let dir = dir.canonicalize().unwrap();
+        run_enter_script(&script, &dir, "1e2233", "fix-login").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("out")).unwrap().trim(),
+            format!("{0} {0} 1e2233 fix-login", dir.display())
+        );
 
-        assert_eq!(messages.len(), 2);
-        assert_eq!(messages[0].role, "user");
-        assert_eq!(messages[0].content, "Implement the change");
-        assert_eq!(messages[1].role, "assistant");
-        assert_eq!(messages[1].content, "Done");
+        fs::write(&script, "#!/bin/sh\nexit 3\n").unwrap();
+        assert!(run_enter_script(&script, &dir, "1e2233", "x").unwrap_err().contains("exited"));
+        assert!(run_enter_script(&dir.join("missing"), &dir, "1e2233", "x").is_err());
+
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn reads_codex_cli_session_identity() {
-        let path = env::temp_dir().join(format!(
-            "checkout-codex-session-meta-{}.jsonl",
-            std::process::id()
-        ));
+    fn claude_settings_link_keeps_a_newer_worktree_copy() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = std::env::temp_dir().join(format!("checkout-settings-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("settings.local.json");
+        fs::write(&source, r#"{"permissions":{"allow":["Bash"]}}"#).unwrap();
+        let set_modified = |path: &Path, time: SystemTime| {
+            fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap()
+        };
+        let now = SystemTime::now();
+        set_modified(&source, now);
+
+        let dest = dir.join("wt/.claude/settings.local.json");
+        assert_eq!(link_claude_settings(&source, &dest), Ok(SettingsChange::Linked(source.clone())));
+        assert_eq!(fs::read_link(&dest).unwrap(), source);
+        assert_eq!(link_claude_settings(&source, &dest), Ok(SettingsChange::Unchanged(source.clone())));
+
+        // A copy edited in the worktree after the repo's file changed wins
+        let local = r#"{"permissions":{"allow":["Bash","Edit"]}}"#;
+        fs::remove_file(&dest).unwrap();
+        fs::write(&dest, local).unwrap();
+        set_modified(&dest, now + Duration::from_secs(60));
+        assert_eq!(link_claude_settings(&source, &dest), Ok(SettingsChange::KeptNewer(source.clone())));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), local);
+
+        // An older one is replaced by the link
+        set_modified(&dest, now - Duration::from_secs(60));
+        assert_eq!(link_claude_settings(&source, &dest), Ok(SettingsChange::Relinked(source.clone())));
+        assert_eq!(fs::read_link(&dest).unwrap(), source);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn claude_trust_only_accepts_the_dialog_on_existing_entries() {
+        let dir = std::env::temp_dir().join(format!("checkout-trust-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let claude_json = dir.join(".claude.json");
         fs::write(
-            &path,
-            serde_json::json!({
-                "type": "session_meta",
-                "payload": {
-                    "id": "019f4918-33a8-7542-96cf-cfcfb2886c75",
-                    "cwd": "/tmp/branch-a",
-                    "source": "cli"
+            &claude_json,
+            r#"{
+                "numStartups": 12,
+                "projects": {
+                    "/repo": {"allowedTools": ["Bash"], "hasTrustDialogAccepted": true, "lastCost": 1.5},
+                    "/wt/pr-1": {"allowedTools": ["Edit"], "mcpServers": {"local": {}}, "hasTrustDialogAccepted": false}
                 }
-            })
-            .to_string(),
+            }"#,
         )
         .unwrap();
+        let read = || serde_json::from_str::<Value>(&fs::read_to_string(&claude_json).unwrap()).unwrap();
+        let (repo, existing, fresh) = (Path::new("/repo"), Path::new("/wt/pr-1"), Path::new("/wt/pr-2"));
 
-        let metadata = codex_session_metadata(&path);
-        let _ = fs::remove_file(path);
+        assert_eq!(trust_claude_project(&claude_json, existing, repo), Ok(TrustChange::Accepted));
+        let data = read();
+        assert_eq!(
+            data["projects"]["/wt/pr-1"],
+            serde_json::json!({"allowedTools": ["Edit"], "mcpServers": {"local": {}}, "hasTrustDialogAccepted": true})
+        );
+        assert_eq!(data["numStartups"], 12);
 
+        let before = fs::read_to_string(&claude_json).unwrap();
+        assert_eq!(trust_claude_project(&claude_json, existing, repo), Ok(TrustChange::Unchanged));
+        assert_eq!(fs::read_to_string(&claude_json).unwrap(), before);
+
+        // New entries start from the main repo's, minus session stats
+        assert_eq!(trust_claude_project(&claude_json, fresh, repo), Ok(TrustChange::Added));
         assert_eq!(
-            metadata,
-            Some((
-                PathBuf::from("/tmp/branch-a"),
-                "019f4918-33a8-7542-96cf-cfcfb2886c75".to_string()
-            ))
+            read()["projects"]["/wt/pr-2"],
+            serde_json::json!({"allowedTools": ["Bash"], "hasTrustDialogAccepted": true})
         );
+
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn builds_codex_launch_and_resume_arguments() {
+    fn env_args_parse_as_key_value_pairs() {
+        let pair = |k: &str, v: &str| Ok((k.to_string(), v.to_string()));
+        assert_eq!(parse_env_var("ANTHROPIC_MODEL=opus"), pair("ANTHROPIC_MODEL", "opus"));
+        assert_eq!(parse_env_var("EMPTY="), pair("EMPTY", ""));
+        assert_eq!(parse_env_var("_X1=a=b"), pair("_X1", "a=b"));
+
+        assert!(parse_env_var("NOVALUE").unwrap_err().contains("expected KEY=VAL"));
+        assert!(parse_env_var("=value").unwrap_err().contains("not a valid variable name"));
+        assert!(parse_env_var("1X=y").unwrap_err().contains("not a valid variable name"));
+        assert!(parse_env_var("MY-VAR=y").unwrap_err().contains("not a valid variable name"));
+    }
+
+    #[test]
+    fn pr_references_parse_from_numbers_fragments_and_urls() {
+        let accepted = [
+            ("123", 123),
+            (" 123 ", 123),
+            ("#4821", 4821),
+            ("pull/4821", 4821),
+            ("/pull/4821", 4821),
+            ("pull/4821/", 4821),
+            ("https://github.com/figma/figma/pull/4821", 4821),
+            ("https://github.com/figma/figma/pull/4821/files", 4821),
+            ("https://github.com/figma/figma/pull/4821#discussion_r1", 4821),
+            ("https://github.com/figma/figma/pull/4821?w=1", 4821),
+            ("github.com/figma/figma/pull/7", 7),
+            ("2147483647", 2147483647),
+        ];
+        for (input, expected) in accepted {
+            assert_eq!(extract_pr_number(input), Ok(expected), "{:?}", input);
+        }
+
+        let rejected = [
+            ("", "Expected one of"),
+            ("abc", "Expected one of"),
+            ("##12", "Expected one of"),
+            ("#", "Expected one of"),
+            ("-5", "Expected one of"),
+            ("12a", "Expected one of"),
+            ("pull/", "Expected one of"),
+            ("pulls/12", "Expected one of"),
+            ("https://github.com/figma/figma/pull/12abc", "Expected one of"),
+            ("https://github.com/figma/figma/issues/12", "Expected one of"),
+            ("0", "start at 1"),
+            ("#000", "start at 1"),
+            ("pull/0", "start at 1"),
+            ("#0123", "leading zeros"),
+            ("pull/007", "leading zeros"),
+            ("2147483648", "out of range"),
+            ("99999999999999999999999", "out of range"),
+        ];
+        for (input, expected) in rejected {
+            let err = extract_pr_number(input).unwrap_err();
+            assert!(err.contains(expected), "{:?}: {}", input, err);
+        }
+
+        assert_eq!(extract_pr_number_up_to("#99999", 99999), Ok(99999));
+        assert!(extract_pr_number_up_to("#481234", 99999).unwrap_err().contains("above max_pr_number (99999)"));
+        assert!(extract_pr_number_up_to("2147483648", 99999).unwrap_err().contains("out of range"));
+
+        let enterprise = parse_pr_ref("https://github.MyCorp.com/figma/figma/pull/4821/files").unwrap();
+        assert_eq!(enterprise, PrRef { number: 4821, host: Some("github.mycorp.com".to_string()) });
+        assert_eq!(parse_pr_ref("github.mycorp.com:8443/o/r/pull/9").unwrap().host.as_deref(), Some("github.mycorp.com:8443"));
+        assert_eq!(parse_pr_ref("https://github.com/figma/figma/pull/4821").unwrap().host, None);
+        assert_eq!(parse_pr_ref("https://www.github.com/figma/figma/pull/1").unwrap().host, None);
+        assert_eq!(parse_pr_ref("pull/4821").unwrap().host, None);
+        assert_eq!(parse_pr_ref("#4821").unwrap(), PrRef { number: 4821, host: None });
+    }
+
+    #[test]
+    fn usage_report_matches_worktrees_and_tolerates_missing_fields() {
+        let claude_json = serde_json::json!({
+            "projects": {
+                "/wt/pr-1-a": {
+                    "lastCost": 1.5,
+                    "lastDuration": 842_000,
+                    "lastLinesAdded": 120,
+                    "lastLinesRemoved": 30,
+                    "lastModelUsage": { "claude-sonnet": {} },
+                    "somethingNew": [1, 2],
+                },
+                "/wt/branch-foo": { "lastCost": "not a number", "lastDuration": 5_000 },
+                "/wt/branch-unused": { "hasTrustDialogAccepted": true },
+                "/wt/pr-9-gone": { "lastCost": 0.25 },
+                "/elsewhere/project": { "lastCost": 3.0 },
+            },
+        });
+        let worktrees = [PathBuf::from("/wt/pr-1-a"), PathBuf::from("/wt/branch-foo"), PathBuf::from("/wt/branch-unused")];
+        let report = claude_usage_report(&claude_json, &worktrees, &[PathBuf::from("/wt")]);
+
         assert_eq!(
-            build_agent_args(
-                Agent::Codex,
-                Some("do the thing"),
-                Some("protect the worktree"),
-                false,
-                None,
-            )
-            .unwrap(),
+            report.worktrees,
             vec![
-                "--config",
-                "developer_instructions=\"protect the worktree\"",
-                "do the thing",
+                ("branch-foo".to_string(), ClaudeUsage { duration_ms: Some(5_000), ..ClaudeUsage::default() }),
+                (
+                    "pr-1-a".to_string(),
+                    ClaudeUsage {
+                        cost: Some(1.5),
+                        duration_ms: Some(842_000),
+                        lines_added: Some(120),
+                        lines_removed: Some(30),
+                        models: vec!["claude-sonnet".to_string()],
+                    }
+                ),
             ]
         );
         assert_eq!(
-            build_agent_args(
-                Agent::Codex,
-                Some("refresh the rollout dossier"),
-                Some("protect the worktree"),
-                true,
-                Some("019f4918-33a8-7542-96cf-cfcfb2886c75"),
-            )
-            .unwrap(),
-            vec![
-                "--config",
-                "developer_instructions=\"protect the worktree\"",
-                "resume",
-                "019f4918-33a8-7542-96cf-cfcfb2886c75",
-                "refresh the rollout dossier",
-            ]
+            report.reclaimable,
+            vec![("/wt/pr-9-gone".to_string(), Some(ClaudeUsage { cost: Some(0.25), ..ClaudeUsage::default() }))]
         );
+        assert!(claude_usage_report(&serde_json::json!({}), &worktrees, &[]).worktrees.is_empty());
+
+        let mut total = ClaudeUsage::default();
+        report.worktrees.iter().for_each(|(_, usage)| total.add(usage));
+        assert_eq!((total.cost, total.duration_ms, total.lines_added), (Some(1.5), Some(847_000), Some(120)));
+
+        assert_eq!(format_duration_ms(42_900), "42s");
+        assert_eq!(format_duration_ms(842_000), "14m 2s");
+        assert_eq!(format_duration_ms(3_900_000), "1h 5m");
     }
 
     #[test]
-    fn preserves_claude_launch_arguments() {
+    fn only_url_pr_inputs_link_to_the_pr_page() {
         assert_eq!(
-            build_agent_args(
-                Agent::Claude,
-                None,
-                Some("protect the worktree"),
-                true,
-                None,
-            )
-            .unwrap(),
-            vec![
-                "--permission-mode",
-                "acceptEdits",
-                "--continue",
-                "--append-system-prompt",
-                "protect the worktree",
-            ]
+            pr_input_url("https://github.com/figma/figma/pull/4821/files").as_deref(),
+            Some("https://github.com/figma/figma/pull/4821/files")
         );
+        assert_eq!(pr_input_url(" github.com/o/r/pull/7 ").as_deref(), Some("https://github.com/o/r/pull/7"));
+        assert_eq!(pr_input_url("4821"), None);
+        assert_eq!(pr_input_url("#4821"), None);
+        assert_eq!(pr_input_url("pull/4821"), None);
+
+        let url = "https://github.com/o/r/pull/7";
+        assert_eq!(pr_input_to_keep(Some(url), "7"), url);
+        assert_eq!(pr_input_to_keep(Some(url), "https://ghe.example.com/o/r/pull/7"), "https://ghe.example.com/o/r/pull/7");
+        assert_eq!(pr_input_to_keep(Some("7"), " #7 "), "#7");
+        assert_eq!(pr_input_to_keep(None, "7"), "7");
     }
 
     #[test]
-    fn resume_target_uses_the_most_recent_sessions_agent() {
-        let older_codex = ResumeTarget {
-            last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(10),
-            agent: Agent::Codex,
-            resume_id: Some("codex-session".to_string()),
+    fn shell_init_defines_a_function_that_cds_to_the_written_path() {
+        let script = shell_init_script("cw").unwrap();
+        assert!(script.starts_with("cw() {"));
+        assert!(script.contains("command checkout --write-path \"$path_file\" \"$@\""));
+        assert!(shell_init_script("cw; rm -rf /").is_err());
+        assert!(shell_init_script("").is_err());
+
+        let dir = std::env::temp_dir().join(format!("checkout-cd-{}", std::process::id())).join("it's here");
+        fs::create_dir_all(&dir).unwrap();
+
+        // Run the function against a stub `checkout` in every shell that's
+        // installed: zsh rejects assigning to its read-only `status` only at
+        // run time, which `-n` alone doesn't catch
+        let bin = dir.parent().unwrap().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let stub = bin.join("checkout");
+        let write = format!("[ \"$1\" = --write-path ] && echo {} > \"$2\"", shell_quote(&dir.to_string_lossy()));
+        fs::write(&stub, format!("#!/bin/sh\n{}\nexit 3\n", write)).unwrap();
+        fs::set_permissions(&stub, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let path = format!("{}:{}", bin.display(), env::var("PATH").unwrap_or_default());
+        for shell in ["sh", "bash", "zsh"] {
+            let Ok(check) = Command::new(shell).args(["-n", "-c", &script]).output() else {
+                continue;
+            };
+            assert!(check.status.success(), "{} -n: {}", shell, String::from_utf8_lossy(&check.stderr));
+            let output = Command::new(shell)
+                .args(["-c", &format!("{}cw; echo \"$?\"; pwd", script)])
+                .env("PATH", &path)
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert_eq!(stdout.lines().next(), Some("3"), "{}: {}", shell, String::from_utf8_lossy(&output.stderr));
+            assert_eq!(stdout.lines().nth(1).map(PathBuf::from), Some(dir.canonicalize().unwrap()), "{}", shell);
+        }
+        let _ = fs::remove_dir_all(dir.parent().unwrap());
+    }
+
+    #[test]
+    fn stale_upstream_flags_branches_tracking_another_origin_branch() {
+        let tracking = |remote: &str, branch: &str| git::Upstream { remote: remote.to_string(), branch: branch.to_string() };
+        let renamed = tracking("origin", "old-name");
+        assert_eq!(
+            stale_upstream(Some("old-name"), Some(&renamed), "new-name"),
+            Some(StaleUpstream { local: "old-name".to_string(), tracked: "old-name".to_string() })
+        );
+        assert_eq!(stale_upstream(Some("new-name"), Some(&renamed), "new-name").unwrap().tracked, "old-name");
+
+        assert_eq!(stale_upstream(Some("new-name"), Some(&tracking("origin", "new-name")), "new-name"), None);
+        assert_eq!(stale_upstream(Some("old-name"), Some(&tracking("fork", "old-name")), "new-name"), None);
+        assert_eq!(stale_upstream(Some("old-name"), None, "new-name"), None);
+        assert_eq!(stale_upstream(None, Some(&renamed), "new-name"), None);
+    }
+
+    /// Run git in `cwd` for a test, failing it if git does; returns the
+    /// trimmed stdout
+    #[test]
+    fn rename_branch_renames_the_checked_out_branch_and_its_push() {
+        let (dir, work) = scratch_repo("rename-branch");
+        let wt = dir.join("wt");
+        run_git(&work, &["worktree", "add", "-q", "-b", "darren/old", "../wt"]);
+        run_git(&wt, &["push", "-q", "-u", "origin", "darren/old"]);
+
+        let renamed = rename_worktree_branch(&wt, "darren/new").unwrap();
+        assert_eq!(renamed, RenamedBranch { old: "darren/old".to_string(), pushed: true, kept_upstream: None });
+        assert_eq!(run_git(&wt, &["symbolic-ref", "--short", "HEAD"]), "darren/new");
+        assert_eq!(run_git(&wt, &["rev-parse", "--abbrev-ref", "@{u}"]), "origin/darren/new");
+        let remote_branches = run_git(&dir.join("remote.git"), &["branch", "--format=%(refname:short)"]);
+        assert_eq!(remote_branches.lines().collect::<Vec<_>>(), ["darren/new", "darren/old"]);
+
+        // Unpushed branches are just renamed; taken names and detached HEADs fail
+        run_git(&wt, &["branch", "-q", "--unset-upstream"]);
+        let renamed = rename_worktree_branch(&wt, "darren/newer").unwrap();
+        assert!(!renamed.pushed && renamed.kept_upstream.is_none());
+        assert!(rename_worktree_branch(&wt, "main").unwrap_err().contains("already exists"));
+        run_git(&wt, &["checkout", "-q", "--detach"]);
+        assert!(rename_worktree_branch(&wt, "darren/x").unwrap_err().contains("detached HEAD"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reattach_puts_a_detached_worktree_back_on_its_pr_branch() {
+        let (dir, work) = scratch_repo("reattach");
+        let wt = dir.join("wt");
+        run_git(&work, &["push", "-q", "origin", "main:darren/fix"]);
+        run_git(&work, &["fetch", "-q", "origin"]);
+        run_git(&work, &["worktree", "add", "-q", "--detach", "../wt", "origin/darren/fix"]);
+        // Local work made while detached must survive
+        run_git(&wt, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "wip"]);
+        let head = run_git(&wt, &["rev-parse", "HEAD"]);
+
+        assert_eq!(reattach_pr_branch(&wt, "darren/fix").unwrap(), Reattached::Created);
+        assert_eq!(run_git(&wt, &["symbolic-ref", "--short", "HEAD"]), "darren/fix");
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), head);
+        assert_eq!(run_git(&wt, &["rev-parse", "--abbrev-ref", "@{u}"]), "origin/darren/fix");
+
+        // An existing branch at HEAD is reused; one elsewhere is left alone
+        run_git(&wt, &["checkout", "-q", "--detach"]);
+        assert_eq!(reattach_pr_branch(&wt, "darren/fix").unwrap(), Reattached::Switched);
+        run_git(&wt, &["checkout", "-q", "--detach", "HEAD~1"]);
+        assert!(reattach_pr_branch(&wt, "darren/fix").unwrap_err().contains("another commit"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pull_refs_are_fetched_into_a_per_pr_ref() {
+        let (dir, work) = scratch_repo("pull-ref");
+        run_git(&work, &["push", "-q", "origin", "HEAD:refs/pull/7/head"]);
+        let head = run_git(&work, &["rev-parse", "HEAD"]);
+
+        fetch_pull_ref(&work, 7, None, false).unwrap();
+        assert_eq!(run_git(&work, &["rev-parse", "refs/checkout/pr-7"]), head);
+
+        // A force-pushed head replaces the fetched one
+        run_git(&work, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--amend", "--allow-empty", "-m", "redo"]);
+        run_git(&work, &["push", "-q", "-f", "origin", "HEAD:refs/pull/7/head"]);
+        let amended = run_git(&work, &["rev-parse", "HEAD"]);
+        fetch_pull_ref(&work, 7, Some(&amended), false).unwrap();
+        assert_eq!(run_git(&work, &["rev-parse", "refs/checkout/pr-7"]), amended);
+
+        // A fork PR's worktree is refreshed from the pull ref, not origin/<branch>
+        let wt = dir.join("pr-7-fork");
+        let other = dir.join("pr-7-fork-2");
+        run_git(&work, &["worktree", "add", "-q", "--detach", &wt.to_string_lossy(), "refs/checkout/pr-7"]);
+        run_git(&work, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "more"]);
+        run_git(&work, &["push", "-q", "origin", "HEAD:refs/pull/7/head"]);
+        let source = Source::RemoteBranch { branch: "contributor/fix".to_string(), pr_number: 7, head_oid: None, fork: true };
+        assert_eq!(source.upstream(), Some(Upstream::PullRef(7)));
+        update_worktree(&wt, source.upstream().unwrap(), LocalCommits::Refuse, false).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), run_git(&work, &["rev-parse", "HEAD"]));
+
+        // The ref stays while another worktree of the PR still uses it
+        run_git(&work, &["worktree", "add", "-q", "--detach", &other.to_string_lossy(), "refs/checkout/pr-7"]);
+        // Two matches and nobody to ask (JSON output, or no terminal) is an error, not a prompt
+        let err = find_pr_worktree(&work, 7, None, false).unwrap_err();
+        assert_eq!(err, "Several worktrees match: pr-7-fork, pr-7-fork-2");
+        run_git(&work, &["worktree", "remove", &wt.to_string_lossy()]);
+        remove_pr_fetch_ref(&work, &wt);
+        assert!(git::ref_exists(&work, "refs/checkout/pr-7").unwrap());
+        run_git(&work, &["worktree", "remove", &other.to_string_lossy()]);
+        remove_pr_fetch_ref(&work, &other);
+        assert!(!git::ref_exists(&work, "refs/checkout/pr-7").unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn force_update_handles_dirty_worktrees_per_on_dirty_policy() {
+        let (dir, work) = scratch_repo("force-update");
+        let wt = dir.join("wt");
+        run_git(&work, &["config", "user.name", "t"]);
+        run_git(&work, &["config", "user.email", "t@t"]);
+        fs::write(work.join("a.txt"), "1\n").unwrap();
+        run_git(&work, &["add", "a.txt"]);
+        run_git(&work, &["commit", "-q", "-m", "one"]);
+        run_git(&work, &["push", "-q", "origin", "main:darren/pr"]);
+        run_git(&work, &["worktree", "add", "-q", "--detach", "../wt", "origin/darren/pr"]);
+
+        // The PR moves on while the worktree has local edits
+        fs::write(work.join("a.txt"), "2\n").unwrap();
+        run_git(&work, &["commit", "-q", "-am", "two"]);
+        run_git(&work, &["push", "-q", "origin", "main:darren/pr"]);
+        let pushed = run_git(&work, &["rev-parse", "HEAD"]);
+        fs::write(wt.join("a.txt"), "local\n").unwrap();
+        fs::write(wt.join("notes.txt"), "keep\n").unwrap();
+
+        let err = force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Fail, false).unwrap_err();
+        assert!(err.contains("uncommitted changes"), "{}", err);
+        assert_eq!(fs::read_to_string(wt.join("a.txt")).unwrap(), "local\n");
+
+        force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Stash, false).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), pushed);
+        assert_eq!(fs::read_to_string(wt.join("a.txt")).unwrap(), "2\n");
+        assert_eq!(run_git(&wt, &["stash", "list"]).lines().count(), 1);
+
+        fs::write(wt.join("a.txt"), "local again\n").unwrap();
+        force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Discard, false).unwrap();
+        assert_eq!(fs::read_to_string(wt.join("a.txt")).unwrap(), "2\n");
+        assert_eq!(run_git(&wt, &["stash", "list"]).lines().count(), 1);
+        assert_eq!(fs::read_to_string(wt.join("notes.txt")).unwrap(), "keep\n");
+
+        // Only untracked files: nothing to protect, so even Fail updates
+        force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Fail, false).unwrap();
+
+        // A commit made in the worktree but never pushed is only reset away with Discard
+        fs::write(wt.join("a.txt"), "committed locally\n").unwrap();
+        run_git(&wt, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "-am", "local"]);
+        let local = run_git(&wt, &["rev-parse", "HEAD"]);
+        let err = force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Stash, false).unwrap_err();
+        assert!(err.contains("1 commit(s) that origin/darren/pr doesn't"), "{}", err);
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), local);
+        force_update_worktree(&wt, Upstream::Branch("darren/pr"), OnDirty::Discard, false).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), pushed);
+
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--force-update"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--use-existing", "--on-dirty", "stash"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--use-existing", "--resume-existing"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--into-current", "--use-existing"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--into-current", "--worktree-in-repo"]).is_err());
+        assert!(Cli::try_parse_from(["checkout", "pr", "5", "--use-existing", "--force-update", "--on-dirty", "stash"])
+            .is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn into_current_only_moves_checkout_worktrees_to_unclaimed_prs() {
+        let (dir, work) = scratch_repo("into-current");
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let work = work.canonicalize().unwrap();
+        for (path, name) in [("branch-x", "x"), ("pr-7-old", "pr-7"), ("outside/wt", "y")] {
+            run_git(&work, &["worktree", "add", "-q", "-b", name, &dir.join(path).to_string_lossy()]);
+        }
+        let details = |fork: bool| -> PrDetails {
+            serde_json::from_value(serde_json::json!({
+                "headRefName": "feature",
+                "title": "feat: Add thing",
+                "isCrossRepository": fork,
+            }))
+            .unwrap()
         };
-        let newer_claude = ResumeTarget {
-            last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(20),
-            agent: Agent::Claude,
-            resume_id: None,
+        let target = |path: &Path, pr: u64, fork: bool| into_current_target(&work, &path.to_path_buf(), pr, &details(fork));
+        let err = |path: &Path, pr: u64, fork: bool| target(path, pr, fork).unwrap_err();
+
+        assert!(err(&work, 8, false).contains("is the main repo"));
+        assert!(err(&dir.join("outside"), 8, false).contains("isn't a worktree"));
+        assert!(err(&dir.join("outside/wt"), 8, false).contains("wasn't created by checkout"));
+        assert!(err(&dir.join("branch-x"), 8, true).contains("is from a fork"));
+        assert!(err(&dir.join("branch-x"), 7, false).contains("already checked out in pr-7-old"));
+
+        // The PR's own worktree stays put; any other is renamed to the PR's
+        assert_eq!(target(&dir.join("pr-7-old"), 7, false).unwrap(), dir.join("pr-7-old"));
+        let renamed = target(&dir.join("branch-x"), 8, false).unwrap();
+        assert_eq!(renamed.parent(), Some(dir.as_path()));
+        assert!(is_pr_worktree_dir(&renamed, 8));
+        fs::create_dir(&renamed).unwrap();
+        assert!(err(&dir.join("branch-x"), 8, false).contains("already exists"));
+
+        // A detached HEAD's own commits are the ones moving it would lose
+        let wt = dir.join("branch-x");
+        run_git(&wt, &["checkout", "-q", "--detach"]);
+        assert!(git::unreferenced_commits(&wt).unwrap().is_empty());
+        run_git(&wt, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", "wip"]);
+        assert_eq!(git::unreferenced_commits(&wt).unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn execute_checkout_creates_reuses_and_refreshes_pr_worktrees() {
+        let (dir, work) = scratch_repo("execute");
+        run_git(&work, &["push", "-q", "origin", "HEAD:main", "HEAD:refs/heads/feature"]);
+        let push_commit = |message: &str| {
+            run_git(&work, &["-c", "user.name=t", "-c", "user.email=t@t", "commit", "-q", "--allow-empty", "-m", message]);
+            run_git(&work, &["push", "-q", "origin", "HEAD:refs/heads/feature"]);
+            run_git(&work, &["rev-parse", "HEAD"])
         };
+        let first = push_commit("first");
+        let plan = |existing: Option<PathBuf>| CheckoutPlan {
+            repo_root: work.clone(),
+            worktree_name: "pr-5-feature".to_string(),
+            source: Source::RemoteBranch { branch: "feature".to_string(), pr_number: 5, head_oid: None, fork: false },
+            existing,
+            title: Some("Feature".to_string()),
+            color_key: None,
+            prompt: AgentPrompt::None,
+        };
+        // Worktrees go next to the scratch repo rather than the configured dir
+        let options = CheckoutOptions { no_agent: true, worktree_in_repo: true, ..CheckoutOptions::default() };
+
+        // Create: a new worktree detached at the PR head
+        execute_checkout(plan(None), Agent::Codex, options).unwrap();
+        let wt = dir.join("pr-5-feature");
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), first);
+        assert_eq!(find_pr_worktree(&work, 5, Some("feature"), false).unwrap(), Some(wt.clone()));
+        let recorded = metadata::load(&get_data_dir()).pr_branches.remove(&metadata::pr_key(&work, 5));
+        assert_eq!(recorded.as_deref(), Some("feature"));
+
+        // Reuse as it is: neither the new push nor the local edit is touched
+        let second = push_commit("second");
+        fs::write(wt.join("wip.txt"), "wip\n").unwrap();
+        execute_checkout(plan(Some(wt.clone())), Agent::Codex, CheckoutOptions { allow_dirty: true, ..options }).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), first);
+        assert!(wt.join("wip.txt").exists());
+
+        // Refresh: reusing the worktree updates it to the PR's latest head
+        execute_checkout(plan(Some(wt.clone())), Agent::Codex, CheckoutOptions { use_existing: true, ..options }).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), second);
+        assert!(wt.join("wip.txt").exists(), "untracked files survive the update");
+
+        // Forced refresh, as `--force-update` runs it unattended
+        let third = push_commit("third");
+        let force = CheckoutOptions { use_existing: true, force_update: Some(OnDirty::Fail), ..options };
+        execute_checkout(plan(Some(wt.clone())), Agent::Codex, force).unwrap();
+        assert_eq!(run_git(&wt, &["rev-parse", "HEAD"]), third);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 
+    #[test]
+    fn migrate_moves_and_repairs_worktrees_with_their_state() {
+        let (dir, work) = scratch_repo("migrate");
+        let (old_dir, new_dir) = (dir.join("old"), dir.join("new"));
+        let (moved_old, moved_new) = (old_dir.join("pr-1-fix"), new_dir.join("pr-1-fix"));
+        let (by_hand_old, by_hand_new) = (old_dir.join("branch-wip"), new_dir.join("branch-wip"));
+        run_git(&work, &["worktree", "add", "-q", "-b", "fix", &moved_old.to_string_lossy()]);
+        run_git(&work, &["worktree", "add", "-q", "-b", "wip", &by_hand_old.to_string_lossy()]);
+        save_worktree_color(&moved_old, "123456").unwrap();
+        metadata::update(&get_data_dir(), |metadata| {
+            metadata.notes.insert(note_key(&moved_old), "waiting on review".to_string());
+        })
+        .unwrap();
+
+        // A dry run changes nothing
+        run_migrate(&old_dir, &new_dir, true, Some(work.clone())).unwrap();
+        assert!(moved_old.exists() && !moved_new.exists());
+
+        // One worktree is moved by hand first; git only needs repairing for it
+        fs::create_dir_all(&new_dir).unwrap();
+        fs::rename(&by_hand_old, &by_hand_new).unwrap();
+        run_migrate(&old_dir, &new_dir, false, Some(work.clone())).unwrap();
+        let mut paths: Vec<PathBuf> = git::list_worktrees(&work).unwrap().into_iter().map(|entry| entry.path).collect();
+        paths.sort();
+        assert_eq!(paths, vec![by_hand_new.clone(), moved_new.clone(), work.clone()]);
+        assert!(!moved_old.exists());
+        assert_eq!(run_git(&moved_new, &["branch", "--show-current"]), "fix");
+        assert_eq!(run_git(&by_hand_new, &["branch", "--show-current"]), "wip");
+        assert_eq!(get_worktree_color(&moved_new).as_deref(), Some("123456"));
+        assert_eq!(get_worktree_color(&moved_old), None);
+        let notes = metadata::load(&get_data_dir()).notes;
+        assert_eq!(notes.get(&note_key(&moved_new)).map(String::as_str), Some("waiting on review"));
+        assert!(!notes.contains_key(&note_key(&moved_old)));
+
+        remove_worktree_color(&moved_new);
+        remove_worktree_note(&moved_new);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn worktree_paths_become_osc8_file_links() {
         assert_eq!(
-            choose_resume_target(Some(older_codex), Some(newer_claude)),
-            Some(ResumeTarget {
-                last_modified: SystemTime::UNIX_EPOCH + Duration::from_secs(20),
-                agent: Agent::Claude,
-                resume_id: None,
-            })
+            file_url("mbp", Path::new("/Users/d/worktrees/pr-5-fix login")),
+            "file://mbp/Users/d/worktrees/pr-5-fix%20login"
+        );
+        assert_eq!(
+            osc8_link("file://mbp/tmp", "/tmp"),
+            "\x1b]8;;file://mbp/tmp\x1b\\/tmp\x1b]8;;\x1b\\"
         );
-        assert_eq!(choose_resume_target(None, None), None);
+    }
+
+    #[test]
+    fn last_checkout_replays_only_pr_and_branch_commands() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let cli = parse_replay_args(&args(&["pr", "123", "--no-agent", "--agent", "claude"])).unwrap();
+        assert!(matches!(cli.agent, Agent::Claude));
+        assert!(matches!(cli.command, Commands::Pr { pr, no_agent: true, .. } if pr == ["123"]));
+
+        let cli = parse_replay_args(&args(&["branch", "darren/foo"])).unwrap();
+        assert!(matches!(cli.command, Commands::Branch { name, .. } if name == "darren/foo"));
+
+        assert!(parse_replay_args(&[]).is_err());
+        assert!(parse_replay_args(&args(&["last"])).is_err());
+        assert!(parse_replay_args(&args(&["clean", "-y"])).is_err());
+    }
+
+    #[test]
+    fn title_template_fills_name_and_repo() {
+        assert_eq!(repo_slug(Path::new("/Users/d/figma")), "figma");
+        assert_eq!(render_title("{name}", "fix-login", "figma"), "fix-login");
+        assert_eq!(render_title("{repo}: {name}", "fix-login", "figma"), "figma: fix-login");
+        assert_eq!(render_title("[WT] {name}", "fix-login", "figma"), "[WT] fix-login");
     }
 
     #[test]
@@ -5760,4 +12983,258 @@ mod tests {
             "Resume last Claude session (keep changes, skip update)"
         );
     }
+
+    #[test]
+    fn slash_command_is_only_read_from_slash_prompts() {
+        assert_eq!(slash_command("/checkout:checkout-pr 42"), Some("checkout:checkout-pr"));
+        assert_eq!(slash_command("/review\n\nAfter completing that, run tests"), Some("review"));
+        assert_eq!(slash_command("Check out and summarize PR #42"), None);
+        assert_eq!(slash_command("/ 42"), None);
+        assert_eq!(slash_command("/usr/bin/true 42"), None);
+        assert_eq!(slash_command("/:pr 42"), None);
+    }
+
+    #[test]
+    fn slash_commands_are_found_in_any_commands_dir() {
+        let root = std::env::temp_dir().join(format!("checkout-slash-{}", std::process::id()));
+        let (worktree, home) = (root.join("worktree"), root.join("home"));
+        std::fs::create_dir_all(home.join(".claude/commands/checkout")).unwrap();
+        std::fs::create_dir_all(worktree.join(".claude/commands")).unwrap();
+        std::fs::write(home.join(".claude/commands/checkout/checkout-pr.md"), "").unwrap();
+        std::fs::write(worktree.join(".claude/commands/review.md"), "").unwrap();
+        std::fs::create_dir_all(home.join(".claude/skills/triage")).unwrap();
+        std::fs::write(home.join(".claude/skills/triage/SKILL.md"), "").unwrap();
+        let plugin = home.join(".claude/plugins/marketplaces/acme/plugins/figma");
+        std::fs::create_dir_all(plugin.join("commands")).unwrap();
+        std::fs::create_dir_all(plugin.join("skills/summarize")).unwrap();
+        std::fs::write(plugin.join("commands/review.md"), "").unwrap();
+        std::fs::write(plugin.join("skills/summarize/SKILL.md"), "").unwrap();
+        let dirs = [worktree, home];
+
+        assert!(slash_command_installed("checkout:checkout-pr", &dirs));
+        assert!(slash_command_installed("review", &dirs));
+        assert!(!slash_command_installed("checkout:review", &dirs));
+        assert!(!slash_command_installed("checkout", &dirs));
+        assert!(!slash_command_installed("review", &dirs[1..]));
+        assert!(slash_command_installed("triage", &dirs));
+        assert!(slash_command_installed("figma:review", &dirs));
+        assert!(slash_command_installed("figma:summarize", &dirs));
+        assert!(!slash_command_installed("figma:deploy", &dirs));
+        assert!(!slash_command_installed("acme:review", &dirs));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn durations_take_a_unit_or_default_to_seconds() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("2 weeks").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn watch_frames_fit_the_terminal_with_a_footer() {
+        let body = "a\nb\nc\nd\n";
+        assert_eq!(watch_frame(body, 10, "q to quit"), "a\r\nb\r\nc\r\nd\r\n\r\nq to quit");
+        assert_eq!(watch_frame(body, 4, "q to quit"), "a\r\nb\r\n\r\nq to quit");
+    }
+
+    #[test]
+    fn slugs_drop_only_a_leading_commit_type() {
+        let cases = [
+            ("Add retry to the uploader", "add-retry-to-the"),
+            ("fix: bug in foo: bar", "bug-in-foo-bar"),
+            ("feat(ui): add button", "add-button"),
+            ("feat(multiplayer-sync)!: drop v1 protocol", "drop-v1-protocol"),
+            ("chore(deps-dev): bump vite", "bump-vite"),
+            ("Handle timeouts in the parser: part 2", "handle-timeouts-in-the"),
+            ("[WIP] auth: refresh tokens", "wip-auth-refresh-tokens"),
+            ("fix:no space", "fix-no-space"),
+            ("fix: ", "fix"),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(create_slug(title), expected, "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn parallel_must_be_a_positive_number() {
+        assert_eq!(parse_parallel("3"), Ok(3));
+        assert!(parse_parallel("0").is_err());
+        assert!(parse_parallel("-1").is_err());
+        assert!(parse_parallel("many").is_err());
+    }
+
+    #[test]
+    fn batch_slots_bound_how_many_threads_run_at_once() {
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..default_parallelism() * 3 {
+                scope.spawn(|| {
+                    let _slot = batch_slot();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(std::time::Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(most.load(Ordering::SeqCst) <= default_parallelism());
+    }
+
+    #[test]
+    fn base_worktrees_are_kept_only_while_a_pr_worktree_uses_them() {
+        let worktree = |path: &str| WorktreeInfo {
+            path: PathBuf::from(path),
+            branch: "(detached)".to_string(),
+            head: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            tag: None,
+            has_changes: false,
+            unpushed_commits: Some(0),
+            has_active_session: false,
+            active_agent: None,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        };
+        assert_eq!(base_worktree_name("master"), "base-master");
+        assert_eq!(base_worktree_name("release/1.2"), "base-release-1-2");
+
+        let pr_bases: BTreeMap<String, BTreeSet<u64>> = [
+            ("master".to_string(), BTreeSet::from([12, 15, 40])),
+            ("release/1.2".to_string(), BTreeSet::from([7])),
+            ("develop".to_string(), BTreeSet::new()),
+        ]
+        .into();
+        let worktrees = vec![
+            worktree("/wt/base-master"),
+            worktree("/wt/base-release-1-2"),
+            worktree("/wt/base-develop"),
+            worktree("/wt/base-other"),
+            worktree("/wt/pr-12-fix"),
+            worktree("/wt/pr-40-feature"),
+            worktree("/wt/branch-7"),
+        ];
+        let users = base_worktree_users(&worktrees, &pr_bases);
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[Path::new("/wt/base-master")], vec![12, 40]);
+
+        let branches = base_worktree_branches(&pr_bases);
+        assert_eq!(branches.get("base-release-1-2"), Some(&"release/1.2"));
+        assert_eq!(branches.get("base-develop"), Some(&"develop"));
+        assert_eq!(branches.get("base-other"), None);
+    }
+
+    #[test]
+    fn pr_args_split_into_prs_and_a_trailing_skill() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(split_pr_args(args(&["123"])), (args(&["123"]), None));
+        assert_eq!(split_pr_args(args(&["123", "/walkthrough"])), (args(&["123"]), Some("/walkthrough".to_string())));
+        assert_eq!(split_pr_args(args(&["1", "#2", "pull/3"])), (args(&["1", "#2", "pull/3"]), None));
+        assert_eq!(
+            split_pr_args(args(&["1", "https://github.com/o/r/pull/2", "$review"])),
+            (args(&["1", "https://github.com/o/r/pull/2"]), Some("$review".to_string()))
+        );
+        // A lone argument is always the PR, so a typo is reported as one
+        assert_eq!(split_pr_args(args(&["walkthrough"])), (args(&["walkthrough"]), None));
+        // So is a mistyped last PR in a batch, rather than becoming the skill
+        assert_eq!(split_pr_args(args(&["101", "102", "1O3"])), (args(&["101", "102", "1O3"]), None));
+        assert_eq!(split_pr_args(Vec::new()), (Vec::new(), None));
+
+        let Commands::Pr { pr, .. } = Cli::try_parse_from(["checkout", "pr", "1", "2", "/review"]).unwrap().command
+        else {
+            panic!("expected pr");
+        };
+        assert_eq!(pr, ["1", "2", "/review"]);
+    }
+
+    #[test]
+    fn quiet_and_git_output_are_set_independently() {
+        let cli = Cli::try_parse_from(["checkout", "pr", "123", "-q", "--git-output", "shown"]).unwrap();
+        assert!(cli.quiet);
+        assert_eq!(cli.git_output, GitOutput::Shown);
+        let cli = Cli::try_parse_from(["checkout", "--quiet", "status"]).unwrap();
+        assert_eq!(cli.git_output, GitOutput::Hidden);
+        assert!(Cli::try_parse_from(["checkout", "status", "--git-output", "loud"]).is_err());
+    }
+
+    #[test]
+    fn color_by_hashes_the_chosen_pr_attribute_into_the_palette() {
+        let details: PrDetails = serde_json::from_str(
+            r#"{"headRefName":"a","title":"b","author":{"login":"darren"},"baseRefName":"master"}"#,
+        )
+        .unwrap();
+        assert_eq!(pr_color_seed(ColorBy::Author, 7, &details).as_deref(), Some("author:darren"));
+        assert_eq!(pr_color_seed(ColorBy::Base, 7, &details).as_deref(), Some("base:master"));
+        assert_eq!(pr_color_seed(ColorBy::Pr, 7, &details).as_deref(), Some("pr:7"));
+        let ghost: PrDetails = serde_json::from_str(r#"{"headRefName":"a","title":"b"}"#).unwrap();
+        assert_eq!(pr_color_seed(ColorBy::Author, 7, &ghost), None);
+        assert_eq!(pr_color_seed(ColorBy::Base, 7, &ghost), None);
+
+        assert_eq!(seeded_color("author:darren"), seeded_color("author:darren"));
+        assert!(COLOR_PALETTE.contains(&seeded_color("author:darren")));
+        let spread: HashSet<&str> = (0..50).map(|i| seeded_color(&format!("author:user{}", i))).collect();
+        assert!(spread.len() > 5);
+    }
+
+    #[test]
+    fn prompt_input_ends_on_a_line_an_eof_or_a_cancel() {
+        let not_interrupted = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Ok(Some("y\n".to_string()))).unwrap();
+        assert_eq!(wait_for_input(&receiver, &not_interrupted), Ok(Some("y\n".to_string())));
+        // End of stdin: read_input cancels, read_confirmation takes the default
+        sender.send(Ok(None)).unwrap();
+        assert_eq!(wait_for_input(&receiver, &not_interrupted), Ok(None));
+        drop(sender);
+        assert_eq!(wait_for_input(&receiver, &not_interrupted), Ok(None));
+        assert_eq!(wait_for_input(&receiver, &AtomicBool::new(true)), Err("Cancelled".to_string()));
+        assert_eq!(Interrupt::take(), Some(Interrupt::Cancelled));
+        assert_eq!(Interrupt::take(), None);
+
+        // Wrapping the error doesn't hide the cancel from `main`
+        let wrapped = wait_for_input(&receiver, &AtomicBool::new(true)).map_err(|e| format!("Failed to pick: {}", e));
+        assert_eq!(wrapped, Err("Failed to pick: Cancelled".to_string()));
+        assert_eq!(Interrupt::take(), Some(Interrupt::Cancelled));
+    }
+
+    #[test]
+    fn ctrl_c_at_a_prompt_releases_the_session_pid_guard() {
+        let worktree = env::temp_dir().join(format!("checkout-cancel-{}", std::process::id()));
+        // A pending stdin read that never answers, and a Ctrl+C shortly after
+        // the prompt starts waiting, as the handler would deliver it
+        let (sender, receiver) = mpsc::channel();
+        *PENDING_INPUT.lock().unwrap() = Some(receiver);
+        let interrupt = thread::spawn(|| {
+            while !PROMPTING.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            PROMPT_INTERRUPTED.store(true, Ordering::SeqCst);
+        });
+        let agent_run = || -> Result<String, String> {
+            let _guard = PidFileGuard::new(&worktree, std::process::id(), Agent::Claude);
+            assert!(session_pid_file(&worktree).exists());
+            assert_eq!(ACTIVE_WORKTREE.lock().unwrap().as_deref(), Some(worktree.as_path()));
+            read_input()
+        };
+        assert_eq!(agent_run(), Err("Cancelled".to_string()));
+        assert_eq!(Interrupt::take(), Some(Interrupt::Cancelled));
+        interrupt.join().unwrap();
+
+        assert!(!session_pid_file(&worktree).exists());
+        assert!(session_exited_file(&worktree).exists());
+        assert_eq!(*ACTIVE_WORKTREE.lock().unwrap(), None);
+        assert_eq!(*ACTIVE_CHILD_PID.lock().unwrap(), None);
+        // The unanswered read is kept for the next prompt rather than lost
+        assert!(PENDING_INPUT.lock().unwrap().take().is_some());
+        drop(sender);
+        let _ = fs::remove_file(session_exited_file(&worktree));
+    }
 }
@@ -0,0 +1,224 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// State that outlives individual worktrees, stored as a single JSON file in
+/// the data dir. Unknown fields are ignored so older binaries can read files
+/// written by newer ones.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Metadata {
+    /// Last background color used for each PR, keyed by `pr_key`. Stored
+    /// under a new name: the old one wasn't split by repo.
+    #[serde(rename = "repo_pr_colors")]
+    pub pr_colors: BTreeMap<String, String>,
+    /// Last background color used for each branch worktree, keyed by
+    /// `branch_key`. Stored under a new name like `pr_colors`.
+    #[serde(rename = "repo_branch_colors")]
+    pub branch_colors: BTreeMap<String, String>,
+    /// PR head commit at its last `checkout pr`, to show what changed since,
+    /// keyed by `pr_key`
+    pub reviewed_commits: BTreeMap<String, String>,
+    /// One-line notes from `checkout note`, keyed by worktree path
+    pub notes: BTreeMap<String, String>,
+    /// Head branch of each PR at its last `checkout pr`, for `fix-detached`,
+    /// keyed by `pr_key`
+    pub pr_branches: BTreeMap<String, String>,
+    /// What was passed to `checkout pr` for each PR (number or URL), so
+    /// `status` can link back to the PR page, keyed by `pr_key`
+    pub pr_inputs: BTreeMap<String, String>,
+    /// PRs last checked out `--with-base` against each base branch, so
+    /// `clean` keeps the shared base worktree while one of them has a
+    /// worktree, by `repo_key` and then base branch. Stored under a new name:
+    /// the old one wasn't split by repo.
+    #[serde(rename = "repo_pr_bases")]
+    pub pr_bases: BTreeMap<String, BTreeMap<String, BTreeSet<u64>>>,
+    /// When each worktree was last checked out into by `pr`, `branch` or
+    /// `new`, or opened or resumed, in seconds since the epoch, keyed by
+    /// worktree path. Directory mtimes move with every build, so this is the
+    /// staleness signal.
+    pub last_used: BTreeMap<String, u64>,
+}
+
+impl Metadata {
+    /// Drop a repo's per-PR and per-branch entries (branches, inputs,
+    /// reviewed commits, base branches and colors), returning how many there
+    /// were.
+    pub fn remove_repo_records(&mut self, repo_root: &Path) -> usize {
+        let prefix = format!("{}#", repo_key(repo_root));
+        let mut removed = 0;
+        for map in [
+            &mut self.pr_branches,
+            &mut self.pr_inputs,
+            &mut self.reviewed_commits,
+            &mut self.pr_colors,
+            &mut self.branch_colors,
+        ] {
+            let before = map.len();
+            map.retain(|key, _| !key.starts_with(&prefix));
+            removed += before - map.len();
+        }
+        removed + self.pr_bases.remove(&repo_key(repo_root)).map_or(0, |bases| bases.values().map(BTreeSet::len).sum())
+    }
+}
+
+/// Key for a repo's entries: its main worktree path.
+pub fn repo_key(repo_root: &Path) -> String {
+    repo_root.to_string_lossy().into_owned()
+}
+
+/// Key for a PR's entries. PR numbers are only unique within a repo, so
+/// two repos' PR #12 must not share a branch, URL or reviewed commit.
+pub fn pr_key(repo_root: &Path, pr_number: u64) -> String {
+    format!("{}#{}", repo_key(repo_root), pr_number)
+}
+
+/// Key for a branch's entries, scoped to its repo for the same reason.
+pub fn branch_key(repo_root: &Path, branch: &str) -> String {
+    format!("{}#{}", repo_key(repo_root), branch)
+}
+
+pub fn metadata_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("metadata.json")
+}
+
+/// Read the metadata file. A missing or unreadable file yields empty metadata:
+/// everything stored here is a convenience, never required for correctness.
+/// A file that doesn't parse is moved aside first, with a warning, so the
+/// next `update` doesn't overwrite what's left of it.
+pub fn load(data_dir: &Path) -> Metadata {
+    let path = metadata_file(data_dir);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Metadata::default();
+    };
+    match serde_json::from_str(&content) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            let moved = match set_aside(&path) {
+                Ok(backup) => format!("moved it to {}", backup.display()),
+                Err(e) => e,
+            };
+            eprintln!("{} Ignoring unreadable {} ({}); {}", "⚠".yellow(), path.display(), e, moved);
+            Metadata::default()
+        }
+    }
+}
+
+/// Rename a corrupt metadata file to `metadata.corrupt-<secs>.json` next to
+/// it, keeping any earlier one.
+fn set_aside(path: &Path) -> Result<PathBuf, String> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut backup = path.with_file_name(format!("metadata.corrupt-{}.json", secs));
+    let mut n = 1;
+    while backup.exists() {
+        n += 1;
+        backup = path.with_file_name(format!("metadata.corrupt-{}_{}.json", secs, n));
+    }
+    fs::rename(path, &backup).map_err(|e| format!("failed to move it aside: {}", e))?;
+    Ok(backup)
+}
+
+/// Write the metadata file atomically (temp file + rename) so a concurrent
+/// reader never observes a half-written file.
+pub fn save(data_dir: &Path, metadata: &Metadata) -> Result<(), String> {
+    fs::create_dir_all(data_dir)
+        .map_err(|e| format!("Failed to create data dir {}: {}", data_dir.display(), e))?;
+    let content = serde_json::to_string_pretty(metadata)
+        .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+    write_atomic(&metadata_file(data_dir), &content)
+}
+
+/// Replace `path` via a temp file + rename in the same directory.
+pub fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    fs::write(&tmp, content).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+/// Load, modify, and save the metadata file in one step.
+pub fn update<T>(data_dir: &Path, f: impl FnOnce(&mut Metadata) -> T) -> Result<T, String> {
+    let mut metadata = load(data_dir);
+    let result = f(&mut metadata);
+    save(data_dir, &metadata)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_tolerates_missing_or_foreign_fields() {
+        let dir = std::env::temp_dir().join(format!("checkout-metadata-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(load(&dir), Metadata::default());
+        update(&dir, |metadata| {
+            metadata.pr_colors.insert(pr_key(Path::new("/repo"), 42), "1e2233".to_string());
+            metadata.branch_colors.insert(branch_key(Path::new("/repo"), "darren/auth"), "2d1f2d".to_string());
+            metadata.reviewed_commits.insert(pr_key(Path::new("/repo"), 42), "a".repeat(40));
+            metadata.notes.insert("/wt/pr-42-auth".to_string(), "waiting on security review".to_string());
+            metadata.pr_branches.insert(pr_key(Path::new("/repo"), 42), "darren/auth".to_string());
+            metadata.pr_bases.entry(repo_key(Path::new("/repo"))).or_default().entry("master".to_string()).or_default().insert(42);
+            metadata.pr_inputs.insert(pr_key(Path::new("/repo"), 42), "https://github.com/o/r/pull/42".to_string());
+            metadata.last_used.insert("/wt/pr-42-auth".to_string(), 1_760_611_200);
+        })
+        .unwrap();
+        let loaded = load(&dir);
+        assert_eq!(loaded.pr_colors.get("/repo#42").map(String::as_str), Some("1e2233"));
+        assert_eq!(
+            loaded.branch_colors.get("/repo#darren/auth").map(String::as_str),
+            Some("2d1f2d")
+        );
+        assert_eq!(loaded.reviewed_commits.get("/repo#42"), Some(&"a".repeat(40)));
+        assert_eq!(
+            loaded.notes.get("/wt/pr-42-auth").map(String::as_str),
+            Some("waiting on security review")
+        );
+        assert_eq!(loaded.pr_branches.get("/repo#42").map(String::as_str), Some("darren/auth"));
+        assert_eq!(loaded.pr_inputs.get("/repo#42").map(String::as_str), Some("https://github.com/o/r/pull/42"));
+        assert!(loaded.pr_bases["/repo"]["master"].contains(&42));
+
+        let mut other = loaded.clone();
+        other.pr_branches.insert(pr_key(Path::new("/repo2"), 42), "theirs".to_string());
+        other.pr_colors.insert(pr_key(Path::new("/repo2"), 42), "1f2d2d".to_string());
+        assert_eq!(other.remove_repo_records(Path::new("/repo")), 6);
+        assert_eq!(other.pr_branches.len(), 1);
+        assert_eq!(other.pr_colors.len(), 1);
+        assert!(other.pr_inputs.is_empty() && other.reviewed_commits.is_empty() && other.pr_bases.is_empty());
+        assert!(other.branch_colors.is_empty());
+        assert_eq!(loaded.last_used.get("/wt/pr-42-auth"), Some(&1_760_611_200));
+
+        fs::write(metadata_file(&dir), r#"{"repo_pr_colors":{"/repo#7":"1f2d2d"},"future":true}"#).unwrap();
+        assert_eq!(load(&dir).pr_colors.get("/repo#7").map(String::as_str), Some("1f2d2d"));
+
+        // Entries from before PRs were keyed by repo still load, and match no repo
+        fs::write(
+            metadata_file(&dir),
+            r#"{"pr_branches":{"42":"old"},"pr_bases":{"master":[42]},"pr_colors":{"42":"1e2233"},"branch_colors":{"main":"2d1f2d"}}"#,
+        )
+        .unwrap();
+        let old = load(&dir);
+        assert_eq!(old.pr_branches.get("42").map(String::as_str), Some("old"));
+        assert!(old.pr_bases.is_empty());
+        assert!(old.pr_colors.is_empty() && old.branch_colors.is_empty());
+
+        // A corrupt file is kept aside instead of being overwritten by the next update
+        fs::write(metadata_file(&dir), r#"{"pr_colors":{"7":"#).unwrap();
+        update(&dir, |metadata| metadata.pr_colors.insert(pr_key(Path::new("/repo"), 8), "2d2d1f".to_string())).unwrap();
+        let kept: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.starts_with("metadata.corrupt-"))
+            .collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(fs::read_to_string(dir.join(&kept[0])).unwrap(), r#"{"pr_colors":{"7":"#);
+        assert_eq!(load(&dir).pr_colors.len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::trace::Traced;
+
+/// Terminal families with different escape dialects for background color and
+/// title. Selected from the environment, or by the `terminal` config key.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalKind {
+    /// iTerm2's proprietary `SetColors`, plus session renames via the API helper
+    Iterm2,
+    /// Standard OSC 11 background / OSC 2 title (WezTerm, Alacritty, ...)
+    Osc,
+    /// kitty, using `kitty @ set-colors` when remote control is enabled
+    Kitty,
+    /// Emit nothing
+    None,
+}
+
+/// Escape-sequence dialect for the terminal checkout is running in. Methods
+/// write to `out` so the exact bytes can be tested; backends may also have
+/// side effects outside the terminal stream (iTerm2 API, `kitty @`).
+pub trait TerminalBackend: Send + Sync {
+    /// Set the background to `hex` (six hex digits, no `#`)
+    fn set_background(&self, out: &mut dyn Write, hex: &str) -> io::Result<()>;
+    fn reset_background(&self, out: &mut dyn Write) -> io::Result<()>;
+    fn set_title(&self, out: &mut dyn Write, title: &str) -> io::Result<()>;
+    fn reset_title(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.set_title(out, "")
+    }
+    /// Report the working directory (OSC 7) so cmd-click paths resolve from it
+    fn set_cwd(&self, out: &mut dyn Write, hostname: &str, path: &Path) -> io::Result<()> {
+        write!(out, "\x1b]7;file://{}{}\x07", hostname, path.display())
+    }
+}
+
+pub struct Iterm2Backend {
+    /// Also rename the session through the iTerm2 API helper. OSC title
+    /// sequences don't reach iTerm2 from every coding-agent subprocess.
+    pub rename_session: bool,
+}
+
+impl TerminalBackend for Iterm2Backend {
+    fn set_background(&self, out: &mut dyn Write, hex: &str) -> io::Result<()> {
+        write!(out, "\x1b]1337;SetColors=bg={}\x07", hex)
+    }
+
+    fn reset_background(&self, out: &mut dyn Write) -> io::Result<()> {
+        // OSC 111 resets background color to default (standard xterm sequence)
+        write!(out, "\x1b]111\x07")
+    }
+
+    fn set_title(&self, out: &mut dyn Write, title: &str) -> io::Result<()> {
+        if self.rename_session {
+            let _ = crate::rename_iterm_session(title);
+        }
+        // OSC 1 sets tab/icon title, OSC 2 sets window title
+        write!(out, "\x1b]1;{}\x07\x1b]2;{}\x07", title, title)
+    }
+}
+
+pub struct OscBackend;
+
+impl TerminalBackend for OscBackend {
+    fn set_background(&self, out: &mut dyn Write, hex: &str) -> io::Result<()> {
+        write!(out, "\x1b]11;#{}\x07", hex)
+    }
+
+    fn reset_background(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "\x1b]111\x07")
+    }
+
+    fn set_title(&self, out: &mut dyn Write, title: &str) -> io::Result<()> {
+        write!(out, "\x1b]2;{}\x07", title)
+    }
+}
+
+pub struct KittyBackend {
+    /// `$KITTY_LISTEN_ON` when remote control is enabled; colors then go
+    /// through `kitty @ set-colors` instead of OSC 11
+    pub listen_on: Option<String>,
+}
+
+impl KittyBackend {
+    fn remote_set_colors(&self, listen_on: &str, args: &[&str]) -> io::Result<()> {
+        Command::new("kitty")
+            .args(["@", "--to", listen_on, "set-colors"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .traced_status()
+            .map(|_| ())
+    }
+}
+
+impl TerminalBackend for KittyBackend {
+    fn set_background(&self, out: &mut dyn Write, hex: &str) -> io::Result<()> {
+        match &self.listen_on {
+            Some(listen_on) => self.remote_set_colors(listen_on, &[&format!("background=#{}", hex)]),
+            None => OscBackend.set_background(out, hex),
+        }
+    }
+
+    fn reset_background(&self, out: &mut dyn Write) -> io::Result<()> {
+        match &self.listen_on {
+            Some(listen_on) => self.remote_set_colors(listen_on, &["--reset"]),
+            None => OscBackend.reset_background(out),
+        }
+    }
+
+    fn set_title(&self, out: &mut dyn Write, title: &str) -> io::Result<()> {
+        OscBackend.set_title(out, title)
+    }
+}
+
+pub struct NoopBackend;
+
+impl TerminalBackend for NoopBackend {
+    fn set_background(&self, _out: &mut dyn Write, _hex: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset_background(&self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_title(&self, _out: &mut dyn Write, _title: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_cwd(&self, _out: &mut dyn Write, _hostname: &str, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Guess the terminal from `$TERM_PROGRAM`, `$TERM` and `$KITTY_WINDOW_ID`.
+/// Unknown terminals get the standard OSC sequences.
+pub fn detect(term_program: Option<&str>, term: Option<&str>, kitty_window: bool) -> TerminalKind {
+    if kitty_window || term == Some("xterm-kitty") {
+        return TerminalKind::Kitty;
+    }
+    match (term_program, term) {
+        (Some("iTerm.app"), _) => TerminalKind::Iterm2,
+        (_, Some("dumb")) => TerminalKind::None,
+        _ => TerminalKind::Osc,
+    }
+}
+
+fn build(kind: TerminalKind) -> Box<dyn TerminalBackend> {
+    match kind {
+        TerminalKind::Iterm2 => Box::new(Iterm2Backend { rename_session: true }),
+        TerminalKind::Osc => Box::new(OscBackend),
+        TerminalKind::Kitty => Box::new(KittyBackend {
+            listen_on: env::var("KITTY_LISTEN_ON").ok().filter(|s| !s.is_empty()),
+        }),
+        TerminalKind::None => Box::new(NoopBackend),
+    }
+}
+
+/// The backend for this process: the `terminal` config override, else detected.
+pub fn backend() -> &'static dyn TerminalBackend {
+    static BACKEND: OnceLock<Box<dyn TerminalBackend>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| {
+            let kind = crate::config::get().terminal.unwrap_or_else(|| {
+                detect(
+                    env::var("TERM_PROGRAM").ok().as_deref(),
+                    env::var("TERM").ok().as_deref(),
+                    env::var_os("KITTY_WINDOW_ID").is_some(),
+                )
+            });
+            build(kind)
+        })
+        .as_ref()
+}
+
+/// Run one backend call against stdout and flush. Terminal decoration is
+/// best-effort, so write errors are ignored.
+fn emit(f: impl FnOnce(&dyn TerminalBackend, &mut dyn Write) -> io::Result<()>) {
+    let mut out = io::stdout();
+    let _ = f(backend(), &mut out);
+    out.flush().ok();
+}
+
+pub fn set_cwd(hostname: &str, path: &Path) {
+    emit(|backend, out| backend.set_cwd(out, hostname, path));
+}
+
+/// Color-file sentinel for worktrees that keep the terminal's own background
+pub const NO_COLOR: &str = "none";
+
+/// Which parts of the terminal a `TerminalGuard` changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuardOptions {
+    pub background: bool,
+    pub title: bool,
+    /// Leave the background set when the guard drops, or on Ctrl+C
+    pub keep_background: bool,
+}
+
+impl Default for GuardOptions {
+    fn default() -> Self {
+        Self { background: true, title: true, keep_background: false }
+    }
+}
+
+// Track which terminal settings we've modified, so only those are reset
+static BACKGROUND_SET: AtomicBool = AtomicBool::new(false);
+static TITLE_SET: AtomicBool = AtomicBool::new(false);
+
+fn reset(backend: &dyn TerminalBackend) {
+    let background = BACKGROUND_SET.swap(false, Ordering::SeqCst);
+    let title = TITLE_SET.swap(false, Ordering::SeqCst);
+    if !background && !title {
+        return;
+    }
+    let mut out = io::stdout();
+    if background {
+        let _ = backend.reset_background(&mut out);
+    }
+    if title {
+        let _ = backend.reset_title(&mut out);
+    }
+    out.flush().ok();
+}
+
+/// Undo background and title changes if a guard applied them. Also called
+/// from the Ctrl+C handler, which bypasses `Drop`.
+pub fn reset_if_modified() {
+    reset(backend());
+}
+
+/// RAII guard that sets the worktree's background and title, and resets them
+/// on drop
+pub struct TerminalGuard {
+    backend: &'static dyn TerminalBackend,
+}
+
+impl TerminalGuard {
+    pub fn new(bg_color: &str, title: &str, options: GuardOptions) -> Self {
+        let guard = Self { backend: backend() };
+        let mut out = io::stdout();
+        if options.background && bg_color != NO_COLOR {
+            let _ = guard.backend.set_background(&mut out, bg_color);
+            BACKGROUND_SET.store(!options.keep_background, Ordering::SeqCst);
+        }
+        if options.title {
+            let _ = guard.backend.set_title(&mut out, title);
+            TITLE_SET.store(true, Ordering::SeqCst);
+        }
+        out.flush().ok();
+        guard
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        reset(self.backend);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(f: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn backends_emit_expected_escape_bytes() {
+        let iterm = Iterm2Backend { rename_session: false };
+        assert_eq!(bytes(|o| iterm.set_background(o, "1e2233")), "\x1b]1337;SetColors=bg=1e2233\x07");
+        assert_eq!(bytes(|o| iterm.reset_background(o)), "\x1b]111\x07");
+        assert_eq!(bytes(|o| iterm.set_title(o, "fix")), "\x1b]1;fix\x07\x1b]2;fix\x07");
+        assert_eq!(bytes(|o| iterm.reset_title(o)), "\x1b]1;\x07\x1b]2;\x07");
+
+        assert_eq!(bytes(|o| OscBackend.set_background(o, "1e2233")), "\x1b]11;#1e2233\x07");
+        assert_eq!(bytes(|o| OscBackend.reset_background(o)), "\x1b]111\x07");
+        assert_eq!(bytes(|o| OscBackend.set_title(o, "fix")), "\x1b]2;fix\x07");
+        assert_eq!(
+            bytes(|o| OscBackend.set_cwd(o, "mbp", Path::new("/wt/pr-1"))),
+            "\x1b]7;file://mbp/wt/pr-1\x07"
+        );
+
+        let kitty = KittyBackend { listen_on: None };
+        assert_eq!(bytes(|o| kitty.set_background(o, "1e2233")), "\x1b]11;#1e2233\x07");
+        assert_eq!(bytes(|o| kitty.reset_background(o)), "\x1b]111\x07");
+        assert_eq!(bytes(|o| kitty.set_title(o, "fix")), "\x1b]2;fix\x07");
+
+        assert_eq!(bytes(|o| NoopBackend.set_background(o, "1e2233")), "");
+        assert_eq!(bytes(|o| NoopBackend.reset_title(o)), "");
+        assert_eq!(bytes(|o| NoopBackend.set_cwd(o, "mbp", Path::new("/wt"))), "");
+    }
+
+    #[test]
+    fn detects_terminal_from_environment() {
+        assert_eq!(detect(Some("iTerm.app"), Some("xterm-256color"), false), TerminalKind::Iterm2);
+        assert_eq!(detect(Some("WezTerm"), Some("xterm-256color"), false), TerminalKind::Osc);
+        assert_eq!(detect(None, Some("alacritty"), false), TerminalKind::Osc);
+        assert_eq!(detect(None, Some("xterm-kitty"), false), TerminalKind::Kitty);
+        assert_eq!(detect(None, Some("xterm-256color"), true), TerminalKind::Kitty);
+        assert_eq!(detect(None, Some("dumb"), false), TerminalKind::None);
+    }
+}
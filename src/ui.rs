@@ -0,0 +1,806 @@
+use crossterm::cursor::Show;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Paragraph;
+use ratatui::Terminal;
+use std::io::{self, Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use colored::Colorize;
+
+use crate::trace::Traced;
+use crate::{git, terminal, Agent};
+
+type Tui = Terminal<CrosstermBackend<Stdout>>;
+
+/// Background `git status` results: worktree path and its local work.
+type ChangesReceiver = mpsc::Receiver<(PathBuf, LocalWork)>;
+
+/// Whether the dashboard currently owns the terminal (raw mode + alternate
+/// screen), so signal and panic paths know to hand it back.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn enter_screen() -> Result<(), String> {
+    enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+    io::stdout()
+        .execute(EnterAlternateScreen)
+        .map_err(|e| format!("Failed to enter alternate screen: {}", e))?;
+    ACTIVE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Leave raw mode and the alternate screen if the dashboard is showing. Called
+/// from the Ctrl+C handler and panic hook, which bypass `Drop`.
+pub fn restore_terminal() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let _ = disable_raw_mode();
+        let mut stdout = io::stdout();
+        let _ = stdout.execute(LeaveAlternateScreen);
+        let _ = stdout.execute(Show);
+    }
+}
+
+/// Restores the terminal however the event loop exits, including `?`.
+struct ScreenGuard;
+
+impl Drop for ScreenGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Row {
+    path: PathBuf,
+    name: String,
+    /// `None` for a detached HEAD (e.g. PR worktrees)
+    branch: Option<String>,
+    head: String,
+    active_agent: Option<Agent>,
+    /// `None` until the background `git status` reports back
+    local_work: Option<LocalWork>,
+}
+
+/// What removing or resetting a worktree would lose
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LocalWork {
+    has_changes: bool,
+    /// `None` when git couldn't count them
+    unpushed_commits: Option<usize>,
+}
+
+impl Row {
+    fn branch_label(&self) -> &str {
+        self.branch.as_deref().unwrap_or("(detached)")
+    }
+
+    /// Same labels as `checkout status`.
+    fn badge(&self) -> (String, Color) {
+        match (self.active_agent, self.local_work) {
+            (Some(agent), _) => (format!("active {}", agent.command()), Color::Blue),
+            (None, Some(work)) if work.has_changes => ("modified".to_string(), Color::Yellow),
+            (None, Some(work)) if work.unpushed_commits != Some(0) => ("unpushed".to_string(), Color::Yellow),
+            (None, Some(_)) => ("clean".to_string(), Color::Green),
+            (None, None) => ("…".to_string(), Color::DarkGray),
+        }
+    }
+
+    /// The row as `clean` sees it, once its status has loaded
+    fn info(&self) -> Option<crate::WorktreeInfo> {
+        let work = self.local_work?;
+        Some(crate::WorktreeInfo {
+            path: self.path.clone(),
+            branch: self.branch_label().to_string(),
+            head: self.head.clone(),
+            tag: None,
+            has_changes: work.has_changes,
+            unpushed_commits: work.unpushed_commits,
+            has_active_session: self.active_agent.is_some(),
+            active_agent: self.active_agent,
+            orphaned_pids: Vec::new(),
+            note: None,
+            last_used: None,
+            stack_parent: None,
+        })
+    }
+}
+
+enum Mode {
+    Normal,
+    Filter,
+    Rename(String),
+    ConfirmDelete,
+}
+
+enum Outcome {
+    Quit,
+    Open(Row),
+}
+
+struct Dashboard {
+    repo_root: PathBuf,
+    rows: Vec<Row>,
+    filter: String,
+    /// Index into the filtered rows
+    selected: usize,
+    scroll: usize,
+    mode: Mode,
+    message: Option<String>,
+    changes: ChangesReceiver,
+}
+
+/// Indices of the rows whose directory name or branch contains `filter`
+/// (case-insensitive).
+fn visible_rows(rows: &[Row], filter: &str) -> Vec<usize> {
+    let filter = filter.to_lowercase();
+    rows.iter()
+        .enumerate()
+        .filter(|(_, row)| {
+            row.name.to_lowercase().contains(&filter)
+                || row.branch_label().to_lowercase().contains(&filter)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+pub(crate) fn pr_number_from_dir(name: &str) -> Option<u64> {
+    name.strip_prefix(crate::config::pr_dir_prefix())?.split('-').next()?.parse().ok()
+}
+
+fn local_work(path: &Path) -> LocalWork {
+    let has_changes = Command::new("git")
+        .args(["-C", &path.to_string_lossy(), "status", "--porcelain"])
+        .stderr(Stdio::null())
+        .traced_output()
+        .map(|output| crate::has_tracked_changes(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or(false);
+    LocalWork { has_changes, unpushed_commits: git::unpushed_commit_count(path).ok() }
+}
+
+/// List worktrees without waiting on `git status`; dirtiness arrives later
+/// through the returned channel, one message per inactive worktree.
+fn load_rows(repo_root: &Path) -> Result<(Vec<Row>, ChangesReceiver), String> {
+    let mut rows: Vec<Row> = git::list_worktrees(repo_root)?
+        .into_iter()
+        .filter(|entry| entry.path != repo_root)
+        .map(|entry| {
+            let active_agent = crate::read_session_pid(&entry.path)
+                .filter(|pid| crate::is_pid_alive(*pid))
+                .map(|_| crate::read_session_agent(&entry.path).unwrap_or(Agent::Claude));
+            Row {
+                name: crate::worktree_dir_name(&entry.path),
+                path: entry.path,
+                branch: entry.branch,
+                head: entry.head,
+                active_agent,
+                local_work: None,
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let (sender, receiver) = mpsc::channel();
+    for row in rows.iter().filter(|row| row.active_agent.is_none()) {
+        let sender = sender.clone();
+        let path = row.path.clone();
+        thread::spawn(move || {
+            let _slot = crate::batch_slot();
+            let work = local_work(&path);
+            let _ = sender.send((path, work));
+        });
+    }
+    Ok((rows, receiver))
+}
+
+impl Dashboard {
+    fn new(repo_root: PathBuf) -> Result<Self, String> {
+        let (rows, changes) = load_rows(&repo_root)?;
+        Ok(Self {
+            repo_root,
+            rows,
+            filter: String::new(),
+            selected: 0,
+            scroll: 0,
+            mode: Mode::Normal,
+            message: None,
+            changes,
+        })
+    }
+
+    fn reload(&mut self) -> Result<(), String> {
+        let (rows, changes) = load_rows(&self.repo_root)?;
+        self.rows = rows;
+        self.changes = changes;
+        self.clamp_selection();
+        Ok(())
+    }
+
+    fn receive_changes(&mut self) {
+        while let Ok((path, work)) = self.changes.try_recv() {
+            if let Some(row) = self.rows.iter_mut().find(|row| row.path == path) {
+                row.local_work = Some(work);
+            }
+        }
+    }
+
+    fn visible(&self) -> Vec<usize> {
+        visible_rows(&self.rows, &self.filter)
+    }
+
+    fn current(&self) -> Option<&Row> {
+        self.visible().get(self.selected).map(|&i| &self.rows[i])
+    }
+
+    fn clamp_selection(&mut self) {
+        self.selected = self.selected.min(self.visible().len().saturating_sub(1));
+    }
+
+    fn draw(&mut self, terminal: &mut Tui) -> Result<(), String> {
+        terminal
+            .draw(|frame| {
+                let area = frame.area();
+                let chunks = Layout::vertical([
+                    Constraint::Length(2), // title + blank
+                    Constraint::Min(1),    // worktree list
+                    Constraint::Length(1), // message
+                    Constraint::Length(1), // footer / prompt
+                ])
+                .split(area);
+
+                let title = Paragraph::new(Line::from(vec![
+                    Span::styled("checkout ui", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        format!(" — {} worktree(s) in {}", self.rows.len(), self.repo_root.display()),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]));
+                frame.render_widget(title, chunks[0]);
+
+                let visible = self.visible();
+                let height = chunks[1].height as usize;
+                if self.selected < self.scroll {
+                    self.scroll = self.selected;
+                } else if height > 0 && self.selected >= self.scroll + height {
+                    self.scroll = self.selected + 1 - height;
+                }
+
+                let selected_bg = Style::default().bg(Color::Rgb(40, 40, 50));
+                let mut lines: Vec<Line> = Vec::new();
+                for (offset, &i) in visible.iter().enumerate().skip(self.scroll).take(height) {
+                    let row = &self.rows[i];
+                    let is_sel = offset == self.selected;
+                    let line_style = if is_sel { selected_bg } else { Style::default() };
+                    let (badge, badge_color) = row.badge();
+                    lines.push(Line::from(vec![
+                        Span::styled(
+                            if is_sel { "▸ " } else { "  " },
+                            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).patch(line_style),
+                        ),
+                        Span::styled(format!("[{:<12}] ", badge), Style::default().fg(badge_color).patch(line_style)),
+                        Span::styled(row.name.clone(), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD).patch(line_style)),
+                        Span::styled(format!(" ({})", row.branch_label()), Style::default().fg(Color::DarkGray).patch(line_style)),
+                    ]));
+                }
+                if visible.is_empty() {
+                    lines.push(Line::from(Span::styled("  No matching worktrees", Style::default().fg(Color::DarkGray))));
+                }
+                frame.render_widget(Paragraph::new(lines), chunks[1]);
+
+                if let Some(message) = &self.message {
+                    frame.render_widget(
+                        Paragraph::new(Span::styled(message.clone(), Style::default().fg(Color::Yellow))),
+                        chunks[2],
+                    );
+                }
+
+                let key = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+                let dim = Style::default().fg(Color::DarkGray);
+                let name = self.current().map(|row| row.name.clone()).unwrap_or_default();
+                let footer = match &self.mode {
+                    Mode::Normal => {
+                        let mut spans = Vec::new();
+                        for (keys, label) in [
+                            ("↑↓", " Navigate  "),
+                            ("Enter", " Open  "),
+                            ("d", " Delete  "),
+                            ("u", " Update  "),
+                            ("r", " Rename  "),
+                            ("/", " Filter  "),
+                            ("q", " Quit"),
+                        ] {
+                            spans.push(Span::styled(keys, key));
+                            spans.push(Span::styled(label, dim));
+                        }
+                        if !self.filter.is_empty() {
+                            spans.push(Span::styled(format!("  filter: {}", self.filter), dim));
+                        }
+                        Line::from(spans)
+                    }
+                    Mode::Filter => Line::from(vec![Span::styled("/", key), Span::raw(self.filter.clone())]),
+                    Mode::Rename(input) => Line::from(vec![
+                        Span::styled(format!("Rename {} to: ", name), key),
+                        Span::raw(input.clone()),
+                    ]),
+                    Mode::ConfirmDelete => {
+                        let work = self.current().and_then(Row::info).and_then(|info| crate::local_work_summary(&info));
+                        let prompt = match work {
+                            Some(work) => format!("Delete {}? It has {}. [y/N]", name, work),
+                            None => format!("Delete {}? [y/N]", name),
+                        };
+                        Line::from(Span::styled(prompt, key))
+                    }
+                };
+                frame.render_widget(Paragraph::new(footer), chunks[3]);
+            })
+            .map_err(|e| format!("Draw error: {}", e))?;
+        Ok(())
+    }
+
+    /// Handle one key press. `Some` ends the event loop.
+    fn handle_key(&mut self, terminal: &mut Tui, key: KeyEvent) -> Result<Option<Outcome>, String> {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(Some(Outcome::Quit));
+        }
+
+        match std::mem::replace(&mut self.mode, Mode::Normal) {
+            Mode::Filter => {
+                match key.code {
+                    KeyCode::Enter => {}
+                    KeyCode::Esc => self.filter.clear(),
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                        self.mode = Mode::Filter;
+                    }
+                    KeyCode::Char(c) => {
+                        self.filter.push(c);
+                        self.mode = Mode::Filter;
+                    }
+                    _ => self.mode = Mode::Filter,
+                }
+                self.selected = 0;
+                self.scroll = 0;
+            }
+            Mode::Rename(mut input) => match key.code {
+                KeyCode::Enter => self.rename_selected(&input)?,
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.mode = Mode::Rename(input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.mode = Mode::Rename(input);
+                }
+                _ => self.mode = Mode::Rename(input),
+            },
+            Mode::ConfirmDelete => {
+                if key.code == KeyCode::Char('y') {
+                    self.delete_selected(terminal)?;
+                }
+            }
+            Mode::Normal => {
+                self.message = None;
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Outcome::Quit)),
+                    KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        if self.selected + 1 < self.visible().len() {
+                            self.selected += 1;
+                        }
+                    }
+                    KeyCode::Char('/') => self.mode = Mode::Filter,
+                    code => {
+                        if let Some(row) = self.current().cloned() {
+                            return self.act_on(terminal, row, code);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Keys that act on the selected worktree.
+    fn act_on(&mut self, terminal: &mut Tui, row: Row, code: KeyCode) -> Result<Option<Outcome>, String> {
+        match code {
+            KeyCode::Enter | KeyCode::Char('d') | KeyCode::Char('r') | KeyCode::Char('u')
+                if row.active_agent.is_some() =>
+            {
+                self.message = Some(format!("{} has a running agent session", row.name));
+            }
+            // Both can throw away work, so wait until it's known whether there is any
+            KeyCode::Char('d') | KeyCode::Char('u') if row.local_work.is_none() => {
+                self.message = Some(format!("Still checking {} for local changes", row.name));
+            }
+            KeyCode::Enter => return Ok(Some(Outcome::Open(row))),
+            KeyCode::Char('d') => self.mode = Mode::ConfirmDelete,
+            KeyCode::Char('r') => self.mode = Mode::Rename(row.name),
+            KeyCode::Char('u') => self.update_selected(terminal)?,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Hand the terminal back to run a helper that prints progress, then wait
+    /// for Enter and redraw the dashboard.
+    fn suspended(&mut self, terminal: &mut Tui, f: impl FnOnce(&Path) -> Result<(), String>) -> Result<(), String> {
+        restore_terminal();
+        println!();
+        match f(&self.repo_root) {
+            Ok(()) => {}
+            Err(_) if crate::Interrupt::take().is_some() => println!("{} Cancelled", "→".blue().bold()),
+            Err(e) => println!("{} {}", "error:".red().bold(), e),
+        }
+        print!("\n{} Press Enter to return to the dashboard", "?".magenta().bold());
+        io::stdout().flush().ok();
+        let _ = crate::read_input();
+        enter_screen()?;
+        terminal.clear().map_err(|e| format!("Failed to clear terminal: {}", e))?;
+        self.reload()
+    }
+
+    fn delete_selected(&mut self, terminal: &mut Tui) -> Result<(), String> {
+        let Some(row) = self.current().cloned() else {
+            return Ok(());
+        };
+        let Some(info) = row.info() else {
+            return Ok(());
+        };
+        // remove_worktrees backs up uncommitted changes before removing
+        self.suspended(terminal, |repo_root| crate::remove_worktrees(&[info], &repo_root.to_path_buf()))
+    }
+
+    fn update_selected(&mut self, terminal: &mut Tui) -> Result<(), String> {
+        let Some(row) = self.current().cloned() else {
+            return Ok(());
+        };
+        self.suspended(terminal, |repo_root| {
+            // PR worktrees are detached at origin/<branch>; ask GitHub which branch
+            let branch = match (&row.branch, pr_number_from_dir(&row.name)) {
+                (Some(branch), _) => branch.clone(),
+                (None, Some(pr_number)) => crate::pr_head_branch(&repo_root.to_path_buf(), pr_number)?,
+                (None, None) => return Err(format!("{} has a detached HEAD and no PR number", row.name)),
+            };
+            // The same confirmation and backup as `checkout pr --use-existing`,
+            // against a fresh status rather than the dashboard's
+            if let Some(changes) = crate::get_uncommitted_status(&row.path)? {
+                crate::confirm_discard_changes(&row.path, &changes)?;
+            }
+            print!("{} Updating {} to origin/{}... ", "→".blue().bold(), row.name.cyan(), branch);
+            io::stdout().flush().ok();
+            crate::update_worktree(&row.path, crate::Upstream::Branch(&branch), crate::LocalCommits::Prompt, false)?;
+            println!("{}", "done".green());
+            Ok(())
+        })
+    }
+
+    fn rename_selected(&mut self, new_name: &str) -> Result<(), String> {
+        let Some(row) = self.current().cloned() else {
+            return Ok(());
+        };
+        let new_name = new_name.trim();
+        if new_name == row.name {
+            return Ok(());
+        }
+        match rename_worktree(&self.repo_root, &row.path, new_name) {
+            Ok(new_path) => {
+                self.message = Some(format!("Renamed {} to {}", row.name, new_name));
+                self.reload()?;
+                if let Some(position) = self.visible().iter().position(|&i| self.rows[i].path == new_path) {
+                    self.selected = position;
+                }
+            }
+            Err(e) => self.message = Some(e),
+        }
+        Ok(())
+    }
+}
+
+/// Move a worktree directory within its parent and carry over the per-path
+/// state (color, session name, note) that is keyed by the old location.
+fn rename_worktree(repo_root: &Path, path: &Path, new_name: &str) -> Result<PathBuf, String> {
+    if new_name.is_empty() || new_name.contains('/') || new_name.starts_with('.') {
+        return Err(format!("Invalid worktree name '{}'", new_name));
+    }
+    let new_path = path.with_file_name(new_name);
+    if new_path.exists() {
+        return Err(format!("{} already exists", new_path.display()));
+    }
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_root)
+        .args(["worktree", "move"])
+        .arg(path)
+        .arg(&new_path)
+        .traced_output()
+        .map_err(|e| format!("Failed to run git worktree move: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git worktree move failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let _ = crate::move_worktree_state(&[path.to_path_buf()], &new_path);
+    Ok(new_path)
+}
+
+fn event_loop(terminal: &mut Tui, dashboard: &mut Dashboard) -> Result<Outcome, String> {
+    loop {
+        dashboard.receive_changes();
+        dashboard.draw(terminal)?;
+
+        if !event::poll(Duration::from_millis(100)).unwrap_or(false) {
+            continue;
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(outcome) = dashboard.handle_key(terminal, key)? {
+                return Ok(outcome);
+            }
+        }
+    }
+}
+
+/// Open a worktree the way `checkout resume` does: color, title, and the
+/// agent's last session if there is one.
+fn open_worktree(repo_root: &PathBuf, row: &Row, agent: Agent) -> Result<(), String> {
+    let target = crate::find_worktree_resume_target(&row.path);
+    let launch_agent = target.as_ref().map_or(agent, |target| target.agent);
+    crate::prepare_agent_worktree(launch_agent, &row.path, repo_root, false)?;
+    let bg_color = crate::assign_worktree_color(&row.path, None, None)?;
+    crate::record_current_iterm_session(&row.path)?;
+    let session_name = crate::session_name_for_resume(&row.path, row.branch_label());
+
+    let tab_title = crate::terminal_title(&session_name, repo_root);
+    let _terminal_guard = terminal::TerminalGuard::new(&bg_color, &tab_title, crate::terminal_guard_options(&crate::CheckoutArgs::default()));
+    crate::run_on_enter_script(&row.path, &bg_color, &session_name);
+
+    let system_prompt = crate::build_worktree_system_prompt();
+    println!(
+        "\n{} Opening {} with {}...\n",
+        "→".blue().bold(),
+        row.path.display().to_string().cyan(),
+        launch_agent.display_name()
+    );
+    match target {
+        Some(target) => crate::spawn_agent_continue(
+            target.agent,
+            &row.path,
+            None,
+            Some(&system_prompt),
+            target.resume_id.as_deref(),
+            &session_name,
+            &[],
+        ),
+        None => crate::spawn_agent(agent, &row.path, Some(&system_prompt), &session_name, &[]),
+    }
+}
+
+pub fn run(repo_root: PathBuf, agent: Agent) -> Result<(), String> {
+    let mut dashboard = Dashboard::new(repo_root.clone())?;
+
+    let original_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        original_hook(info);
+    }));
+
+    let outcome = {
+        enter_screen()?;
+        let _screen = ScreenGuard;
+        let mut terminal =
+            Terminal::new(CrosstermBackend::new(io::stdout())).map_err(|e| format!("Failed to create terminal: {}", e))?;
+        event_loop(&mut terminal, &mut dashboard)?
+    };
+
+    match outcome {
+        Outcome::Quit => Ok(()),
+        Outcome::Open(row) => open_worktree(&repo_root, &row, agent),
+    }
+}
+
+/// Checkbox state for `multi_select`. Everything starts checked.
+struct Checklist {
+    checked: Vec<bool>,
+    cursor: usize,
+}
+
+impl Checklist {
+    fn new(len: usize) -> Self {
+        Self { checked: vec![true; len], cursor: 0 }
+    }
+
+    /// Apply a key press. `Some(true)` confirms the selection and
+    /// `Some(false)` cancels.
+    fn handle_key(&mut self, key: KeyEvent) -> Option<bool> {
+        let last = self.checked.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(false),
+            KeyCode::Esc | KeyCode::Char('q') => return Some(false),
+            KeyCode::Enter => return Some(true),
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            KeyCode::Char(' ') => {
+                if let Some(checked) = self.checked.get_mut(self.cursor) {
+                    *checked = !*checked;
+                }
+            }
+            KeyCode::Char('a') => {
+                let all = self.checked.iter().all(|&checked| checked);
+                self.checked.iter_mut().for_each(|checked| *checked = !all);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn selected(&self) -> Vec<usize> {
+        (0..self.checked.len()).filter(|&i| self.checked[i]).collect()
+    }
+}
+
+/// Full-screen checkbox list. Returns the indices the user kept checked, or
+/// `None` if they cancelled.
+pub fn multi_select(title: &str, items: &[String]) -> Result<Option<Vec<usize>>, String> {
+    let mut list = Checklist::new(items.len());
+    enter_screen()?;
+    let _screen = ScreenGuard;
+    let mut terminal =
+        Terminal::new(CrosstermBackend::new(io::stdout())).map_err(|e| format!("Failed to create terminal: {}", e))?;
+
+    loop {
+        terminal
+            .draw(|frame| {
+                let chunks = Layout::vertical([Constraint::Length(2), Constraint::Min(1), Constraint::Length(1)])
+                    .split(frame.area());
+                frame.render_widget(
+                    Paragraph::new(Span::styled(
+                        title.to_string(),
+                        Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                    )),
+                    chunks[0],
+                );
+
+                let height = chunks[1].height as usize;
+                let offset = list.cursor.saturating_sub(height.saturating_sub(1));
+                let lines: Vec<Line> = items
+                    .iter()
+                    .enumerate()
+                    .skip(offset)
+                    .take(height)
+                    .map(|(i, item)| {
+                        let style = if i == list.cursor {
+                            Style::default().bg(Color::Rgb(40, 40, 50))
+                        } else {
+                            Style::default()
+                        };
+                        let (mark, mark_color) = if list.checked[i] { ("[x] ", Color::Red) } else { ("[ ] ", Color::DarkGray) };
+                        Line::from(vec![
+                            Span::styled(if i == list.cursor { "▸ " } else { "  " }, style.fg(Color::Cyan)),
+                            Span::styled(mark, style.fg(mark_color)),
+                            Span::styled(item.clone(), style),
+                        ])
+                    })
+                    .collect();
+                frame.render_widget(Paragraph::new(lines), chunks[1]);
+
+                frame.render_widget(
+                    Paragraph::new(Span::styled(
+                        format!(
+                            "{} of {} selected · space toggle · a all · enter confirm · esc cancel",
+                            list.selected().len(),
+                            items.len()
+                        ),
+                        Style::default().fg(Color::DarkGray),
+                    )),
+                    chunks[2],
+                );
+            })
+            .map_err(|e| format!("Failed to draw: {}", e))?;
+
+        if let Ok(Event::Key(key)) = event::read() {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match list.handle_key(key) {
+                Some(true) => return Ok(Some(list.selected())),
+                Some(false) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, branch: Option<&str>) -> Row {
+        Row {
+            path: PathBuf::from("/wt").join(name),
+            name: name.to_string(),
+            branch: branch.map(str::to_string),
+            head: String::new(),
+            active_agent: None,
+            local_work: None,
+        }
+    }
+
+    #[test]
+    fn filter_matches_directory_or_branch_and_pr_numbers_parse() {
+        let rows = [
+            row("pr-123-fix-login", None),
+            row("branch-auth", Some("darren/auth")),
+            row("calm-otter", Some("darren/calm-otter")),
+        ];
+        assert_eq!(visible_rows(&rows, ""), vec![0, 1, 2]);
+        assert_eq!(visible_rows(&rows, "AUTH"), vec![1]);
+        assert_eq!(visible_rows(&rows, "darren/"), vec![1, 2]);
+        assert_eq!(visible_rows(&rows, "detached"), vec![0]);
+        assert!(visible_rows(&rows, "nope").is_empty());
+
+        assert_eq!(pr_number_from_dir("pr-123-fix-login"), Some(123));
+        assert_eq!(pr_number_from_dir("pr-7"), Some(7));
+        assert_eq!(pr_number_from_dir("branch-auth"), None);
+        assert_eq!(row("x", None).badge().0, "…");
+    }
+
+    #[test]
+    fn rows_report_local_work_once_status_loads() {
+        let mut pr = row("pr-5-fix", None);
+        assert!(pr.info().is_none());
+
+        pr.local_work = Some(LocalWork { has_changes: false, unpushed_commits: Some(0) });
+        assert_eq!(pr.badge().0, "clean");
+        assert_eq!(crate::local_work_summary(&pr.info().unwrap()), None);
+
+        pr.local_work = Some(LocalWork { has_changes: true, unpushed_commits: Some(2) });
+        assert_eq!(pr.badge().0, "modified");
+        assert_eq!(
+            crate::local_work_summary(&pr.info().unwrap()).as_deref(),
+            Some("uncommitted changes and 2 unpushed commit(s)")
+        );
+
+        pr.local_work = Some(LocalWork { has_changes: false, unpushed_commits: None });
+        assert_eq!(pr.badge().0, "unpushed");
+        assert!(pr.info().unwrap().has_local_work());
+    }
+
+    #[test]
+    fn checklist_toggles_items_and_confirms_or_cancels() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut list = Checklist::new(3);
+        assert_eq!(list.selected(), vec![0, 1, 2]);
+
+        assert_eq!(list.handle_key(key(KeyCode::Down)), None);
+        list.handle_key(key(KeyCode::Char(' ')));
+        assert_eq!(list.selected(), vec![0, 2]);
+        for _ in 0..5 {
+            list.handle_key(key(KeyCode::Char('j')));
+        }
+        assert_eq!(list.cursor, 2);
+
+        list.handle_key(key(KeyCode::Char('a')));
+        assert_eq!(list.selected(), vec![0, 1, 2]);
+        list.handle_key(key(KeyCode::Char('a')));
+        assert!(list.selected().is_empty());
+
+        assert_eq!(list.handle_key(key(KeyCode::Enter)), Some(true));
+        assert_eq!(list.handle_key(key(KeyCode::Esc)), Some(false));
+        assert_eq!(list.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Some(false));
+    }
+}